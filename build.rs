@@ -1,8 +1,12 @@
 use std::env;
 use std::fs;
 use std::path::PathBuf;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 fn main() {
+    embed_version_info();
+
     let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
     let ico_path = out_dir.join("app.ico");
 
@@ -27,6 +31,52 @@ fn main() {
     }
 }
 
+/// 嵌入 git 提交哈希与构建时间（UTC），供 `version` 子命令读取
+/// 不在 git 仓库中构建时哈希为 "unknown"
+fn embed_version_info() {
+    let git_hash = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=GIT_HASH={}", git_hash);
+    println!("cargo:rustc-env=BUILD_TIME={}", build_time_utc());
+}
+
+/// 当前 UTC 时间，格式 YYYY-MM-DDTHH:MM:SSZ（build 依赖不引入 chrono，手动换算）
+fn build_time_utc() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    let days = secs.div_euclid(86400);
+    let rem = secs.rem_euclid(86400);
+
+    // 公历日期换算（Howard Hinnant 的 civil_from_days 算法）
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
 // 主题色彩配置 - 与 gpui-component 暗色主题匹配
 const COL_BG: [u8; 4] = [0x1A, 0x1A, 0x2E, 0xFF]; // 深蓝黑背景 (#1A1A2E)
 const COL_BG_LIGHT: [u8; 4] = [0x22, 0x22, 0x3A, 0xFF]; // 稍浅的内部背景 (#22223A)
@@ -0,0 +1,282 @@
+//! 注册服务前检查服务账户：是否能以服务身份登录、能否读取配置目录
+//!
+//! “作为服务登录”（SeServiceLogonRight）与“拒绝作为服务登录”（SeDenyServiceLogonRight）是账户权利而不是特权，
+//! `LookupPrivilegeName` 查不到，这里通过 LSA 枚举被授予该权利的账户；目录读取权限用 `GetEffectiveRightsFromAclW` 计算。
+//! 只报告问题，是否继续注册由调用方决定。检查本身失败（如未以管理员身份运行）时记录警告并跳过该项
+
+use anyhow::Result;
+use sha1::{Digest, Sha1};
+use std::path::PathBuf;
+use windows_sys::Win32::Foundation::{LocalFree, GENERIC_ALL, GENERIC_READ, PSID};
+use windows_sys::Win32::Security::Authentication::Identity::{
+    LsaClose, LsaEnumerateAccountsWithUserRight, LsaFreeMemory, LsaNtStatusToWinError,
+    LsaOpenPolicy, LSA_ENUMERATION_INFORMATION, LSA_HANDLE, LSA_OBJECT_ATTRIBUTES,
+    LSA_UNICODE_STRING, POLICY_LOOKUP_NAMES, POLICY_VIEW_LOCAL_INFORMATION,
+};
+use windows_sys::Win32::Security::Authorization::ConvertStringSidToSidW;
+use windows_sys::Win32::Security::EqualSid;
+use windows_sys::Win32::Storage::FileSystem::FILE_GENERIC_READ;
+
+use crate::acl;
+use crate::config;
+use crate::service::{self, ServiceAccount};
+
+/// 枚举结果为空（没有账户被授予该权利）
+const STATUS_NO_MORE_ENTRIES: i32 = 0x8000_001A_u32 as i32;
+
+/// 服务账户存在的问题及解决办法
+#[derive(Debug, Clone)]
+pub struct AccountIssue {
+    /// 缺少的权利或权限
+    pub problem: String,
+    /// 如何授予
+    pub fix: String,
+}
+
+/// 按字符串 SID 创建的 SID，离开作用域时释放
+struct OwnedSid(PSID);
+
+impl OwnedSid {
+    fn parse(sid: &str) -> Result<Self> {
+        let wide: Vec<u16> = sid.encode_utf16().chain(std::iter::once(0)).collect();
+        let mut psid: PSID = std::ptr::null_mut();
+        if unsafe { ConvertStringSidToSidW(wide.as_ptr(), &mut psid) } == 0 {
+            anyhow::bail!("无法解析 SID {}", sid);
+        }
+        Ok(Self(psid))
+    }
+}
+
+impl Drop for OwnedSid {
+    fn drop(&mut self) {
+        unsafe { LocalFree(self.0 as _) };
+    }
+}
+
+/// 服务 SID（`NT SERVICE\<服务名>`）：S-1-5-80- 加大写服务名 UTF-16LE 的 SHA-1（按 5 个小端 u32），
+/// 与 `sc showsid` 一致，服务注册前即可得到
+fn service_sid(service_name: &str) -> String {
+    let bytes: Vec<u8> = service_name
+        .to_uppercase()
+        .encode_utf16()
+        .flat_map(u16::to_le_bytes)
+        .collect();
+    let digest = Sha1::digest(&bytes);
+    let parts: Vec<String> = digest
+        .chunks(4)
+        .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]).to_string())
+        .collect();
+    format!("S-1-5-80-{}", parts.join("-"))
+}
+
+/// 服务账户本身及其所属的、可能出现在用户权利分配中的组：(SID, 显示名)
+fn account_sids(account: ServiceAccount) -> Vec<(String, String)> {
+    let mut sids = match account {
+        ServiceAccount::LocalSystem => vec![
+            ("S-1-5-18".to_string(), "NT AUTHORITY\\SYSTEM".to_string()),
+            (
+                "S-1-5-32-544".to_string(),
+                "BUILTIN\\Administrators".to_string(),
+            ),
+        ],
+        ServiceAccount::Virtual => vec![
+            (
+                service_sid(service::service_name()),
+                format!("NT SERVICE\\{}", service::service_name()),
+            ),
+            (
+                "S-1-5-80-0".to_string(),
+                "NT SERVICE\\ALL SERVICES".to_string(),
+            ),
+        ],
+    };
+    sids.push(("S-1-5-6".to_string(), "NT AUTHORITY\\SERVICE".to_string()));
+    sids.push((
+        "S-1-5-11".to_string(),
+        "NT AUTHORITY\\Authenticated Users".to_string(),
+    ));
+    sids.push(("S-1-1-0".to_string(), "Everyone".to_string()));
+    sids
+}
+
+/// 检查服务账户，返回发现的问题（为空表示可以注册）
+pub fn check_service_account(account: ServiceAccount) -> Vec<AccountIssue> {
+    let mut issues = Vec::new();
+    match check_logon_rights(account) {
+        Ok(mut found) => issues.append(&mut found),
+        Err(e) => log::warn!("无法检查服务账户的登录权利: {:#}", e),
+    }
+    match check_config_access(account) {
+        Ok(mut found) => issues.append(&mut found),
+        Err(e) => log::warn!("无法检查服务账户对配置目录的权限: {:#}", e),
+    }
+    issues
+}
+
+fn check_logon_rights(account: ServiceAccount) -> Result<Vec<AccountIssue>> {
+    let policy = LsaPolicy::open()?;
+    let sids: Vec<(OwnedSid, String)> = account_sids(account)
+        .into_iter()
+        .map(|(sid, name)| Ok((OwnedSid::parse(&sid)?, name)))
+        .collect::<Result<_>>()?;
+    let mut issues = Vec::new();
+
+    let denied = policy.accounts_with_right("SeDenyServiceLogonRight")?;
+    for (sid, name) in &sids {
+        if denied.iter().any(|d| unsafe { EqualSid(d, sid.0) } != 0) {
+            issues.push(AccountIssue {
+                problem: format!(
+                    "{} 被分配了“拒绝作为服务登录”（SeDenyServiceLogonRight），服务将无法启动",
+                    name
+                ),
+                fix: format!(
+                    "在 secpol.msc → 本地策略 → 用户权限分配 → 拒绝作为服务登录 中移除 {}；由域组策略下发时需联系域管理员",
+                    name
+                ),
+            });
+        }
+    }
+
+    // LocalSystem 隐式拥有登录权利；虚拟账户在策略未定义该权利时由 SCM 授予，
+    // 策略定义了该权利（列表非空）但不含服务本身或 ALL SERVICES 时启动会失败（错误 1069）
+    if account == ServiceAccount::Virtual {
+        let allowed = policy.accounts_with_right("SeServiceLogonRight")?;
+        let covered = allowed.is_empty()
+            || sids
+                .iter()
+                .any(|(sid, _)| allowed.iter().any(|a| unsafe { EqualSid(a, sid.0) } != 0));
+        if !covered {
+            issues.push(AccountIssue {
+                problem: format!(
+                    "“作为服务登录”（SeServiceLogonRight）由策略限定为特定账户，其中不包含 NT SERVICE\\{} 或 NT SERVICE\\ALL SERVICES，服务将无法启动（错误 1069）",
+                    service::service_name()
+                ),
+                fix: "在 secpol.msc → 本地策略 → 用户权限分配 → 作为服务登录 中添加 NT SERVICE\\ALL SERVICES，或改用 --account localsystem".to_string(),
+            });
+        }
+    }
+    Ok(issues)
+}
+
+/// 配置目录（conf/ 与实例清单所在目录）需要可读
+///
+/// 虚拟账户的读取权限由注册时的 icacls 授予，这里只检查 LocalSystem
+fn check_config_access(account: ServiceAccount) -> Result<Vec<AccountIssue>> {
+    if account != ServiceAccount::LocalSystem {
+        return Ok(Vec::new());
+    }
+    let system = OwnedSid::parse("S-1-5-18")?;
+    let mut dirs: Vec<PathBuf> = vec![config::conf_dir()?];
+    let instances_dir = config::instances_dir()?;
+    if !dirs.contains(&instances_dir) {
+        dirs.push(instances_dir);
+    }
+    let mut issues = Vec::new();
+    for dir in dirs.into_iter().filter(|d| d.exists()) {
+        let rights = acl::effective_rights_for_sid(&dir, system.0)?;
+        let readable = rights & FILE_GENERIC_READ == FILE_GENERIC_READ
+            || rights & (GENERIC_READ | GENERIC_ALL) != 0;
+        if !readable {
+            issues.push(AccountIssue {
+                problem: format!(
+                    "NT AUTHORITY\\SYSTEM 没有配置目录 {} 的读取权限",
+                    dir.display()
+                ),
+                fix: format!(
+                    "以管理员身份执行 icacls \"{}\" /grant SYSTEM:(OI)(CI)RX",
+                    dir.display()
+                ),
+            });
+        }
+    }
+    Ok(issues)
+}
+
+/// 本机 LSA 策略句柄
+struct LsaPolicy(LSA_HANDLE);
+
+impl LsaPolicy {
+    fn open() -> Result<Self> {
+        let attributes: LSA_OBJECT_ATTRIBUTES = unsafe { std::mem::zeroed() };
+        let mut handle: LSA_HANDLE = 0;
+        let status = unsafe {
+            LsaOpenPolicy(
+                std::ptr::null(),
+                &attributes,
+                (POLICY_LOOKUP_NAMES | POLICY_VIEW_LOCAL_INFORMATION) as u32,
+                &mut handle,
+            )
+        };
+        if status != 0 {
+            anyhow::bail!(
+                "无法打开本机安全策略，错误码: {}（需要管理员权限）",
+                unsafe { LsaNtStatusToWinError(status) }
+            );
+        }
+        Ok(Self(handle))
+    }
+
+    /// 被授予指定账户权利的 SID；返回的指针在 [`RightHolders`] 释放前有效
+    fn accounts_with_right(&self, right: &str) -> Result<RightHolders> {
+        let mut wide: Vec<u16> = right.encode_utf16().collect();
+        let name = LSA_UNICODE_STRING {
+            Length: (wide.len() * 2) as u16,
+            MaximumLength: (wide.len() * 2) as u16,
+            Buffer: wide.as_mut_ptr(),
+        };
+        let mut buffer: *mut std::ffi::c_void = std::ptr::null_mut();
+        let mut count = 0u32;
+        let status =
+            unsafe { LsaEnumerateAccountsWithUserRight(self.0, &name, &mut buffer, &mut count) };
+        match status {
+            0 => Ok(RightHolders { buffer, count }),
+            STATUS_NO_MORE_ENTRIES => Ok(RightHolders {
+                buffer: std::ptr::null_mut(),
+                count: 0,
+            }),
+            _ => anyhow::bail!("无法枚举拥有 {} 的账户，错误码: {}", right, unsafe {
+                LsaNtStatusToWinError(status)
+            }),
+        }
+    }
+}
+
+impl Drop for LsaPolicy {
+    fn drop(&mut self) {
+        unsafe { LsaClose(self.0) };
+    }
+}
+
+/// `LsaEnumerateAccountsWithUserRight` 返回的 SID 列表
+struct RightHolders {
+    buffer: *mut std::ffi::c_void,
+    count: u32,
+}
+
+impl RightHolders {
+    fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    fn iter(&self) -> impl Iterator<Item = PSID> + '_ {
+        let entries: &[LSA_ENUMERATION_INFORMATION] = if self.buffer.is_null() {
+            &[]
+        } else {
+            unsafe {
+                std::slice::from_raw_parts(
+                    self.buffer as *const LSA_ENUMERATION_INFORMATION,
+                    self.count as usize,
+                )
+            }
+        };
+        entries.iter().map(|e| e.Sid)
+    }
+}
+
+impl Drop for RightHolders {
+    fn drop(&mut self) {
+        if !self.buffer.is_null() {
+            unsafe { LsaFreeMemory(self.buffer) };
+        }
+    }
+}
@@ -0,0 +1,200 @@
+//! 目录权限自检：程序目录或配置目录对 Everyone / BUILTIN\Users 可写时记录安全警告
+//!
+//! 服务以 LocalSystem 运行，普通用户可写的程序目录可被用于 DLL 劫持、替换 frpc.exe
+//! 或篡改配置，从而以 SYSTEM 权限执行任意代码。自检只告警，不阻止服务启动。
+
+use anyhow::Result;
+use std::os::windows::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use windows_sys::Win32::Foundation::{LocalFree, GENERIC_ALL, GENERIC_WRITE, PSID};
+use windows_sys::Win32::Security::Authorization::{
+    BuildTrusteeWithSidW, GetEffectiveRightsFromAclW, GetNamedSecurityInfoW, SE_FILE_OBJECT,
+    TRUSTEE_W,
+};
+use windows_sys::Win32::Security::{
+    CreateWellKnownSid, WinBuiltinUsersSid, WinWorldSid, ACL, DACL_SECURITY_INFORMATION,
+    PSECURITY_DESCRIPTOR, WELL_KNOWN_SID_TYPE,
+};
+use windows_sys::Win32::Storage::FileSystem::{
+    DELETE, FILE_ADD_FILE, FILE_ADD_SUBDIRECTORY, WRITE_DAC, WRITE_OWNER,
+};
+
+use crate::config;
+
+/// 视为"可写"的权限：新建文件/子目录、删除、修改权限或所有者
+const WRITE_RIGHTS: u32 = FILE_ADD_FILE
+    | FILE_ADD_SUBDIRECTORY
+    | DELETE
+    | WRITE_DAC
+    | WRITE_OWNER
+    | GENERIC_WRITE
+    | GENERIC_ALL;
+
+/// SID 最大长度（SECURITY_MAX_SID_SIZE）
+const MAX_SID_SIZE: usize = 68;
+
+/// 需要检查的内置账户
+const CHECKED_ACCOUNTS: [(WELL_KNOWN_SID_TYPE, &str); 2] = [
+    (WinWorldSid, "Everyone"),
+    (WinBuiltinUsersSid, "BUILTIN\\Users"),
+];
+
+/// 目录权限检查结果
+#[derive(Debug)]
+pub struct AclWarning {
+    pub path: PathBuf,
+    /// 对该目录有写权限的内置账户，为空表示安全
+    pub writable_by: Vec<&'static str>,
+}
+
+impl AclWarning {
+    pub fn is_empty(&self) -> bool {
+        self.writable_by.is_empty()
+    }
+}
+
+/// 检查目录的 DACL 是否授予 Everyone 或 BUILTIN\Users 写权限
+pub fn check_directory_acl(path: &Path) -> Result<AclWarning> {
+    let wide: Vec<u16> = path
+        .as_os_str()
+        .encode_wide()
+        .chain(std::iter::once(0))
+        .collect();
+    let mut dacl: *mut ACL = std::ptr::null_mut();
+    let mut sd: PSECURITY_DESCRIPTOR = std::ptr::null_mut();
+    let err = unsafe {
+        GetNamedSecurityInfoW(
+            wide.as_ptr(),
+            SE_FILE_OBJECT,
+            DACL_SECURITY_INFORMATION,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            &mut dacl,
+            std::ptr::null_mut(),
+            &mut sd,
+        )
+    };
+    if err != 0 {
+        anyhow::bail!("无法读取 {:?} 的安全信息，错误码: {}", path, err);
+    }
+
+    let mut warning = AclWarning {
+        path: path.to_path_buf(),
+        writable_by: Vec::new(),
+    };
+    if dacl.is_null() {
+        // 空 DACL 表示所有人完全控制
+        warning.writable_by = CHECKED_ACCOUNTS.iter().map(|(_, name)| *name).collect();
+    } else {
+        for (sid_type, name) in CHECKED_ACCOUNTS {
+            match unsafe { effective_rights(dacl, sid_type) } {
+                Ok(rights) if rights & WRITE_RIGHTS != 0 => warning.writable_by.push(name),
+                Ok(_) => {}
+                Err(e) => log::warn!("无法检查 {} 对 {:?} 的权限: {}", name, path, e),
+            }
+        }
+    }
+    unsafe { LocalFree(sd) };
+    Ok(warning)
+}
+
+/// 计算指定 SID 对路径的有效权限（按目录 DACL 计算，空 DACL 视为完全控制）
+pub fn effective_rights_for_sid(path: &Path, sid: PSID) -> Result<u32> {
+    let wide: Vec<u16> = path
+        .as_os_str()
+        .encode_wide()
+        .chain(std::iter::once(0))
+        .collect();
+    let mut dacl: *mut ACL = std::ptr::null_mut();
+    let mut sd: PSECURITY_DESCRIPTOR = std::ptr::null_mut();
+    let err = unsafe {
+        GetNamedSecurityInfoW(
+            wide.as_ptr(),
+            SE_FILE_OBJECT,
+            DACL_SECURITY_INFORMATION,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            &mut dacl,
+            std::ptr::null_mut(),
+            &mut sd,
+        )
+    };
+    if err != 0 {
+        anyhow::bail!("无法读取 {:?} 的安全信息，错误码: {}", path, err);
+    }
+    let result = if dacl.is_null() {
+        Ok(GENERIC_ALL)
+    } else {
+        unsafe {
+            let mut trustee: TRUSTEE_W = std::mem::zeroed();
+            BuildTrusteeWithSidW(&mut trustee, sid);
+            let mut rights = 0u32;
+            match GetEffectiveRightsFromAclW(dacl, &trustee, &mut rights) {
+                0 => Ok(rights),
+                err => Err(anyhow::anyhow!(
+                    "GetEffectiveRightsFromAclW 失败，错误码: {}",
+                    err
+                )),
+            }
+        }
+    };
+    unsafe { LocalFree(sd) };
+    result
+}
+
+/// 计算内置账户在 DACL 中的有效权限
+unsafe fn effective_rights(dacl: *const ACL, sid_type: WELL_KNOWN_SID_TYPE) -> Result<u32> {
+    let mut sid = [0u8; MAX_SID_SIZE];
+    let mut sid_len = MAX_SID_SIZE as u32;
+    if CreateWellKnownSid(
+        sid_type,
+        std::ptr::null_mut(),
+        sid.as_mut_ptr() as PSID,
+        &mut sid_len,
+    ) == 0
+    {
+        anyhow::bail!("无法创建内置账户 SID");
+    }
+    let mut trustee: TRUSTEE_W = std::mem::zeroed();
+    BuildTrusteeWithSidW(&mut trustee, sid.as_mut_ptr() as PSID);
+    let mut rights = 0u32;
+    let err = GetEffectiveRightsFromAclW(dacl, &trustee, &mut rights);
+    if err != 0 {
+        anyhow::bail!("GetEffectiveRightsFromAclW 失败，错误码: {}", err);
+    }
+    Ok(rights)
+}
+
+/// 需要检查权限的目录：程序目录、bin 目录与配置目录（已去重，只含存在的目录）
+pub fn sensitive_directories() -> Vec<PathBuf> {
+    let dirs = [
+        std::env::current_exe()
+            .ok()
+            .and_then(|p| p.parent().map(Path::to_path_buf)),
+        config::bin_dir().ok(),
+        config::conf_dir().ok(),
+        config::instances_dir().ok(),
+    ];
+    let mut result: Vec<PathBuf> = Vec::new();
+    for dir in dirs.into_iter().flatten() {
+        if !result.contains(&dir) && dir.exists() {
+            result.push(dir);
+        }
+    }
+    result
+}
+
+/// 服务启动时检查程序目录、bin 目录与配置目录，存在风险时记录醒目的安全警告
+pub fn warn_insecure_directories() {
+    for dir in sensitive_directories() {
+        match check_directory_acl(&dir) {
+            Ok(warning) if !warning.is_empty() => log::warn!(
+                "========== 安全警告：目录 {:?} 对 {} 可写，服务以 LocalSystem 运行，可能被用于提权（替换 frpc.exe、DLL 劫持、篡改配置），请收紧该目录的权限 ==========",
+                warning.path,
+                warning.writable_by.join("、")
+            ),
+            Ok(_) => {}
+            Err(e) => log::warn!("目录权限检查失败: {:?}", e),
+        }
+    }
+}
@@ -0,0 +1,325 @@
+//! admin API 聚合代理：在一个端口上统一访问所有实例的 frpc admin API
+//!
+//! - `GET /instances`：列出实例及其 admin API 状态
+//! - `/<实例名>/api/...`：原样转发到该实例配置中 webServer 指定的地址，自动附带其 user/password
+//!
+//! 每个连接由独立线程处理（同时处理的连接数有上限），请求处理完即关闭连接。
+//! 默认只监听 127.0.0.1；监听其他地址时必须配置 token，请求需携带 `Authorization: Bearer <token>`
+
+use anyhow::{Context, Result};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+use crate::config::{self, AdminProxySettings};
+use crate::probe;
+
+/// 同时处理的连接数上限，超出时直接返回 503
+const MAX_CONNECTIONS: usize = 32;
+
+/// 读取请求、转发到实例的超时
+const IO_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 请求头与请求体的大小上限
+const MAX_HEADER_BYTES: usize = 64 * 1024;
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+/// 按设置启动代理线程；设置无效或端口被占用时记录错误，不影响服务运行
+pub fn start(settings: &AdminProxySettings) {
+    let listener = match bind(settings) {
+        Ok(l) => l,
+        Err(e) => {
+            log::error!("admin API 聚合代理未启动: {:#}", e);
+            return;
+        }
+    };
+    log::info!("admin API 聚合代理已启动: http://{}", settings.listen);
+    let token = settings.token.clone();
+    let active = Arc::new(AtomicUsize::new(0));
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { continue };
+            if active.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+                active.fetch_sub(1, Ordering::SeqCst);
+                let mut stream = stream;
+                let _ = respond_error(&mut stream, 503, "同时处理的请求过多，请稍后重试");
+                continue;
+            }
+            let token = token.clone();
+            let active = Arc::clone(&active);
+            thread::spawn(move || {
+                if let Err(e) = handle_connection(stream, token.as_deref()) {
+                    log::debug!("admin API 聚合代理处理请求失败: {:#}", e);
+                }
+                active.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+    });
+}
+
+fn bind(settings: &AdminProxySettings) -> Result<TcpListener> {
+    let addr: SocketAddr = settings
+        .listen
+        .parse()
+        .context(format!("监听地址无效: {}", settings.listen))?;
+    let has_token = settings.token.as_deref().is_some_and(|t| !t.is_empty());
+    if !addr.ip().is_loopback() && !has_token {
+        anyhow::bail!(
+            "监听非本机地址 {} 时必须在 admin_proxy 中设置 token",
+            settings.listen
+        );
+    }
+    TcpListener::bind(addr).context(format!("无法监听 {}", settings.listen))
+}
+
+/// 解析后的 HTTP 请求
+struct Request {
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+fn handle_connection(mut stream: TcpStream, token: Option<&str>) -> Result<()> {
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    let request = match read_request(&mut stream) {
+        Ok(r) => r,
+        Err(e) => return respond_error(&mut stream, 400, &format!("{:#}", e)),
+    };
+
+    if let Some(token) = token.filter(|t| !t.is_empty()) {
+        let authorized = request
+            .header("Authorization")
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .is_some_and(|t| t == token);
+        if !authorized {
+            return respond_error(
+                &mut stream,
+                401,
+                "缺少或错误的 Authorization: Bearer <token>",
+            );
+        }
+    }
+
+    let path = request.path.clone();
+    if path == "/instances" || path.starts_with("/instances?") {
+        if request.method != "GET" {
+            return respond_error(&mut stream, 405, "/instances 只支持 GET");
+        }
+        let body = serde_json::to_vec_pretty(&list_instances())?;
+        return respond(&mut stream, 200, "application/json", &body);
+    }
+
+    // /<实例名>/api/...
+    let Some((instance, rest)) = path
+        .strip_prefix('/')
+        .and_then(|p| p.split_once('/'))
+        .map(|(name, rest)| (percent_decode(name), format!("/{}", rest)))
+    else {
+        return respond_error(&mut stream, 404, "路径应为 /instances 或 /<实例名>/api/...");
+    };
+    if !rest.starts_with("/api/") {
+        return respond_error(&mut stream, 404, "只转发 /<实例名>/api/... 请求");
+    }
+    forward(&mut stream, &request, &instance, &rest)
+}
+
+/// 读取请求行、请求头与请求体（按 Content-Length）
+fn read_request(stream: &mut TcpStream) -> Result<Request> {
+    let mut reader = BufReader::new(stream.try_clone()?).take(MAX_HEADER_BYTES as u64);
+    let mut line = String::new();
+    reader.read_line(&mut line).context("无法读取请求行")?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().context("请求行缺少方法")?.to_string();
+    let path = parts.next().context("请求行缺少路径")?.to_string();
+
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).context("无法读取请求头")? == 0 {
+            anyhow::bail!("请求头不完整或过大");
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((k, v)) = header.split_once(':') {
+            headers.push((k.trim().to_string(), v.trim().to_string()));
+        }
+    }
+
+    let length = headers
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case("Content-Length"))
+        .map(|(_, v)| v.parse::<usize>().context("Content-Length 无效"))
+        .transpose()?
+        .unwrap_or(0);
+    if length > MAX_BODY_BYTES {
+        anyhow::bail!("请求体过大（上限 {} 字节）", MAX_BODY_BYTES);
+    }
+    let mut reader = reader.into_inner();
+    let mut body = vec![0u8; length];
+    reader.read_exact(&mut body).context("无法读取请求体")?;
+    Ok(Request {
+        method,
+        path,
+        headers,
+        body,
+    })
+}
+
+/// `/instances` 的内容：每个实例的名称、是否自启动与 admin API 状态
+fn list_instances() -> serde_json::Value {
+    let configs = config::load_configs().unwrap_or_default();
+    let instances: Vec<serde_json::Value> = configs
+        .iter()
+        .map(|meta| {
+            let (admin_api, error) = match config::config_toml_path(&meta.name)
+                .and_then(|p| probe::admin_api_lookup(&p))
+            {
+                Ok(Some(api)) => (Some(api.base_url), None),
+                Ok(None) => (None, None),
+                Err(e) => (None, Some(format!("{:#}", e))),
+            };
+            serde_json::json!({
+                "name": meta.name,
+                "auto_start": meta.auto_start,
+                "admin_api": admin_api,
+                "proxy_path": admin_api.as_ref().map(|_| format!("/{}/api/", meta.name)),
+                "error": error,
+            })
+        })
+        .collect();
+    serde_json::json!({ "instances": instances })
+}
+
+fn client() -> &'static reqwest::blocking::Client {
+    static CLIENT: OnceLock<reqwest::blocking::Client> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        reqwest::blocking::Client::builder()
+            .timeout(IO_TIMEOUT)
+            .build()
+            .unwrap_or_default()
+    })
+}
+
+/// 转发到实例的 admin API，原样返回状态码与响应体
+fn forward(stream: &mut TcpStream, request: &Request, instance: &str, rest: &str) -> Result<()> {
+    if config::find_config(instance).is_none() {
+        return respond_error(stream, 404, &format!("实例 '{}' 不存在", instance));
+    }
+    let api = match config::config_toml_path(instance).and_then(|p| probe::admin_api_lookup(&p)) {
+        Ok(Some(api)) => api,
+        Ok(None) => {
+            return respond_error(
+                stream,
+                404,
+                &format!(
+                    "实例 '{}' 未开启 admin API（配置中没有 webServer）",
+                    instance
+                ),
+            )
+        }
+        Err(e) => {
+            return respond_error(
+                stream,
+                500,
+                &format!("无法解析实例 '{}' 的 admin API 地址: {:#}", instance, e),
+            )
+        }
+    };
+
+    let method = reqwest::Method::from_bytes(request.method.as_bytes())
+        .context(format!("不支持的请求方法: {}", request.method))?;
+    let mut upstream = client()
+        .request(method, format!("{}{}", api.base_url, rest))
+        .body(request.body.clone());
+    if let Some(content_type) = request.header("Content-Type") {
+        upstream = upstream.header(reqwest::header::CONTENT_TYPE, content_type);
+    }
+    if let Some(ref user) = api.user {
+        upstream = upstream.basic_auth(user, api.password.as_ref());
+    }
+    let response = match upstream.send() {
+        Ok(r) => r,
+        Err(e) => {
+            return respond_error(
+                stream,
+                502,
+                &format!("无法连接实例 '{}' 的 admin API: {}", instance, e),
+            )
+        }
+    };
+    let status = response.status().as_u16();
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("application/octet-stream")
+        .to_string();
+    let body = response.bytes().unwrap_or_default();
+    respond(stream, status, &content_type, &body)
+}
+
+fn respond(stream: &mut TcpStream, status: u16, content_type: &str, body: &[u8]) -> Result<()> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        500 => "Internal Server Error",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        _ => "",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        reason,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()?;
+    Ok(())
+}
+
+fn respond_error(stream: &mut TcpStream, status: u16, message: &str) -> Result<()> {
+    let body = serde_json::to_vec(&serde_json::json!({ "error": message }))?;
+    respond(stream, status, "application/json; charset=utf-8", &body)
+}
+
+/// 解码路径中的 `%XX`（实例名可能包含中文等字符）
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+            if let Ok(b) = u8::from_str_radix(hex, 16) {
+                out.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
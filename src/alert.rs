@@ -0,0 +1,328 @@
+//! 实例告警：进程崩溃或进程守护放弃重启时，按实例的告警策略分发到日志/事件日志/webhook
+//!
+//! 策略与事件的触发矩阵：
+//!
+//! | 策略 | 崩溃 | 连续崩溃达到阈值 | 放弃重启 | 输出规则 |
+//! |------|------|------------------|----------|----------|
+//! | `give_up` | - | - | ✓ | ✓ |
+//! | `critical_only` | - | ✓ | ✓ | ✓ |
+//! | `each_crash` | ✓ | ✓ | ✓ | ✓ |
+//!
+//! 输出规则（`on_output` 的 alert/stop）由用户显式配置，始终告警
+
+use chrono::Local;
+use serde::Serialize;
+use std::thread;
+use std::time::Duration;
+
+use crate::config::{self, AlertChannel, AlertOn, AlertPolicy};
+use crate::eventlog;
+
+/// 连续崩溃达到该次数视为严重（启动探测通过后计数清零）
+const CRASH_LOOP_THRESHOLD: u32 = 3;
+
+/// webhook 请求超时
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 告警事件
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AlertEvent {
+    /// 进程意外退出，`consecutive_restarts` 为此前的连续重启次数
+    Crash {
+        instance: String,
+        consecutive_restarts: u32,
+    },
+    /// 进程守护重启失败，不再重试
+    GiveUp { instance: String, error: String },
+    /// 运行中实例的程序或配置文件已从磁盘上消失，`missing` 为各路径的说明
+    PathMissing {
+        instance: String,
+        missing: Vec<String>,
+    },
+    /// frpc 输出匹配了 `on_output` 规则（alert 或 stop），`stopped` 为实例已被停止
+    OutputMatched {
+        instance: String,
+        pattern: String,
+        line: String,
+        stopped: bool,
+    },
+    /// 日志所在磁盘剩余空间不足（不属于某个实例），`critical` 为已暂停文件日志
+    DiskSpaceLow {
+        logs_dir: String,
+        free_mb: u64,
+        critical: bool,
+    },
+    /// 全局熔断：`window_secs` 秒内所有实例共崩溃 `crashes` 次，暂停全部重启
+    CircuitOpen {
+        crashes: usize,
+        window_secs: u64,
+        open_secs: u64,
+    },
+}
+
+impl AlertEvent {
+    /// 日志前缀：实例名，服务级事件为 "服务"
+    fn instance(&self) -> &str {
+        match self {
+            AlertEvent::Crash { instance, .. }
+            | AlertEvent::GiveUp { instance, .. }
+            | AlertEvent::PathMissing { instance, .. }
+            | AlertEvent::OutputMatched { instance, .. } => instance,
+            AlertEvent::DiskSpaceLow { .. } | AlertEvent::CircuitOpen { .. } => "服务",
+        }
+    }
+
+    fn is_critical(&self) -> bool {
+        match self {
+            AlertEvent::Crash {
+                consecutive_restarts,
+                ..
+            } => *consecutive_restarts >= CRASH_LOOP_THRESHOLD,
+            AlertEvent::GiveUp { .. } | AlertEvent::CircuitOpen { .. } => true,
+            AlertEvent::PathMissing { .. } => false,
+            AlertEvent::OutputMatched { stopped, .. } => *stopped,
+            AlertEvent::DiskSpaceLow { critical, .. } => *critical,
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            AlertEvent::Crash {
+                instance,
+                consecutive_restarts,
+            } => format!(
+                "[{}] frpc 进程意外退出（此前连续重启 {} 次）",
+                instance, consecutive_restarts
+            ),
+            AlertEvent::GiveUp { instance, error } => {
+                format!("[{}] 进程守护重启失败，已放弃: {}", instance, error)
+            }
+            AlertEvent::PathMissing { instance, missing } => format!(
+                "[{}] {}，实例退出后将无法重启",
+                instance,
+                missing.join("；")
+            ),
+            AlertEvent::OutputMatched {
+                instance,
+                pattern,
+                line,
+                stopped,
+            } => format!(
+                "[{}] frpc 输出匹配规则 {:?}{}: {}",
+                instance,
+                pattern,
+                if *stopped {
+                    "，已停止实例且不再重启"
+                } else {
+                    ""
+                },
+                line
+            ),
+            AlertEvent::DiskSpaceLow {
+                logs_dir,
+                free_mb,
+                critical: false,
+            } => format!(
+                "日志目录 {} 所在磁盘剩余空间不足（{} MB），已清理旧日志",
+                logs_dir, free_mb
+            ),
+            AlertEvent::DiskSpaceLow {
+                logs_dir,
+                free_mb,
+                critical: true,
+            } => format!(
+                "日志目录 {} 所在磁盘剩余空间严重不足（{} MB），已暂停写入文件日志",
+                logs_dir, free_mb
+            ),
+            AlertEvent::CircuitOpen {
+                crashes,
+                window_secs,
+                open_secs,
+            } => format!(
+                "{} 秒内 frpc 实例共崩溃 {} 次，已熔断：暂停所有重启至少 {} 秒，frps 可连接后统一拉起",
+                window_secs, crashes, open_secs
+            ),
+        }
+    }
+}
+
+/// 策略是否需要为该事件告警
+fn should_alert(event: &AlertEvent, alert_on: AlertOn) -> bool {
+    if matches!(event, AlertEvent::OutputMatched { .. }) {
+        return true;
+    }
+    match alert_on {
+        AlertOn::EachCrash => true,
+        AlertOn::CriticalOnly => event.is_critical(),
+        AlertOn::GiveUp => matches!(event, AlertEvent::GiveUp { .. }),
+    }
+}
+
+/// 按策略将告警分发到各通道；webhook 在后台线程发送，不阻塞进程守护
+pub fn dispatch_alert(event: &AlertEvent, policy: &AlertPolicy) {
+    if !should_alert(event, policy.alert_on) {
+        return;
+    }
+    let message = event.message();
+    for channel in &policy.alert_channels {
+        match channel {
+            AlertChannel::Log => log::error!("告警: {}", message),
+            AlertChannel::Eventlog => {
+                if !eventlog::report_error(&message) {
+                    log::warn!("[{}] 写入事件日志失败", event.instance());
+                }
+            }
+            AlertChannel::Webhook => send_webhook(event.clone(), message.clone()),
+        }
+    }
+}
+
+/// 服务级告警（不属于某个实例，没有实例告警策略）：写入日志与事件日志，配置了 alert_webhook_url 时同时发送 webhook
+///
+/// 文件日志可能已暂停，事件日志始终写入
+pub fn dispatch_service_alert(event: &AlertEvent) {
+    let message = event.message();
+    log::error!("告警: {}", message);
+    if !eventlog::report_error(&message) {
+        log::warn!("写入事件日志失败");
+    }
+    if config::load_settings().alert_webhook_url.is_some() {
+        send_webhook(event.clone(), message);
+    }
+}
+
+#[derive(Serialize)]
+struct WebhookPayload {
+    #[serde(flatten)]
+    event: AlertEvent,
+    message: String,
+    time: String,
+    host: String,
+}
+
+fn send_webhook(event: AlertEvent, message: String) {
+    let Some(url) = config::load_settings().alert_webhook_url else {
+        log::warn!(
+            "[{}] 告警通道包含 webhook，但 settings.json 未配置 alert_webhook_url",
+            event.instance()
+        );
+        return;
+    };
+    let payload = WebhookPayload {
+        event,
+        message,
+        time: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        host: std::env::var("COMPUTERNAME").unwrap_or_default(),
+    };
+    thread::spawn(move || {
+        let result = reqwest::blocking::Client::builder()
+            .timeout(WEBHOOK_TIMEOUT)
+            .build()
+            .and_then(|client| client.post(&url).json(&payload).send())
+            .and_then(|resp| resp.error_for_status());
+        if let Err(e) = result {
+            log::error!(
+                "[{}] 告警 webhook 发送失败: {}",
+                payload.event.instance(),
+                e
+            );
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn crash(consecutive_restarts: u32) -> AlertEvent {
+        AlertEvent::Crash {
+            instance: "web".to_string(),
+            consecutive_restarts,
+        }
+    }
+
+    #[test]
+    fn alert_matrix() {
+        let give_up = AlertEvent::GiveUp {
+            instance: "web".to_string(),
+            error: "配置文件已消失".to_string(),
+        };
+        let path_missing = AlertEvent::PathMissing {
+            instance: "web".to_string(),
+            missing: vec!["配置文件已消失: web.toml".to_string()],
+        };
+        let output = |stopped| AlertEvent::OutputMatched {
+            instance: "web".to_string(),
+            pattern: "token".to_string(),
+            line: "authorization failed".to_string(),
+            stopped,
+        };
+        // (事件, give_up, each_crash, critical_only)
+        let cases = [
+            (crash(0), false, true, false),
+            (crash(CRASH_LOOP_THRESHOLD - 1), false, true, false),
+            (crash(CRASH_LOOP_THRESHOLD), false, true, true),
+            (give_up, true, true, true),
+            (path_missing, false, true, false),
+            // 输出规则命中总是告警
+            (output(false), true, true, true),
+            (output(true), true, true, true),
+        ];
+        for (event, give_up, each_crash, critical_only) in cases {
+            assert_eq!(
+                should_alert(&event, AlertOn::GiveUp),
+                give_up,
+                "{:?}",
+                event
+            );
+            assert_eq!(
+                should_alert(&event, AlertOn::EachCrash),
+                each_crash,
+                "{:?}",
+                event
+            );
+            assert_eq!(
+                should_alert(&event, AlertOn::CriticalOnly),
+                critical_only,
+                "{:?}",
+                event
+            );
+        }
+    }
+
+    #[test]
+    fn policy_defaults() {
+        let policy: AlertPolicy = serde_json::from_str("{}").unwrap();
+        assert_eq!(policy, AlertPolicy::default());
+        assert_eq!(policy.alert_on, AlertOn::GiveUp);
+        assert_eq!(policy.alert_channels, [AlertChannel::Log]);
+        let policy: AlertPolicy = serde_json::from_str(
+            r#"{"alert_on":"critical_only","alert_channels":["eventlog","webhook"]}"#,
+        )
+        .unwrap();
+        assert_eq!(policy.alert_on, AlertOn::CriticalOnly);
+        assert_eq!(
+            policy.alert_channels,
+            [AlertChannel::Eventlog, AlertChannel::Webhook]
+        );
+    }
+
+    #[test]
+    fn webhook_payload_flattens_event() {
+        let payload = WebhookPayload {
+            message: crash(3).message(),
+            event: crash(3),
+            time: "2026-01-01 00:00:00".to_string(),
+            host: "host".to_string(),
+        };
+        let value = serde_json::to_value(&payload).unwrap();
+        assert_eq!(value["event"], "crash");
+        assert_eq!(value["instance"], "web");
+        assert_eq!(value["consecutive_restarts"], 3);
+        assert_eq!(
+            value["message"],
+            "[web] frpc 进程意外退出（此前连续重启 3 次）"
+        );
+    }
+}
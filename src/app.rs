@@ -2,25 +2,30 @@
 
 use anyhow::Result;
 use gpui::{
-    div, prelude::*, px, size, App, Bounds, Context, Entity, SharedString, Task, TitlebarOptions,
-    Window, WindowBounds, WindowOptions,
+    div, prelude::*, px, size, App, AsyncApp, Bounds, Context, Entity, PathPromptOptions,
+    PromptLevel, SharedString, Task, TitlebarOptions, WeakEntity, Window, WindowBounds,
+    WindowOptions,
 };
 use gpui_component::input::InputState;
 use gpui_component::select::{SelectEvent, SelectState};
 use gpui_component::{ActiveTheme, IndexPath, Root};
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
+use crate::account_check;
+use crate::av_check;
 use crate::config::{self, FrpcConfigMeta};
 use crate::download;
 use crate::frpc_mg::FrpcProcess;
 use crate::message::MessageLevel;
 use crate::pages;
-use crate::service::{self, PreCheckResult};
+use crate::service::{self, PreCheckResult, ServiceAccount};
 use crate::sidebar;
 use crate::theme;
+use crate::toast;
 
 /// 自定义暗色主题 JSON
 /// 当前页面
@@ -58,6 +63,9 @@ pub(crate) struct AppView {
     pub config_page: usize,
     pub theme_select: Entity<SelectState<Vec<SharedString>>>,
     pub process_guard: bool,
+    pub toast_notifications: bool,
+    /// 健康检查发现异常退出、尚未恢复的配置，恢复时推送通知
+    pub crashed_configs: std::collections::HashSet<String>,
 }
 
 impl AppView {
@@ -74,19 +82,17 @@ impl AppView {
 
         // 恢复上次运行的 frpc 进程状态
         let mut running = HashMap::new();
-        let frpc_exe = config::frpc_exe_path().ok().filter(|p| p.exists());
-        if let Some(exe_path) = frpc_exe {
-            for (name, pid) in service::discover_running_frpc_processes() {
-                if FrpcProcess::is_pid_running(pid) {
-                    let config_path = config::config_toml_path(&name).unwrap_or_default();
-                    let process =
-                        FrpcProcess::from_pid(pid, name.clone(), exe_path.clone(), config_path);
-                    running.insert(name.clone(), RunningProcess { process });
-                    log::info!("恢复 frpc 进程状态: {} (PID: {})", name, pid);
-                }
+        for (name, pid) in service::discover_running_frpc_processes() {
+            if FrpcProcess::is_pid_running(pid) {
+                let exe_path = config::instance_exe_path(&name).unwrap_or_default();
+                let config_path = config::instance_config_path(&name).unwrap_or_default();
+                let process = FrpcProcess::from_pid(pid, name.clone(), exe_path, config_path);
+                running.insert(name.clone(), RunningProcess { process });
+                log::info!("恢复 frpc 进程状态: {} (PID: {})", name, pid);
             }
         }
 
+        let settings = config::load_settings();
         let s = Self {
             page: Page::ConfigList,
             service_registered,
@@ -107,7 +113,9 @@ impl AppView {
             status_level: MessageLevel::Info,
             config_page: 0,
             theme_select: theme_select.clone(),
-            process_guard: config::load_settings().process_guard,
+            process_guard: settings.process_guard,
+            toast_notifications: settings.toast_notifications,
+            crashed_configs: std::collections::HashSet::new(),
         };
 
         // 订阅主题下拉选择事件
@@ -135,9 +143,8 @@ impl AppView {
             return;
         }
         self.process_guard = !self.process_guard;
-        let settings = config::AppSettings {
-            process_guard: self.process_guard,
-        };
+        let mut settings = config::load_settings();
+        settings.process_guard = self.process_guard;
         match config::save_settings(&settings) {
             Ok(()) => {
                 if self.process_guard {
@@ -177,6 +184,23 @@ impl AppView {
         cx.notify();
     }
 
+    pub fn toggle_toast_notifications(&mut self, cx: &mut Context<Self>) {
+        self.toast_notifications = !self.toast_notifications;
+        let mut settings = config::load_settings();
+        settings.toast_notifications = self.toast_notifications;
+        match config::save_settings(&settings) {
+            Ok(()) => {
+                log::info!("系统通知设置已变更: {}", self.toast_notifications);
+            }
+            Err(e) => {
+                self.toast_notifications = !self.toast_notifications;
+                log::error!("保存系统通知设置失败: {}", e);
+                self.set_status_message(format!("保存设置失败: {}", e), MessageLevel::Error, cx);
+            }
+        }
+        cx.notify();
+    }
+
     pub fn on_theme_selected(
         &mut self,
         event: &SelectEvent<Vec<SharedString>>,
@@ -356,7 +380,12 @@ impl AppView {
 
     pub fn delete_config(&mut self, name: &str, cx: &mut Context<Self>) {
         if let Some(mut rp) = self.running.remove(name) {
-            let _ = rp.process.stop();
+            // 等待 frpc 自行退出可能需要数秒，放到后台执行
+            let grace = config::graceful_stop_timeout(name);
+            cx.background_spawn(async move {
+                let _ = rp.process.stop(grace);
+            })
+            .detach();
         }
         match config::delete_config(name) {
             Ok(()) => {
@@ -384,9 +413,9 @@ impl AppView {
             let running_frpc = service::discover_running_frpc_processes();
             if let Some((_, pid)) = running_frpc.iter().find(|(n, _)| n == name) {
                 if FrpcProcess::is_pid_running(*pid) {
-                    let frpc_exe = config::frpc_exe_path().ok().filter(|p| p.exists());
+                    let frpc_exe = config::instance_exe_path(name).ok().filter(|p| p.exists());
                     if let Some(exe_path) = frpc_exe {
-                        let config_path = config::config_toml_path(name).unwrap_or_default();
+                        let config_path = config::instance_config_path(name).unwrap_or_default();
                         let process =
                             FrpcProcess::from_pid(*pid, name.to_string(), exe_path, config_path);
                         self.running
@@ -400,6 +429,7 @@ impl AppView {
         }
         // 从手动停止列表中移除，通过命名管道通知 Service
         self.stopped_configs.remove(name);
+        self.crashed_configs.remove(name);
         service::send_guard_stopped_command(&format!("START:{}", name));
         // 检查 frpc.exe 是否存在
         if !crate::download::has_frpc_executable(
@@ -518,12 +548,14 @@ impl AppView {
     pub fn stop_config(&mut self, name: &str, cx: &mut Context<Self>) {
         // 标记为手动停止，通过命名管道通知 Service 不要重启
         self.stopped_configs.insert(name.to_string());
+        self.crashed_configs.remove(name);
         service::send_guard_stopped_command(&format!("STOP:{}", name));
         if let Some(mut rp) = self.running.remove(name) {
             self.is_processing = true;
             cx.notify();
+            let grace = config::graceful_stop_timeout(name);
             let task: Task<Result<()>> = cx.background_spawn(async move {
-                rp.process.stop()?;
+                rp.process.stop(grace)?;
                 Ok(())
             });
             let nc = name.to_string();
@@ -721,7 +753,66 @@ impl AppView {
         .detach();
     }
 
-    pub fn install_service(&mut self, cx: &mut Context<Self>) {
+    /// 注册服务：检测到杀毒软件时先弹窗提示添加排除（不阻止注册），然后检查服务账户
+    pub fn install_service(&mut self, window: &mut Window, cx: &mut Context<Self>) {
+        let antivirus = av_check::detect_antivirus();
+        if antivirus.is_empty() {
+            self.check_account_then_install(window, cx);
+            return;
+        }
+        let answer = window.prompt(
+            PromptLevel::Info,
+            "检测到杀毒软件",
+            Some(&av_check::exclusion_hint(&antivirus)),
+            &["继续注册"],
+            cx,
+        );
+        cx.spawn_in(window, async move |this, cx| {
+            // 仅作提示：无论如何关闭对话框都继续注册
+            let _ = answer.await;
+            this.update_in(cx, |v, window, cx| v.check_account_then_install(window, cx))
+                .ok();
+        })
+        .detach();
+    }
+
+    /// 检查 LocalSystem 的登录权利与配置目录读取权限，有问题时弹窗说明，用户确认后继续
+    fn check_account_then_install(&mut self, window: &mut Window, cx: &mut Context<Self>) {
+        let issues = account_check::check_service_account(ServiceAccount::LocalSystem);
+        if issues.is_empty() {
+            self.continue_install(window, cx);
+            return;
+        }
+        let detail = issues
+            .iter()
+            .map(|i| format!("• {}\n  解决办法：{}", i.problem, i.fix))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        let answer = window.prompt(
+            PromptLevel::Warning,
+            "服务账户缺少必要的权限，注册后服务可能无法启动",
+            Some(&detail),
+            &["仍然注册", "取消"],
+            cx,
+        );
+        cx.spawn_in(window, async move |this, cx| {
+            if answer.await == Ok(0) {
+                this.update_in(cx, |v, window, cx| v.continue_install(window, cx))
+                    .ok();
+            }
+        })
+        .detach();
+    }
+
+    /// 账户检查通过后：缺少 frpc.exe 或配置时先让用户选择，然后注册
+    fn continue_install(&mut self, window: &mut Window, cx: &mut Context<Self>) {
+        let need_exe = !config::frpc_exe_path().is_ok_and(|p| p.exists());
+        let need_config = config::load_configs().map_or(true, |c| c.is_empty());
+        if need_exe || need_config {
+            self.select_first_install_files(need_exe, need_config, window, cx);
+            return;
+        }
+
         self.is_processing = true;
         self.status_message = None;
         cx.notify();
@@ -755,6 +846,65 @@ impl AppView {
         .detach();
     }
 
+    /// 首次注册服务时未找到 frpc.exe 或配置：弹出文件选择框让用户选择，
+    /// 复制到程序目录（bin/frpc.exe、conf/）后继续注册
+    fn select_first_install_files(
+        &mut self,
+        need_exe: bool,
+        need_config: bool,
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) {
+        cx.spawn_in(window, async move |this, cx| {
+            if need_exe {
+                let Some(src) = pick_file(&this, cx, "选择 frpc.exe").await else {
+                    this.update(cx, |v, cx| {
+                        v.set_status_message(
+                            "未找到 frpc.exe，请先下载或选择本地的 frpc.exe".to_string(),
+                            MessageLevel::Warning,
+                            cx,
+                        );
+                    })
+                    .ok();
+                    return;
+                };
+                if let Err(e) = config::import_frpc_exe(&src) {
+                    this.update(cx, |v, cx| {
+                        v.set_status_message(format!("导入失败：{}", e), MessageLevel::Error, cx);
+                    })
+                    .ok();
+                    return;
+                }
+                this.update(cx, |v, cx| v.detect_frpc_version(cx)).ok();
+            }
+            if need_config {
+                let Some(src) = pick_file(&this, cx, "选择 frpc 配置文件（.toml）").await
+                else {
+                    this.update(cx, |v, cx| {
+                        v.set_status_message(
+                            "还没有任何配置，请先添加或选择一个配置文件".to_string(),
+                            MessageLevel::Warning,
+                            cx,
+                        );
+                    })
+                    .ok();
+                    return;
+                };
+                if let Err(e) = config::import_config_file(&src) {
+                    this.update(cx, |v, cx| {
+                        v.set_status_message(format!("导入失败：{}", e), MessageLevel::Error, cx);
+                    })
+                    .ok();
+                    return;
+                }
+                this.update(cx, |v, cx| v.reload_configs(cx)).ok();
+            }
+            this.update_in(cx, |v, window, cx| v.continue_install(window, cx))
+                .ok();
+        })
+        .detach();
+    }
+
     pub fn uninstall_service(&mut self, cx: &mut Context<Self>) {
         self.is_processing = true;
         self.status_message = None;
@@ -771,9 +921,8 @@ impl AppView {
 
                         // 关闭进程守护
                         v.process_guard = false;
-                        let settings = config::AppSettings {
-                            process_guard: false,
-                        };
+                        let mut settings = config::load_settings();
+                        settings.process_guard = false;
                         if let Err(e) = config::save_settings(&settings) {
                             log::error!("保存进程守护设置失败: {}", e);
                         }
@@ -783,27 +932,18 @@ impl AppView {
                         service::send_guard_stopped_command("CLEAR");
 
                         // 注销后重新发现仍在运行的进程（服务注销不会停止 frpc）
-                        let frpc_exe = config::frpc_exe_path().ok().filter(|p| p.exists());
-                        if let Some(exe_path) = frpc_exe {
-                            for (name, pid) in service::discover_running_frpc_processes() {
-                                if FrpcProcess::is_pid_running(pid)
-                                    && !v.running.contains_key(&name)
-                                {
-                                    let config_path =
-                                        config::config_toml_path(&name).unwrap_or_default();
-                                    let process = FrpcProcess::from_pid(
-                                        pid,
-                                        name.clone(),
-                                        exe_path.clone(),
-                                        config_path,
-                                    );
-                                    v.running.insert(name.clone(), RunningProcess { process });
-                                    log::info!(
-                                        "[{}] 注销后发现仍在运行的进程 (PID: {})",
-                                        name,
-                                        pid
-                                    );
-                                }
+                        for (name, pid) in service::discover_running_frpc_processes() {
+                            if FrpcProcess::is_pid_running(pid) && !v.running.contains_key(&name) {
+                                let config_path =
+                                    config::instance_config_path(&name).unwrap_or_default();
+                                let process = FrpcProcess::from_pid(
+                                    pid,
+                                    name.clone(),
+                                    config::instance_exe_path(&name).unwrap_or_default(),
+                                    config_path,
+                                );
+                                v.running.insert(name.clone(), RunningProcess { process });
+                                log::info!("[{}] 注销后发现仍在运行的进程 (PID: {})", name, pid);
                             }
                         }
 
@@ -851,6 +991,11 @@ impl AppView {
                             for name in &dead_names {
                                 view.running.remove(name);
                                 log::info!("[{}] 进程已退出，已从运行列表移除", name);
+                                toast::notify(
+                                    &format!("{} 已掉线", name),
+                                    "frpc 进程异常退出，隧道已断开",
+                                );
+                                view.crashed_configs.insert(name.clone());
                             }
                             cx.notify();
                         }
@@ -862,37 +1007,40 @@ impl AppView {
                             if discover_tick >= 3 {
                                 discover_tick = 0;
                                 let running_frpc = service::discover_running_frpc_processes();
-                                let frpc_exe = config::frpc_exe_path().ok().filter(|p| p.exists());
-                                if let Some(exe_path) = frpc_exe {
-                                    let mut changed = false;
-                                    for (name, pid) in running_frpc {
-                                        // 不检查 stopped_configs：如果 Service 已拉起进程，UI 应显示
-                                        if FrpcProcess::is_pid_running(pid)
-                                            && !view.running.contains_key(&name)
-                                        {
-                                            let config_path =
-                                                config::config_toml_path(&name).unwrap_or_default();
-                                            let process = FrpcProcess::from_pid(
-                                                pid,
-                                                name.clone(),
-                                                exe_path.clone(),
-                                                config_path,
-                                            );
-                                            view.running
-                                                .insert(name.clone(), RunningProcess { process });
-                                            // 发现新进程时同步清除 stopped_configs
-                                            view.stopped_configs.remove(&name);
-                                            log::info!(
-                                                "[{}] 发现 Service 管理的进程 (PID: {})",
-                                                name,
-                                                pid
+                                let mut changed = false;
+                                for (name, pid) in running_frpc {
+                                    // 不检查 stopped_configs：如果 Service 已拉起进程，UI 应显示
+                                    if FrpcProcess::is_pid_running(pid)
+                                        && !view.running.contains_key(&name)
+                                    {
+                                        let config_path =
+                                            config::instance_config_path(&name).unwrap_or_default();
+                                        let process = FrpcProcess::from_pid(
+                                            pid,
+                                            name.clone(),
+                                            config::instance_exe_path(&name).unwrap_or_default(),
+                                            config_path,
+                                        );
+                                        view.running
+                                            .insert(name.clone(), RunningProcess { process });
+                                        // 发现新进程时同步清除 stopped_configs
+                                        view.stopped_configs.remove(&name);
+                                        if view.crashed_configs.remove(&name) {
+                                            toast::notify(
+                                                &format!("{} 已恢复", name),
+                                                &format!("frpc 进程已重新启动 (PID: {})", pid),
                                             );
-                                            changed = true;
                                         }
+                                        log::info!(
+                                            "[{}] 发现 Service 管理的进程 (PID: {})",
+                                            name,
+                                            pid
+                                        );
+                                        changed = true;
                                     }
-                                    if changed {
-                                        cx.notify();
-                                    }
+                                }
+                                if changed {
+                                    cx.notify();
                                 }
                             }
                         }
@@ -936,6 +1084,28 @@ impl Drop for AppView {
     }
 }
 
+/// 弹出单文件选择框，取消或出错时返回 None
+async fn pick_file(this: &WeakEntity<AppView>, cx: &mut AsyncApp, prompt: &str) -> Option<PathBuf> {
+    let rx = this
+        .update(cx, |_, cx| {
+            cx.prompt_for_paths(PathPromptOptions {
+                files: true,
+                directories: false,
+                multiple: false,
+                prompt: Some(prompt.to_string().into()),
+            })
+        })
+        .ok()?;
+    match rx.await {
+        Ok(Ok(Some(paths))) => paths.into_iter().next(),
+        Ok(Err(e)) => {
+            log::error!("打开文件选择框失败: {}", e);
+            None
+        }
+        _ => None,
+    }
+}
+
 pub fn run_app(pre_check: PreCheckResult) {
     let app = gpui_platform::application().with_assets(crate::icons::AppAssets);
     app.run(move |cx: &mut App| {
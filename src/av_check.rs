@@ -0,0 +1,91 @@
+//! 注册服务前检测杀毒软件：frpc 的内网穿透行为常被杀毒软件拦截或隔离
+//!
+//! 按服务名查询已知杀毒软件的服务是否正在运行，只用于提示用户把 frpc.exe 加入排除列表，
+//! 不阻止注册。服务名列表可在 settings.json 的 `antivirus_services` 中替换
+
+use windows_service::service::{ServiceAccess, ServiceState};
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+
+use crate::config;
+
+/// 内置的杀毒软件服务：(服务名, 产品名)
+const KNOWN_SERVICES: &[(&str, &str)] = &[
+    ("WinDefend", "Microsoft Defender"),
+    ("MBAMService", "Malwarebytes"),
+    ("avast! Antivirus", "Avast"),
+    ("AVG Antivirus", "AVG"),
+    ("ekrn", "ESET"),
+    ("VSSERV", "Bitdefender"),
+    ("SAVService", "Sophos"),
+    ("McAfeeFramework", "McAfee"),
+    ("NortonSecurity", "Norton"),
+    ("HipsDaemon", "火绒安全"),
+    ("ZhuDongFangYu", "360 安全卫士"),
+    ("QQPCRTP", "腾讯电脑管家"),
+];
+
+/// 检测到的杀毒软件
+#[derive(Debug, Clone)]
+pub struct DetectedAntivirus {
+    pub service: String,
+    /// 产品名，settings.json 中自定义的服务名没有产品名时与服务名相同
+    pub product: String,
+}
+
+/// 正在运行的杀毒软件服务；无法连接服务管理器时返回空列表
+pub fn detect_antivirus() -> Vec<DetectedAntivirus> {
+    let manager = match ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+    {
+        Ok(m) => m,
+        Err(e) => {
+            log::warn!("无法连接服务管理器，跳过杀毒软件检测: {:?}", e);
+            return Vec::new();
+        }
+    };
+    candidate_services()
+        .into_iter()
+        .filter(|(service, _)| {
+            manager
+                .open_service(service, ServiceAccess::QUERY_STATUS)
+                .and_then(|s| s.query_status())
+                .is_ok_and(|status| status.current_state == ServiceState::Running)
+        })
+        .map(|(service, product)| DetectedAntivirus { service, product })
+        .collect()
+}
+
+/// 要检查的服务：settings.json 设置了 `antivirus_services` 时使用该列表，否则使用内置列表
+fn candidate_services() -> Vec<(String, String)> {
+    match config::load_settings().antivirus_services {
+        Some(services) => services
+            .into_iter()
+            .map(|service| {
+                let product = KNOWN_SERVICES
+                    .iter()
+                    .find(|(known, _)| known.eq_ignore_ascii_case(&service))
+                    .map_or_else(|| service.clone(), |(_, product)| product.to_string());
+                (service, product)
+            })
+            .collect(),
+        None => KNOWN_SERVICES
+            .iter()
+            .map(|(service, product)| (service.to_string(), product.to_string()))
+            .collect(),
+    }
+}
+
+/// 提示文本：检测到的产品与建议排除的 frpc.exe 所在目录
+pub fn exclusion_hint(detected: &[DetectedAntivirus]) -> String {
+    let products = detected
+        .iter()
+        .map(|av| av.product.as_str())
+        .collect::<Vec<_>>()
+        .join("、");
+    let bin = config::bin_dir()
+        .map(|p| p.display().to_string())
+        .unwrap_or_else(|_| "bin".to_string());
+    format!(
+        "检测到杀毒软件：{}。frpc 的内网穿透行为可能被拦截或隔离，必要时请将 {} 目录下的 frpc.exe 加入杀毒软件的排除列表",
+        products, bin
+    )
+}
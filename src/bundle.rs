@@ -0,0 +1,646 @@
+//! 配置包（`export` / `import`）：把全局设置、实例清单与各实例的 frpc 配置打包为 zip，在其他机器上展开
+//!
+//! frpc 可执行文件不打包，只在 manifest.json 中记录路径、大小与 SHA-256，导入时与目标机上的文件比较。
+//! 以其他用户身份运行的实例，其密码由 DPAPI 按本机加密、换机器无法解密，导出时清空并在导入后提示重新设置；
+//! frpc 配置中的令牌等敏感值按原样打包（frpc 需要明文），导出时逐项列出。
+//! 导入时内容不同的已有文件按冲突策略覆盖、跳过，或先移动到 backup/import-<时间>/ 再写入
+
+use anyhow::{Context, Result};
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+use crate::config::{self, FrpcConfigMeta};
+use crate::fingerprint;
+
+/// 配置包格式版本，不兼容的变化时递增
+const FORMAT_VERSION: u32 = 1;
+
+/// 配置包中的清单文件
+const MANIFEST_ENTRY: &str = "manifest.json";
+
+/// 全局设置在配置包中的路径，导入到 conf/settings.json
+const SETTINGS_ENTRY: &str = "conf/settings.json";
+
+/// 实例清单在配置包中的路径，导入到 conf/metadata.json（或 `--instances-file` 指定的路径）
+const METADATA_ENTRY: &str = "instances/metadata.json";
+
+/// 实例配置在配置包中的目录前缀，其下的相对路径导入到实例配置目录
+const INSTANCES_PREFIX: &str = "instances/";
+
+/// 导入时已有文件与配置包内容不同的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// 直接覆盖
+    Overwrite,
+    /// 保留已有文件
+    Skip,
+    /// 先把已有文件移动到数据目录的 backup/import-<时间>/ 下再写入（默认）
+    Backup,
+}
+
+impl ConflictPolicy {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "overwrite" => Ok(ConflictPolicy::Overwrite),
+            "skip" => Ok(ConflictPolicy::Skip),
+            "backup" => Ok(ConflictPolicy::Backup),
+            other => anyhow::bail!("不支持的冲突策略: {}（可选 overwrite/skip/backup）", other),
+        }
+    }
+}
+
+/// 配置包的 manifest.json
+#[derive(Debug, Serialize, Deserialize)]
+struct BundleManifest {
+    format_version: u32,
+    /// 导出时间（本地时间）
+    created: String,
+    /// 导出时的 frpdesk 版本
+    frpdesk_version: String,
+    /// 配置包中的文件（不含 manifest.json 本身）
+    files: Vec<String>,
+    /// 实例使用的 frpc 可执行文件
+    #[serde(default)]
+    executables: Vec<ExecutableEntry>,
+    /// 导出时清空了密码的运行身份，导入后需重新执行 `set-run-as`
+    #[serde(default)]
+    run_as: Vec<RunAsEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ExecutableEntry {
+    /// 相对 bin/ 的路径，位于 bin/ 之外时为绝对路径
+    path: String,
+    size: u64,
+    sha256: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RunAsEntry {
+    instance: String,
+    user: String,
+}
+
+/// 把当前的设置、实例清单与实例配置导出到 `out`（不能已存在）
+pub fn export(out: &Path) -> Result<()> {
+    anyhow::ensure!(!out.exists(), "{} 已存在", out.display());
+
+    let mut entries: BTreeMap<String, Vec<u8>> = BTreeMap::new();
+    let mut sensitive: Vec<String> = Vec::new();
+
+    let settings_path = config::settings_path()?;
+    if settings_path.exists() {
+        let content = fs::read(&settings_path).context("无法读取 settings.json")?;
+        if let Ok(value) = serde_json::from_slice::<serde_json::Value>(&content) {
+            json_sensitive_keys(SETTINGS_ENTRY, "", &value, &mut sensitive);
+        }
+        entries.insert(SETTINGS_ENTRY.to_string(), content);
+    }
+
+    let metadata_path = config::metadata_path()?;
+    anyhow::ensure!(
+        metadata_path.exists(),
+        "实例清单不存在: {}",
+        metadata_path.display()
+    );
+    let (metadata, run_as) =
+        clear_run_as_passwords(&fs::read(&metadata_path).context("无法读取 metadata.json")?)?;
+    entries.insert(METADATA_ENTRY.to_string(), metadata);
+
+    let instances_dir = config::instances_dir()?;
+    let bin_dir = config::bin_dir()?;
+    let mut executables: BTreeMap<String, PathBuf> = BTreeMap::new();
+    for meta in config::load_configs()? {
+        match instance_files(&meta) {
+            Ok(files) => {
+                for file in files {
+                    let Ok(relative) = file.strip_prefix(&instances_dir) else {
+                        eprintln!(
+                            "警告: [{}] 配置 {} 位于实例配置目录之外，未打包，请在目标机上自行准备",
+                            meta.name,
+                            file.display()
+                        );
+                        continue;
+                    };
+                    let name = format!("{}{}", INSTANCES_PREFIX, entry_path(relative));
+                    let content =
+                        fs::read(&file).context(format!("无法读取配置文件: {}", file.display()))?;
+                    if let Ok(value) =
+                        toml::from_str::<toml::Value>(&String::from_utf8_lossy(&content))
+                    {
+                        toml_sensitive_keys(&name, "", &value, &mut sensitive);
+                    }
+                    entries.insert(name, content);
+                }
+            }
+            Err(e) => eprintln!("警告: [{}] 配置未打包: {:#}", meta.name, e),
+        }
+        let exe = meta.exe_path()?;
+        let key = match exe.strip_prefix(&bin_dir) {
+            Ok(relative) => entry_path(relative),
+            Err(_) => exe.display().to_string(),
+        };
+        executables.insert(key, exe);
+    }
+
+    let manifest = BundleManifest {
+        format_version: FORMAT_VERSION,
+        created: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        frpdesk_version: env!("CARGO_PKG_VERSION").to_string(),
+        files: entries.keys().cloned().collect(),
+        executables: executables
+            .into_iter()
+            .filter_map(|(path, exe)| match executable_entry(path, &exe) {
+                Ok(entry) => Some(entry),
+                Err(e) => {
+                    eprintln!("警告: {:#}，目标机导入时不会校验该文件", e);
+                    None
+                }
+            })
+            .collect(),
+        run_as,
+    };
+
+    write_bundle(out, &manifest, &entries)?;
+
+    println!("已导出 {} 个文件到 {}", manifest.files.len(), out.display());
+    for entry in &manifest.executables {
+        println!(
+            "  frpc 可执行文件 {} 未打包（SHA-256 {}），请在目标机上准备相同的版本",
+            entry.path, entry.sha256
+        );
+    }
+    for entry in &manifest.run_as {
+        println!(
+            "  实例 '{}' 的运行身份 {} 的密码只能在本机解密，已从配置包中清空",
+            entry.instance, entry.user
+        );
+    }
+    if !sensitive.is_empty() {
+        println!("警告: 以下敏感值以明文写入了配置包，请妥善保管（可改用 {{{{ .Envs.NAME }}}} 环境变量模板）：");
+        for item in &sensitive {
+            println!("  {}", item);
+        }
+    }
+    Ok(())
+}
+
+/// 把配置包展开到当前的数据目录
+pub fn import(bundle: &Path, policy: ConflictPolicy) -> Result<()> {
+    // 先读出全部内容并确定目标路径，配置包有问题时不写入任何文件
+    let (manifest, entries) = read_bundle(bundle)?;
+    let mut planned = Vec::new();
+    for (name, content) in &entries {
+        planned.push((name.as_str(), target_path(name)?, content.as_slice()));
+    }
+
+    let backup_dir = config::data_dir()?
+        .join("backup")
+        .join(format!("import-{}", Local::now().format("%Y%m%d-%H%M%S")));
+    for (name, target, content) in planned {
+        let backup = backup_dir.join(name);
+        match write_file(&target, content, policy, &backup)? {
+            WriteOutcome::Created => println!("  新建: {}", target.display()),
+            WriteOutcome::Unchanged => println!("  相同: {}", target.display()),
+            WriteOutcome::Overwritten => println!("  覆盖: {}", target.display()),
+            WriteOutcome::Skipped => println!("  跳过（已存在且内容不同）: {}", target.display()),
+            WriteOutcome::BackedUp => println!(
+                "  覆盖: {}（原文件已备份到 {}）",
+                target.display(),
+                backup.display()
+            ),
+        }
+    }
+    log::info!("已导入配置包 {:?}（冲突策略: {:?}）", bundle, policy);
+
+    let bin_dir = config::bin_dir()?;
+    for entry in &manifest.executables {
+        let path = Path::new(&entry.path);
+        let path = if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            bin_dir.join(path)
+        };
+        match fingerprint::file_sha256(&path) {
+            Ok(hash) if hash == entry.sha256 => {
+                println!("frpc 可执行文件与源机器一致: {}", path.display())
+            }
+            Ok(_) => println!(
+                "警告: {} 与源机器上的文件不同（SHA-256 不一致），请确认 frpc 版本",
+                path.display()
+            ),
+            Err(_) => println!(
+                "警告: 缺少 {}，请复制源机器上的 frpc（SHA-256 {}）",
+                path.display(),
+                entry.sha256
+            ),
+        }
+    }
+    for entry in &manifest.run_as {
+        println!(
+            "提示: 实例 '{}' 以 {} 身份运行，需重新设置密码: frpdesk set-run-as {} --user {}",
+            entry.instance, entry.user, entry.instance, entry.user
+        );
+    }
+    Ok(())
+}
+
+/// 实例需要打包的配置文件：单文件模式为 `<name>.toml`，目录模式为目录中的文件
+fn instance_files(meta: &FrpcConfigMeta) -> Result<Vec<PathBuf>> {
+    let path = meta.config_path()?;
+    if meta.config_dir.is_some() {
+        return config::config_dir_files(&path);
+    }
+    anyhow::ensure!(path.exists(), "配置文件不存在: {}", path.display());
+    Ok(vec![path])
+}
+
+/// 清空实例清单中运行身份的密码，返回处理后的内容与被清空的实例
+///
+/// 按 JSON 值处理以保留未知字段；没有运行身份时原样返回
+fn clear_run_as_passwords(content: &[u8]) -> Result<(Vec<u8>, Vec<RunAsEntry>)> {
+    let mut store: serde_json::Value =
+        serde_json::from_slice(content).context("无法解析 metadata.json")?;
+    let mut cleared = Vec::new();
+    if let Some(configs) = store.get_mut("configs").and_then(|c| c.as_array_mut()) {
+        for meta in configs {
+            let instance = meta
+                .get("name")
+                .and_then(|n| n.as_str())
+                .unwrap_or_default()
+                .to_string();
+            let Some(run_as) = meta.get_mut("run_as").and_then(|r| r.as_object_mut()) else {
+                continue;
+            };
+            let user = run_as
+                .get("user")
+                .and_then(|u| u.as_str())
+                .unwrap_or_default()
+                .to_string();
+            run_as.insert(
+                "password_protected".to_string(),
+                serde_json::Value::String(String::new()),
+            );
+            cleared.push(RunAsEntry { instance, user });
+        }
+    }
+    if cleared.is_empty() {
+        return Ok((content.to_vec(), cleared));
+    }
+    Ok((serde_json::to_vec_pretty(&store)?, cleared))
+}
+
+fn executable_entry(path: String, exe: &Path) -> Result<ExecutableEntry> {
+    let size = fs::metadata(exe)
+        .context(format!("无法读取 {}", exe.display()))?
+        .len();
+    Ok(ExecutableEntry {
+        path,
+        size,
+        sha256: fingerprint::file_sha256(exe)?,
+    })
+}
+
+/// 相对路径在配置包中的形式（以 `/` 分隔）
+fn entry_path(relative: &Path) -> String {
+    relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// 配置包中的路径对应的目标路径；实例配置只能位于实例配置目录之下
+fn target_path(name: &str) -> Result<PathBuf> {
+    match name {
+        SETTINGS_ENTRY => config::settings_path(),
+        METADATA_ENTRY => config::metadata_path(),
+        _ => {
+            let relative =
+                instance_entry_path(name).context(format!("配置包中的路径无效: {}", name))?;
+            Ok(config::instances_dir()?.join(relative))
+        }
+    }
+}
+
+/// 实例配置在实例配置目录下的相对路径；不在 `instances/` 下或含 `..` 等非普通路径段时为 None
+fn instance_entry_path(name: &str) -> Option<&Path> {
+    name.strip_prefix(INSTANCES_PREFIX)
+        .map(Path::new)
+        .filter(|p| {
+            p.components().next().is_some()
+                && p.components().all(|c| matches!(c, Component::Normal(_)))
+        })
+}
+
+/// 写出配置包：manifest.json 在前，其后为各文件
+fn write_bundle(
+    out: &Path,
+    manifest: &BundleManifest,
+    entries: &BTreeMap<String, Vec<u8>>,
+) -> Result<()> {
+    let file = File::create(out).context(format!("无法创建 {}", out.display()))?;
+    let mut zip = zip::ZipWriter::new(file);
+    let options = zip::write::SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated);
+    zip.start_file(MANIFEST_ENTRY, options)?;
+    zip.write_all(&serde_json::to_vec_pretty(manifest)?)?;
+    for (name, content) in entries {
+        zip.start_file(name.as_str(), options)?;
+        zip.write_all(content)?;
+    }
+    zip.finish()
+        .context(format!("无法写入 {}", out.display()))?;
+    Ok(())
+}
+
+/// 读取配置包的 manifest.json 与其中列出的全部文件
+fn read_bundle(bundle: &Path) -> Result<(BundleManifest, BTreeMap<String, Vec<u8>>)> {
+    let file = File::open(bundle).context(format!("无法打开 {}", bundle.display()))?;
+    let mut archive = zip::ZipArchive::new(file).context("无法解析配置包")?;
+    let manifest: BundleManifest =
+        serde_json::from_slice(&read_entry(&mut archive, MANIFEST_ENTRY)?)
+            .context("配置包的 manifest.json 无效")?;
+    anyhow::ensure!(
+        manifest.format_version <= FORMAT_VERSION,
+        "配置包格式版本 {} 高于当前程序支持的 {}，请先升级 frpdesk",
+        manifest.format_version,
+        FORMAT_VERSION
+    );
+    let mut entries = BTreeMap::new();
+    for name in &manifest.files {
+        entries.insert(name.clone(), read_entry(&mut archive, name)?);
+    }
+    Ok((manifest, entries))
+}
+
+fn read_entry(archive: &mut zip::ZipArchive<File>, name: &str) -> Result<Vec<u8>> {
+    let mut entry = archive
+        .by_name(name)
+        .context(format!("配置包中缺少 {}", name))?;
+    let mut content = Vec::new();
+    entry
+        .read_to_end(&mut content)
+        .context(format!("无法读取配置包中的 {}", name))?;
+    Ok(content)
+}
+
+enum WriteOutcome {
+    Created,
+    Unchanged,
+    Overwritten,
+    Skipped,
+    BackedUp,
+}
+
+/// 按冲突策略写入一个文件；`backup` 为 [`ConflictPolicy::Backup`] 时已有文件的去处
+fn write_file(
+    target: &Path,
+    content: &[u8],
+    policy: ConflictPolicy,
+    backup: &Path,
+) -> Result<WriteOutcome> {
+    let outcome = if target.exists() {
+        if fs::read(target).is_ok_and(|existing| existing == content) {
+            return Ok(WriteOutcome::Unchanged);
+        }
+        match policy {
+            ConflictPolicy::Skip => return Ok(WriteOutcome::Skipped),
+            ConflictPolicy::Overwrite => WriteOutcome::Overwritten,
+            ConflictPolicy::Backup => {
+                if let Some(dir) = backup.parent() {
+                    fs::create_dir_all(dir).context(format!("无法创建目录 {}", dir.display()))?;
+                }
+                // 备份放在数据目录下而不是原文件旁，避免目录模式的 frpc 把备份当作配置加载
+                fs::rename(target, backup).context(format!("无法备份 {}", target.display()))?;
+                WriteOutcome::BackedUp
+            }
+        }
+    } else {
+        WriteOutcome::Created
+    };
+    if let Some(dir) = target.parent() {
+        fs::create_dir_all(dir).context(format!("无法创建目录 {}", dir.display()))?;
+    }
+    fs::write(target, content).context(format!("无法写入 {}", target.display()))?;
+    Ok(outcome)
+}
+
+/// 收集 TOML 中值为明文的敏感键（值为环境变量模板的不算）
+fn toml_sensitive_keys(file: &str, prefix: &str, value: &toml::Value, out: &mut Vec<String>) {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, item) in table {
+                let path = join_key(prefix, key);
+                match item.as_str() {
+                    Some(s) if config::is_sensitive_config_key(key) && !s.contains("{{") => {
+                        out.push(format!("{}: {}", file, path))
+                    }
+                    _ => toml_sensitive_keys(file, &path, item, out),
+                }
+            }
+        }
+        toml::Value::Array(items) => {
+            for (index, item) in items.iter().enumerate() {
+                toml_sensitive_keys(file, &format!("{}[{}]", prefix, index), item, out);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// 收集 JSON 中值为非空字符串的敏感键
+fn json_sensitive_keys(file: &str, prefix: &str, value: &serde_json::Value, out: &mut Vec<String>) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, item) in map {
+                let path = join_key(prefix, key);
+                match item.as_str() {
+                    Some(s) if config::is_sensitive_config_key(key) && !s.is_empty() => {
+                        out.push(format!("{}: {}", file, path))
+                    }
+                    _ => json_sensitive_keys(file, &path, item, out),
+                }
+            }
+        }
+        serde_json::Value::Array(items) => {
+            for (index, item) in items.iter().enumerate() {
+                json_sensitive_keys(file, &format!("{}[{}]", prefix, index), item, out);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn join_key(prefix: &str, key: &str) -> String {
+    if prefix.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", prefix, key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("frpdesk-bundle-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn manifest(files: &BTreeMap<String, Vec<u8>>, format_version: u32) -> BundleManifest {
+        BundleManifest {
+            format_version,
+            created: "2026-01-01 00:00:00".to_string(),
+            frpdesk_version: env!("CARGO_PKG_VERSION").to_string(),
+            files: files.keys().cloned().collect(),
+            executables: vec![ExecutableEntry {
+                path: "frpc.exe".to_string(),
+                size: 3,
+                sha256: "abc".to_string(),
+            }],
+            run_as: vec![RunAsEntry {
+                instance: "web".to_string(),
+                user: "svc".to_string(),
+            }],
+        }
+    }
+
+    #[test]
+    fn bundle_round_trip() {
+        let dir = temp_dir("round-trip");
+        let (metadata, _) = clear_run_as_passwords(br#"{"configs":[{"name":"web"}]}"#).unwrap();
+        let entries = BTreeMap::from([
+            (
+                SETTINGS_ENTRY.to_string(),
+                br#"{"process_guard":true}"#.to_vec(),
+            ),
+            (METADATA_ENTRY.to_string(), metadata),
+            (
+                format!("{}web.toml", INSTANCES_PREFIX),
+                b"serverAddr = \"frps.example\"\n".to_vec(),
+            ),
+            (
+                format!("{}site/common.toml", INSTANCES_PREFIX),
+                b"auth.token = \"{{ .Envs.TOKEN }}\"\n".to_vec(),
+            ),
+        ]);
+        let bundle = dir.join("bundle.zip");
+        write_bundle(&bundle, &manifest(&entries, FORMAT_VERSION), &entries).unwrap();
+
+        let (read, read_entries) = read_bundle(&bundle).unwrap();
+        assert_eq!(read_entries, entries);
+        assert_eq!(read.files, entries.keys().cloned().collect::<Vec<_>>());
+        assert_eq!(read.executables[0].sha256, "abc");
+        assert_eq!(read.run_as[0].instance, "web");
+        assert_eq!(
+            instance_entry_path("instances/site/common.toml"),
+            Some(Path::new("site/common.toml"))
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn newer_format_version_is_rejected() {
+        let dir = temp_dir("version");
+        let entries = BTreeMap::new();
+        let bundle = dir.join("bundle.zip");
+        write_bundle(&bundle, &manifest(&entries, FORMAT_VERSION + 1), &entries).unwrap();
+        assert!(read_bundle(&bundle).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn run_as_passwords_are_cleared_and_unknown_fields_kept() {
+        let content = br#"{"configs":[
+            {"name":"web","future_field":1,"run_as":{"user":"svc","password_protected":"0a0b"}},
+            {"name":"ssh"}
+        ],"store_version":2}"#;
+        let (cleared, run_as) = clear_run_as_passwords(content).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&cleared).unwrap();
+        assert_eq!(value["store_version"], 2);
+        assert_eq!(value["configs"][0]["future_field"], 1);
+        assert_eq!(value["configs"][0]["run_as"]["user"], "svc");
+        assert_eq!(value["configs"][0]["run_as"]["password_protected"], "");
+        assert_eq!(run_as.len(), 1);
+        assert_eq!(
+            (run_as[0].instance.as_str(), run_as[0].user.as_str()),
+            ("web", "svc")
+        );
+
+        // 没有运行身份时原样返回
+        let plain = br#"{"configs":[{"name":"ssh"}]}"#;
+        let (unchanged, run_as) = clear_run_as_passwords(plain).unwrap();
+        assert_eq!(unchanged, plain);
+        assert!(run_as.is_empty());
+    }
+
+    #[test]
+    fn conflict_policies() {
+        let dir = temp_dir("conflict");
+        let target = dir.join("conf").join("web.toml");
+        let backup = dir.join("backup").join("import-1").join("web.toml");
+
+        assert!(matches!(
+            write_file(&target, b"new", ConflictPolicy::Skip, &backup).unwrap(),
+            WriteOutcome::Created
+        ));
+        assert!(matches!(
+            write_file(&target, b"new", ConflictPolicy::Skip, &backup).unwrap(),
+            WriteOutcome::Unchanged
+        ));
+
+        fs::write(&target, "local").unwrap();
+        assert!(matches!(
+            write_file(&target, b"new", ConflictPolicy::Skip, &backup).unwrap(),
+            WriteOutcome::Skipped
+        ));
+        assert_eq!(fs::read(&target).unwrap(), b"local");
+
+        assert!(matches!(
+            write_file(&target, b"new", ConflictPolicy::Backup, &backup).unwrap(),
+            WriteOutcome::BackedUp
+        ));
+        assert_eq!(fs::read(&target).unwrap(), b"new");
+        assert_eq!(fs::read(&backup).unwrap(), b"local");
+
+        fs::write(&target, "local").unwrap();
+        assert!(matches!(
+            write_file(&target, b"newer", ConflictPolicy::Overwrite, &backup).unwrap(),
+            WriteOutcome::Overwritten
+        ));
+        assert_eq!(fs::read(&target).unwrap(), b"newer");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn entries_outside_instances_dir_are_rejected() {
+        for name in [
+            "instances/",
+            "instances/../settings.json",
+            "instances/site/../../x.toml",
+            "other/web.toml",
+        ] {
+            assert!(instance_entry_path(name).is_none(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn sensitive_keys_skip_env_templates() {
+        let value: toml::Value = toml::from_str(
+            "auth.token = \"plain\"\n[[proxies]]\nname = \"a\"\nsecretKey = \"{{ .Envs.KEY }}\"\n",
+        )
+        .unwrap();
+        let mut found = Vec::new();
+        toml_sensitive_keys("web.toml", "", &value, &mut found);
+        assert_eq!(found, vec!["web.toml: auth.token".to_string()]);
+    }
+}
@@ -0,0 +1,386 @@
+//! 检查 frps 的 TLS 证书（`certinfo`）：按实例配置连接 frps 完成 TLS 握手，输出服务器证书的 CN、SAN 与有效期
+//!
+//! 握手时接受任何证书，已过期或不受信任的证书同样可以查看；配置了 `transport.tls.trustedCaFile` 时
+//! 再按 frpc 的方式单独校验并报告结果（未配置时 frpc 不校验服务器证书）。证书字段由 Windows CryptoAPI 解析。
+//! 只支持 TCP 传输；目录模式下逐个检查目录中的配置文件
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, TimeZone};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::{self, CryptoProvider};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{
+    ClientConfig, ClientConnection, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use windows_sys::Win32::Foundation::{LocalFree, FILETIME};
+use windows_sys::Win32::Security::Cryptography::{
+    szOID_COMMON_NAME, szOID_SUBJECT_ALT_NAME2, CertCreateCertificateContext, CertFindExtension,
+    CertFreeCertificateContext, CertGetNameStringW, CryptDecodeObjectEx, CERT_ALT_NAME_INFO,
+    CERT_INFO, CERT_NAME_ATTR_TYPE, CRYPT_DECODE_ALLOC_FLAG, PKCS_7_ASN_ENCODING,
+    X509_ALTERNATE_NAME, X509_ASN_ENCODING,
+};
+
+use crate::config;
+
+/// 证书剩余有效期少于该天数时警告
+const EXPIRY_WARNING_DAYS: i64 = 30;
+
+/// 连接与握手的超时
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// frpc 的 serverPort 默认值
+const DEFAULT_SERVER_PORT: u16 = 7000;
+
+/// CERT_ALT_NAME_ENTRY.dwAltNameChoice：DNS 名称与 IP 地址
+const CERT_ALT_NAME_DNS_NAME: u32 = 3;
+const CERT_ALT_NAME_IP_ADDRESS: u32 = 8;
+
+/// 检查实例连接的 frps 的证书；证书即将过期、已过期或校验失败时返回 1
+pub fn run(name: &str) -> Result<i32> {
+    let meta = config::find_config(name).context(format!("配置 '{}' 不存在", name))?;
+    let path = meta.config_path()?;
+    let files = if meta.config_dir.is_some() {
+        config::config_dir_files(&path)?
+    } else {
+        vec![path]
+    };
+
+    let mut code = 0;
+    for (index, file) in files.iter().enumerate() {
+        if files.len() > 1 {
+            if index > 0 {
+                println!();
+            }
+            println!("[{}]", file.display());
+        }
+        if !check_file(file)? {
+            code = 1;
+        }
+    }
+    Ok(code)
+}
+
+/// frpc 配置中与 TLS 连接相关的设置
+struct TlsTarget {
+    host: String,
+    port: u16,
+    /// `transport.tls.serverName`，未设置时为 serverAddr
+    server_name: String,
+    trusted_ca: Option<PathBuf>,
+}
+
+/// 检查单个配置文件，没有问题时返回 true
+fn check_file(file: &Path) -> Result<bool> {
+    let content =
+        std::fs::read_to_string(file).context(format!("无法读取配置文件: {}", file.display()))?;
+    let effective =
+        config::effective_config(&content).context(format!("配置文件 {} 无效", file.display()))?;
+    for env in &effective.missing_envs {
+        eprintln!("警告: 环境变量 {} 未设置，模板保持原样", env);
+    }
+    let value = &effective.value;
+
+    let protocol = lookup(value, &["transport", "protocol"])
+        .and_then(|v| v.as_str())
+        .unwrap_or("tcp");
+    if protocol != "tcp" {
+        anyhow::bail!(
+            "transport.protocol 为 {}，certinfo 只支持 tcp 传输",
+            protocol
+        );
+    }
+    if lookup(value, &["transport", "tls", "enable"]).and_then(|v| v.as_bool()) == Some(false) {
+        println!("transport.tls.enable = false，frpc 与 frps 之间不使用 TLS");
+        return Ok(true);
+    }
+    let host = value
+        .get("serverAddr")
+        .and_then(|v| v.as_str())
+        .filter(|s| !s.is_empty())
+        .context("配置中没有 serverAddr")?
+        .to_string();
+    let target = TlsTarget {
+        port: value
+            .get("serverPort")
+            .and_then(|v| v.as_integer())
+            .and_then(|v| u16::try_from(v).ok())
+            .unwrap_or(DEFAULT_SERVER_PORT),
+        server_name: lookup(value, &["transport", "tls", "serverName"])
+            .and_then(|v| v.as_str())
+            .filter(|s| !s.is_empty())
+            .map_or_else(|| host.clone(), str::to_string),
+        trusted_ca: lookup(value, &["transport", "tls", "trustedCaFile"])
+            .and_then(|v| v.as_str())
+            .filter(|s| !s.is_empty())
+            .map(PathBuf::from),
+        host,
+    };
+
+    let chain = fetch_certificates(&target)?;
+    let leaf = chain.first().context("frps 没有提供证书")?;
+    let cert = parse_certificate(leaf)?;
+    let days_left = (cert.not_after - Local::now()).num_days();
+
+    println!("frps:       {}:{}", target.host, target.port);
+    println!(
+        "CN:         {}",
+        cert.common_name.as_deref().unwrap_or("（无）")
+    );
+    if cert.subject_alt_names.is_empty() {
+        println!("SAN:        （无）");
+    } else {
+        println!("SAN:        {}", cert.subject_alt_names.join(", "));
+    }
+    println!("有效期至:   {}", cert.not_after.format("%Y-%m-%d %H:%M:%S"));
+    println!("剩余天数:   {}", days_left);
+
+    let mut ok = true;
+    if cert.not_after <= Local::now() {
+        println!("警告: 证书已过期，frpc 校验证书时将无法连接");
+        ok = false;
+    } else if days_left < EXPIRY_WARNING_DAYS {
+        println!("警告: 证书将在 {} 天内过期", EXPIRY_WARNING_DAYS);
+        ok = false;
+    }
+    match &target.trusted_ca {
+        Some(ca) => match verify_chain(&chain, ca, &target.server_name) {
+            Ok(()) => println!("证书校验:   通过（trustedCaFile: {}）", ca.display()),
+            Err(e) => {
+                println!(
+                    "证书校验:   失败（trustedCaFile: {}）: {:#}",
+                    ca.display(),
+                    e
+                );
+                ok = false;
+            }
+        },
+        None => println!("证书校验:   未配置 transport.tls.trustedCaFile，frpc 不校验服务器证书"),
+    }
+    Ok(ok)
+}
+
+/// 按点分路径读取嵌套的表项
+fn lookup<'a>(value: &'a toml::Value, keys: &[&str]) -> Option<&'a toml::Value> {
+    keys.iter().try_fold(value, |v, key| v.get(key))
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(crypto::ring::default_provider())
+}
+
+/// 连接 frps 完成 TLS 握手，返回服务器提供的证书链（第一个为服务器证书）
+///
+/// frps 同时接受标准 TLS 握手与 frpc 旧版本的自定义首字节，这里总是使用标准握手
+fn fetch_certificates(target: &TlsTarget) -> Result<Vec<CertificateDer<'static>>> {
+    let addr = (target.host.as_str(), target.port)
+        .to_socket_addrs()
+        .context(format!("无法解析 {}", target.host))?
+        .next()
+        .context(format!("{} 没有可用的地址", target.host))?;
+    let mut tcp = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)
+        .context(format!("无法连接 {}:{}", target.host, target.port))?;
+    tcp.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+    tcp.set_write_timeout(Some(CONNECT_TIMEOUT))?;
+
+    let config = ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate(provider())))
+        .with_no_client_auth();
+    let server_name = ServerName::try_from(target.server_name.clone())
+        .context(format!("无效的服务器名称: {}", target.server_name))?;
+    let mut conn = ClientConnection::new(Arc::new(config), server_name)?;
+    while conn.is_handshaking() {
+        conn.complete_io(&mut tcp)
+            .context("TLS 握手失败（frps 是否启用了 TLS？）")?;
+    }
+    Ok(conn
+        .peer_certificates()
+        .map(|certs| certs.iter().map(|c| c.clone().into_owned()).collect())
+        .unwrap_or_default())
+}
+
+/// 按 frpc 的方式校验证书链：受 trustedCaFile 中的 CA 信任，且与服务器名称匹配
+fn verify_chain(
+    chain: &[CertificateDer<'static>],
+    ca_file: &Path,
+    server_name: &str,
+) -> Result<()> {
+    let mut roots = RootCertStore::empty();
+    for cert in
+        CertificateDer::pem_file_iter(ca_file).context(format!("无法读取 {}", ca_file.display()))?
+    {
+        roots.add(cert.context(format!("{} 不是有效的 PEM 证书", ca_file.display()))?)?;
+    }
+    let verifier =
+        WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider()).build()?;
+    let server_name = ServerName::try_from(server_name.to_string())?;
+    verifier.verify_server_cert(&chain[0], &chain[1..], &server_name, &[], UnixTime::now())?;
+    Ok(())
+}
+
+/// 接受任何服务器证书（只用于查看证书，不传输数据）；握手签名仍正常校验
+#[derive(Debug)]
+struct AcceptAnyCertificate(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// 证书中需要展示的字段
+struct CertificateSummary {
+    common_name: Option<String>,
+    /// `DNS:example.com`、`IP:1.2.3.4` 形式
+    subject_alt_names: Vec<String>,
+    not_after: DateTime<Local>,
+}
+
+/// 用 CryptoAPI 解析 DER 编码的证书
+fn parse_certificate(der: &[u8]) -> Result<CertificateSummary> {
+    unsafe {
+        let context = CertCreateCertificateContext(
+            X509_ASN_ENCODING | PKCS_7_ASN_ENCODING,
+            der.as_ptr(),
+            der.len() as u32,
+        );
+        if context.is_null() {
+            anyhow::bail!("无法解析服务器证书: {}", std::io::Error::last_os_error());
+        }
+        let info = &*(*context).pCertInfo;
+        let mut name = [0u16; 256];
+        // 返回的长度含结尾的 0，为 1 表示没有 CN
+        let len = CertGetNameStringW(
+            context,
+            CERT_NAME_ATTR_TYPE,
+            0,
+            szOID_COMMON_NAME as *const _,
+            name.as_mut_ptr(),
+            name.len() as u32,
+        ) as usize;
+        let summary = CertificateSummary {
+            common_name: (len > 1).then(|| String::from_utf16_lossy(&name[..len - 1])),
+            subject_alt_names: subject_alt_names(info),
+            not_after: filetime_to_local(&info.NotAfter),
+        };
+        CertFreeCertificateContext(context);
+        Ok(summary)
+    }
+}
+
+/// 证书的 SAN 扩展中的 DNS 名称与 IP 地址
+unsafe fn subject_alt_names(info: &CERT_INFO) -> Vec<String> {
+    let extension = CertFindExtension(szOID_SUBJECT_ALT_NAME2, info.cExtension, info.rgExtension);
+    if extension.is_null() {
+        return Vec::new();
+    }
+    let mut decoded: *mut CERT_ALT_NAME_INFO = std::ptr::null_mut();
+    let mut size = 0u32;
+    if CryptDecodeObjectEx(
+        X509_ASN_ENCODING,
+        X509_ALTERNATE_NAME,
+        (*extension).Value.pbData,
+        (*extension).Value.cbData,
+        CRYPT_DECODE_ALLOC_FLAG,
+        std::ptr::null(),
+        &mut decoded as *mut _ as *mut _,
+        &mut size,
+    ) == 0
+    {
+        return Vec::new();
+    }
+    let entries = std::slice::from_raw_parts((*decoded).rgAltEntry, (*decoded).cAltEntry as usize);
+    let names = entries
+        .iter()
+        .filter_map(|entry| match entry.dwAltNameChoice {
+            CERT_ALT_NAME_DNS_NAME => {
+                let ptr = entry.Anonymous.pwszDNSName;
+                let len = (0..).take_while(|&i| *ptr.add(i) != 0).count();
+                Some(format!(
+                    "DNS:{}",
+                    String::from_utf16_lossy(std::slice::from_raw_parts(ptr, len))
+                ))
+            }
+            CERT_ALT_NAME_IP_ADDRESS => {
+                let blob = entry.Anonymous.IPAddress;
+                let bytes = std::slice::from_raw_parts(blob.pbData, blob.cbData as usize);
+                ip_address(bytes).map(|ip| format!("IP:{}", ip))
+            }
+            _ => None,
+        })
+        .collect();
+    LocalFree(decoded as _);
+    names
+}
+
+fn ip_address(bytes: &[u8]) -> Option<IpAddr> {
+    match bytes.len() {
+        4 => Some(IpAddr::V4(Ipv4Addr::new(
+            bytes[0], bytes[1], bytes[2], bytes[3],
+        ))),
+        16 => Some(IpAddr::V6(Ipv6Addr::from(
+            <[u8; 16]>::try_from(bytes).ok()?,
+        ))),
+        _ => None,
+    }
+}
+
+/// FILETIME（1601 年起的 100ns 数）转换为本地时间
+fn filetime_to_local(time: &FILETIME) -> DateTime<Local> {
+    const UNIX_EPOCH_OFFSET: i64 = 116_444_736_000_000_000;
+    let ticks = ((time.dwHighDateTime as i64) << 32) | time.dwLowDateTime as i64;
+    let unix = ticks - UNIX_EPOCH_OFFSET;
+    Local
+        .timestamp_opt(
+            unix.div_euclid(10_000_000),
+            (unix.rem_euclid(10_000_000) * 100) as u32,
+        )
+        .single()
+        .unwrap_or_default()
+}
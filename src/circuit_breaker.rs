@@ -0,0 +1,316 @@
+//! 全局熔断：短时间内所有实例的崩溃总数超过阈值时暂停全部重启
+//!
+//! 大量实例同时反复崩溃多半是 frps 宕机或本机网络故障，继续重启只会浪费资源、刷屏日志。
+//! 熔断后至少暂停 `open_secs` 秒，之后每隔 `probe_interval_secs` 秒在后台线程 TCP 连接
+//! 各实例配置中的 frps（`serverAddr:serverPort`），任一可连接即恢复，由进程守护统一拉起未运行的实例
+
+use chrono::Local;
+use std::collections::VecDeque;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::CircuitBreakerSettings;
+use crate::snapshot::CircuitBreakerStatus;
+
+/// 单个 frps 地址的连接超时
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// frpc 的 serverPort 默认值
+const DEFAULT_SERVER_PORT: u16 = 7000;
+
+/// 熔断器；未配置（`settings` 为 None）时不统计、不熔断
+pub(crate) struct CircuitBreaker {
+    settings: Option<CircuitBreakerSettings>,
+    /// 统计窗口内各次崩溃的时间
+    crashes: VecDeque<Instant>,
+    open: Option<OpenCircuit>,
+}
+
+/// 熔断中的状态
+struct OpenCircuit {
+    status: CircuitBreakerStatus,
+    /// 最早恢复时间
+    probe_after: Instant,
+    /// 下一次探测时间
+    next_probe: Instant,
+    /// 进行中的探测，结果为是否有 frps 可连接
+    probe: Option<Receiver<bool>>,
+}
+
+impl CircuitBreaker {
+    pub(crate) fn new(settings: Option<CircuitBreakerSettings>) -> Self {
+        Self {
+            settings,
+            crashes: VecDeque::new(),
+            open: None,
+        }
+    }
+
+    /// 重新加载设置；熔断中时保持熔断并按新设置继续，设置中关闭熔断时立即恢复并返回 true
+    pub(crate) fn set_settings(&mut self, settings: Option<CircuitBreakerSettings>) -> bool {
+        let closed = settings.is_none() && self.open.take().is_some();
+        self.settings = settings;
+        self.crashes.clear();
+        closed
+    }
+
+    pub(crate) fn settings(&self) -> Option<&CircuitBreakerSettings> {
+        self.settings.as_ref()
+    }
+
+    pub(crate) fn is_open(&self) -> bool {
+        self.open.is_some()
+    }
+
+    /// 熔断中的状态（写入 status.json）
+    pub(crate) fn status(&self) -> Option<&CircuitBreakerStatus> {
+        self.open.as_ref().map(|open| &open.status)
+    }
+
+    /// 记录本轮检测到的崩溃，统计窗口内的总数达到阈值时熔断并返回 true
+    pub(crate) fn record_crashes(&mut self, count: usize, now: Instant) -> bool {
+        let Some(settings) = &self.settings else {
+            return false;
+        };
+        if self.open.is_some() {
+            return false;
+        }
+        let window = Duration::from_secs(settings.window_secs);
+        while self
+            .crashes
+            .front()
+            .is_some_and(|&t| now.duration_since(t) >= window)
+        {
+            self.crashes.pop_front();
+        }
+        self.crashes.extend(std::iter::repeat_n(now, count));
+        if count == 0 || self.crashes.len() < settings.max_crashes.max(1) as usize {
+            return false;
+        }
+        let open_for = Duration::from_secs(settings.open_secs);
+        let probe_after = now + open_for;
+        let opened_at = Local::now();
+        self.open = Some(OpenCircuit {
+            status: CircuitBreakerStatus {
+                opened_at: opened_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+                crashes: self.crashes.len(),
+                probe_after: (opened_at + open_for)
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string(),
+            },
+            probe_after,
+            next_probe: probe_after,
+            probe: None,
+        });
+        self.crashes.clear();
+        true
+    }
+
+    /// 熔断中推进探测：暂停时间已过且到了探测时间时在后台探测 `configs` 中的 frps，
+    /// 探测成功（恢复）时返回 true
+    pub(crate) fn poll(&mut self, now: Instant, configs: impl FnOnce() -> Vec<PathBuf>) -> bool {
+        let Some(settings) = &self.settings else {
+            return false;
+        };
+        let Some(open) = &mut self.open else {
+            return false;
+        };
+        if now < open.probe_after {
+            return false;
+        }
+        if let Some(probe) = &open.probe {
+            let reachable = match probe.try_recv() {
+                Ok(reachable) => reachable,
+                Err(TryRecvError::Empty) => return false,
+                Err(TryRecvError::Disconnected) => false,
+            };
+            open.probe = None;
+            if reachable {
+                self.open = None;
+                return true;
+            }
+            log::info!(
+                "熔断中：frps 仍无法连接，{} 秒后再次探测",
+                settings.probe_interval_secs
+            );
+            open.next_probe = now + Duration::from_secs(settings.probe_interval_secs.max(1));
+            return false;
+        }
+        if now >= open.next_probe {
+            let configs = configs();
+            let (tx, rx) = mpsc::channel();
+            thread::spawn(move || {
+                let _ = tx.send(any_server_reachable(&configs));
+            });
+            open.probe = Some(rx);
+        }
+        false
+    }
+}
+
+/// 任一配置中的 frps 是否可连接；没有可探测的地址（如非 TOML 配置）时视为可连接，只按时间恢复
+fn any_server_reachable(configs: &[PathBuf]) -> bool {
+    let mut servers: Vec<(String, u16)> =
+        configs.iter().filter_map(|c| server_endpoint(c)).collect();
+    servers.sort();
+    servers.dedup();
+    if servers.is_empty() {
+        log::info!("熔断中：配置中没有可探测的 frps 地址，暂停时间结束后直接恢复");
+        return true;
+    }
+    servers.iter().any(|(host, port)| {
+        let reachable = (host.as_str(), *port)
+            .to_socket_addrs()
+            .map(|mut addrs| addrs.any(|a| TcpStream::connect_timeout(&a, CONNECT_TIMEOUT).is_ok()))
+            .unwrap_or(false);
+        if reachable {
+            log::info!("熔断中：frps {}:{} 可连接", host, port);
+        }
+        reachable
+    })
+}
+
+/// 从 TOML 配置中读取 `serverAddr` 与 `serverPort`（默认 7000）
+fn server_endpoint(config: &std::path::Path) -> Option<(String, u16)> {
+    let content = std::fs::read_to_string(config).ok()?;
+    let value: toml::Value = toml::from_str(&content).ok()?;
+    let host = value
+        .get("serverAddr")
+        .or_else(|| value.get("server_addr"))
+        .and_then(|v| v.as_str())
+        .filter(|s| !s.is_empty())?
+        .to_string();
+    let port = value
+        .get("serverPort")
+        .or_else(|| value.get("server_port"))
+        .and_then(|v| v.as_integer())
+        .and_then(|v| u16::try_from(v).ok())
+        .unwrap_or(DEFAULT_SERVER_PORT);
+    Some((host, port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> CircuitBreakerSettings {
+        CircuitBreakerSettings {
+            max_crashes: 3,
+            window_secs: 60,
+            open_secs: 30,
+            probe_interval_secs: 10,
+        }
+    }
+
+    /// 推进探测直到后台探测出结果，返回是否恢复
+    fn finish_probe(breaker: &mut CircuitBreaker, now: Instant, configs: &[PathBuf]) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            if breaker.poll(now, || configs.to_vec()) {
+                return true;
+            }
+            let probing = breaker.open.as_ref().is_some_and(|o| o.probe.is_some());
+            if !probing || Instant::now() > deadline {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// 指向本机未监听端口的 frpc 配置
+    fn unreachable_config() -> PathBuf {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let path = std::env::temp_dir().join(format!("frpdesk-breaker-{}.toml", port));
+        std::fs::write(
+            &path,
+            format!("serverAddr = \"127.0.0.1\"\nserverPort = {}\n", port),
+        )
+        .unwrap();
+        path
+    }
+
+    #[test]
+    fn crashes_outside_window_do_not_open() {
+        let mut breaker = CircuitBreaker::new(Some(settings()));
+        let start = Instant::now();
+        assert!(!breaker.record_crashes(2, start));
+        // 窗口（60 秒）已过，之前的 2 次不再计入
+        assert!(!breaker.record_crashes(1, start + Duration::from_secs(60)));
+        assert!(!breaker.record_crashes(1, start + Duration::from_secs(61)));
+        assert!(!breaker.is_open());
+        assert!(breaker.record_crashes(1, start + Duration::from_secs(62)));
+        assert!(breaker.is_open());
+        assert_eq!(breaker.status().map(|s| s.crashes), Some(3));
+    }
+
+    #[test]
+    fn disabled_breaker_never_opens() {
+        let mut breaker = CircuitBreaker::new(None);
+        assert!(!breaker.record_crashes(100, Instant::now()));
+        assert!(!breaker.is_open());
+    }
+
+    #[test]
+    fn open_then_probe_then_recover() {
+        let mut breaker = CircuitBreaker::new(Some(settings()));
+        let opened = Instant::now();
+        assert!(breaker.record_crashes(3, opened));
+        // 熔断中不再统计崩溃
+        assert!(!breaker.record_crashes(3, opened));
+
+        // 暂停时间内不探测
+        let too_early = opened + Duration::from_secs(29);
+        assert!(!breaker.poll(too_early, || panic!("暂停时间内不应读取配置")));
+        assert!(breaker.is_open());
+
+        // 探测失败：保持熔断，下一次探测在间隔之后
+        let unreachable = unreachable_config();
+        let first_probe = opened + Duration::from_secs(30);
+        assert!(!finish_probe(
+            &mut breaker,
+            first_probe,
+            std::slice::from_ref(&unreachable)
+        ));
+        assert!(breaker.is_open());
+        let retry = first_probe + Duration::from_secs(5);
+        assert!(!breaker.poll(retry, || panic!("探测间隔内不应再次探测")));
+        std::fs::remove_file(&unreachable).ok();
+
+        // 没有可探测的地址时视为可连接，恢复
+        let second_probe = first_probe + Duration::from_secs(10);
+        assert!(finish_probe(&mut breaker, second_probe, &[]));
+        assert!(!breaker.is_open());
+        assert!(breaker.status().is_none());
+    }
+
+    #[test]
+    fn turning_off_while_open_recovers_immediately() {
+        let mut breaker = CircuitBreaker::new(Some(settings()));
+        assert!(breaker.record_crashes(3, Instant::now()));
+        assert!(breaker.set_settings(None));
+        assert!(!breaker.is_open());
+        assert!(!breaker.record_crashes(100, Instant::now()));
+        // 未熔断时关闭不需要拉起实例
+        assert!(!breaker.set_settings(None));
+    }
+
+    #[test]
+    fn reloading_settings_keeps_open_circuit() {
+        let mut breaker = CircuitBreaker::new(Some(settings()));
+        let now = Instant::now();
+        assert!(!breaker.record_crashes(2, now));
+        assert!(!breaker.set_settings(Some(settings())));
+        // 重新加载时已统计的崩溃清零
+        assert!(!breaker.record_crashes(2, now));
+        assert!(breaker.record_crashes(1, now));
+        assert!(!breaker.set_settings(Some(settings())));
+        assert!(breaker.is_open());
+    }
+}
@@ -0,0 +1,97 @@
+//! 启动清理：删除上次运行生成、本次会重新生成的文件
+//!
+//! 只按固定的文件名清理本程序生成的文件，不做通配匹配，不会触及用户的配置与日志
+
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use crate::config;
+use crate::download::DOWNLOAD_TEMP_FILE;
+use crate::snapshot::{SNAPSHOT_FILE, SNAPSHOT_TEMP_FILE};
+
+/// 最近修改过的文件可能正被其他进程使用（如界面正在下载 frpc），不清理
+const MIN_AGE: Duration = Duration::from_secs(5 * 60);
+
+/// 本程序生成的文件（完整路径）
+fn generated_files() -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    if let Ok(bin) = config::bin_dir() {
+        // 下载中断遗留的压缩包
+        files.push(bin.join(DOWNLOAD_TEMP_FILE));
+    }
+    if let Ok(logs) = config::logs_dir() {
+        // 上次会话的状态快照，本次启动后重新生成
+        files.push(logs.join(SNAPSHOT_FILE));
+        files.push(logs.join(SNAPSHOT_TEMP_FILE));
+    }
+    files
+}
+
+/// 服务启动早期调用，删除上次运行遗留的生成文件
+pub fn clean_generated_files() {
+    remove_stale_files(&generated_files(), SystemTime::now());
+}
+
+/// 删除 `files` 中修改时间早于 `now` 前 [`MIN_AGE`] 的普通文件，不存在的与目录跳过
+fn remove_stale_files(files: &[PathBuf], now: SystemTime) {
+    for path in files {
+        let Ok(meta) = fs::metadata(path) else {
+            continue;
+        };
+        if !meta.is_file() {
+            continue;
+        }
+        let recent = meta
+            .modified()
+            .ok()
+            .and_then(|m| now.duration_since(m).ok())
+            .is_some_and(|age| age < MIN_AGE);
+        if recent {
+            log::info!("跳过最近修改的文件: {:?}", path);
+            continue;
+        }
+        match fs::remove_file(path) {
+            Ok(()) => log::info!("已清理上次运行遗留的文件: {:?}", path),
+            Err(e) => log::warn!("清理遗留文件失败 {:?}: {}", path, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_stale_files_are_removed() {
+        let dir = std::env::temp_dir().join(format!("frpdesk-cleanup-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let (stale, recent, directory, missing) = (
+            dir.join(SNAPSHOT_FILE),
+            dir.join(SNAPSHOT_TEMP_FILE),
+            dir.join(DOWNLOAD_TEMP_FILE),
+            dir.join("missing"),
+        );
+        fs::write(&stale, "{}").unwrap();
+        fs::write(&recent, "{}").unwrap();
+        fs::create_dir(&directory).unwrap();
+
+        // 以 stale 的修改时间之后 MIN_AGE 为当前时间：stale 刚好到期，recent 晚 1 秒修改、尚未到期
+        let modified = fs::metadata(&stale).unwrap().modified().unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&recent)
+            .unwrap()
+            .set_modified(modified + Duration::from_secs(1))
+            .unwrap();
+        remove_stale_files(
+            &[stale.clone(), recent.clone(), directory.clone(), missing],
+            modified + MIN_AGE,
+        );
+        assert!(!stale.exists());
+        assert!(recent.exists());
+        assert!(directory.is_dir());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
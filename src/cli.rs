@@ -0,0 +1,972 @@
+//! 命令行子命令：在交互模式与服务模式之外执行一次性命令后退出
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use windows_sys::Win32::System::Console::{
+    AttachConsole, GetConsoleMode, GetStdHandle, SetConsoleMode, ATTACH_PARENT_PROCESS,
+    ENABLE_ECHO_INPUT, STD_INPUT_HANDLE,
+};
+
+use crate::account_check;
+use crate::av_check;
+use crate::bundle::{self, ConflictPolicy};
+use crate::certinfo;
+use crate::compare_configs;
+use crate::config;
+#[cfg(feature = "debug")]
+use crate::debug_attach;
+use crate::download;
+use crate::frpc_mg;
+use crate::logger;
+use crate::metrics::{self, ServiceMetrics};
+use crate::minidump;
+use crate::profile;
+use crate::run_as;
+use crate::self_check;
+use crate::service::{
+    self, DiscoveryReport, GroupCommand, InstallOutcome, ServiceAccount, ServiceStartMode,
+    SetEnvCommand,
+};
+use crate::snapshot;
+use crate::tail_errors;
+use crate::watch_logs;
+
+/// 版本字符串，形如 `frpdesk 1.4.0 (abc1234 2024-01-15T10:00:00Z)`
+///
+/// GIT_HASH 与 BUILD_TIME 由 build.rs 在编译期注入
+pub fn version_string() -> String {
+    format!(
+        "{} {} ({} {})",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        env!("GIT_HASH"),
+        env!("BUILD_TIME")
+    )
+}
+
+/// 尝试把命令行参数作为子命令执行
+///
+/// 返回 Some(退出码) 表示已处理；None 表示不是子命令，继续进入交互/服务模式
+pub fn run(args: &[String]) -> Option<i32> {
+    let command = args.get(1)?.as_str();
+    let result = match command {
+        "version" | "--version" | "-V" => {
+            attach_parent_console();
+            cmd_version()
+        }
+        "apply" => {
+            attach_parent_console();
+            cmd_apply()
+        }
+        "restart" | "start" | "stop" => {
+            attach_parent_console();
+            cmd_group(command, &args[2..])
+        }
+        "install" => {
+            attach_parent_console();
+            cmd_install(&args[2..])
+        }
+        "uninstall" => {
+            attach_parent_console();
+            cmd_uninstall()
+        }
+        "--check-only" => {
+            attach_parent_console();
+            cmd_check_only(&args[2..])
+        }
+        "self-register" => {
+            attach_parent_console();
+            cmd_self_register(&args[2..])
+        }
+        "pause-monitor" => {
+            attach_parent_console();
+            cmd_pause_monitor(true)
+        }
+        "resume-monitor" => {
+            attach_parent_console();
+            cmd_pause_monitor(false)
+        }
+        "rotate-logs" => {
+            attach_parent_console();
+            cmd_rotate_logs()
+        }
+        "set-run-as" => {
+            attach_parent_console();
+            cmd_set_run_as(&args[2..])
+        }
+        "set-env" => {
+            attach_parent_console();
+            cmd_set_env(&args[2..])
+        }
+        "check-update" => {
+            attach_parent_console();
+            cmd_check_update(&args[2..])
+        }
+        "discover" | "list" => {
+            attach_parent_console();
+            cmd_discover(&args[2..])
+        }
+        "show-config" => {
+            attach_parent_console();
+            cmd_show_config(&args[2..])
+        }
+        "compare-configs" => {
+            attach_parent_console();
+            cmd_compare_configs(&args[2..])
+        }
+        "export" => {
+            attach_parent_console();
+            cmd_export(&args[2..])
+        }
+        "import" => {
+            attach_parent_console();
+            cmd_import(&args[2..])
+        }
+        "certinfo" => {
+            attach_parent_console();
+            cmd_certinfo(&args[2..])
+        }
+        "health" => {
+            attach_parent_console();
+            cmd_health()
+        }
+        "export-metrics" => {
+            attach_parent_console();
+            cmd_export_metrics(&args[2..])
+        }
+        "snapshot" => {
+            attach_parent_console();
+            cmd_snapshot(&args[2..])
+        }
+        "profile" => {
+            attach_parent_console();
+            cmd_profile(&args[2..])
+        }
+        "watch-logs" => {
+            attach_parent_console();
+            cmd_watch_logs(&args[2..])
+        }
+        "tail-errors" => {
+            attach_parent_console();
+            cmd_tail_errors(&args[2..])
+        }
+        #[cfg(feature = "debug")]
+        "debug-attach" => {
+            attach_parent_console();
+            cmd_debug_attach(&args[2..])
+        }
+        _ => return None,
+    };
+    Some(match result {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("错误: {:?}", e);
+            1
+        }
+    })
+}
+
+/// 程序使用 windows 子系统，默认没有控制台；附加到父进程（命令行）的控制台以便输出
+fn attach_parent_console() {
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+/// `version`：打印版本、提交哈希与构建时间
+fn cmd_version() -> Result<i32> {
+    println!("{}", version_string());
+    Ok(0)
+}
+
+/// `show-config <配置名>`：打印实例实际生效的 frpc 配置
+///
+/// 渲染环境变量模板（使用当前进程的环境，服务以 LocalSystem 运行时可能不同），敏感值显示为 `[REDACTED]`
+fn cmd_show_config(args: &[String]) -> Result<i32> {
+    config::apply_instances_file_arg(args)?;
+    let name = args
+        .first()
+        .filter(|a| !a.starts_with("--"))
+        .context("用法: show-config <配置名>")?;
+    let meta = config::find_config(name).context(format!("配置 '{}' 不存在", name))?;
+    let path = meta.config_path()?;
+
+    println!("# 实例: {}", name);
+    // 目录模式依次输出目录中的每个配置文件
+    let files = if meta.config_dir.is_some() {
+        println!("# 配置目录: {}", path.display());
+        config::config_dir_files(&path)?
+    } else {
+        vec![path]
+    };
+    println!("# frpc: {}", meta.exe_path()?.display());
+    for path in &files {
+        let content = std::fs::read_to_string(path)
+            .context(format!("无法读取配置文件: {}", path.display()))?;
+        let effective = config::effective_config(&content)
+            .context(format!("配置文件 {} 无效", path.display()))?;
+        println!("# 配置文件: {}", path.display());
+        for env in &effective.missing_envs {
+            println!("# 警告: 环境变量 {} 未设置，模板保持原样", env);
+        }
+        println!();
+        print!(
+            "{}",
+            toml::to_string_pretty(&effective.value).context("无法格式化配置")?
+        );
+        println!();
+    }
+    Ok(0)
+}
+
+/// `compare-configs <配置名1> <配置名2>`：输出两个实例规范化后的配置差异；相同时返回 0，不同时返回 1
+fn cmd_compare_configs(args: &[String]) -> Result<i32> {
+    config::apply_instances_file_arg(args)?;
+    let mut names = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            config::INSTANCES_FILE_ARG => {
+                iter.next();
+            }
+            a if a.starts_with("--") => anyhow::bail!("未知参数: {}", a),
+            a => names.push(a),
+        }
+    }
+    let [first, second] = names[..] else {
+        anyhow::bail!("用法: compare-configs <配置名1> <配置名2>");
+    };
+    if compare_configs::run(first, second)? {
+        Ok(1)
+    } else {
+        println!("'{}' 与 '{}' 的配置相同", first, second);
+        Ok(0)
+    }
+}
+
+/// `certinfo <配置名>`：连接实例的 frps 查看 TLS 证书的 CN、SAN 与有效期；
+/// 证书 30 天内过期、已过期或按 trustedCaFile 校验失败时返回 1
+fn cmd_certinfo(args: &[String]) -> Result<i32> {
+    config::apply_instances_file_arg(args)?;
+    let name = args
+        .first()
+        .filter(|a| !a.starts_with("--"))
+        .context("用法: certinfo <配置名>")?;
+    certinfo::run(name)
+}
+
+/// `discover` / `list`：打印发现到的实例及被跳过的候选，不启动任何进程
+///
+/// 参数：`--format table|json|toml`（默认 table），`--instances-file <路径>`
+fn cmd_discover(args: &[String]) -> Result<i32> {
+    config::apply_instances_file_arg(args)?;
+    let format = match args.iter().position(|a| a == "--format") {
+        Some(pos) => args
+            .get(pos + 1)
+            .map(String::as_str)
+            .context("--format 缺少参数（table/json/toml）")?,
+        None => "table",
+    };
+    let report = service::discover_instances()?;
+    match format {
+        "table" => print_discovery_table(&report),
+        "json" => println!(
+            "{}",
+            serde_json::to_string_pretty(&report).context("无法序列化为 JSON")?
+        ),
+        "toml" => print!(
+            "{}",
+            toml::to_string_pretty(&report).context("无法序列化为 TOML")?
+        ),
+        other => anyhow::bail!("不支持的输出格式: {}（可选 table/json/toml）", other),
+    }
+    Ok(0)
+}
+
+fn print_discovery_table(report: &DiscoveryReport) {
+    if report.instances.is_empty() {
+        println!("未发现任何实例");
+    } else {
+        let width = report
+            .instances
+            .iter()
+            .map(|i| i.identifier.chars().count())
+            .max()
+            .unwrap_or(0)
+            .max(4);
+        println!(
+            "{:<width$}  {:<10}  {:<6}  配置文件",
+            "名称",
+            "分组",
+            "自启动",
+            width = width
+        );
+        for inst in &report.instances {
+            println!(
+                "{:<width$}  {:<10}  {:<6}  {}{}",
+                inst.identifier,
+                inst.group,
+                if inst.enabled { "是" } else { "否" },
+                inst.config.display(),
+                if inst.config_dir {
+                    "（目录模式）"
+                } else {
+                    ""
+                },
+                width = width
+            );
+        }
+        if let Some(first) = report.instances.first() {
+            println!("\nfrpc: {}", first.exe.display());
+        }
+    }
+    if !report.skipped.is_empty() {
+        println!("\n已跳过 {} 个候选:", report.skipped.len());
+        for skipped in &report.skipped {
+            println!(
+                "  {}: {}（{}）",
+                skipped.identifier,
+                skipped.reason,
+                skipped.path.display()
+            );
+        }
+    }
+    if !report.duplicates.is_empty() {
+        println!("\n警告：以下实例引用同一配置文件，两个 frpc 会争抢同一组代理:");
+        for dup in &report.duplicates {
+            println!(
+                "  {} 与 {}: {}",
+                dup.duplicate,
+                dup.first,
+                dup.config.display()
+            );
+        }
+    }
+}
+
+/// `--check-only`：以服务启动的流程做一次自检后退出，不注册服务、不启动 frpc
+///
+/// 参数：`--instances-file <路径>`
+fn cmd_check_only(args: &[String]) -> Result<i32> {
+    config::apply_instances_file_arg(args)?;
+    Ok(self_check::run())
+}
+
+/// `apply`：通知服务重新发现实例并平滑应用（热重载 / 滚动重启 / 启动新增 / 停止移除）
+fn cmd_apply() -> Result<i32> {
+    service::send_pipe_command("APPLY")?;
+    println!("已通知服务应用最新配置，执行结果见服务日志");
+    Ok(0)
+}
+
+/// `install`：注册 Windows 服务（需管理员权限）
+///
+/// 参数：`--account localsystem|virtual`（默认 localsystem），`--instances-file <路径>`
+///
+/// 幂等：服务已存在且配置一致时直接返回 0，不一致时重新注册（一致性判定见
+/// [`service::ensure_service_installed`]）
+fn cmd_install(args: &[String]) -> Result<i32> {
+    config::apply_instances_file_arg(args)?;
+    install_service(args)
+}
+
+/// 按 `--account`、`--start-type`、`--delayed-auto` 参数注册服务（`install` 与 `import` 共用）
+fn install_service(args: &[String]) -> Result<i32> {
+    let account = match args.iter().position(|a| a == "--account") {
+        Some(pos) => ServiceAccount::parse(
+            args.get(pos + 1)
+                .context("--account 缺少参数（localsystem/virtual）")?,
+        )?,
+        None => ServiceAccount::LocalSystem,
+    };
+    // --delayed-auto 是 --start-type delayed-auto 的简写
+    let start_mode = match args.iter().position(|a| a == "--start-type") {
+        Some(pos) => ServiceStartMode::parse(
+            args.get(pos + 1)
+                .context("--start-type 缺少参数（auto/delayed-auto/manual）")?,
+        )?,
+        None if args.iter().any(|a| a == "--delayed-auto") => ServiceStartMode::DelayedAuto,
+        None => ServiceStartMode::Auto,
+    };
+    if args.iter().any(|a| a == "--delayed-auto") && start_mode != ServiceStartMode::DelayedAuto {
+        anyhow::bail!("--delayed-auto 与 --start-type {:?} 冲突", start_mode);
+    }
+    // 账户缺少权利时服务注册后也无法启动，提前给出原因与解决办法（不阻止注册）
+    for issue in account_check::check_service_account(account) {
+        eprintln!("警告: {}\n  解决办法: {}", issue.problem, issue.fix);
+    }
+    // 杀毒软件可能拦截 frpc，只提示不阻止注册
+    let antivirus = av_check::detect_antivirus();
+    if !antivirus.is_empty() {
+        eprintln!("提示: {}", av_check::exclusion_hint(&antivirus));
+    }
+    match service::ensure_service_installed(account, start_mode)? {
+        InstallOutcome::Unchanged => {
+            println!("服务已注册且配置一致，无需改动");
+            return Ok(0);
+        }
+        InstallOutcome::Reinstalled(differences) => {
+            println!("服务已注册但配置不一致，已重新注册：");
+            for difference in differences {
+                println!("  {}", difference);
+            }
+        }
+        InstallOutcome::Installed => {}
+    }
+    println!(
+        "服务已注册（账户: {:?}，启动类型: {:?}），重启电脑后生效",
+        account, start_mode
+    );
+    Ok(0)
+}
+
+/// `export --out <路径>`：把设置、实例清单与各实例的 frpc 配置导出为配置包（zip）
+fn cmd_export(args: &[String]) -> Result<i32> {
+    config::apply_instances_file_arg(args)?;
+    let out = match args.iter().position(|a| a == "--out") {
+        Some(pos) => args
+            .get(pos + 1)
+            .map(PathBuf::from)
+            .context("--out 缺少路径参数")?,
+        None => anyhow::bail!("用法: export --out <路径>"),
+    };
+    bundle::export(&out)?;
+    Ok(0)
+}
+
+/// `import <配置包> [--on-conflict overwrite|skip|backup] [--no-install]`：展开配置包并注册服务
+///
+/// 注册服务接受与 `install` 相同的 `--account`、`--start-type` 参数
+fn cmd_import(args: &[String]) -> Result<i32> {
+    config::apply_instances_file_arg(args)?;
+    let path = args
+        .first()
+        .filter(|a| !a.starts_with("--"))
+        .map(PathBuf::from)
+        .context("用法: import <配置包> [--on-conflict overwrite|skip|backup] [--no-install]")?;
+    let policy = match args.iter().position(|a| a == "--on-conflict") {
+        Some(pos) => ConflictPolicy::parse(
+            args.get(pos + 1)
+                .context("--on-conflict 缺少参数（overwrite/skip/backup）")?,
+        )?,
+        None => ConflictPolicy::Backup,
+    };
+    bundle::import(&path, policy)?;
+    if args.iter().any(|a| a == "--no-install") {
+        println!("已跳过注册服务（--no-install）");
+        return Ok(0);
+    }
+    install_service(args)
+}
+
+/// `uninstall`：注销 Windows 服务（需管理员权限），服务不存在时视为成功
+fn cmd_uninstall() -> Result<i32> {
+    if service::ensure_service_uninstalled()? {
+        println!("服务已注销");
+    } else {
+        println!("服务未注册，无需注销");
+    }
+    Ok(0)
+}
+
+/// `self-register [--frpc-exe <路径>] [--frpc-config <路径>]`：一步完成部署
+///
+/// 导入 frpc.exe 与配置文件（已在程序目录中时跳过）、注册服务并启动；
+/// 任一步失败时撤销已完成的步骤：删除导入的文件、注销本次注册的服务
+fn cmd_self_register(args: &[String]) -> Result<i32> {
+    config::apply_instances_file_arg(args)?;
+    let arg_value = |flag: &str| -> Result<Option<PathBuf>> {
+        match args.iter().position(|a| a == flag) {
+            Some(pos) => Ok(Some(
+                args.get(pos + 1)
+                    .map(PathBuf::from)
+                    .context(format!("{} 缺少路径参数", flag))?,
+            )),
+            None => Ok(None),
+        }
+    };
+    let frpc_exe = arg_value("--frpc-exe")?;
+    let frpc_config = arg_value("--frpc-config")?;
+
+    let mut rollback = SelfRegisterRollback::default();
+    match self_register(frpc_exe.as_deref(), frpc_config.as_deref(), &mut rollback) {
+        Ok(()) => {
+            rollback.discard();
+            println!("已注册并启动服务 {}", service::service_name());
+            Ok(0)
+        }
+        Err(e) => {
+            eprintln!("部署失败，正在撤销已完成的步骤");
+            rollback.run();
+            Err(e)
+        }
+    }
+}
+
+fn self_register(
+    frpc_exe: Option<&Path>,
+    frpc_config: Option<&Path>,
+    rollback: &mut SelfRegisterRollback,
+) -> Result<()> {
+    if let Some(src) = frpc_exe {
+        let dest = config::frpc_exe_path()?;
+        if same_file(src, &dest) {
+            println!("frpc.exe 已在程序目录中，跳过复制");
+        } else {
+            // 覆盖已有的 frpc.exe 前先备份，失败时还原
+            if dest.exists() {
+                let backup = dest.with_extension("exe.bak");
+                std::fs::copy(&dest, &backup).context("无法备份已有的 frpc.exe")?;
+                rollback.exe_backup = Some((dest.clone(), backup));
+            } else {
+                rollback.created_files.push(dest.clone());
+            }
+            config::import_frpc_exe(src)?;
+            println!("已复制 frpc.exe: {}", dest.display());
+        }
+    }
+    if !config::frpc_exe_path()?.exists() {
+        anyhow::bail!("bin/frpc.exe 不存在，请通过 --frpc-exe 指定");
+    }
+
+    if let Some(src) = frpc_config {
+        match existing_config_name(src) {
+            Some(name) => println!("配置文件已在程序目录中（{}），跳过导入", name),
+            None => {
+                let name = config::import_config_file(src)?;
+                rollback.imported_config = Some(name.clone());
+                println!("已导入配置 '{}'（自启动）", name);
+            }
+        }
+    }
+
+    service::install_service()?;
+    rollback.service_registered = true;
+    service::start_service()?;
+    Ok(())
+}
+
+/// 两个路径是否指向同一个文件（任一不存在时视为不同）
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// 若配置文件已是 conf/ 中某个配置的 toml，返回该配置名称
+fn existing_config_name(src: &Path) -> Option<String> {
+    config::load_configs()
+        .unwrap_or_default()
+        .into_iter()
+        .find(|c| {
+            config::config_toml_path(&c.name)
+                .map(|p| same_file(src, &p))
+                .unwrap_or(false)
+        })
+        .map(|c| c.name)
+}
+
+/// `self-register` 已完成、失败时需要撤销的步骤
+#[derive(Default)]
+struct SelfRegisterRollback {
+    /// 本次新建的文件
+    created_files: Vec<PathBuf>,
+    /// 被覆盖的文件及其备份
+    exe_backup: Option<(PathBuf, PathBuf)>,
+    /// 本次导入的配置名称
+    imported_config: Option<String>,
+    service_registered: bool,
+}
+
+impl SelfRegisterRollback {
+    /// 部署成功，删除备份文件
+    fn discard(self) {
+        if let Some((_, backup)) = self.exe_backup {
+            let _ = std::fs::remove_file(backup);
+        }
+    }
+
+    /// 按与执行相反的顺序撤销，单步失败只打印警告并继续
+    fn run(self) {
+        if self.service_registered {
+            match service::uninstall_service() {
+                Ok(()) => eprintln!("  已注销服务"),
+                Err(e) => eprintln!("  警告: 注销服务失败: {}", e),
+            }
+        }
+        if let Some(name) = self.imported_config {
+            match config::delete_config(&name) {
+                Ok(()) => eprintln!("  已删除导入的配置 '{}'", name),
+                Err(e) => eprintln!("  警告: 删除配置 '{}' 失败: {}", name, e),
+            }
+        }
+        if let Some((dest, backup)) = self.exe_backup {
+            match std::fs::rename(&backup, &dest) {
+                Ok(()) => eprintln!("  已还原 {}", dest.display()),
+                Err(e) => eprintln!("  警告: 还原 {} 失败: {}", dest.display(), e),
+            }
+        }
+        for path in self.created_files {
+            match std::fs::remove_file(&path) {
+                Ok(()) => eprintln!("  已删除 {}", path.display()),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => eprintln!("  警告: 删除 {} 失败: {}", path.display(), e),
+            }
+        }
+    }
+}
+
+/// `pause-monitor` / `resume-monitor`：暂停/恢复服务的进程监控，不影响正在运行的进程
+fn cmd_pause_monitor(pause: bool) -> Result<i32> {
+    if pause {
+        service::send_pipe_command("PAUSE_MONITOR")?;
+        println!("已暂停监控：进程继续运行，退出后不会被重启（logs/status.json 中 monitor_paused 为 true）");
+    } else {
+        service::send_pipe_command("RESUME_MONITOR")?;
+        println!("已恢复监控");
+    }
+    Ok(0)
+}
+
+/// `rotate-logs`：立即轮转日志
+///
+/// 服务正在运行时通过管道交给服务执行（服务持有日志句柄，需重新打开），否则直接重命名
+fn cmd_rotate_logs() -> Result<i32> {
+    if service::send_pipe_command("ROTATE_LOGS").is_ok() {
+        println!("已通知服务轮转日志，归档文件见 logs/ 目录");
+        return Ok(0);
+    }
+    let archived = logger::rotate_logs_now()?;
+    if archived.is_empty() {
+        println!("今天还没有日志文件，无需轮转");
+    }
+    for path in archived {
+        println!("已归档: {}", path.display());
+    }
+    Ok(0)
+}
+
+/// `export-metrics [--format prometheus|json]`：输出一次指标快照，便于推送式监控采集
+///
+/// 服务运行时通过管道获取实时指标；服务未运行时回退到 logs/status.json 中最后记录的内容
+fn cmd_export_metrics(args: &[String]) -> Result<i32> {
+    let format = match args.iter().position(|a| a == "--format") {
+        Some(pos) => args
+            .get(pos + 1)
+            .map(String::as_str)
+            .context("--format 缺少参数（prometheus/json）")?,
+        None => "prometheus",
+    };
+    if !matches!(format, "prometheus" | "json") {
+        anyhow::bail!("不支持的格式: {}（可选 prometheus/json）", format);
+    }
+    let metrics: ServiceMetrics = match service::query_pipe("GET_METRICS") {
+        Ok(response) => serde_json::from_str(&response).context("无法解析服务返回的指标")?,
+        Err(e) => {
+            let (metrics, updated_at) =
+                snapshot::load_metrics().context(format!("{:#}；也无法从状态快照读取指标", e))?;
+            eprintln!(
+                "服务未运行，指标来自 logs/status.json（更新于 {}），运行时长不可用",
+                updated_at
+            );
+            metrics
+        }
+    };
+    match format {
+        "json" => println!("{}", serde_json::to_string_pretty(&metrics)?),
+        _ => print!("{}", metrics::render(&metrics)),
+    }
+    Ok(0)
+}
+
+/// `health`：所有自启动实例都在运行且健康时退出码为 0，否则列出不健康的实例并以 1 退出
+///
+/// 通过命名管道向服务查询；服务未运行时同样以 1 退出。供脚本、计划任务等检查使用
+fn cmd_health() -> Result<i32> {
+    let metrics: ServiceMetrics = match service::query_pipe("GET_METRICS") {
+        Ok(response) => serde_json::from_str(&response).context("无法解析服务返回的状态")?,
+        Err(e) => {
+            println!("不健康: 服务未运行（{:#}）", e);
+            return Ok(1);
+        }
+    };
+    let problems = metrics::health_problems(&metrics);
+    if problems.is_empty() {
+        println!("健康: {} 个实例均正常", metrics.instances.len());
+        return Ok(0);
+    }
+    println!("不健康: {} 个实例", problems.len());
+    for problem in &problems {
+        println!("  {}", problem);
+    }
+    Ok(1)
+}
+
+/// `snapshot <配置名> [--output <路径>]`：为运行中的 frpc 写入内存转储（minidump）
+fn cmd_snapshot(args: &[String]) -> Result<i32> {
+    config::apply_instances_file_arg(args)?;
+    let name = args
+        .first()
+        .filter(|a| !a.starts_with("--"))
+        .context("用法: snapshot <配置名> [--output <路径>]")?;
+    let output = match args.iter().position(|a| a == "--output") {
+        Some(pos) => Some(
+            args.get(pos + 1)
+                .map(PathBuf::from)
+                .context("--output 缺少路径参数")?,
+        ),
+        None => None,
+    };
+    let path = minidump::run(name, output)?;
+    println!("已写入转储文件: {}", path.display());
+    Ok(0)
+}
+
+/// `profile <配置名> [--duration <秒>] [--output <路径>]`：对运行中的 frpc 采样调用栈，写入热点报告
+fn cmd_profile(args: &[String]) -> Result<i32> {
+    const USAGE: &str = "用法: profile <配置名> [--duration <秒>] [--output <路径>]";
+    config::apply_instances_file_arg(args)?;
+    let name = args
+        .first()
+        .filter(|a| !a.starts_with("--"))
+        .context(USAGE)?;
+    let duration = match args.iter().position(|a| a == "--duration") {
+        Some(pos) => {
+            let secs: u64 = args
+                .get(pos + 1)
+                .and_then(|v| v.parse().ok())
+                .filter(|secs| (1..=3600).contains(secs))
+                .context("--duration 需要 1-3600 之间的秒数")?;
+            Duration::from_secs(secs)
+        }
+        None => Duration::from_secs(30),
+    };
+    let output = match args.iter().position(|a| a == "--output") {
+        Some(pos) => Some(
+            args.get(pos + 1)
+                .map(PathBuf::from)
+                .context("--output 缺少路径参数")?,
+        ),
+        None => None,
+    };
+    println!("正在对 '{}' 采样 {} 秒……", name, duration.as_secs());
+    let path = profile::run(name, duration, output)?;
+    println!("已写入采样报告: {}", path.display());
+    Ok(0)
+}
+
+/// `watch-logs [<配置名>] [--level error|warn|info|debug]`：跟踪主日志或实例日志并按级别着色
+fn cmd_watch_logs(args: &[String]) -> Result<i32> {
+    config::apply_instances_file_arg(args)?;
+    let mut instance = None;
+    let mut level = log::LevelFilter::Trace;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--level" => {
+                level = watch_logs::parse_level(
+                    iter.next()
+                        .context("--level 缺少参数（error/warn/info/debug）")?,
+                )?;
+            }
+            config::INSTANCES_FILE_ARG => {
+                iter.next();
+            }
+            a if a.starts_with("--") => anyhow::bail!("未知参数: {}", a),
+            a => {
+                if instance.replace(a).is_some() {
+                    anyhow::bail!("用法: watch-logs [<配置名>] [--level error|warn|info|debug]");
+                }
+            }
+        }
+    }
+    watch_logs::run(instance, level)
+}
+
+/// `tail-errors [--since <时长>]`：按时间顺序输出近期（默认 24 小时内）所有日志中的 ERROR 与 WARN 行
+fn cmd_tail_errors(args: &[String]) -> Result<i32> {
+    let since = match args.iter().position(|a| a == "--since") {
+        Some(pos) => args
+            .get(pos + 1)
+            .map(String::as_str)
+            .context("--since 缺少参数（如 30m、1h、2d）")?,
+        None => "24h",
+    };
+    if tail_errors::run(tail_errors::parse_since(since)?)? == 0 {
+        eprintln!("最近 {} 内没有 ERROR 或 WARN 日志", since);
+    }
+    Ok(0)
+}
+
+/// `check-update`：查询 GitHub 上 frp 的最新版本并与已安装的 frpc 比较
+///
+/// 参数：`--timeout <秒>`（默认 30）
+fn cmd_check_update(args: &[String]) -> Result<i32> {
+    if config::load_settings().no_update_check {
+        println!("更新检查已禁用（settings.json 中 no_update_check 为 true）");
+        return Ok(0);
+    }
+    let timeout = match args.iter().position(|a| a == "--timeout") {
+        Some(pos) => args
+            .get(pos + 1)
+            .and_then(|v| v.parse::<u64>().ok())
+            .context("--timeout 需要一个整数秒数")?,
+        None => 30,
+    };
+    match download::check_update_with_timeout(std::time::Duration::from_secs(timeout))? {
+        None => println!("已是最新版本"),
+        Some(tag) => {
+            let installed = download::installed_frpc_version();
+            println!(
+                "有新版本可用: {}（当前: {}）— 下载地址: https://github.com/fatedier/frp/releases/tag/{}",
+                tag,
+                installed.as_deref().unwrap_or("未安装"),
+                tag
+            );
+        }
+    }
+    Ok(0)
+}
+
+/// `restart` / `start` / `stop --group <分组>`：通知服务对整组实例执行操作
+fn cmd_group(cmd: &str, args: &[String]) -> Result<i32> {
+    config::apply_instances_file_arg(args)?;
+    let group = args
+        .iter()
+        .position(|a| a == "--group")
+        .and_then(|pos| args.get(pos + 1))
+        .context(format!("用法: {} --group <分组>", cmd))?;
+    // 先在本地校验分组，未知分组时列出已有分组
+    let members = config::group_members(group)?;
+    let command = GroupCommand {
+        cmd: cmd.to_string(),
+        group: group.clone(),
+    };
+    service::send_pipe_command(&serde_json::to_string(&command)?)?;
+    println!(
+        "已通知服务 {} 分组 [{}]（{}），执行结果见服务日志",
+        cmd,
+        group,
+        members.join(", ")
+    );
+    Ok(0)
+}
+
+/// `set-run-as <配置名> --user <用户名>`：设置 frpc 运行身份，密码从标准输入读取一行
+///
+/// `set-run-as <配置名> --clear` 清除，恢复继承服务身份
+fn cmd_set_run_as(args: &[String]) -> Result<i32> {
+    config::apply_instances_file_arg(args)?;
+    let name = args
+        .first()
+        .filter(|a| !a.starts_with("--"))
+        .context("用法: set-run-as <配置名> --user <用户名> | --clear")?;
+    if args.iter().any(|a| a == "--clear") {
+        config::set_run_as(name, None)?;
+        println!("已清除 '{}' 的运行身份", name);
+        return Ok(0);
+    }
+    let user = args
+        .iter()
+        .position(|a| a == "--user")
+        .and_then(|pos| args.get(pos + 1))
+        .context("缺少 --user <用户名>")?;
+
+    eprint!("请输入 {} 的密码: ", user);
+    let mut password = read_password()?;
+    let protected = run_as::protect_password(password.trim_end_matches(['\r', '\n']));
+    // 明文密码用完即清零
+    unsafe { password.as_bytes_mut() }
+        .iter_mut()
+        .for_each(|b| *b = 0);
+    config::set_run_as(
+        name,
+        Some(config::RunAs {
+            user: user.clone(),
+            password_protected: protected?,
+        }),
+    )?;
+    println!(
+        "已设置 '{}' 以用户 {} 身份运行（密码已加密保存）",
+        name, user
+    );
+    Ok(0)
+}
+
+/// 从标准输入读取一行密码；标准输入是控制台时读取期间关闭回显，读取后恢复原来的控制台模式
+fn read_password() -> Result<String> {
+    let stdin = unsafe { GetStdHandle(STD_INPUT_HANDLE) };
+    let mut mode = 0;
+    // 输入被重定向（管道、文件）时不是控制台，直接读取
+    let console = unsafe { GetConsoleMode(stdin, &mut mode) } != 0;
+    if console {
+        unsafe { SetConsoleMode(stdin, mode & !ENABLE_ECHO_INPUT) };
+    }
+    let mut password = String::new();
+    let result = std::io::stdin().read_line(&mut password);
+    if console {
+        unsafe { SetConsoleMode(stdin, mode) };
+        // 回显关闭时回车不会换行
+        eprintln!();
+    }
+    result.context("无法读取密码")?;
+    Ok(password)
+}
+
+/// `set-env <配置名> <变量名> <值>`：设置注入实例的环境变量（如更新后的令牌），服务随即重启该实例使其生效
+///
+/// 只保存在服务内存中，服务重启后失效
+fn cmd_set_env(args: &[String]) -> Result<i32> {
+    config::apply_instances_file_arg(args)?;
+    // 值可能以 `--` 开头，只去掉全局参数 `--instances-file <路径>`，其余按位置读取
+    let mut positional = Vec::new();
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        if arg == config::INSTANCES_FILE_ARG {
+            rest.next();
+        } else {
+            positional.push(arg);
+        }
+    }
+    let [name, key, value, ..] = positional[..] else {
+        anyhow::bail!("用法: set-env <配置名> <变量名> <值>");
+    };
+    if !frpc_mg::is_valid_env_key(key) {
+        anyhow::bail!(
+            "无效的环境变量名 '{}'：必须为非空的 ASCII，且不含 '=' 与控制字符",
+            key
+        );
+    }
+    config::find_config(name).context(format!("配置 '{}' 不存在", name))?;
+    let command = SetEnvCommand {
+        instance: name.clone(),
+        key: key.clone(),
+        value: value.clone(),
+    };
+    service::send_pipe_command(&format!("SET_ENV:{}", serde_json::to_string(&command)?))?;
+    println!(
+        "已通知服务为 '{}' 设置环境变量 {}，实例正在运行时将重启使其生效，结果见服务日志",
+        name, key
+    );
+    Ok(0)
+}
+
+/// `debug-attach <配置名> [--debugger windbg|vsjit]`：启动新的 frpc 实例并附加调试器（仅 debug 构建）
+#[cfg(feature = "debug")]
+fn cmd_debug_attach(args: &[String]) -> Result<i32> {
+    config::apply_instances_file_arg(args)?;
+    let name = args
+        .first()
+        .filter(|a| !a.starts_with("--"))
+        .context("用法: debug-attach <配置名> [--debugger windbg|vsjit]")?;
+    let debugger = match args.iter().position(|a| a == "--debugger") {
+        Some(pos) => debug_attach::Debugger::parse(
+            args.get(pos + 1)
+                .context("--debugger 缺少参数（windbg/vsjit）")?,
+        )?,
+        None => debug_attach::Debugger::WinDbg,
+    };
+    debug_attach::run(name, debugger)
+}
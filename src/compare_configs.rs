@@ -0,0 +1,101 @@
+//! 比较两个实例的配置（`compare-configs`）：规范化为按键排序的 `键 = 值` 行后输出统一格式的差异
+//!
+//! 规范化使用与 `show-config` 相同的有效配置（渲染环境变量模板、遮蔽敏感值），因此只是格式、
+//! 键顺序或代理先后不同的配置没有差异。`[[proxies]]`、`[[visitors]]` 等表数组按其中的 `name` 标识元素，
+//! 没有 `name` 时按下标；目录模式下每个文件的键前加文件名
+
+use anyhow::{Context, Result};
+use similar::TextDiff;
+use std::path::PathBuf;
+
+use crate::config;
+
+/// 差异上下文的行数
+const CONTEXT_LINES: usize = 3;
+
+/// 输出两个实例配置的差异，配置相同时返回 false
+pub fn run(first: &str, second: &str) -> Result<bool> {
+    let (first_label, first_lines) = normalized_config(first)?;
+    let (second_label, second_lines) = normalized_config(second)?;
+    if first_lines == second_lines {
+        return Ok(false);
+    }
+    let old = lines_text(&first_lines);
+    let new = lines_text(&second_lines);
+    let diff = TextDiff::from_lines(&old, &new);
+    print!(
+        "{}",
+        diff.unified_diff()
+            .context_radius(CONTEXT_LINES)
+            .header(&first_label, &second_label)
+    );
+    Ok(true)
+}
+
+/// 实例的规范化配置：(差异标题, 排序后的 `键 = 值` 行)
+fn normalized_config(name: &str) -> Result<(String, Vec<String>)> {
+    let meta = config::find_config(name).context(format!("配置 '{}' 不存在", name))?;
+    let path = meta.config_path()?;
+    let files: Vec<(Option<String>, PathBuf)> = if meta.config_dir.is_some() {
+        config::config_dir_files(&path)?
+            .into_iter()
+            .map(|file| {
+                let label = file.file_name().map(|n| n.to_string_lossy().into_owned());
+                (label, file)
+            })
+            .collect()
+    } else {
+        vec![(None, path.clone())]
+    };
+
+    let mut lines = Vec::new();
+    for (label, file) in files {
+        let content = std::fs::read_to_string(&file)
+            .context(format!("无法读取配置文件: {}", file.display()))?;
+        let effective = config::effective_config(&content)
+            .context(format!("配置文件 {} 无效", file.display()))?;
+        for env in &effective.missing_envs {
+            eprintln!("警告: [{}] 环境变量 {} 未设置，模板保持原样", name, env);
+        }
+        let prefix = label.map(|l| format!("[{}] ", l)).unwrap_or_default();
+        let mut pairs = Vec::new();
+        flatten("", &effective.value, &mut pairs);
+        lines.extend(
+            pairs
+                .into_iter()
+                .map(|(key, value)| format!("{}{} = {}", prefix, key, value)),
+        );
+    }
+    lines.sort();
+    Ok((format!("{} ({})", name, path.display()), lines))
+}
+
+/// 展开为 (点分键, 值) 对；表数组的元素按 `name` 标识为 `proxies[ssh]`，没有 `name` 时为 `proxies[0]`
+fn flatten(prefix: &str, value: &toml::Value, out: &mut Vec<(String, String)>) {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, item) in table {
+                let key = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                flatten(&key, item, out);
+            }
+        }
+        toml::Value::Array(items) if !items.is_empty() && items.iter().all(|i| i.is_table()) => {
+            for (index, item) in items.iter().enumerate() {
+                let id = item
+                    .get("name")
+                    .and_then(|n| n.as_str())
+                    .map_or_else(|| index.to_string(), str::to_string);
+                flatten(&format!("{}[{}]", prefix, id), item, out);
+            }
+        }
+        other => out.push((prefix.to_string(), other.to_string())),
+    }
+}
+
+fn lines_text(lines: &[String]) -> String {
+    lines.iter().map(|l| format!("{}\n", l)).collect()
+}
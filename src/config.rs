@@ -1,9 +1,15 @@
 //! frpc 配置管理模块，负责在 conf/ 目录下管理多个 frpc 配置
 
 use anyhow::{Context, Result};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::policy;
 
 /// frpc 代理信息
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -14,7 +20,7 @@ pub struct FrpcProxyInfo {
 }
 
 /// 单个 frpc 配置的元数据
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct FrpcConfigMeta {
     /// 配置名称（也是文件名前缀）
     pub name: String,
@@ -27,6 +33,365 @@ pub struct FrpcConfigMeta {
     /// 代理列表
     #[serde(default)]
     pub proxies: Vec<FrpcProxyInfo>,
+    /// 启动探测超时（秒），0 表示不探测
+    ///
+    /// 启动/重启后在该时间内等待 "login to server success" / "start proxy success"
+    /// 输出，或（配置了 webServer 时）admin API 报告至少一个代理 running
+    #[serde(default)]
+    pub probe_timeout_secs: u64,
+    /// 启动探测超时后是否提前重启（仅进程守护开启时生效）
+    #[serde(default)]
+    pub probe_restart_on_timeout: bool,
+    /// 视为就绪的 frpc 输出行（正则），未设置时匹配 "login to server success" /
+    /// "start proxy success"；不同 frpc 版本或只关心某个代理时可单独指定
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ready_pattern: Option<String>,
+    /// 停止时等待 frpc 自行退出的时间（秒），超时后强制终止；未设置时为 5 秒，0 表示直接终止
+    ///
+    /// 持有长连接的实例可适当调大，让 frpc 有时间关闭连接
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub graceful_stop_timeout_secs: Option<u64>,
+    /// 依赖的实例名：服务启动时先启动这些实例，系统关机时本实例先于它们停止；不在自启动列表中的实例忽略
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub depends_on: Vec<String>,
+    /// 该实例日志目录（logs/<name>/）的保留策略，未设置时使用全局策略
+    #[serde(default)]
+    pub log_retention: Option<LogRetention>,
+    /// 该实例日志（logger `frpc::<name>`）的级别：off/error/warn/info/debug/trace，
+    /// 未设置时继承默认的 info；只影响该实例，排障时可单独调到 debug
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_level: Option<String>,
+    /// 只在这些主机上启用（短名或 FQDN，不区分大小写），为空时不限制；匹配规则见 `host_filter`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub only_on_hosts: Vec<String>,
+    /// 在这些主机上不启用，优先于 `only_on_hosts`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_hosts: Vec<String>,
+    /// 所属分组（如 "prod"、"lab"），用于按组批量启动/停止/重启
+    #[serde(default = "default_group")]
+    pub group: String,
+    /// 以指定用户身份运行 frpc，未设置时继承服务/程序自身的身份
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_as: Option<RunAs>,
+    /// 该实例使用的 frpc 可执行文件，未设置时使用 `bin/frpc.exe`；相对路径相对于 `bin/`
+    ///
+    /// 便于用不同版本的 frpc 运行不同隧道（新版测试、旧版兼容），多个实例可引用同一文件
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exe: Option<PathBuf>,
+    /// 目录模式：把整个目录交给单个 frpc 进程（`frpc --config_dir <目录>`，目录中每个文件作为一个 frpc 服务运行），
+    /// 不使用 `<name>.toml`；相对路径相对于实例清单所在目录
+    ///
+    /// 目录中的配置共用一个进程：任一文件修改后 `apply` 重启整个进程，启动探测要求每个文件都输出就绪行
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config_dir: Option<PathBuf>,
+    /// 滚动重启（`apply`、分组 restart）的方式，默认先停旧进程再启动新进程
+    #[serde(default)]
+    pub restart_strategy: RestartStrategy,
+    /// 崩溃/放弃重启时的告警策略（`alert_on`、`alert_channels`）
+    #[serde(default, flatten)]
+    pub alert: AlertPolicy,
+    /// 运行期间的健康检查（存活探测、资源占用、输出看门狗），未设置时不检查
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health_check: Option<HealthCheckConfig>,
+    /// frpc 输出规则：按顺序匹配每行输出，第一条命中的规则生效
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub on_output: Vec<OutputRule>,
+}
+
+/// 未配置时停止 frpc 的宽限时间
+pub const DEFAULT_GRACEFUL_STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+impl FrpcConfigMeta {
+    /// 编译就绪规则，未设置时返回 None（使用默认的就绪输出）
+    pub fn ready_regex(&self) -> Result<Option<Regex>> {
+        self.ready_pattern
+            .as_deref()
+            .map(|pattern| {
+                Regex::new(pattern).context(format!(
+                    "[{}] ready_pattern 不是有效的正则表达式: {}",
+                    self.name, pattern
+                ))
+            })
+            .transpose()
+    }
+
+    /// 编译输出规则（`on_output`），任一正则无效时返回错误
+    pub fn output_rules(&self) -> Result<Vec<(Regex, OutputAction)>> {
+        self.on_output
+            .iter()
+            .map(|rule| {
+                let regex = Regex::new(&rule.pattern).context(format!(
+                    "[{}] on_output 中的 pattern 不是有效的正则表达式: {}",
+                    self.name, rule.pattern
+                ))?;
+                Ok((regex, rule.action))
+            })
+            .collect()
+    }
+
+    /// 解析实例日志级别，未设置时返回 None（继承默认级别）
+    pub fn log_level_filter(&self) -> Result<Option<log::LevelFilter>> {
+        self.log_level
+            .as_deref()
+            .map(|level| {
+                level.parse().map_err(|_| {
+                    anyhow::anyhow!(
+                        "[{}] log_level 无效: {}（可选 off/error/warn/info/debug/trace）",
+                        self.name,
+                        level
+                    )
+                })
+            })
+            .transpose()
+    }
+
+    /// 停止该实例时的宽限时间
+    pub fn graceful_stop_timeout(&self) -> Duration {
+        self.graceful_stop_timeout_secs
+            .map_or(DEFAULT_GRACEFUL_STOP_TIMEOUT, Duration::from_secs)
+    }
+
+    /// 该实例使用的 frpc 可执行文件路径
+    pub fn exe_path(&self) -> Result<PathBuf> {
+        match &self.exe {
+            Some(exe) if exe.is_absolute() => Ok(exe.clone()),
+            Some(exe) => Ok(bin_dir()?.join(exe)),
+            None => frpc_exe_path(),
+        }
+    }
+
+    /// 传给 frpc 的配置路径：目录模式下为配置目录，否则为 `<name>.toml`
+    pub fn config_path(&self) -> Result<PathBuf> {
+        match &self.config_dir {
+            Some(dir) if dir.is_absolute() => Ok(dir.clone()),
+            Some(dir) => Ok(instances_dir()?.join(dir)),
+            None => config_toml_path(&self.name),
+        }
+    }
+}
+
+/// frpc 子进程的运行身份
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunAs {
+    /// 用户名：`user`（本地账户）、`DOMAIN\user` 或 `user@domain`
+    pub user: String,
+    /// DPAPI（本机范围）加密后的密码（十六进制），由 `frpdesk set-run-as` 写入，不保存明文
+    pub password_protected: String,
+}
+
+/// 滚动重启实例的方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RestartStrategy {
+    /// 先停止旧进程，再启动新进程
+    #[default]
+    StopFirst,
+    /// 先启动新进程，就绪后再停止旧进程；新进程未就绪时停止新进程，旧进程继续运行
+    ///
+    /// 新旧进程会短暂同时运行，frpc 配置需允许这一点（如服务端允许同名代理重连、admin API 不固定端口）
+    BlueGreen,
+}
+
+/// 输出规则命中后的动作
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputAction {
+    /// 按实例告警策略的通道告警（不受 `alert_on` 限制）
+    Alert,
+    /// 滚动重启实例
+    Restart,
+    /// 停止实例并加入手动停止列表，进程守护不再重启；同时告警
+    Stop,
+    /// 不执行动作，也不再匹配后续规则（用于排除更宽泛的规则会命中的行）
+    Ignore,
+}
+
+/// 实例输出规则：frpc 输出行（已去除 ANSI）匹配 `pattern` 时执行 `action`，同一进程每条规则只触发一次
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutputRule {
+    pub pattern: String,
+    pub action: OutputAction,
+}
+
+/// 单个实例运行期间的健康检查；各项标准未设置时不检查该项
+///
+/// 启动探测（`probe_timeout_secs`）只确认启动后隧道建立，健康检查在启动探测通过后按间隔持续进行
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HealthCheckConfig {
+    /// 两次检查之间的间隔（秒）
+    #[serde(default = "default_health_check_interval_secs")]
+    pub interval_secs: u64,
+    /// 存活探测：轮询 admin API（webServer），没有任何代理为 running 时视为不健康；未配置 webServer 时忽略
+    #[serde(default)]
+    pub liveness_probe: bool,
+    /// 内存（工作集）上限（MB），超过视为不健康
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_memory_mb: Option<u64>,
+    /// CPU 占用上限（占全部核心的百分比，两次检查之间的平均值），超过视为降级，只记录不重启
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_cpu_percent: Option<f64>,
+    /// 看门狗：超过该时间（秒）输出行数没有增长视为卡死（不健康）；frpc 空闲时输出很少，需配合 frpc 配置中的 `log.level` 使用
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub watchdog_timeout_secs: Option<u64>,
+    /// 连续多少次检查不健康后终止进程，由进程守护重启
+    #[serde(default = "default_unhealthy_threshold")]
+    pub unhealthy_threshold: u32,
+}
+
+fn default_health_check_interval_secs() -> u64 {
+    30
+}
+
+fn default_unhealthy_threshold() -> u32 {
+    3
+}
+
+impl Default for HealthCheckConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: default_health_check_interval_secs(),
+            liveness_probe: false,
+            max_memory_mb: None,
+            max_cpu_percent: None,
+            watchdog_timeout_secs: None,
+            unhealthy_threshold: default_unhealthy_threshold(),
+        }
+    }
+}
+
+/// 未指定分组时的默认分组名
+pub const DEFAULT_GROUP: &str = "default";
+
+fn default_group() -> String {
+    DEFAULT_GROUP.to_string()
+}
+
+/// 日志保留策略，作用于单个日志目录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogRetention {
+    /// 保留天数（按文件名中的日期判断），0 表示不按天数清理
+    #[serde(default = "default_retention_days")]
+    pub max_days: u32,
+    /// 最多保留的文件数，0 表示不限制
+    #[serde(default)]
+    pub max_files: usize,
+    /// 目录内日志总大小上限（MB），0 表示不限制
+    #[serde(default)]
+    pub max_total_mb: u64,
+}
+
+fn default_retention_days() -> u32 {
+    30
+}
+
+impl Default for LogRetention {
+    fn default() -> Self {
+        Self {
+            max_days: default_retention_days(),
+            max_files: 0,
+            max_total_mb: 0,
+        }
+    }
+}
+
+/// 日志采样与单行长度限制，写入日志文件前应用；WARN 与 ERROR 始终全量记录
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogSampling {
+    /// 单条消息的最大字节数，超出部分截断并注明原长度，0（默认）表示不限制
+    #[serde(default)]
+    pub max_line_bytes: usize,
+    /// 采样规则，按顺序匹配，第一条匹配的规则生效
+    #[serde(default)]
+    pub rules: Vec<LogSampleRule>,
+}
+
+/// 一条采样规则：`target` 前缀与级别都匹配的日志每 `one_in` 条只记录 1 条
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogSampleRule {
+    /// 日志 target 前缀（如 `frpc::`、`frpc::web`），未设置时匹配所有 target
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    /// 匹配的级别：info/debug/trace；warn 与 error 不采样
+    pub level: String,
+    /// 每多少条记录 1 条（记录第 1、N+1、2N+1… 条），1 表示全部记录
+    pub one_in: u32,
+}
+
+impl LogSampleRule {
+    /// 解析匹配的级别，warn/error 或无效的级别返回错误
+    pub fn level(&self) -> Result<log::Level> {
+        let level: log::Level = self.level.parse().map_err(|_| {
+            anyhow::anyhow!(
+                "log_sampling 规则的 level 无效: {}（可选 info/debug/trace）",
+                self.level
+            )
+        })?;
+        anyhow::ensure!(
+            level > log::Level::Warn,
+            "log_sampling 规则不能用于 {}：warn 与 error 始终全量记录",
+            self.level
+        );
+        anyhow::ensure!(self.one_in > 0, "log_sampling 规则的 one_in 必须大于 0");
+        Ok(level)
+    }
+}
+
+/// 日志文件的行格式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// `时间 [级别] [实例] 消息 key=value`
+    #[default]
+    Text,
+    /// 每行一个 JSON 对象，便于日志采集工具解析
+    Json,
+}
+
+/// 何时触发告警
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertOn {
+    /// 仅在进程守护放弃重启时
+    #[default]
+    GiveUp,
+    /// 每次崩溃退出时（含放弃重启）
+    EachCrash,
+    /// 放弃重启，或连续崩溃达到阈值（启动探测一直未通过）时
+    CriticalOnly,
+}
+
+/// 告警通道
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertChannel {
+    /// 写入程序日志
+    Log,
+    /// 写入 Windows 事件日志
+    Eventlog,
+    /// POST 到 settings.json 中的 `alert_webhook_url`
+    Webhook,
+}
+
+/// 单个实例的告警策略
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AlertPolicy {
+    #[serde(default)]
+    pub alert_on: AlertOn,
+    #[serde(default = "default_alert_channels")]
+    pub alert_channels: Vec<AlertChannel>,
+}
+
+fn default_alert_channels() -> Vec<AlertChannel> {
+    vec![AlertChannel::Log]
+}
+
+impl Default for AlertPolicy {
+    fn default() -> Self {
+        Self {
+            alert_on: AlertOn::default(),
+            alert_channels: default_alert_channels(),
+        }
+    }
 }
 
 /// 所有配置的元数据集合
@@ -35,74 +400,675 @@ pub struct ConfigStore {
     pub configs: Vec<FrpcConfigMeta>,
 }
 
+/// settings.json 的当前格式版本
+///
+/// 没有 `schema_version` 字段的旧文件视为版本 1；调整字段含义或重命名键时递增，
+/// 并在 [`SETTINGS_MIGRATIONS`] 末尾追加对应的迁移步骤
+pub const SETTINGS_SCHEMA_VERSION: u32 = 2;
+
+/// 设置迁移步骤：第 i 项把版本 i+1 的内容升级到版本 i+2
+const SETTINGS_MIGRATIONS: &[fn(&mut serde_json::Map<String, serde_json::Value>)] =
+    &[migrate_settings_v1_to_v2];
+
 /// 全局应用设置
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AppSettings {
+    /// 文件格式版本，加载时据此迁移旧格式
+    #[serde(default = "current_settings_schema_version")]
+    pub schema_version: u32,
     /// 进程守护：开启后服务模式下进程异常退出会自动重启
     #[serde(default)]
     pub process_guard: bool,
+    /// 主日志目录（logs/）的保留策略
+    #[serde(default)]
+    pub log_retention: LogRetention,
+    /// 日志文件的行格式，修改后重启程序生效
+    #[serde(default)]
+    pub log_format: LogFormat,
+    /// 日志采样（按 target 与级别只记录 1/N）与单行最大长度，修改后重启程序生效
+    #[serde(default)]
+    pub log_sampling: LogSampling,
+    /// 服务自身日志（根 logger）的级别（off/error/warn/info/debug/trace），默认 info，修改后重启程序生效；
+    /// 未设置 `frpc_log_level` 时同时作用于 frpc 输出
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_level: Option<String>,
+    /// frpc 输出（logger `frpc`）的级别，默认与 `log_level` 相同，修改后重启程序生效；
+    /// 单独设置了 `log_level` 的实例以实例的为准
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frpc_log_level: Option<String>,
+    /// 把日志同时以 RFC 5424 格式通过 UDP 发送到 syslog 服务器，未设置时不发送；修改后重启程序生效
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub syslog: Option<SyslogSettings>,
+    /// 进程守护每轮检查发生 panic 时记录日志并继续下一轮，而不是让服务崩溃
+    #[serde(default = "default_true")]
+    pub monitor_panic_recovery: bool,
+    /// 禁用 `check-update` 命令的在线版本检查
+    #[serde(default)]
+    pub no_update_check: bool,
+    /// 多个实例引用同一配置文件时，只保留第一个，其余跳过（默认仅警告）
+    #[serde(default)]
+    pub strict_unique_configs: bool,
+    /// 告警通道包含 webhook 的实例，告警以 JSON POST 到该地址
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alert_webhook_url: Option<String>,
+    /// frpc 输出行序号（日志中的 `seq` 字段）在实例重启后继续递增，默认每次启动从 1 开始
+    #[serde(default)]
+    pub output_seq_across_restarts: bool,
+    /// 交互模式下实例掉线/恢复时推送 Windows 系统通知（Toast）
+    #[serde(default = "default_true")]
+    pub toast_notifications: bool,
+    /// admin API 聚合代理，未设置时不启动
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub admin_proxy: Option<AdminProxySettings>,
+    /// Prometheus 指标导出（`/metrics`），未设置时不启动
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<MetricsSettings>,
+    /// 日志所在磁盘的剩余空间检查
+    #[serde(default)]
+    pub disk_guard: DiskGuardSettings,
+    /// 维护时间窗口（本地时间）：窗口内实例退出只记录，不重启、不告警
+    #[serde(default)]
+    pub maintenance_windows: Vec<MaintenanceWindow>,
+    /// 全局熔断：短时间内所有实例的崩溃总数超过阈值时暂停全部重启，未设置时不启用
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub circuit_breaker: Option<CircuitBreakerSettings>,
+    /// 注册服务前检测的杀毒软件服务名，未设置时使用内置列表
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub antivirus_services: Option<Vec<String>>,
+    /// 检查运行中实例的程序与配置文件是否仍存在的间隔（秒），0 表示不检查
+    #[serde(default = "default_path_check_interval_secs")]
+    pub path_check_interval_secs: u64,
+    /// 服务启动时至少需要成功启动（含按 PID 接管）的实例数，不足时服务以失败退出，由 SCM 恢复操作处理；
+    /// 默认 0：无论是否有实例启动成功都继续运行（没有实例启动时只记录警告）。自启动实例少于该值时按实例数计算
+    #[serde(default)]
+    pub min_successful_instances: usize,
+    /// 系统关机时正常停止 frpc 的耗时达到预关机时限的该百分比仍未完成时，记录一次诊断（尚未退出的实例、
+    /// PID 与最近的输出），0 表示不记录；取值 0–100，默认 80
+    #[serde(default = "default_stop_diagnostics_percent")]
+    pub stop_diagnostics_percent: u8,
+}
+
+/// 全局熔断设置
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CircuitBreakerSettings {
+    /// `window_secs` 内所有实例的崩溃总数达到该值时熔断
+    #[serde(default = "default_breaker_max_crashes")]
+    pub max_crashes: u32,
+    /// 统计崩溃次数的时间窗口（秒）
+    #[serde(default = "default_breaker_window")]
+    pub window_secs: u64,
+    /// 熔断后至少暂停重启的时间（秒）
+    #[serde(default = "default_breaker_open")]
+    pub open_secs: u64,
+    /// 暂停结束后探测 frps 的间隔（秒），探测成功即恢复
+    #[serde(default = "default_breaker_probe_interval")]
+    pub probe_interval_secs: u64,
+}
+
+fn default_breaker_max_crashes() -> u32 {
+    10
+}
+
+fn default_breaker_window() -> u64 {
+    60
+}
+
+fn default_breaker_open() -> u64 {
+    300
+}
+
+fn default_breaker_probe_interval() -> u64 {
+    30
+}
+
+impl Default for CircuitBreakerSettings {
+    fn default() -> Self {
+        Self {
+            max_crashes: default_breaker_max_crashes(),
+            window_secs: default_breaker_window(),
+            open_secs: default_breaker_open(),
+            probe_interval_secs: default_breaker_probe_interval(),
+        }
+    }
+}
+
+/// 每天重复的维护时间窗口，`start`、`end` 为本地时间 `HH:MM`；`end` 早于 `start` 时跨越午夜
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MaintenanceWindow {
+    pub start: String,
+    pub end: String,
+}
+
+impl MaintenanceWindow {
+    /// 解析起止时间
+    pub fn bounds(&self) -> Result<(chrono::NaiveTime, chrono::NaiveTime)> {
+        let parse = |value: &str| {
+            chrono::NaiveTime::parse_from_str(value.trim(), "%H:%M").map_err(|_| {
+                anyhow::anyhow!("维护窗口时间无效: {}（格式为 HH:MM，如 02:00）", value)
+            })
+        };
+        let (start, end) = (parse(&self.start)?, parse(&self.end)?);
+        anyhow::ensure!(start != end, "维护窗口 {} 的起止时间相同", self);
+        Ok((start, end))
+    }
+
+    /// `time` 是否在窗口内（含起点、不含终点）；时间无效的窗口视为不包含任何时间
+    pub fn contains(&self, time: chrono::NaiveTime) -> bool {
+        match self.bounds() {
+            Ok((start, end)) if start < end => start <= time && time < end,
+            // 跨越午夜，如 23:30-00:30
+            Ok((start, end)) => time >= start || time < end,
+            Err(_) => false,
+        }
+    }
+}
+
+impl std::fmt::Display for MaintenanceWindow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)
+    }
+}
+
+/// `time` 所在的维护窗口（有多个时取第一个）
+pub fn active_maintenance_window(
+    windows: &[MaintenanceWindow],
+    time: chrono::NaiveTime,
+) -> Option<&MaintenanceWindow> {
+    windows.iter().find(|window| window.contains(time))
+}
+
+/// 日志磁盘空间检查设置（仅服务模式）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiskGuardSettings {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// 剩余空间低于该值（MB）时只保留每个日志目录中最新的文件并告警
+    #[serde(default = "default_disk_low_mb")]
+    pub low_free_mb: u64,
+    /// 剩余空间低于该值（MB）时暂停写入文件日志，只写事件日志与告警
+    #[serde(default = "default_disk_critical_mb")]
+    pub critical_free_mb: u64,
+    /// 检查间隔（秒）
+    #[serde(default = "default_disk_check_interval")]
+    pub check_interval_secs: u64,
+}
+
+fn default_disk_low_mb() -> u64 {
+    1024
+}
+
+fn default_disk_critical_mb() -> u64 {
+    200
+}
+
+fn default_disk_check_interval() -> u64 {
+    60
+}
+
+impl Default for DiskGuardSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            low_free_mb: default_disk_low_mb(),
+            critical_free_mb: default_disk_critical_mb(),
+            check_interval_secs: default_disk_check_interval(),
+        }
+    }
+}
+
+/// admin API 聚合代理设置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdminProxySettings {
+    /// 监听地址，默认 127.0.0.1:7499（仅本机）
+    #[serde(default = "default_admin_proxy_listen")]
+    pub listen: String,
+    /// 访问令牌，请求需携带 `Authorization: Bearer <token>`；监听非本机地址时必须设置
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+fn default_admin_proxy_listen() -> String {
+    "127.0.0.1:7499".to_string()
+}
+
+/// 远程 syslog 设置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyslogSettings {
+    /// syslog 服务器地址（IP:端口，如 `10.0.0.5:514`）
+    pub server: String,
+    /// facility 编号（0–23），默认 16（local0）
+    #[serde(default = "default_syslog_facility")]
+    pub facility: u8,
+    /// 消息中的主机名，未设置时使用本机计算机名
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+}
+
+fn default_syslog_facility() -> u8 {
+    16
+}
+
+/// Prometheus 指标导出设置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetricsSettings {
+    /// 是否启用，便于保留配置临时关闭
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// 监听地址，默认 127.0.0.1:9464（仅本机）
+    #[serde(default = "default_metrics_listen")]
+    pub listen: String,
+}
+
+fn default_metrics_listen() -> String {
+    "127.0.0.1:9464".to_string()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_path_check_interval_secs() -> u64 {
+    60
+}
+
+fn default_stop_diagnostics_percent() -> u8 {
+    80
+}
+
+fn current_settings_schema_version() -> u32 {
+    SETTINGS_SCHEMA_VERSION
 }
 
 impl Default for AppSettings {
     fn default() -> Self {
         Self {
+            schema_version: SETTINGS_SCHEMA_VERSION,
             process_guard: false,
+            log_retention: LogRetention::default(),
+            log_format: LogFormat::default(),
+            log_sampling: LogSampling::default(),
+            log_level: None,
+            frpc_log_level: None,
+            syslog: None,
+            monitor_panic_recovery: true,
+            no_update_check: false,
+            strict_unique_configs: false,
+            alert_webhook_url: None,
+            output_seq_across_restarts: false,
+            toast_notifications: true,
+            admin_proxy: None,
+            metrics: None,
+            disk_guard: DiskGuardSettings::default(),
+            maintenance_windows: Vec::new(),
+            circuit_breaker: None,
+            antivirus_services: None,
+            path_check_interval_secs: default_path_check_interval_secs(),
+            min_successful_instances: 0,
+            stop_diagnostics_percent: default_stop_diagnostics_percent(),
         }
     }
 }
 
-/// 获取程序目录下的 conf/ 目录路径
-pub fn conf_dir() -> Result<PathBuf> {
+impl AppSettings {
+    /// 解析服务自身的日志级别，未设置时返回 None（使用 INFO）
+    pub fn log_level_filter(&self) -> Result<Option<log::LevelFilter>> {
+        parse_level_filter("log_level", self.log_level.as_deref())
+    }
+
+    /// 解析 frpc 输出的日志级别，未设置时返回 None（与服务自身的级别相同）
+    pub fn frpc_log_level_filter(&self) -> Result<Option<log::LevelFilter>> {
+        parse_level_filter("frpc_log_level", self.frpc_log_level.as_deref())
+    }
+}
+
+fn parse_level_filter(key: &str, level: Option<&str>) -> Result<Option<log::LevelFilter>> {
+    level
+        .map(|level| {
+            level.parse().map_err(|_| {
+                anyhow::anyhow!(
+                    "{} 无效: {}（可选 off/error/warn/info/debug/trace）",
+                    key,
+                    level
+                )
+            })
+        })
+        .transpose()
+}
+
+/// 环境变量：数据根目录，设置后 conf/、logs/ 等数据从该目录读写，而不是程序目录
+///
+/// 程序本身与 bin/ 下的 frpc.exe 仍位于程序目录，便于安装包把程序与数据分开存放
+pub const CONFIG_DIR_ENV: &str = "FRPC_SERVICE_CONFIG_DIR";
+
+/// 环境变量 `FRPC_SERVICE_CONFIG_DIR` 的值（未设置或为空时为 None）
+pub fn config_dir_override() -> Option<PathBuf> {
+    std::env::var_os(CONFIG_DIR_ENV)
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)
+}
+
+/// 程序所在目录
+pub fn exe_dir() -> Result<PathBuf> {
     let exe_path = std::env::current_exe().context("无法获取可执行文件路径")?;
     let exe_dir = exe_path.parent().context("无法获取可执行文件目录")?;
-    Ok(exe_dir.join("conf"))
+    Ok(exe_dir.to_path_buf())
+}
+
+/// 注册表 `HKLM\SOFTWARE\FrpcService` 中 `ConfigDir` 的值（未设置或为空时为 None）
+pub fn policy_config_dir() -> Option<PathBuf> {
+    policy::string_value(&policy::MachinePolicy, policy::CONFIG_DIR_VALUE).map(PathBuf::from)
+}
+
+/// 数据根目录：依次取 `FRPC_SERVICE_CONFIG_DIR` 与注册表 `ConfigDir` 指定的目录（必须为绝对路径），
+/// 都未设置时为程序目录
+pub fn data_dir() -> Result<PathBuf> {
+    if let Some(dir) = config_dir_override() {
+        anyhow::ensure!(
+            dir.is_absolute(),
+            "环境变量 {} 必须为绝对路径: {}",
+            CONFIG_DIR_ENV,
+            dir.display()
+        );
+        return Ok(dir);
+    }
+    if let Some(dir) = policy_config_dir() {
+        anyhow::ensure!(
+            dir.is_absolute(),
+            "注册表值 HKLM\\{}\\{} 必须为绝对路径: {}",
+            policy::POLICY_KEY,
+            policy::CONFIG_DIR_VALUE,
+            dir.display()
+        );
+        return Ok(dir);
+    }
+    exe_dir()
+}
+
+/// 获取数据根目录下的 conf/ 目录路径
+pub fn conf_dir() -> Result<PathBuf> {
+    Ok(data_dir()?.join("conf"))
 }
 
 /// 获取程序目录下的 bin/ 目录路径
 pub fn bin_dir() -> Result<PathBuf> {
-    let exe_path = std::env::current_exe().context("无法获取可执行文件路径")?;
-    let exe_dir = exe_path.parent().context("无法获取可执行文件目录")?;
-    Ok(exe_dir.join("bin"))
+    Ok(exe_dir()?.join("bin"))
+}
+
+/// 获取数据根目录下的 logs/ 目录路径
+pub fn logs_dir() -> Result<PathBuf> {
+    Ok(data_dir()?.join("logs"))
+}
+
+/// 获取数据根目录下的 control/ 目录路径（信号文件）
+pub fn control_dir() -> Result<PathBuf> {
+    Ok(data_dir()?.join("control"))
+}
+
+/// 命令行参数 `--instances-file <路径>`：使用程序目录之外的实例清单（metadata.json）
+pub const INSTANCES_FILE_ARG: &str = "--instances-file";
+
+/// 通过 `--instances-file` 指定的实例清单路径，进程启动时设置一次
+static INSTANCES_FILE: OnceLock<PathBuf> = OnceLock::new();
+
+/// 从命令行参数中读取 `--instances-file <路径>` 并生效
+///
+/// 路径必须为绝对路径：服务由 SCM 启动时工作目录为 System32，相对路径容易指向错误位置
+pub fn apply_instances_file_arg(args: &[String]) -> Result<()> {
+    let Some(pos) = args.iter().position(|a| a == INSTANCES_FILE_ARG) else {
+        return Ok(());
+    };
+    let path = args
+        .get(pos + 1)
+        .map(PathBuf::from)
+        .context(format!("{} 缺少路径参数", INSTANCES_FILE_ARG))?;
+    if !path.is_absolute() {
+        anyhow::bail!("{} 必须为绝对路径: {}", INSTANCES_FILE_ARG, path.display());
+    }
+    INSTANCES_FILE
+        .set(path)
+        .map_err(|_| anyhow::anyhow!("{} 只能设置一次", INSTANCES_FILE_ARG))
 }
 
-/// 元数据文件路径: conf/metadata.json
-fn metadata_path() -> Result<PathBuf> {
-    Ok(conf_dir()?.join("metadata.json"))
+/// 通过 `--instances-file` 指定的实例清单路径，未指定时为 None
+pub fn instances_file() -> Option<&'static PathBuf> {
+    INSTANCES_FILE.get()
+}
+
+/// 实例配置所在目录：默认为 conf/，指定 `--instances-file` 时为清单文件所在目录
+pub fn instances_dir() -> Result<PathBuf> {
+    match instances_file().and_then(|p| p.parent()) {
+        Some(dir) => Ok(dir.to_path_buf()),
+        None => conf_dir(),
+    }
+}
+
+/// 元数据文件路径: conf/metadata.json，或 `--instances-file` 指定的路径
+pub(crate) fn metadata_path() -> Result<PathBuf> {
+    match instances_file() {
+        Some(path) => Ok(path.clone()),
+        None => Ok(conf_dir()?.join("metadata.json")),
+    }
 }
 
 /// 全局设置文件路径: conf/settings.json
-fn settings_path() -> Result<PathBuf> {
+pub(crate) fn settings_path() -> Result<PathBuf> {
     Ok(conf_dir()?.join("settings.json"))
 }
 
-/// 加载全局设置
+/// 加载全局设置（旧格式在内存中迁移），并叠加注册表策略
+///
+/// 文件无法解析或来自更新版本的程序时记录错误并使用默认设置；
+/// 需要明确失败的场景（服务启动）使用 [`upgrade_settings_file`]
 pub fn load_settings() -> AppSettings {
     let path = match settings_path() {
         Ok(p) => p,
-        Err(_) => return AppSettings::default(),
+        Err(_) => return with_policy(empty_settings()).unwrap_or_default(),
     };
     if !path.exists() {
-        return AppSettings::default();
+        return with_policy(empty_settings()).unwrap_or_default();
+    }
+    match read_settings(&path) {
+        Ok((settings, _)) => settings,
+        Err(e) => {
+            log::error!("{:#}，使用默认设置", e);
+            AppSettings::default()
+        }
+    }
+}
+
+/// 加载全局设置，旧格式迁移后回写 settings.json（回写失败只记录警告）
+///
+/// settings.json 的 `schema_version` 高于本程序支持的版本时返回错误
+pub fn upgrade_settings_file() -> Result<AppSettings> {
+    let path = settings_path()?;
+    if !path.exists() {
+        return with_policy(empty_settings());
+    }
+    let (value, from_version) = read_settings_value(&path)?;
+    if from_version < SETTINGS_SCHEMA_VERSION {
+        // 只回写文件本身的内容，注册表中的设置不写入文件
+        let upgraded = serde_json::from_value::<AppSettings>(value.clone())
+            .context("settings.json 格式错误")
+            .and_then(|settings| save_settings(&settings));
+        match upgraded {
+            Ok(()) => log::info!(
+                "settings.json 已从版本 {} 升级到版本 {}",
+                from_version,
+                SETTINGS_SCHEMA_VERSION
+            ),
+            Err(e) => log::warn!("回写升级后的 settings.json 失败: {:#}", e),
+        }
+    }
+    with_policy(value)
+}
+
+/// 校验 settings.json 能否被本程序加载（只在内存中迁移，不回写）
+pub fn validate_settings_file() -> Result<AppSettings> {
+    let path = settings_path()?;
+    if !path.exists() {
+        return with_policy(empty_settings());
+    }
+    read_settings(&path).map(|(settings, _)| settings)
+}
+
+/// 读取并迁移 settings.json，叠加注册表策略，返回设置与文件原来的版本
+fn read_settings(path: &Path) -> Result<(AppSettings, u32)> {
+    let (value, from_version) = read_settings_value(path)?;
+    Ok((with_policy(value)?, from_version))
+}
+
+/// 读取并迁移 settings.json，返回迁移后的内容与文件原来的版本
+fn read_settings_value(path: &Path) -> Result<(serde_json::Value, u32)> {
+    let content = fs::read_to_string(path).context("无法读取 settings.json")?;
+    let mut value: serde_json::Value =
+        serde_json::from_str(&content).context("settings.json 不是有效的 JSON")?;
+    let from_version = migrate_settings(&mut value)?;
+    Ok((value, from_version))
+}
+
+/// 没有 settings.json 时的内容
+fn empty_settings() -> serde_json::Value {
+    serde_json::Value::Object(serde_json::Map::new())
+}
+
+/// 在 settings.json 的内容上叠加注册表 `HKLM\SOFTWARE\FrpcService` 中的设置
+///
+/// 默认 settings.json 中已有的项优先，注册表只补充文件中没有的项；注册表值
+/// `PolicyOverridesSettings` 非 0 时反过来由注册表覆盖文件。注册表中的值导致
+/// 解析失败（如 `log_format` 取值无效）时记录警告并忽略注册表
+fn with_policy(file: serde_json::Value) -> Result<AppSettings> {
+    with_policy_from(&policy::MachinePolicy, file)
+}
+
+/// [`with_policy`]，策略值取自 `source`
+fn with_policy_from(
+    source: &dyn policy::PolicySource,
+    file: serde_json::Value,
+) -> Result<AppSettings> {
+    let layer = policy::settings_layer(source);
+    if layer.is_empty() {
+        return serde_json::from_value(file).context("settings.json 格式错误");
+    }
+    let overrides = policy::overrides_settings(source);
+    let mut merged = file.clone();
+    if let Some(obj) = merged.as_object_mut() {
+        for (key, value) in layer {
+            if overrides {
+                obj.insert(key, value);
+            } else {
+                obj.entry(key).or_insert(value);
+            }
+        }
+    }
+    match serde_json::from_value(merged) {
+        Ok(settings) => Ok(settings),
+        Err(e) => {
+            policy::warn_once(
+                "settings",
+                format!(
+                    "注册表 HKLM\\{} 中的设置无效（{}），已忽略",
+                    policy::POLICY_KEY,
+                    e
+                ),
+            );
+            serde_json::from_value(file).context("settings.json 格式错误")
+        }
+    }
+}
+
+/// 文件中的格式版本，没有 `schema_version` 字段时为 1
+fn settings_schema_version(obj: &serde_json::Map<String, serde_json::Value>) -> Result<u32> {
+    match obj.get("schema_version") {
+        None => Ok(1),
+        Some(v) => v
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .filter(|v| *v >= 1)
+            .context(format!("settings.json 的 schema_version 无效: {}", v)),
+    }
+}
+
+/// 依次执行迁移步骤，把设置内容升级到当前版本，返回原来的版本
+fn migrate_settings(value: &mut serde_json::Value) -> Result<u32> {
+    let obj = value
+        .as_object_mut()
+        .context("settings.json 顶层必须是 JSON 对象")?;
+    let from_version = settings_schema_version(obj)?;
+    if from_version > SETTINGS_SCHEMA_VERSION {
+        anyhow::bail!(
+            "settings.json 的 schema_version 为 {}，高于本程序支持的版本 {}，请升级 frpdesk 后再运行",
+            from_version,
+            SETTINGS_SCHEMA_VERSION
+        );
+    }
+    for version in from_version..SETTINGS_SCHEMA_VERSION {
+        SETTINGS_MIGRATIONS[(version - 1) as usize](obj);
+    }
+    obj.insert(
+        "schema_version".to_string(),
+        serde_json::Value::from(SETTINGS_SCHEMA_VERSION),
+    );
+    Ok(from_version)
+}
+
+/// 版本 1 -> 2：补全缺失的设置项为默认值，升级后的文件列出全部可用设置
+fn migrate_settings_v1_to_v2(obj: &mut serde_json::Map<String, serde_json::Value>) {
+    if let Ok(serde_json::Value::Object(defaults)) = serde_json::to_value(AppSettings::default()) {
+        for (key, value) in defaults {
+            obj.entry(key).or_insert(value);
+        }
     }
-    fs::read_to_string(&path)
-        .ok()
-        .and_then(|s| serde_json::from_str(&s).ok())
-        .unwrap_or_default()
 }
 
 /// 保存全局设置
+///
+/// 已有文件来自更新版本的程序时拒绝覆盖，避免丢失新版本的设置项
 pub fn save_settings(settings: &AppSettings) -> Result<()> {
     let dir = conf_dir()?;
     fs::create_dir_all(&dir).context("无法创建 conf 目录")?;
     let path = settings_path()?;
+    if let Some(existing) = fs::read_to_string(&path)
+        .ok()
+        .and_then(|s| serde_json::from_str::<serde_json::Value>(&s).ok())
+    {
+        if let Some(obj) = existing.as_object() {
+            let version = settings_schema_version(obj)?;
+            if version > SETTINGS_SCHEMA_VERSION {
+                anyhow::bail!(
+                    "settings.json 来自更新版本的程序（schema_version {}），请升级 frpdesk 后再修改设置",
+                    version
+                );
+            }
+        }
+    }
     let content = serde_json::to_string_pretty(settings).context("无法序列化设置")?;
     fs::write(&path, content).context("无法写入 settings.json")?;
     Ok(())
 }
 
-/// 获取指定配置的 toml 文件路径: conf/<name>.toml
+/// 获取指定配置的 toml 文件路径: conf/<name>.toml（与实例清单位于同一目录）
 pub fn config_toml_path(name: &str) -> Result<PathBuf> {
-    Ok(conf_dir()?.join(format!("{}.toml", name)))
+    Ok(instances_dir()?.join(format!("{}.toml", name)))
+}
+
+/// 按配置名获取传给 frpc 的配置路径（目录模式下为配置目录），配置不存在时为 conf/<name>.toml
+pub fn instance_config_path(name: &str) -> Result<PathBuf> {
+    match find_config(name) {
+        Some(meta) => meta.config_path(),
+        None => config_toml_path(name),
+    }
+}
+
+/// 目录模式下 frpc 会加载的配置文件（目录中的普通文件，按文件名排序）
+pub fn config_dir_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .context(format!("无法读取配置目录 {}", dir.display()))?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .collect();
+    files.sort();
+    Ok(files)
 }
 
 /// 获取 frpc.exe 路径: bin/frpc.exe
@@ -110,6 +1076,14 @@ pub fn frpc_exe_path() -> Result<PathBuf> {
     Ok(bin_dir()?.join("frpc.exe"))
 }
 
+/// 按配置名获取其 frpc 可执行文件路径，配置不存在时为 bin/frpc.exe
+pub fn instance_exe_path(name: &str) -> Result<PathBuf> {
+    match find_config(name) {
+        Some(meta) => meta.exe_path(),
+        None => frpc_exe_path(),
+    }
+}
+
 /// 加载所有配置元数据
 pub fn load_configs() -> Result<Vec<FrpcConfigMeta>> {
     let path = metadata_path()?;
@@ -123,9 +1097,9 @@ pub fn load_configs() -> Result<Vec<FrpcConfigMeta>> {
 
 /// 保存所有配置元数据
 fn save_configs(configs: &[FrpcConfigMeta]) -> Result<()> {
-    let dir = conf_dir()?;
+    let dir = instances_dir()?;
     fs::create_dir_all(&dir).context("无法创建 conf 目录")?;
-    let path = dir.join("metadata.json");
+    let path = metadata_path()?;
     let store = ConfigStore {
         configs: configs.to_vec(),
     };
@@ -175,6 +1149,74 @@ pub fn validate_toml(content: &str) -> Result<(String, Vec<FrpcProxyInfo>)> {
     Ok((server_addr, proxies))
 }
 
+/// frpc 配置中视为敏感的键（不区分大小写，包含即匹配）；stcp/xtcp 的 `sk` 单独判断
+const SENSITIVE_CONFIG_KEYS: &[&str] = &["token", "password", "secret"];
+
+/// 遮蔽后的占位文本
+const REDACTED: &str = "[REDACTED]";
+
+/// 生效的 frpc 配置（`show-config`）
+pub struct EffectiveConfig {
+    /// 渲染环境变量模板、遮蔽敏感值后的配置
+    pub value: toml::Value,
+    /// 模板中引用但当前环境未设置的变量（模板保持原样）
+    pub missing_envs: Vec<String>,
+}
+
+/// 按 frpc 的规则渲染 `{{ .Envs.NAME }}` 环境变量模板，解析 TOML 并遮蔽敏感值
+///
+/// 名称敏感的环境变量（含 TOKEN、PASSWORD 等）代入 `[REDACTED]`，
+/// 键名敏感的字符串值（`auth.token`、`webServer.password`、`secretKey`、`sk` 等）同样遮蔽
+pub fn effective_config(content: &str) -> Result<EffectiveConfig> {
+    static ENV_TEMPLATE: OnceLock<Regex> = OnceLock::new();
+    let template = ENV_TEMPLATE.get_or_init(|| {
+        Regex::new(r"\{\{\s*\.Envs\.([A-Za-z_][A-Za-z0-9_]*)\s*\}\}").expect("模板正则无效")
+    });
+    let mut missing_envs = Vec::new();
+    let rendered = template.replace_all(content, |caps: &regex::Captures| {
+        let name = &caps[1];
+        match std::env::var(name) {
+            Ok(_) if crate::frpc_mg::is_sensitive_env(name) => REDACTED.to_string(),
+            Ok(value) => value,
+            Err(_) => {
+                if !missing_envs.iter().any(|m| m == name) {
+                    missing_envs.push(name.to_string());
+                }
+                caps[0].to_string()
+            }
+        }
+    });
+    let mut value: toml::Value =
+        toml::from_str(&rendered).map_err(|e| anyhow::anyhow!("TOML 格式不正确: {}", e))?;
+    redact_config_value(&mut value);
+    Ok(EffectiveConfig {
+        value,
+        missing_envs,
+    })
+}
+
+/// 键名是否视为敏感（`auth.token`、`webServer.password`、`secretKey`、`sk` 等）
+pub(crate) fn is_sensitive_config_key(key: &str) -> bool {
+    let lower = key.to_lowercase();
+    lower == "sk" || SENSITIVE_CONFIG_KEYS.iter().any(|k| lower.contains(k))
+}
+
+fn redact_config_value(value: &mut toml::Value) {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, item) in table.iter_mut() {
+                if is_sensitive_config_key(key) && item.is_str() {
+                    *item = toml::Value::String(REDACTED.to_string());
+                } else {
+                    redact_config_value(item);
+                }
+            }
+        }
+        toml::Value::Array(items) => items.iter_mut().for_each(redact_config_value),
+        _ => {}
+    }
+}
+
 /// 添加或更新一个配置
 ///
 /// - `name`: 配置名称
@@ -190,7 +1232,7 @@ pub fn save_config(
     proxies: Vec<FrpcProxyInfo>,
 ) -> Result<()> {
     // 1. 写入 toml 文件
-    let dir = conf_dir()?;
+    let dir = instances_dir()?;
     fs::create_dir_all(&dir).context("无法创建 conf 目录")?;
     let toml_path = dir.join(format!("{}.toml", name));
     fs::write(&toml_path, toml_content).context("无法写入配置文件")?;
@@ -207,6 +1249,8 @@ pub fn save_config(
             auto_start,
             server_addr: server_addr.to_string(),
             proxies,
+            group: default_group(),
+            ..Default::default()
         });
     }
     save_configs(&configs)?;
@@ -214,6 +1258,55 @@ pub fn save_config(
     Ok(())
 }
 
+/// 将用户选择的 frpc.exe 复制到 bin/frpc.exe（首次使用、未下载 frpc 时）
+pub fn import_frpc_exe(src: &Path) -> Result<PathBuf> {
+    let is_exe = src
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("exe"));
+    if !is_exe {
+        anyhow::bail!("请选择 frpc.exe 可执行文件: {:?}", src);
+    }
+    let dest = frpc_exe_path()?;
+    fs::create_dir_all(bin_dir()?).context("无法创建 bin 目录")?;
+    fs::copy(src, &dest).context(format!("无法复制 {:?} 到 {:?}", src, dest))?;
+    log::info!("已导入 frpc.exe: {:?}", src);
+    Ok(dest)
+}
+
+/// 将用户选择的 frpc 配置文件导入为一个自启动配置，返回配置名称
+///
+/// 配置名取文件名（不含扩展名），与已有配置重名时追加序号
+pub fn import_config_file(src: &Path) -> Result<String> {
+    let content = fs::read_to_string(src).context(format!("无法读取配置文件: {:?}", src))?;
+    let (server_addr, proxies) = validate_toml(&content)?;
+    let stem = src
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .filter(|s| !s.is_empty())
+        .unwrap_or("frpc");
+    let existing = load_configs().unwrap_or_default();
+    let mut name = stem.to_string();
+    let mut n = 2;
+    while existing.iter().any(|c| c.name == name) {
+        name = format!("{}-{}", stem, n);
+        n += 1;
+    }
+    save_config(&name, &content, true, &server_addr, proxies)?;
+    Ok(name)
+}
+
+/// 设置或清除指定配置的运行身份
+pub fn set_run_as(name: &str, run_as: Option<RunAs>) -> Result<()> {
+    let mut configs = load_configs()?;
+    let meta = configs
+        .iter_mut()
+        .find(|c| c.name == name)
+        .context(format!("配置 '{}' 不存在", name))?;
+    meta.run_as = run_as;
+    save_configs(&configs)
+}
+
 /// 删除一个配置
 pub fn delete_config(name: &str) -> Result<()> {
     // 1. 删除 toml 文件
@@ -242,8 +1335,294 @@ pub fn get_auto_start_configs() -> Result<Vec<FrpcConfigMeta>> {
     Ok(configs.into_iter().filter(|c| c.auto_start).collect())
 }
 
+/// 获取指定配置的元数据
+pub fn find_config(name: &str) -> Option<FrpcConfigMeta> {
+    load_configs()
+        .unwrap_or_default()
+        .into_iter()
+        .find(|c| c.name == name)
+}
+
+/// 获取指定配置停止时的宽限时间，配置不存在时使用默认值
+pub fn graceful_stop_timeout(name: &str) -> Duration {
+    find_config(name).map_or(DEFAULT_GRACEFUL_STOP_TIMEOUT, |m| m.graceful_stop_timeout())
+}
+
+/// 按 `depends_on` 把实例分层：每层只依赖之前各层中的实例，层内按名称排序
+///
+/// 启动按层顺序进行，停止按层的逆序进行。不在 `names` 中的依赖忽略；存在循环依赖时记录警告，
+/// 剩余的实例作为最后一层按名称顺序处理。`settings` 为各实例的设置（按名称），没有设置的实例视为没有依赖
+pub fn dependency_layers(
+    names: &[String],
+    settings: &HashMap<String, FrpcConfigMeta>,
+) -> Vec<Vec<String>> {
+    let depends_on = |name: &str| -> Vec<String> {
+        settings
+            .get(name)
+            .map(|c| {
+                c.depends_on
+                    .iter()
+                    .filter(|d| d.as_str() != name && names.contains(d))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    let mut remaining: Vec<String> = names.to_vec();
+    remaining.sort();
+    remaining.dedup();
+    let mut placed: Vec<String> = Vec::new();
+    let mut layers = Vec::new();
+    while !remaining.is_empty() {
+        let (ready, blocked): (Vec<String>, Vec<String>) = remaining
+            .into_iter()
+            .partition(|name| depends_on(name).iter().all(|d| placed.contains(d)));
+        if ready.is_empty() {
+            log::warn!(
+                "实例之间存在循环依赖（depends_on），按名称顺序处理: {}",
+                blocked.join(", ")
+            );
+            layers.push(blocked);
+            break;
+        }
+        placed.extend(ready.iter().cloned());
+        layers.push(ready);
+        remaining = blocked;
+    }
+    layers
+}
+
+/// 所有已有的分组名（去重、排序）
+pub fn config_groups() -> Vec<String> {
+    let mut groups: Vec<String> = load_configs()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|c| c.group)
+        .collect();
+    groups.sort();
+    groups.dedup();
+    groups
+}
+
+/// 获取指定分组的配置名称列表，分组不存在时返回错误并列出已有分组
+pub fn group_members(group: &str) -> Result<Vec<String>> {
+    let members: Vec<String> = load_configs()?
+        .into_iter()
+        .filter(|c| c.group == group)
+        .map(|c| c.name)
+        .collect();
+    if members.is_empty() {
+        anyhow::bail!(
+            "未知分组 '{}'，已有分组: {}",
+            group,
+            config_groups().join(", ")
+        );
+    }
+    Ok(members)
+}
+
 /// 检查指定名称的配置是否存在
 pub fn config_exists(name: &str) -> bool {
     let configs = load_configs().unwrap_or_default();
     configs.iter().any(|c| c.name == name)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveTime;
+
+    fn window(start: &str, end: &str) -> MaintenanceWindow {
+        MaintenanceWindow {
+            start: start.to_string(),
+            end: end.to_string(),
+        }
+    }
+
+    fn at(h: u32, m: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(h, m, 0).unwrap()
+    }
+
+    /// 内存中的策略值
+    struct MapPolicy(HashMap<&'static str, policy::RegValue>);
+
+    impl policy::PolicySource for MapPolicy {
+        fn value(&self, name: &str) -> Option<policy::RegValue> {
+            self.0.get(name).cloned()
+        }
+    }
+
+    fn policy_of(values: &[(&'static str, policy::RegValue)]) -> MapPolicy {
+        MapPolicy(values.iter().cloned().collect())
+    }
+
+    fn file(json: &str) -> serde_json::Value {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn policy_fills_keys_missing_from_file() {
+        let source = policy_of(&[
+            ("process_guard", policy::RegValue::Dword(1)),
+            ("log_level", policy::RegValue::String("debug".into())),
+        ]);
+        let settings = with_policy_from(&source, file(r#"{"log_level":"warn"}"#)).unwrap();
+        // 默认 settings.json 优先，注册表只补充文件中没有的项
+        assert_eq!(settings.log_level.as_deref(), Some("warn"));
+        assert!(settings.process_guard);
+        // 两处都没有的项取默认值
+        assert!(!settings.no_update_check);
+    }
+
+    #[test]
+    fn policy_overrides_file_when_enabled() {
+        let source = policy_of(&[
+            ("PolicyOverridesSettings", policy::RegValue::Dword(1)),
+            ("process_guard", policy::RegValue::Dword(0)),
+            ("log_level", policy::RegValue::String("debug".into())),
+        ]);
+        let settings = with_policy_from(
+            &source,
+            file(r#"{"log_level":"warn","process_guard":true,"no_update_check":true}"#),
+        )
+        .unwrap();
+        assert_eq!(settings.log_level.as_deref(), Some("debug"));
+        assert!(!settings.process_guard);
+        // 注册表中没有的项仍取文件中的值
+        assert!(settings.no_update_check);
+    }
+
+    #[test]
+    fn empty_policy_uses_file_then_defaults() {
+        let settings =
+            with_policy_from(&policy_of(&[]), file(r#"{"process_guard":true}"#)).unwrap();
+        assert!(settings.process_guard);
+        assert_eq!(settings.log_level, None);
+        assert_eq!(settings.log_format, LogFormat::default());
+    }
+
+    #[test]
+    fn mismatched_or_invalid_policy_values_are_ignored() {
+        let source = policy_of(&[
+            ("PolicyOverridesSettings", policy::RegValue::Dword(1)),
+            // 类型不符：跳过该项
+            ("process_guard", policy::RegValue::String("yes".into())),
+            ("log_level", policy::RegValue::Other(3)),
+        ]);
+        let settings = with_policy_from(&source, file(r#"{"process_guard":true}"#)).unwrap();
+        assert!(settings.process_guard);
+        assert_eq!(settings.log_level, None);
+
+        // 取值无效导致解析失败：忽略整个注册表层
+        let source = policy_of(&[
+            ("PolicyOverridesSettings", policy::RegValue::Dword(1)),
+            ("log_format", policy::RegValue::String("xml".into())),
+            ("process_guard", policy::RegValue::Dword(0)),
+        ]);
+        let settings = with_policy_from(&source, file(r#"{"process_guard":true}"#)).unwrap();
+        assert!(settings.process_guard);
+    }
+
+    #[test]
+    fn maintenance_window_includes_start_excludes_end() {
+        let w = window("02:00", "04:30");
+        assert!(!w.contains(at(1, 59)));
+        assert!(w.contains(at(2, 0)));
+        assert!(w.contains(at(4, 29)));
+        assert!(!w.contains(at(4, 30)));
+    }
+
+    #[test]
+    fn maintenance_window_crossing_midnight() {
+        let w = window("23:30", "00:30");
+        assert!(!w.contains(at(23, 29)));
+        assert!(w.contains(at(23, 30)));
+        assert!(w.contains(at(0, 0)));
+        assert!(w.contains(at(0, 29)));
+        assert!(!w.contains(at(0, 30)));
+        assert!(!w.contains(at(12, 0)));
+    }
+
+    #[test]
+    fn invalid_maintenance_window_contains_nothing() {
+        assert!(window("25:00", "03:00").bounds().is_err());
+        assert!(!window("25:00", "03:00").contains(at(2, 0)));
+        assert!(window("02:00", "02:00").bounds().is_err());
+        assert!(!window("02:00", "02:00").contains(at(2, 0)));
+    }
+
+    #[test]
+    fn active_maintenance_window_takes_first_match() {
+        let windows = vec![
+            window("bad", "03:00"),
+            window("01:00", "03:00"),
+            window("02:00", "05:00"),
+        ];
+        assert_eq!(
+            active_maintenance_window(&windows, at(2, 30)),
+            Some(&windows[1])
+        );
+        assert_eq!(
+            active_maintenance_window(&windows, at(4, 0)),
+            Some(&windows[2])
+        );
+        assert_eq!(active_maintenance_window(&windows, at(5, 0)), None);
+        assert_eq!(active_maintenance_window(&[], at(2, 30)), None);
+    }
+
+    #[test]
+    fn ready_pattern_is_compiled() {
+        let meta = |pattern: Option<&str>| FrpcConfigMeta {
+            name: "web".to_string(),
+            ready_pattern: pattern.map(str::to_string),
+            ..Default::default()
+        };
+        assert!(meta(None).ready_regex().unwrap().is_none());
+        let regex = meta(Some(r"proxy \[web\]")).ready_regex().unwrap().unwrap();
+        assert!(regex.is_match("[I] [proxy.go:204] proxy [web] start proxy success"));
+        let err = meta(Some("proxy [web")).ready_regex().unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("[web] ready_pattern 不是有效的正则表达式"));
+    }
+
+    #[test]
+    fn version_1_settings_are_migrated() {
+        let mut value = file(r#"{"process_guard":false,"log_level":"debug","future_key":1}"#);
+        assert_eq!(migrate_settings(&mut value).unwrap(), 1);
+        let obj = value.as_object().unwrap();
+        assert_eq!(obj["schema_version"], SETTINGS_SCHEMA_VERSION);
+        // 已有的项保留，缺失的项补为默认值，未知的项不动
+        assert_eq!(obj["process_guard"], false);
+        assert_eq!(obj["log_level"], "debug");
+        assert_eq!(obj["future_key"], 1);
+        assert_eq!(obj["monitor_panic_recovery"], true);
+        let settings = with_policy_from(&policy_of(&[]), value).unwrap();
+        assert!(!settings.process_guard);
+        assert_eq!(settings.schema_version, SETTINGS_SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn current_settings_are_not_changed() {
+        let json = format!(
+            r#"{{"schema_version":{},"process_guard":false}}"#,
+            SETTINGS_SCHEMA_VERSION
+        );
+        let mut value = file(&json);
+        assert_eq!(
+            migrate_settings(&mut value).unwrap(),
+            SETTINGS_SCHEMA_VERSION
+        );
+        assert_eq!(value, file(&json));
+    }
+
+    #[test]
+    fn newer_or_invalid_schema_versions_are_rejected() {
+        let newer = format!(r#"{{"schema_version":{}}}"#, SETTINGS_SCHEMA_VERSION + 1);
+        let err = migrate_settings(&mut file(&newer)).unwrap_err();
+        assert!(err.to_string().contains("请升级 frpdesk"), "{}", err);
+        for json in [r#"{"schema_version":0}"#, r#"{"schema_version":"2"}"#, "[]"] {
+            assert!(migrate_settings(&mut file(json)).is_err(), "{}", json);
+        }
+    }
+}
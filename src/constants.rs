@@ -0,0 +1,10 @@
+//! 服务模式与交互模式共用的常量：服务名、显示名称与服务启动参数
+
+/// 默认服务名，可通过注册表 `HKLM\SOFTWARE\FrpcService` 的 `ServiceName` 修改
+pub const DEFAULT_SERVICE_NAME: &str = "FrpcService";
+
+/// 服务显示名称
+pub const DISPLAY_NAME: &str = "FRP Client Service";
+
+/// SCM 启动服务时传入的参数，据此进入服务模式
+pub const SERVICE_ARG: &str = "--service";
@@ -0,0 +1,109 @@
+//! 调试附加（`debug-attach`，仅 `--features debug` 构建）：启动一个新的 frpc 实例并附加调试器
+//!
+//! 用于排查难以复现的崩溃。frpc 以与服务相同的参数启动，输出直接显示在当前控制台，
+//! 随后通过 `ShellExecuteW` 打开调试器附加到该进程（`windbg -p <pid>` 或 `vsjitdebugger -p <pid>`）。
+//! 要求实例先停止，避免两个 frpc 争抢同一组代理。
+
+use anyhow::{Context, Result};
+use std::process::Command;
+use windows_sys::Win32::UI::Shell::ShellExecuteW;
+use windows_sys::Win32::UI::WindowsAndMessaging::SW_SHOWNORMAL;
+
+use crate::config;
+use crate::frpc_mg;
+use crate::service;
+
+/// 可用的调试器
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Debugger {
+    /// WinDbg（需在 PATH 中）
+    WinDbg,
+    /// Visual Studio 即时调试器，弹出选择调试器的对话框
+    VsJit,
+}
+
+impl Debugger {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "windbg" => Ok(Debugger::WinDbg),
+            "vsjit" | "vsjitdebugger" => Ok(Debugger::VsJit),
+            other => anyhow::bail!("不支持的调试器: {}（可选 windbg/vsjit）", other),
+        }
+    }
+
+    fn program(self) -> &'static str {
+        match self {
+            Debugger::WinDbg => "windbg.exe",
+            Debugger::VsJit => "vsjitdebugger.exe",
+        }
+    }
+}
+
+fn to_wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+/// 启动实例并附加调试器，等待 frpc 退出后返回其退出码
+pub fn run(identifier: &str, debugger: Debugger) -> Result<i32> {
+    if config::find_config(identifier).is_none() {
+        anyhow::bail!("实例 '{}' 不存在", identifier);
+    }
+    let exe = config::instance_exe_path(identifier)?;
+    if !exe.exists() {
+        anyhow::bail!("未找到 frpc 可执行文件: {}", exe.display());
+    }
+    let conf = config::instance_config_path(identifier)?;
+    if !conf.exists() {
+        anyhow::bail!("未找到配置文件: {}", conf.display());
+    }
+    if let Some((_, pid)) = service::discover_running_frpc_processes()
+        .into_iter()
+        .find(|(name, _)| name == identifier)
+    {
+        anyhow::bail!(
+            "实例 '{}' 正在运行（PID: {}），请先在界面或通过分组命令停止",
+            identifier,
+            pid
+        );
+    }
+
+    let mut child = Command::new(&exe)
+        .arg(frpc_mg::config_flag(&conf))
+        .arg(&conf)
+        .spawn()
+        .context(format!("无法启动 frpc: {}", exe.display()))?;
+    let pid = child.id();
+    println!(
+        "frpc 已启动（PID: {}），正在打开 {}",
+        pid,
+        debugger.program()
+    );
+
+    let operation = to_wide("open");
+    let file = to_wide(debugger.program());
+    let parameters = to_wide(&format!("-p {}", pid));
+    // 返回值大于 32 表示成功
+    let result = unsafe {
+        ShellExecuteW(
+            0,
+            operation.as_ptr(),
+            file.as_ptr(),
+            parameters.as_ptr(),
+            std::ptr::null(),
+            SW_SHOWNORMAL,
+        )
+    };
+    if result <= 32 {
+        let _ = child.kill();
+        let _ = child.wait();
+        anyhow::bail!(
+            "无法启动 {}（错误码 {}），请确认已安装并位于 PATH 中；frpc 已终止",
+            debugger.program(),
+            result
+        );
+    }
+
+    let status = child.wait().context("等待 frpc 退出失败")?;
+    println!("frpc 已退出，退出状态: {}", status);
+    Ok(status.code().unwrap_or(1))
+}
@@ -0,0 +1,148 @@
+//! 日志磁盘空间检查（服务模式）：定期查询 logs/ 所在磁盘的剩余空间
+//!
+//! - 低于 `low_free_mb`：各日志目录只保留最新的文件，并发出告警
+//! - 低于 `critical_free_mb`：暂停写入文件日志，只写事件日志与告警，空间恢复到 `low_free_mb` 以上后继续写入
+//!
+//! 每次检查只调用一次 `GetDiskFreeSpaceExW`，查询失败时跳过本轮；同一状态只告警一次，回到正常后再次下降才重新告警
+
+use anyhow::Result;
+use std::os::windows::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use windows_sys::Win32::Foundation::GetLastError;
+use windows_sys::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+
+use crate::alert::{self, AlertEvent};
+use crate::config::DiskGuardSettings;
+use crate::eventlog;
+use crate::logger;
+
+/// 剩余空间所处的区间
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DiskState {
+    Normal,
+    Low,
+    Critical,
+}
+
+/// 启动后台检查线程，随服务进程退出；未启用时不启动
+pub fn start(settings: &DiskGuardSettings, logs_dir: PathBuf) {
+    if !settings.enabled {
+        return;
+    }
+    let settings = settings.clone();
+    let interval = Duration::from_secs(settings.check_interval_secs.max(1));
+    log::info!(
+        "磁盘空间检查已启动：低于 {} MB 清理旧日志，低于 {} MB 暂停文件日志，每 {} 秒检查一次",
+        settings.low_free_mb,
+        settings.critical_free_mb,
+        interval.as_secs()
+    );
+    let result = thread::Builder::new()
+        .name("disk-guard".to_string())
+        .spawn(move || {
+            let mut state = DiskState::Normal;
+            let mut query_failed = false;
+            loop {
+                match free_mb(&logs_dir) {
+                    Ok(free) => {
+                        query_failed = false;
+                        state = check(&settings, &logs_dir, free, state);
+                    }
+                    Err(e) => {
+                        // 只在第一次失败时记录，避免每轮重复
+                        if !query_failed {
+                            log::warn!("无法查询日志磁盘剩余空间，跳过检查: {:#}", e);
+                            query_failed = true;
+                        }
+                    }
+                }
+                thread::sleep(interval);
+            }
+        });
+    if let Err(e) = result {
+        log::error!("无法启动磁盘空间检查线程: {}", e);
+    }
+}
+
+/// 根据本次查询到的剩余空间处理状态变化，返回新状态
+fn check(settings: &DiskGuardSettings, logs_dir: &Path, free: u64, state: DiskState) -> DiskState {
+    let new_state = if free < settings.critical_free_mb {
+        DiskState::Critical
+    } else if free < settings.low_free_mb {
+        // 严重不足状态下空间回升到两个阈值之间时保持暂停，避免在阈值附近反复切换
+        if state == DiskState::Critical {
+            DiskState::Critical
+        } else {
+            DiskState::Low
+        }
+    } else {
+        DiskState::Normal
+    };
+    if new_state == state {
+        return state;
+    }
+
+    let dir = logs_dir.display().to_string();
+    match new_state {
+        DiskState::Low => {
+            let freed = logger::clean_logs_aggressively(logs_dir);
+            log::warn!(
+                "日志磁盘剩余空间 {} MB，低于 {} MB，已清理旧日志（释放 {} MB）",
+                free,
+                settings.low_free_mb,
+                freed / 1024 / 1024
+            );
+            alert::dispatch_service_alert(&AlertEvent::DiskSpaceLow {
+                logs_dir: dir,
+                free_mb: free,
+                critical: false,
+            });
+        }
+        DiskState::Critical => {
+            logger::clean_logs_aggressively(logs_dir);
+            // 先告警再暂停，告警的日志行仍能写入文件
+            alert::dispatch_service_alert(&AlertEvent::DiskSpaceLow {
+                logs_dir: dir,
+                free_mb: free,
+                critical: true,
+            });
+            logger::set_file_logging_paused(true);
+        }
+        DiskState::Normal => {
+            if logger::set_file_logging_paused(false) {
+                let message = format!("日志磁盘剩余空间已恢复到 {} MB，继续写入文件日志", free);
+                log::warn!("{}", message);
+                eventlog::report_error(&message);
+            } else {
+                log::info!("日志磁盘剩余空间已恢复到 {} MB", free);
+            }
+        }
+    }
+    new_state
+}
+
+/// 目录所在磁盘对当前账户可用的剩余空间（MB）
+fn free_mb(dir: &Path) -> Result<u64> {
+    let wide: Vec<u16> = dir
+        .as_os_str()
+        .encode_wide()
+        .chain(std::iter::once(0))
+        .collect();
+    let mut available = 0u64;
+    let ok = unsafe {
+        GetDiskFreeSpaceExW(
+            wide.as_ptr(),
+            &mut available,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        )
+    };
+    if ok == 0 {
+        anyhow::bail!("GetDiskFreeSpaceExW 失败，错误码: {}", unsafe {
+            GetLastError()
+        });
+    }
+    Ok(available / 1024 / 1024)
+}
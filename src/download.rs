@@ -137,40 +137,57 @@ fn extract_frpc_from_zip(zip_path: &Path, dest_dir: &Path) -> Result<()> {
 ///
 /// 返回 Some(latest_tag) 如果有更新，None 如果已是最新
 pub fn check_update() -> Result<Option<String>> {
-    let exe_dir = std::env::current_exe()
-        .ok()
-        .and_then(|p| p.parent().map(|p| p.to_path_buf()))
-        .unwrap_or_else(|| std::path::PathBuf::from("."));
-    let bin_dir = exe_dir.join("bin");
-    let exe_path = bin_dir.join("frpc.exe");
+    check_update_with_timeout(std::time::Duration::from_secs(30))
+}
 
+/// 同 [`check_update`]，可指定 HTTP 请求超时
+pub fn check_update_with_timeout(timeout: std::time::Duration) -> Result<Option<String>> {
     let client = reqwest::blocking::Client::builder()
-        .timeout(std::time::Duration::from_secs(30))
+        .timeout(timeout)
         .build()
         .context("创建 HTTP 客户端失败")?;
 
     let tag = get_latest_release_tag(&client)?;
 
-    if exe_path.exists() {
-        let mut cmd = std::process::Command::new(&exe_path);
-        cmd.arg("--version");
-        #[cfg(windows)]
-        {
-            use std::os::windows::process::CommandExt;
-            const CREATE_NO_WINDOW: u32 = 0x08000000;
-            cmd.creation_flags(CREATE_NO_WINDOW);
-        }
-        if let Ok(output) = cmd.output() {
-            let version_str = String::from_utf8_lossy(&output.stdout).trim().to_string();
-            if version_str.contains(tag.trim_start_matches('v')) {
-                return Ok(None);
-            }
+    if let Some(version_str) = installed_frpc_version() {
+        if version_str.contains(tag.trim_start_matches('v')) {
+            return Ok(None);
         }
     }
 
     Ok(Some(tag))
 }
 
+/// 执行 `bin/frpc.exe --version` 获取已安装的 frpc 版本，未安装或执行失败时返回 None
+pub fn installed_frpc_version() -> Option<String> {
+    let exe_dir = std::env::current_exe()
+        .ok()
+        .and_then(|p| p.parent().map(|p| p.to_path_buf()))
+        .unwrap_or_else(|| std::path::PathBuf::from("."));
+    let exe_path = exe_dir.join("bin").join("frpc.exe");
+    if !exe_path.exists() {
+        return None;
+    }
+    frpc_version(&exe_path)
+}
+
+/// 执行 `<exe> --version` 获取指定 frpc 的版本，执行失败时返回 None
+pub fn frpc_version(exe_path: &Path) -> Option<String> {
+    let mut cmd = std::process::Command::new(exe_path);
+    cmd.arg("--version");
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        const CREATE_NO_WINDOW: u32 = 0x08000000;
+        cmd.creation_flags(CREATE_NO_WINDOW);
+    }
+    let output = cmd.output().ok()?;
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// 下载中的临时压缩包（位于 bin/），下载中断时可能遗留
+pub(crate) const DOWNLOAD_TEMP_FILE: &str = "__frpc_download_temp.zip";
+
 /// 主入口：下载并解压 frpc.exe 到 bin/ 目录
 ///
 /// `program_dir` 为程序所在目录，frpc.exe 会解压到 `program_dir/bin/` 下。
@@ -227,7 +244,7 @@ pub fn download_and_extract_frpc(
     }
 
     // 3. 依次尝试下载
-    let zip_path = bin_dir.join("__frpc_download_temp.zip");
+    let zip_path = bin_dir.join(DOWNLOAD_TEMP_FILE);
     let mut last_error = String::new();
 
     for url in &candidate_urls {
@@ -0,0 +1,155 @@
+//! ETW（Windows 事件跟踪）提供程序：服务启停、实例崩溃/重启/启动失败时写入事件
+//!
+//! 没有会话订阅时 `EventWriteString` 几乎没有开销；可用 WPR/WPA、`logman`、
+//! PerfView 等工具按提供程序名称 `FrpcService` 采集。
+//!
+//! 提供程序 GUID 按 .NET EventSource 的规则由名称生成，工具中可直接用 `*FrpcService` 引用。
+//! 事件内容为 `event=... key=value` 形式的文本，级别区分严重程度。
+
+use sha1::{Digest, Sha1};
+use std::sync::atomic::{AtomicU64, Ordering};
+use windows_sys::core::GUID;
+use windows_sys::Win32::System::Diagnostics::Etw::{
+    EventRegister, EventUnregister, EventWriteString,
+};
+
+/// 注册句柄，0 表示未注册（写事件时直接忽略）
+static REG_HANDLE: AtomicU64 = AtomicU64::new(0);
+
+/// ETW 级别：错误 / 警告 / 信息
+const LEVEL_ERROR: u8 = 2;
+const LEVEL_WARNING: u8 = 3;
+const LEVEL_INFO: u8 = 4;
+
+/// 写入 ETW 的事件
+pub enum EtwEvent<'a> {
+    ServiceStart,
+    ServiceStop,
+    /// 实例进程意外退出
+    InstanceCrash {
+        instance: &'a str,
+    },
+    /// 进程守护重启实例成功
+    InstanceRestart {
+        instance: &'a str,
+        pid: u32,
+    },
+    /// 实例启动失败
+    StartupFailure {
+        instance: &'a str,
+        error: &'a str,
+    },
+}
+
+impl EtwEvent<'_> {
+    fn level(&self) -> u8 {
+        match self {
+            EtwEvent::ServiceStart | EtwEvent::ServiceStop | EtwEvent::InstanceRestart { .. } => {
+                LEVEL_INFO
+            }
+            EtwEvent::InstanceCrash { .. } => LEVEL_WARNING,
+            EtwEvent::StartupFailure { .. } => LEVEL_ERROR,
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            EtwEvent::ServiceStart => "event=service_start".to_string(),
+            EtwEvent::ServiceStop => "event=service_stop".to_string(),
+            EtwEvent::InstanceCrash { instance } => {
+                format!("event=instance_crash instance={}", instance)
+            }
+            EtwEvent::InstanceRestart { instance, pid } => {
+                format!("event=instance_restart instance={} pid={}", instance, pid)
+            }
+            EtwEvent::StartupFailure { instance, error } => format!(
+                "event=startup_failure instance={} error={:?}",
+                instance, error
+            ),
+        }
+    }
+}
+
+/// 已注册的提供程序，释放时写入 `service_stop` 并注销
+pub struct EtwProvider(());
+
+impl EtwProvider {
+    /// 注册提供程序并写入 `service_start`；注册失败只记录警告，后续事件被忽略
+    pub fn register(name: &str) -> Self {
+        let guid = provider_guid(name);
+        let mut handle = 0u64;
+        let status = unsafe { EventRegister(&guid, None, std::ptr::null(), &mut handle) };
+        if status == 0 {
+            REG_HANDLE.store(handle, Ordering::SeqCst);
+            log::info!(
+                "已注册 ETW 提供程序 {}（{{{:08X}-{:04X}-{:04X}-{}}}）",
+                name,
+                guid.data1,
+                guid.data2,
+                guid.data3,
+                guid_tail(&guid)
+            );
+        } else {
+            log::warn!("注册 ETW 提供程序失败，错误码: {}", status);
+        }
+        write(EtwEvent::ServiceStart);
+        EtwProvider(())
+    }
+}
+
+impl Drop for EtwProvider {
+    fn drop(&mut self) {
+        write(EtwEvent::ServiceStop);
+        let handle = REG_HANDLE.swap(0, Ordering::SeqCst);
+        if handle != 0 {
+            unsafe { EventUnregister(handle) };
+        }
+    }
+}
+
+/// 写入一条事件（未注册时忽略）
+pub fn write(event: EtwEvent) {
+    let handle = REG_HANDLE.load(Ordering::SeqCst);
+    if handle == 0 {
+        return;
+    }
+    let message: Vec<u16> = event
+        .message()
+        .encode_utf16()
+        .chain(std::iter::once(0))
+        .collect();
+    unsafe { EventWriteString(handle, event.level(), 0, message.as_ptr()) };
+}
+
+/// 按 EventSource 规则由名称生成提供程序 GUID：
+/// SHA-1(命名空间 + 大写名称的 UTF-16BE) 取前 16 字节，标记为版本 5
+fn provider_guid(name: &str) -> GUID {
+    const NAMESPACE: [u8; 16] = [
+        0x48, 0x2C, 0x2D, 0xB2, 0xC3, 0x90, 0x47, 0xC8, 0x87, 0xF8, 0x1A, 0x15, 0xBF, 0xC1, 0x30,
+        0xFB,
+    ];
+    let mut hasher = Sha1::new();
+    hasher.update(NAMESPACE);
+    for unit in name.to_uppercase().encode_utf16() {
+        hasher.update(unit.to_be_bytes());
+    }
+    let hash = hasher.finalize();
+    let mut b = [0u8; 16];
+    b.copy_from_slice(&hash[..16]);
+    b[7] = (b[7] & 0x0F) | 0x50;
+    GUID {
+        data1: u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+        data2: u16::from_le_bytes([b[4], b[5]]),
+        data3: u16::from_le_bytes([b[6], b[7]]),
+        data4: [b[8], b[9], b[10], b[11], b[12], b[13], b[14], b[15]],
+    }
+}
+
+/// GUID 后两段的十六进制文本（`XXXX-XXXXXXXXXXXX`）
+fn guid_tail(guid: &GUID) -> String {
+    let d = guid.data4;
+    format!(
+        "{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}",
+        d[0], d[1], d[2], d[3], d[4], d[5], d[6], d[7]
+    )
+}
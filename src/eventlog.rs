@@ -0,0 +1,43 @@
+//! Windows 事件日志：日志系统不可用等无法写入文件日志的情况下的最后诊断途径
+//!
+//! 事件源未在注册表中登记，事件查看器会提示"找不到事件 ID 的描述"，但消息文本仍会完整显示
+
+use windows_sys::Win32::System::EventLog::{
+    DeregisterEventSource, RegisterEventSourceW, ReportEventW, EVENTLOG_ERROR_TYPE,
+};
+
+use crate::constants::DEFAULT_SERVICE_NAME;
+
+/// 事件源名称，与默认服务名一致
+const EVENT_SOURCE: &str = DEFAULT_SERVICE_NAME;
+
+/// 写一条错误级别的事件日志，返回是否写入成功
+///
+/// 不依赖 log 宏，可在日志系统初始化前调用
+pub fn report_error(message: &str) -> bool {
+    let source: Vec<u16> = EVENT_SOURCE
+        .encode_utf16()
+        .chain(std::iter::once(0))
+        .collect();
+    let text: Vec<u16> = message.encode_utf16().chain(std::iter::once(0)).collect();
+    unsafe {
+        let handle = RegisterEventSourceW(std::ptr::null(), source.as_ptr());
+        if handle == 0 {
+            return false;
+        }
+        let strings = [text.as_ptr()];
+        let ok = ReportEventW(
+            handle,
+            EVENTLOG_ERROR_TYPE,
+            0,
+            1,
+            std::ptr::null_mut(),
+            1,
+            0,
+            strings.as_ptr(),
+            std::ptr::null(),
+        );
+        DeregisterEventSource(handle);
+        ok != 0
+    }
+}
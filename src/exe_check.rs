@@ -0,0 +1,181 @@
+//! frpc 可执行文件冒烟检测：批量启动实例前对每个要用到的 frpc.exe 运行一次 `--version`
+//!
+//! 文件损坏、架构不匹配、缺少依赖的 DLL 时，提前给出明确的原因，
+//! 而不是在启动实例时才得到含糊的错误。检测结果按路径缓存，文件被替换（大小或修改时间变化）后重新检测
+
+use anyhow::Result;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+/// 等待 `--version` 退出的最长时间
+const VERSION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 文件标识：大小与修改时间，任一变化即视为换了文件
+type FileStamp = (u64, Option<SystemTime>);
+
+/// 一次检测：(路径, 文件标识, 检测结果：版本号或失败原因)
+type CheckedExe = (PathBuf, FileStamp, Result<String, String>);
+
+/// 已检测的文件；用到的 frpc.exe 通常只有几个，线性查找即可
+static CACHE: Mutex<Vec<CheckedExe>> = Mutex::new(Vec::new());
+
+/// 检测 frpc 可执行文件能否运行，成功时返回版本号；同一文件只检测一次
+pub(crate) fn check_frpc_exe(exe: &Path) -> Result<String> {
+    let stamp = std::fs::metadata(exe)
+        .map(|m| (m.len(), m.modified().ok()))
+        .map_err(|e| anyhow::anyhow!("{} 不存在或无法访问: {}", exe.display(), e))?;
+    let cached = CACHE
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|(path, cached_stamp, _)| path == exe && *cached_stamp == stamp)
+        .map(|(_, _, result)| result.clone());
+    let result = match cached {
+        Some(result) => result,
+        None => {
+            let result =
+                run_version(exe).map_err(|reason| format!("{}: {}", exe.display(), reason));
+            let mut cache = CACHE.lock().unwrap();
+            cache.retain(|(path, _, _)| path != exe);
+            cache.push((exe.to_path_buf(), stamp, result.clone()));
+            result
+        }
+    };
+    result.map_err(anyhow::Error::msg)
+}
+
+/// 运行 `<exe> --version`，返回版本号或失败原因
+fn run_version(exe: &Path) -> std::result::Result<String, String> {
+    let mut cmd = Command::new(exe);
+    cmd.arg("--version")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null());
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        const CREATE_NO_WINDOW: u32 = 0x08000000;
+        cmd.creation_flags(CREATE_NO_WINDOW);
+    }
+    let mut child = cmd.spawn().map_err(|e| match e.raw_os_error() {
+        Some(code) => spawn_error_reason(code as u32),
+        None => format!("无法执行: {}", e),
+    })?;
+
+    let deadline = Instant::now() + VERSION_TIMEOUT;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(50)),
+            Ok(None) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!(
+                    "--version 在 {} 秒内没有退出",
+                    VERSION_TIMEOUT.as_secs()
+                ));
+            }
+            Err(e) => return Err(format!("等待进程退出失败: {}", e)),
+        }
+    };
+    if !status.success() {
+        return Err(match status.code() {
+            Some(code) => exit_code_reason(code as u32),
+            None => "进程异常退出".to_string(),
+        });
+    }
+    let mut stdout = String::new();
+    if let Some(mut out) = child.stdout.take() {
+        let _ = out.read_to_string(&mut stdout);
+    }
+    let version = stdout.trim();
+    if version.is_empty() {
+        return Err("--version 没有输出版本号，可能不是 frpc".to_string());
+    }
+    Ok(version.to_string())
+}
+
+/// 创建进程失败的原因（Win32 错误码）
+fn spawn_error_reason(code: u32) -> String {
+    match code {
+        2 | 3 => "文件不存在".to_string(),
+        5 => "拒绝访问（检查文件权限或是否被安全软件拦截）".to_string(),
+        193 => "不是有效的 Windows 程序（文件损坏或下载不完整）".to_string(),
+        216 => "与当前系统的处理器架构不匹配".to_string(),
+        225 => "被安全软件判定为病毒或潜在有害程序".to_string(),
+        other => format!("无法执行（错误码 {}）", other),
+    }
+}
+
+/// `--version` 非零退出的原因；加载阶段失败时退出码为 NTSTATUS
+fn exit_code_reason(code: u32) -> String {
+    match code {
+        0xC000_0135 => "缺少依赖的 DLL（STATUS_DLL_NOT_FOUND）".to_string(),
+        0xC000_0139 => {
+            "依赖的 DLL 缺少所需的函数（STATUS_ENTRYPOINT_NOT_FOUND），系统版本可能过旧".to_string()
+        }
+        0xC000_007B => "程序或依赖的 DLL 架构不匹配（STATUS_INVALID_IMAGE_FORMAT）".to_string(),
+        0xC000_0142 => "DLL 初始化失败（STATUS_DLL_INIT_FAILED）".to_string(),
+        other => format!("--version 退出码 {:#x}", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_exe(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "frpdesk-exe-check-{}-{}.exe",
+            name,
+            std::process::id()
+        ));
+        std::fs::write(&path, "MZ").unwrap();
+        path
+    }
+
+    fn stamp(path: &Path) -> FileStamp {
+        let meta = std::fs::metadata(path).unwrap();
+        (meta.len(), meta.modified().ok())
+    }
+
+    #[test]
+    fn cached_result_is_reused_for_same_file() {
+        let ok = temp_exe("ok");
+        let broken = temp_exe("broken");
+        {
+            let mut cache = CACHE.lock().unwrap();
+            cache.push((ok.clone(), stamp(&ok), Ok("0.61.0".to_string())));
+            cache.push((
+                broken.clone(),
+                stamp(&broken),
+                Err(format!("{}: 文件不存在", broken.display())),
+            ));
+        }
+        assert_eq!(check_frpc_exe(&ok).unwrap(), "0.61.0");
+        let err = check_frpc_exe(&broken).unwrap_err().to_string();
+        assert!(err.ends_with("文件不存在"), "{}", err);
+        let _ = std::fs::remove_file(&ok);
+        let _ = std::fs::remove_file(&broken);
+    }
+
+    #[test]
+    fn missing_file_is_reported() {
+        let path = std::env::temp_dir().join("frpdesk-exe-check-missing.exe");
+        let err = check_frpc_exe(&path).unwrap_err().to_string();
+        assert!(err.contains("不存在或无法访问"), "{}", err);
+    }
+
+    #[test]
+    fn failure_reasons() {
+        assert!(spawn_error_reason(193).contains("不是有效的 Windows 程序"));
+        assert!(spawn_error_reason(216).contains("架构不匹配"));
+        assert_eq!(spawn_error_reason(1234), "无法执行（错误码 1234）");
+        assert!(exit_code_reason(0xC000_0135).contains("STATUS_DLL_NOT_FOUND"));
+        assert!(exit_code_reason(0xC000_007B).contains("STATUS_INVALID_IMAGE_FORMAT"));
+        assert_eq!(exit_code_reason(1), "--version 退出码 0x1");
+    }
+}
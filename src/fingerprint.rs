@@ -0,0 +1,80 @@
+//! 文件指纹：计算 SHA-256，按 (修改时间, 大小) 缓存，文件未变化时不重复读取
+//!
+//! 目录模式的配置按目录中各文件的哈希组合计算
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::SystemTime;
+
+/// 缓存项：(修改时间, 文件大小, 十六进制哈希)
+type CacheEntry = (SystemTime, u64, String);
+
+fn cache() -> &'static Mutex<HashMap<PathBuf, CacheEntry>> {
+    static CACHE: OnceLock<Mutex<HashMap<PathBuf, CacheEntry>>> = OnceLock::new();
+    CACHE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// 计算文件的 SHA-256（小写十六进制）
+///
+/// 修改时间与大小均未变化时直接返回缓存结果
+pub fn file_sha256(path: &Path) -> Result<String> {
+    let meta = std::fs::metadata(path).context(format!("无法读取文件信息: {:?}", path))?;
+    let mtime = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+    let len = meta.len();
+
+    if let Some((cached_mtime, cached_len, hash)) = cache().lock().unwrap().get(path) {
+        if *cached_mtime == mtime && *cached_len == len {
+            return Ok(hash.clone());
+        }
+    }
+
+    let mut file = File::open(path).context(format!("无法打开文件: {:?}", path))?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let n = file
+            .read(&mut buf)
+            .context(format!("无法读取文件: {:?}", path))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    let hash: String = hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+
+    cache()
+        .lock()
+        .unwrap()
+        .insert(path.to_path_buf(), (mtime, len, hash.clone()));
+    Ok(hash)
+}
+
+/// 计算 frpc 配置的 SHA-256：文件直接计算；目录（目录模式）按文件名排序后对“文件名 + 文件哈希”整体计算，
+/// 任一文件内容变化、增加或删除文件都会改变结果
+pub fn config_sha256(path: &Path) -> Result<String> {
+    if !path.is_dir() {
+        return file_sha256(path);
+    }
+    let mut hasher = Sha256::new();
+    for file in crate::config::config_dir_files(path)? {
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        hasher.update(name.as_bytes());
+        hasher.update([0]);
+        hasher.update(file_sha256(&file)?.as_bytes());
+        hasher.update([b'\n']);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
@@ -1,22 +1,230 @@
 //! frpc 进程管理，负责启动和停止 frpc 进程
 
 use anyhow::{Context, Result};
-use std::io::{BufRead, BufReader};
+use regex::Regex;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
 #[cfg(windows)]
 use std::os::windows::process::CommandExt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::{Child, Command, ExitStatus, Stdio};
-use std::sync::mpsc::Sender;
-use strip_ansi_escapes::strip;
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::config;
+use crate::download;
+use crate::fingerprint;
+use crate::logger::FRPC_TARGET_PREFIX;
+use crate::output_pump::{self, InstanceSink, OutputSource, OutputSubscribers, Stream};
+use crate::run_as;
+
+/// frpc 崩溃通知的注册入口（交互模式用于推送系统通知）
+pub use crate::output_pump::set_panic_notifier;
+
+/// 启动时记录的运行信息，用于事后追溯"当时到底运行了什么"
+#[derive(Debug, Clone, Serialize)]
+pub struct LaunchRecord {
+    /// 完整命令行（含可执行文件路径）
+    pub argv: Vec<String>,
+    /// 额外注入的环境变量名（不记录值）
+    pub env_keys: Vec<String>,
+    pub exe_sha256: String,
+    pub config_sha256: String,
+    /// `frpc --version` 的输出（按 exe 哈希缓存，替换 frpc.exe 后重新获取）
+    pub frpc_version: Option<String>,
+    /// 启动时的工作目录
+    pub working_dir: Option<PathBuf>,
+    /// 可直接粘贴到 cmd 中复现启动的等效命令行（敏感环境变量的值已遮蔽）
+    pub command_line: String,
+}
+
+/// 环境变量名包含这些片段时，记录中遮蔽其值
+const SENSITIVE_ENV_MARKERS: &[&str] = &["TOKEN", "PASSWORD", "PASSWD", "SECRET", "KEY", "AUTH"];
+
+/// 环境变量的值是否应遮蔽（按变量名判断，不区分大小写）
+pub(crate) fn is_sensitive_env(key: &str) -> bool {
+    let upper = key.to_uppercase();
+    SENSITIVE_ENV_MARKERS.iter().any(|m| upper.contains(m))
+}
+
+/// 通过 `set-env` 注入的环境变量（实例名 -> 变量），只保存在服务内存中，之后每次启动该实例时生效
+static ENV_OVERRIDES: Mutex<BTreeMap<String, BTreeMap<String, String>>> =
+    Mutex::new(BTreeMap::new());
+
+/// 是否为可设置的环境变量名：非空、仅 ASCII、不含 `=` 与控制字符
+pub(crate) fn is_valid_env_key(key: &str) -> bool {
+    !key.is_empty()
+        && key.is_ascii()
+        && !key.contains('=')
+        && !key.chars().any(|c| c.is_ascii_control())
+}
+
+/// 设置实例启动时注入的环境变量，下次启动（含重启）时生效
+pub(crate) fn set_env_override(identifier: &str, key: &str, value: &str) {
+    ENV_OVERRIDES
+        .lock()
+        .unwrap()
+        .entry(identifier.to_string())
+        .or_default()
+        .insert(key.to_string(), value.to_string());
+}
+
+/// 按 cmd / CommandLineToArgvW 的规则给参数加引号：含空白、引号或 cmd 特殊字符时整体加双引号
+fn quote_arg(arg: &str) -> String {
+    let needs_quotes = arg.is_empty()
+        || arg.chars().any(|c| {
+            c.is_whitespace() || matches!(c, '"' | '&' | '|' | '<' | '>' | '^' | '(' | ')')
+        });
+    if !needs_quotes {
+        return arg.to_string();
+    }
+    let mut quoted = String::from('"');
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                // 引号前的反斜杠需要加倍，引号本身转义
+                quoted.push_str(&"\\".repeat(backslashes * 2 + 1));
+                backslashes = 0;
+            }
+            _ => {
+                quoted.push_str(&"\\".repeat(backslashes));
+                backslashes = 0;
+            }
+        }
+        if c != '\\' {
+            quoted.push(c);
+        }
+    }
+    // 结尾的反斜杠紧挨着闭合引号，同样需要加倍
+    quoted.push_str(&"\\".repeat(backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+/// 传给 frpc 的配置参数：目录模式（配置路径为目录）用 `--config_dir`，否则用 `-c`
+pub(crate) fn config_flag(config_path: &Path) -> &'static str {
+    if config_path.is_dir() {
+        "--config_dir"
+    } else {
+        "-c"
+    }
+}
+
+/// frpc 子命令失败时的输出：优先 stderr，为空时取 stdout
+fn output_message(output: &std::process::Output) -> String {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if stderr.trim().is_empty() {
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    } else {
+        stderr.trim().to_string()
+    }
+}
+
+/// 生成等效的 cmd 命令行：`cd /d <目录> && set "K=V" && <exe> <参数>`
+fn reproducible_command_line(argv: &[String], working_dir: Option<&Path>, cmd: &Command) -> String {
+    let mut parts = Vec::new();
+    if let Some(dir) = working_dir {
+        parts.push(format!("cd /d {}", quote_arg(&dir.to_string_lossy())));
+    }
+    for (key, value) in cmd.get_envs() {
+        let key = key.to_string_lossy();
+        let value = match value {
+            Some(_) if is_sensitive_env(&key) => "***".to_string(),
+            Some(v) => v.to_string_lossy().into_owned(),
+            // 显式移除的变量
+            None => String::new(),
+        };
+        parts.push(format!("set \"{}={}\"", key, value));
+    }
+    parts.push(
+        argv.iter()
+            .map(|a| quote_arg(a))
+            .collect::<Vec<_>>()
+            .join(" "),
+    );
+    parts.join(" && ")
+}
+
+/// 获取 frpc 版本，同一个 exe（哈希相同）只执行一次 `--version`
+fn cached_frpc_version(exe_path: &Path, exe_sha256: &str) -> Option<String> {
+    static CACHE: OnceLock<Mutex<HashMap<String, Option<String>>>> = OnceLock::new();
+    let cache = CACHE.get_or_init(|| Mutex::new(HashMap::new()));
+    if let Some(version) = cache.lock().unwrap().get(exe_sha256) {
+        return version.clone();
+    }
+    let version = download::frpc_version(exe_path);
+    cache
+        .lock()
+        .unwrap()
+        .insert(exe_sha256.to_string(), version.clone());
+    version
+}
+
+/// 预先计算启动记录中耗时的部分（文件哈希、`frpc --version`），结果进入缓存；
+/// 调用方在持有进程列表等锁之前调用，之后的启动直接命中缓存
+pub(crate) fn prefetch_launch_info(exe_path: &Path, config_path: &Path) {
+    if let Ok(exe_sha256) = fingerprint::file_sha256(exe_path) {
+        cached_frpc_version(exe_path, &exe_sha256);
+    }
+    let _ = fingerprint::config_sha256(config_path);
+}
+
+impl LaunchRecord {
+    /// 从即将执行的命令采集运行信息；哈希失败时记录错误信息而不阻止启动
+    ///
+    /// `working_dir` 为 None 时取命令自身设置的目录，未设置则为当前进程的工作目录
+    fn capture(
+        cmd: &Command,
+        exe_path: &Path,
+        config_path: &Path,
+        working_dir: Option<PathBuf>,
+    ) -> Self {
+        let hash = |path: &Path| {
+            fingerprint::file_sha256(path).unwrap_or_else(|e| format!("<无法计算: {}>", e))
+        };
+        let exe_sha256 = hash(exe_path);
+        let argv: Vec<String> = std::iter::once(cmd.get_program())
+            .chain(cmd.get_args())
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect();
+        let working_dir = working_dir
+            .or_else(|| cmd.get_current_dir().map(Path::to_path_buf))
+            .or_else(|| std::env::current_dir().ok());
+        LaunchRecord {
+            frpc_version: cached_frpc_version(exe_path, &exe_sha256),
+            command_line: reproducible_command_line(&argv, working_dir.as_deref(), cmd),
+            working_dir,
+            argv,
+            env_keys: cmd
+                .get_envs()
+                .map(|(k, _)| k.to_string_lossy().into_owned())
+                .collect(),
+            exe_sha256,
+            config_sha256: fingerprint::config_sha256(config_path)
+                .unwrap_or_else(|e| format!("<无法计算: {}>", e)),
+        }
+    }
+}
 
 pub struct FrpcProcess {
     child: Option<Child>,
-    pub identifier: String, // 用于日志和重启
-    #[allow(dead_code)]
-    pub exe_path: PathBuf, // 用于重启
-    #[allow(dead_code)]
+    pub identifier: String,   // 用于日志和重启
+    pub exe_path: PathBuf,    // 用于重启
     pub config_path: PathBuf, // 用于重启
-    pid: u32,               // 进程 ID
+    pid: u32,                 // 进程 ID
+    /// 本次启动的运行信息（从 PID 恢复跟踪的进程为 None）
+    pub launch: Option<LaunchRecord>,
+    /// 实时输出订阅者（从 PID 恢复跟踪的进程没有输出管道，为 None）
+    output: Option<Arc<OutputSubscribers>>,
+    /// 首次观察到的退出状态，之后的查询直接返回
+    exit_status: Option<ExitStatus>,
+    /// 输出看门狗最近一次观察到的输出行数及其增长的时间（开始跟踪时为 0 行）
+    line_progress: Mutex<(usize, Instant)>,
+    /// 健康检查上一次的 CPU 采样：(采样时间, 累计 CPU 时间，100ns)；评估时不持有进程列表锁，因此共享
+    pub(crate) cpu_sample: Arc<Mutex<Option<(Instant, u64)>>>,
 }
 
 impl FrpcProcess {
@@ -28,15 +236,54 @@ impl FrpcProcess {
             exe_path,
             config_path,
             pid,
+            launch: None,
+            output: None,
+            exit_status: None,
+            line_progress: Mutex::new((0, Instant::now())),
+            cpu_sample: Arc::new(Mutex::new(None)),
         }
     }
 
+    /// 订阅实时输出：返回一个独立的接收端，每行 frpc 输出（已去除 ANSI）发送一次
+    ///
+    /// 可多次调用，多个调用方（日志接口、监控界面等）各自接收完整输出；
+    /// 接收端处理不及时时丢弃新行。从 PID 恢复跟踪的进程无法获取输出，返回 None
+    #[allow(dead_code)]
+    pub fn subscribe_output(&self) -> Option<Receiver<String>> {
+        self.output
+            .as_ref()
+            .map(|subscribers| subscribers.subscribe())
+    }
+
     /// 获取进程 ID
     #[allow(dead_code)]
     pub fn pid(&self) -> u32 {
         self.pid
     }
 
+    /// 输出订阅者列表（含最近的输出行），可在进程被移交给其他线程后继续读取；
+    /// 从 PID 恢复跟踪的进程没有输出管道，返回 None
+    pub(crate) fn output_subscribers(&self) -> Option<Arc<OutputSubscribers>> {
+        self.output.clone()
+    }
+
+    /// 本次启动以来 stdout 与 stderr 输出的总行数；从 PID 恢复跟踪的进程没有输出管道，为 0
+    pub fn log_line_count(&self) -> usize {
+        self.output.as_ref().map_or(0, |output| output.line_count())
+    }
+
+    /// 输出行数持续没有增长的时间（从开始跟踪算起），按调用时观察到的行数判断，精度为调用间隔；
+    /// 从 PID 恢复跟踪的进程没有输出管道，返回 None
+    pub fn output_idle(&self) -> Option<Duration> {
+        self.output.as_ref()?;
+        let count = self.log_line_count();
+        let mut progress = self.line_progress.lock().unwrap();
+        if count != progress.0 {
+            *progress = (count, Instant::now());
+        }
+        Some(progress.1.elapsed())
+    }
+
     /// 检查是否有子进程句柄
     #[allow(dead_code)]
     pub fn has_child_handle(&self) -> bool {
@@ -89,14 +336,26 @@ impl FrpcProcess {
 }
 
 impl FrpcProcess {
-    /// 启动一个 frpc 进程实例，并将其标准输出和错误输出重定向到日志
+    /// 启动一个 frpc 进程实例，并将其标准输出和错误输出交给输出泵写入日志
     ///
-    /// `on_connected` 回调在检测到 "login to server success" 时触发（仅一次）
+    /// `on_connected` 回调在检测到 "login to server success" 或 "start proxy success" 时触发
     pub fn start(
         identifier: String,
         exe_path: PathBuf,
         config_path: PathBuf,
         on_connected: Option<Sender<()>>,
+    ) -> Result<Self> {
+        let meta = config::find_config(&identifier);
+        Self::start_with_settings(identifier, exe_path, config_path, on_connected, meta)
+    }
+
+    /// [`start`](Self::start)，实例设置（就绪规则、输出规则、run_as）由调用方提供，不读取实例清单
+    pub(crate) fn start_with_settings(
+        identifier: String,
+        exe_path: PathBuf,
+        config_path: PathBuf,
+        on_connected: Option<Sender<()>>,
+        meta: Option<config::FrpcConfigMeta>,
     ) -> Result<Self> {
         // 验证文件存在
         if !exe_path.exists() {
@@ -116,77 +375,217 @@ impl FrpcProcess {
             ));
         }
         log::info!("[{}] 找到 frpc.exe: {:?}", identifier, exe_path);
-        log::info!("[{}] 找到 frpc.toml: {:?}", identifier, config_path);
+        if config_path.is_dir() {
+            // 目录中各服务的输出都带同一实例前缀，记录文件列表便于对照
+            let files = config::config_dir_files(&config_path)?;
+            log::info!(
+                "[{}] 目录模式，配置目录: {:?}，{} 个配置文件: {}",
+                identifier,
+                config_path,
+                files.len(),
+                files
+                    .iter()
+                    .filter_map(|f| f.file_name().map(|n| n.to_string_lossy().into_owned()))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        } else {
+            log::info!("[{}] 找到 frpc.toml: {:?}", identifier, config_path);
+        }
 
         // 启动 frpc 进程，并捕获标准输出和标准错误
         let mut cmd = Command::new(&exe_path);
-        cmd.arg("-c")
-            .arg(&config_path)
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped());
+        cmd.arg(config_flag(&config_path)).arg(&config_path);
         // Windows: 隐藏控制台窗口
         #[cfg(windows)]
         {
             const CREATE_NO_WINDOW: u32 = 0x08000000;
             cmd.creation_flags(CREATE_NO_WINDOW);
         }
-        let mut child = cmd.spawn().context(format!(
-            "[{}] 无法启动 frpc 进程: {:?}",
-            identifier, exe_path
-        ))?;
-        log::info!("[{}] frpc 进程启动成功，PID: {}", identifier, child.id());
-        let pid = child.id();
-
-        // 为日志捕获克隆标识符
-        let log_identifier_stdout = identifier.clone();
-        if let Some(stdout) = child.stdout.take() {
-            std::thread::spawn(move || {
-                let reader = BufReader::new(stdout);
-                for line in reader.lines() {
-                    if let Ok(line) = line {
-                        let cleaned_bytes = strip(line);
-                        let cleaned_line = String::from_utf8_lossy(&cleaned_bytes).into_owned();
-                        log::info!("FRPC STDOUT [{}]: {}", log_identifier_stdout, cleaned_line);
-                        if cleaned_line.contains("login to server success") {
-                            if let Some(ref tx) = on_connected {
-                                let _ = tx.send(());
-                            }
-                        }
-                    }
+        let env = ENV_OVERRIDES
+            .lock()
+            .unwrap()
+            .get(&identifier)
+            .cloned()
+            .unwrap_or_default();
+        cmd.envs(&env);
+        // 就绪规则无效时不启动，避免探测永远无法通过
+        let mut ready = match meta {
+            Some(ref m) => m.ready_regex()?,
+            None => None,
+        };
+        // 输出规则同样预先编译，无效时不启动
+        let output_rules = match meta {
+            Some(ref m) => m.output_rules()?,
+            None => Vec::new(),
+        };
+        // 目录模式下启动探测按就绪行计数，默认只数登录成功行（每个配置文件一次），不数各代理的启动行
+        if ready.is_none() && config_path.is_dir() {
+            ready = Some(Regex::new("login to server success")?);
+        }
+        // 配置了 run_as 时以指定用户身份启动，只能按 PID 跟踪
+        let run_as = meta.and_then(|m| m.run_as);
+        // 每次启动（含重启）重新采集，文件可能在两次启动之间被替换；
+        // 以其他用户身份启动时工作目录为 frpc.exe 所在目录
+        let launch = LaunchRecord::capture(
+            &cmd,
+            &exe_path,
+            &config_path,
+            run_as
+                .as_ref()
+                .and_then(|_| exe_path.parent().map(Path::to_path_buf)),
+        );
+        let (child, pid, stdout, stderr) = if let Some(ref run_as) = run_as {
+            log::info!("[{}] 以用户 {} 身份启动 frpc", identifier, run_as.user);
+            let spawned = run_as::spawn_as_user(run_as, &exe_path, &config_path, &env)
+                .context(format!("[{}] 无法启动 frpc 进程", identifier))?;
+            let stdout = OutputSource::Reader(Box::new(spawned.stdout));
+            let stderr = OutputSource::Reader(Box::new(spawned.stderr));
+            (None, spawned.pid, Some(stdout), Some(stderr))
+        } else {
+            Self::check_config_readable(&identifier, &config_path)?;
+            // 优先使用由完成端口读取的命名管道，创建失败时回退到匿名管道与读取线程
+            let pipes = match output_pump::output_pipe()
+                .and_then(|out| Ok((out, output_pump::output_pipe()?)))
+            {
+                Ok((out, err)) => {
+                    cmd.stdout(Stdio::from(out.writer))
+                        .stderr(Stdio::from(err.writer));
+                    Some((out.reader, err.reader))
                 }
-            });
-        }
-
-        let log_identifier_stderr = identifier.clone();
-        if let Some(stderr) = child.stderr.take() {
-            std::thread::spawn(move || {
-                let reader = BufReader::new(stderr);
-                for line in reader.lines() {
-                    if let Ok(line) = line {
-                        let cleaned_bytes = strip(line);
-                        let cleaned_line = String::from_utf8_lossy(&cleaned_bytes).into_owned();
-                        log::error!("FRPC STDERR [{}]: {}", log_identifier_stderr, cleaned_line);
-                    }
+                Err(e) => {
+                    log::warn!("[{}] 无法创建输出管道，改用读取线程: {}", identifier, e);
+                    cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+                    None
                 }
-            });
+            };
+            let mut child = cmd.spawn().context(format!(
+                "[{}] 无法启动 frpc 进程: {:?}",
+                identifier, exe_path
+            ))?;
+            // 写端已复制给子进程，释放本进程持有的副本，否则进程退出后读端收不到 EOF
+            drop(cmd);
+            let pid = child.id();
+            let (stdout, stderr) = match pipes {
+                Some((out, err)) => (Some(OutputSource::Pipe(out)), Some(OutputSource::Pipe(err))),
+                None => (
+                    child
+                        .stdout
+                        .take()
+                        .map(|s| OutputSource::Reader(Box::new(s))),
+                    child
+                        .stderr
+                        .take()
+                        .map(|s| OutputSource::Reader(Box::new(s))),
+                ),
+            };
+            (Some(child), pid, stdout, stderr)
+        };
+        log::info!(
+            instance = identifier.as_str(), pid = pid;
+            "[{}] frpc 进程启动成功，PID: {}", identifier, pid
+        );
+        log::info!(
+            instance = identifier.as_str(),
+            argv:? = launch.argv,
+            env:? = launch.env_keys,
+            exe_sha256 = launch.exe_sha256.as_str(),
+            config_sha256 = launch.config_sha256.as_str(),
+            frpc_version:? = launch.frpc_version;
+            "[{}] 运行信息", identifier
+        );
+        // 写入实例日志，便于手动复现
+        log::info!(
+            target: &format!("{}{}", FRPC_TARGET_PREFIX, identifier),
+            "等效命令行: {}",
+            launch.command_line
+        );
+
+        // 输出交给共享输出泵处理（ANSI 清理、写日志、连接信号检测）
+        let subscribers = Arc::new(OutputSubscribers::default());
+        let sink = InstanceSink::new(
+            &identifier,
+            pid,
+            on_connected,
+            ready,
+            output_rules,
+            Arc::clone(&subscribers),
+        );
+        if let Some(stdout) = stdout {
+            output_pump::start_reading(stdout, Arc::clone(&sink), Stream::Stdout);
+        }
+        if let Some(stderr) = stderr {
+            output_pump::start_reading(stderr, sink, Stream::Stderr);
         }
 
         Ok(FrpcProcess {
-            child: Some(child),
+            child,
             identifier,
             exe_path,
             config_path,
             pid,
+            launch: Some(launch),
+            output: Some(subscribers),
+            exit_status: None,
+            line_progress: Mutex::new((0, Instant::now())),
+            cpu_sample: Arc::new(Mutex::new(None)),
         })
     }
 
+    /// 启动前确认当前账户能读取配置文件（目录模式下为目录中的每个文件）
+    ///
+    /// 服务账户没有配置文件的读取权限时 frpc 只会输出一行难以理解的错误后退出，在这里给出明确提示
+    fn check_config_readable(identifier: &str, config_path: &Path) -> Result<()> {
+        if config_path.is_dir() {
+            let files = config::config_dir_files(config_path)?;
+            if files.is_empty() {
+                log::error!("[{}] 配置目录 {:?} 中没有配置文件", identifier, config_path);
+                anyhow::bail!("[{}] 配置目录 {:?} 中没有配置文件", identifier, config_path);
+            }
+            return files
+                .iter()
+                .try_for_each(|file| Self::check_config_readable(identifier, file));
+        }
+        match std::fs::File::open(config_path) {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+                let message = format!(
+                    "[{}] 无法读取配置文件 {:?}：拒绝访问（Windows 错误码 {}）。请确认运行服务的账户（默认 LocalSystem，`install --account virtual` 时为 NT SERVICE\\FrpcService）对该文件有读取权限",
+                    identifier,
+                    config_path,
+                    e.raw_os_error().unwrap_or_default()
+                );
+                log::error!("{}", message);
+                Err(anyhow::anyhow!(message))
+            }
+            Err(e) => {
+                log::error!("[{}] 无法打开配置文件 {:?}: {}", identifier, config_path, e);
+                Err(anyhow::anyhow!(
+                    "[{}] 无法打开配置文件 {:?}: {}",
+                    identifier,
+                    config_path,
+                    e
+                ))
+            }
+        }
+    }
+
     /// 停止 frpc 进程
-    pub fn stop(&mut self) -> Result<()> {
+    ///
+    /// 先发送 Ctrl+C 让 frpc 自行关闭连接并退出，`grace` 内未退出再强制终止；
+    /// `grace` 为 0 时直接终止
+    pub fn stop(&mut self, grace: Duration) -> Result<()> {
         log::info!(
-            "[{}] 尝试终止 frpc 进程，PID: {}",
-            self.identifier,
-            self.pid
+            instance = self.identifier.as_str(), pid = self.pid;
+            "[{}] 尝试终止 frpc 进程，PID: {}", self.identifier, self.pid
         );
+        if !grace.is_zero() && self.stop_gracefully(grace) {
+            log::info!(
+                instance = self.identifier.as_str(), pid = self.pid;
+                "[{}] frpc 进程已正常退出", self.identifier
+            );
+            return Ok(());
+        }
         if let Some(ref mut child) = self.child {
             child
                 .kill()
@@ -198,79 +597,323 @@ impl FrpcProcess {
             // 只有 PID，通过 taskkill 终止
             Self::kill_pid(self.pid)?;
         }
-        log::info!("[{}] frpc 进程已停止", self.identifier);
+        log::info!(
+            instance = self.identifier.as_str(), pid = self.pid;
+            "[{}] frpc 进程已停止", self.identifier
+        );
         Ok(())
     }
 
-    /// 检查 frpc 进程是否仍在运行
-    pub fn is_running(&mut self) -> bool {
-        if let Some(ref mut child) = self.child {
-            match child.try_wait() {
-                Ok(Some(status)) => {
-                    log::warn!(
-                        "[{}] frpc 进程已退出，退出状态: {}",
+    /// 发送 Ctrl+C 并在 `grace` 内等待进程退出，返回是否已退出
+    fn stop_gracefully(&mut self, grace: Duration) -> bool {
+        if let Err(e) = self.send_custom_signal(0) {
+            log::warn!(
+                "[{}] 无法通知 frpc 退出，直接终止: {:?}",
+                self.identifier,
+                e
+            );
+            return false;
+        }
+        let deadline = Instant::now() + grace;
+        loop {
+            let exited = match self.child {
+                Some(ref mut child) => !matches!(child.try_wait(), Ok(None)),
+                None => !Self::is_pid_running(self.pid),
+            };
+            if exited {
+                return true;
+            }
+            if Instant::now() >= deadline {
+                log::warn!(
+                    "[{}] frpc 未在 {} 秒内退出，强制终止",
+                    self.identifier,
+                    grace.as_secs_f32()
+                );
+                return false;
+            }
+            std::thread::sleep(Duration::from_millis(200));
+        }
+    }
+
+    /// 用 `frpc verify -c <配置>` 校验配置，热重载或重启前调用，避免把坏配置应用到运行中的实例
+    ///
+    /// `frpc verify` 不支持 `--config_dir`，目录模式下逐个校验目录中的文件
+    pub fn verify_config(exe_path: &Path, config_path: &Path) -> Result<()> {
+        if config_path.is_dir() {
+            for file in config::config_dir_files(config_path)? {
+                Self::verify_config(exe_path, &file)
+                    .context(format!("配置目录中的 {} 未通过校验", file.display()))?;
+            }
+            return Ok(());
+        }
+        let mut cmd = Command::new(exe_path);
+        cmd.arg("verify").arg("-c").arg(config_path);
+        #[cfg(windows)]
+        {
+            const CREATE_NO_WINDOW: u32 = 0x08000000;
+            cmd.creation_flags(CREATE_NO_WINDOW);
+        }
+        let output = cmd.output().context("无法执行 frpc verify")?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "frpc verify 未通过（{}）: {}",
+                output.status,
+                output_message(&output)
+            );
+        }
+        Ok(())
+    }
+
+    /// 通过 `frpc reload -c <配置>` 热重载配置，不中断已有连接
+    ///
+    /// 依赖配置中的 webServer（admin API）。会启动子进程，调用方不应持有进程列表锁；
+    /// 成功后用 [`FrpcProcess::record_reload`] 更新启动记录
+    pub fn reload(identifier: &str, exe_path: &Path, config_path: &Path) -> Result<()> {
+        if config_path.is_dir() {
+            anyhow::bail!(
+                "[{}] 目录模式不支持热重载（frpc reload 只接受单个配置文件），请重启实例",
+                identifier
+            );
+        }
+        let mut cmd = Command::new(exe_path);
+        cmd.arg("reload").arg("-c").arg(config_path);
+        #[cfg(windows)]
+        {
+            const CREATE_NO_WINDOW: u32 = 0x08000000;
+            cmd.creation_flags(CREATE_NO_WINDOW);
+        }
+        let output = cmd
+            .output()
+            .context(format!("[{}] 无法执行 frpc reload", identifier))?;
+        if !output.status.success() {
+            return Err(anyhow::anyhow!(
+                "[{}] frpc reload 失败（{}）: {}",
+                identifier,
+                output.status,
+                output_message(&output)
+            ));
+        }
+        Ok(())
+    }
+
+    /// 热重载成功后更新启动记录中的配置哈希
+    pub fn record_reload(&mut self) {
+        if let Some(ref mut launch) = self.launch {
+            if let Ok(hash) = fingerprint::config_sha256(&self.config_path) {
+                launch.config_sha256 = hash;
+            }
+        }
+        log::info!(
+            instance = self.identifier.as_str(), pid = self.pid;
+            "[{}] 配置已热重载", self.identifier
+        );
+    }
+
+    /// 向 frpc 进程发送控制台控制事件（`CTRL_C_EVENT` = 0 / `CTRL_BREAK_EVENT` = 1）
+    ///
+    /// frpc 以 CREATE_NO_WINDOW 启动，拥有独立的隐藏控制台；本程序没有控制台，
+    /// 因此临时附加到 frpc 的控制台后广播事件，期间忽略本进程自身收到的事件。
+    /// 所有控制事件都经由此函数发送，便于统一审计
+    pub fn send_custom_signal(&self, signal: u32) -> Result<()> {
+        #[cfg(windows)]
+        {
+            use windows_sys::Win32::Foundation::GetLastError;
+            use windows_sys::Win32::System::Console::{
+                AttachConsole, FreeConsole, GenerateConsoleCtrlEvent, SetConsoleCtrlHandler,
+                CTRL_BREAK_EVENT, CTRL_C_EVENT,
+            };
+
+            // 一个进程同一时间只能附加一个控制台，并发发送需串行化
+            static CONSOLE_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+            if signal != CTRL_C_EVENT && signal != CTRL_BREAK_EVENT {
+                return Err(anyhow::anyhow!(
+                    "[{}] 不支持的控制事件: {}",
+                    self.identifier,
+                    signal
+                ));
+            }
+            let _guard = CONSOLE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+            log::info!(
+                "[{}] 发送控制事件 {} 到 PID: {}",
+                self.identifier,
+                signal,
+                self.pid
+            );
+            unsafe {
+                if AttachConsole(self.pid) == 0 {
+                    return Err(anyhow::anyhow!(
+                        "[{}] 无法附加到进程控制台（PID: {}），错误码: {}",
                         self.identifier,
-                        status
-                    );
-                    false
+                        self.pid,
+                        GetLastError()
+                    ));
                 }
-                Ok(None) => true,
-                Err(e) => {
-                    log::error!("[{}] 无法检查 frpc 进程状态: {}", self.identifier, e);
-                    false
+                SetConsoleCtrlHandler(None, 1);
+                let sent = GenerateConsoleCtrlEvent(signal, 0);
+                let err = GetLastError();
+                // 事件异步投递，稍等片刻再分离，避免本进程在恢复处理后收到该事件
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                FreeConsole();
+                SetConsoleCtrlHandler(None, 0);
+                if sent == 0 {
+                    return Err(anyhow::anyhow!(
+                        "[{}] 发送控制事件失败，错误码: {}",
+                        self.identifier,
+                        err
+                    ));
                 }
             }
-        } else {
-            // 只有 PID，通过 tasklist 检查
-            Self::is_pid_running(self.pid)
+            Ok(())
+        }
+        #[cfg(not(windows))]
+        {
+            let _ = signal;
+            Err(anyhow::anyhow!("当前平台不支持发送控制台事件"))
         }
     }
 
+    /// 检查 frpc 进程是否仍在运行（不阻塞，不改变进程状态）
+    pub fn is_running(&mut self) -> bool {
+        self.poll_exit().is_ok_and(|status| status.is_none())
+    }
+
     /// 检查 frpc 进程是否已退出（返回退出状态）
     pub fn check_exit_status(&mut self) -> Option<std::process::ExitStatus> {
-        if let Some(ref mut child) = self.child {
-            match child.try_wait() {
-                Ok(Some(status)) => Some(status),
-                _ => None,
-            }
-        } else {
-            // 只有 PID，检查是否还在运行
-            if !Self::is_pid_running(self.pid) {
-                // 进程已退出，返回一个模拟的退出状态
-                Some(std::process::ExitStatus::default())
-            } else {
-                None
-            }
-        }
+        self.poll_exit().ok().flatten()
     }
 
     // 检查 frpc 进程是否已退出
     #[allow(dead_code)]
     pub fn check_status(&mut self) -> Result<Option<ExitStatus>> {
-        if let Some(ref mut child) = self.child {
+        self.poll_exit()
+    }
+
+    /// 查询退出状态，首次观察到退出时记录日志并缓存
+    ///
+    /// 有子进程句柄时使用 `try_wait`：未退出返回 `Ok(None)`，不阻塞、不回收进程；
+    /// 已退出时返回退出状态。进程句柄在 `Child` 释放前一直有效，重复 `try_wait` 会得到同样的结果，
+    /// 但缓存后不再调用，也不重复记录退出日志。查询出错（如句柄失效）不缓存，下次重新查询。
+    /// 只有 PID 时无法获取真实退出码，返回默认状态；同样缓存，避免 PID 被复用后误判为仍在运行
+    fn poll_exit(&mut self) -> Result<Option<ExitStatus>> {
+        if let Some(status) = self.exit_status {
+            return Ok(Some(status));
+        }
+        let status = if let Some(ref mut child) = self.child {
             match child.try_wait() {
-                Ok(Some(status)) => {
-                    log::warn!(
-                        "[{}] frpc 子进程已退出，退出状态: {}",
-                        self.identifier,
-                        status
-                    );
-                    Ok(Some(status))
-                }
-                Ok(None) => Ok(None),
+                Ok(status) => status,
                 Err(e) => {
-                    log::error!("[{}] 无法检查 frpc 子进程状态: {}", self.identifier, e);
-                    Err(e.into())
+                    log::error!("[{}] 无法检查 frpc 进程状态: {}", self.identifier, e);
+                    return Err(e.into());
                 }
             }
+        } else if Self::is_pid_running(self.pid) {
+            None
         } else {
-            // 只有 PID
-            if !Self::is_pid_running(self.pid) {
-                log::warn!("[{}] frpc 进程已退出（PID: {}）", self.identifier, self.pid);
-                Ok(Some(std::process::ExitStatus::default()))
+            Some(ExitStatus::default())
+        };
+        if let Some(status) = status {
+            if self.child.is_some() {
+                log::warn!(
+                    instance = self.identifier.as_str(), pid = self.pid, exit_status:% = status;
+                    "[{}] frpc 进程已退出，退出状态: {}", self.identifier, status
+                );
             } else {
-                Ok(None)
+                log::warn!(
+                    instance = self.identifier.as_str(), pid = self.pid;
+                    "[{}] frpc 进程已退出（PID: {}）", self.identifier, self.pid
+                );
             }
+            self.exit_status = Some(status);
+        }
+        Ok(status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 以本测试程序自身作为子进程（不匹配任何测试，立即以 0 退出）
+    fn exited_process() -> FrpcProcess {
+        let exe = std::env::current_exe().unwrap();
+        let child = Command::new(&exe)
+            .args(["--exact", "no_such_test"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        FrpcProcess {
+            child: Some(child),
+            ..FrpcProcess::from_pid(0, "web".to_string(), exe, PathBuf::from("web.toml"))
         }
     }
+
+    #[test]
+    fn exit_status_is_cached_after_exit() {
+        let mut proc = exited_process();
+        let deadline = Instant::now() + Duration::from_secs(30);
+        let status = loop {
+            if let Some(status) = proc.check_exit_status() {
+                break status;
+            }
+            assert!(Instant::now() < deadline, "子进程没有退出");
+            std::thread::sleep(Duration::from_millis(20));
+        };
+        assert!(status.success());
+        assert_eq!(proc.exit_status, Some(status));
+
+        // 之后的查询直接返回缓存，不再查询子进程
+        proc.child = None;
+        assert_eq!(proc.check_exit_status(), Some(status));
+        assert_eq!(proc.check_status().unwrap(), Some(status));
+        assert!(!proc.is_running());
+    }
+
+    #[test]
+    fn verify_failure_reports_frpc_output() {
+        let dir = std::env::temp_dir().join(format!("frpdesk-verify-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let conf = dir.join("web.toml");
+        std::fs::write(&conf, "").unwrap();
+
+        // 本测试程序不认识 `-c`，以非零退出并在 stderr 说明原因，相当于 verify 未通过
+        let exe = std::env::current_exe().unwrap();
+        let err = format!("{:#}", FrpcProcess::verify_config(&exe, &conf).unwrap_err());
+        assert!(err.starts_with("frpc verify 未通过"), "{}", err);
+
+        // 目录模式逐个校验，错误中指明未通过的文件
+        let err = format!("{:#}", FrpcProcess::verify_config(&exe, &dir).unwrap_err());
+        assert!(
+            err.starts_with(&format!("配置目录中的 {} 未通过校验", conf.display())),
+            "{}",
+            err
+        );
+
+        let missing = dir.join("frpc.exe");
+        let err = format!(
+            "{:#}",
+            FrpcProcess::verify_config(&missing, &conf).unwrap_err()
+        );
+        assert!(err.starts_with("无法执行 frpc verify"), "{}", err);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn output_message_prefers_stderr() {
+        let output = |stdout: &str, stderr: &str| std::process::Output {
+            status: ExitStatus::default(),
+            stdout: stdout.as_bytes().to_vec(),
+            stderr: stderr.as_bytes().to_vec(),
+        };
+        assert_eq!(
+            output_message(&output("ignored\n", " proxy [ssh] remotePort 0 invalid\n")),
+            "proxy [ssh] remotePort 0 invalid"
+        );
+        assert_eq!(
+            output_message(&output("toml: line 3: expected '='\n", "  \n")),
+            "toml: line 3: expected '='"
+        );
+    }
 }
@@ -0,0 +1,169 @@
+//! 运行期间的健康检查：按实例的 `health_check` 设置评估运行中的 frpc 进程
+//!
+//! 评估项：admin API 存活探测、内存上限、CPU 上限、输出看门狗。不健康的项优先于降级的项；
+//! 进程守护连续 `unhealthy_threshold` 次得到不健康后终止进程，由崩溃重启流程拉起，降级只记录日志
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use windows_sys::Win32::Foundation::{CloseHandle, FILETIME, HANDLE};
+use windows_sys::Win32::System::ProcessStatus::{GetProcessMemoryInfo, PROCESS_MEMORY_COUNTERS};
+use windows_sys::Win32::System::Threading::{
+    GetProcessTimes, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION,
+};
+
+use crate::config::HealthCheckConfig;
+use crate::frpc_mg::FrpcProcess;
+use crate::probe;
+
+/// 存活探测请求 admin API 的超时
+const LIVENESS_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// 一次健康检查的结果（随指标以 `{"status": "degraded", "reason": "..."}` 的形式传输）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", content = "reason", rename_all = "lowercase")]
+pub enum HealthStatus {
+    Healthy,
+    /// 超出了不需要重启的标准（如 CPU），附带原因
+    Degraded(String),
+    /// 超出了需要重启的标准，附带原因
+    Unhealthy(String),
+}
+
+impl fmt::Display for HealthStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HealthStatus::Healthy => write!(f, "健康"),
+            HealthStatus::Degraded(reason) => write!(f, "降级（{}）", reason),
+            HealthStatus::Unhealthy(reason) => write!(f, "不健康（{}）", reason),
+        }
+    }
+}
+
+/// 评估所需的进程信息：在进程列表锁内取得，评估（可能访问 admin API）时不再持有锁
+pub struct HealthTarget {
+    pid: u32,
+    config_path: PathBuf,
+    /// 取快照时距最近一次输出的时间；没有输出管道时为 None
+    output_idle: Option<Duration>,
+    /// 与进程共享的上一次 CPU 采样，评估时更新
+    cpu_sample: Arc<Mutex<Option<(Instant, u64)>>>,
+}
+
+impl HealthTarget {
+    pub fn of(process: &FrpcProcess) -> Self {
+        HealthTarget {
+            pid: process.pid(),
+            config_path: process.config_path.clone(),
+            output_idle: process.output_idle(),
+            cpu_sample: Arc::clone(&process.cpu_sample),
+        }
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+}
+
+impl HealthCheckConfig {
+    /// 评估所有设置了的标准；同时有多项超出时原因以"；"连接
+    ///
+    /// CPU 占用为与上一次评估之间的平均值，同一进程的第一次评估只记录采样，不判断 CPU
+    pub fn is_healthy(&self, target: &HealthTarget) -> HealthStatus {
+        let mut unhealthy = Vec::new();
+        let mut degraded = Vec::new();
+
+        if self.liveness_probe {
+            if let Ok(Some(api)) = probe::admin_api_lookup(&target.config_path) {
+                let reachable = reqwest::blocking::Client::builder()
+                    .timeout(LIVENESS_TIMEOUT)
+                    .build()
+                    .is_ok_and(|client| probe::admin_api_has_running_proxy(&client, &api));
+                if !reachable {
+                    unhealthy.push(format!("admin API {} 没有 running 的代理", api.base_url));
+                }
+            }
+        }
+
+        let usage = ProcessUsage::query(target.pid);
+        if let (Some(limit), Some(usage)) = (self.max_memory_mb, &usage) {
+            let memory_mb = usage.working_set / (1024 * 1024);
+            if memory_mb > limit {
+                unhealthy.push(format!("内存 {} MB 超过上限 {} MB", memory_mb, limit));
+            }
+        }
+        if let Some(usage) = &usage {
+            let now = Instant::now();
+            let previous = target
+                .cpu_sample
+                .lock()
+                .unwrap()
+                .replace((now, usage.cpu_time));
+            if let (Some(limit), Some((at, cpu_time))) = (self.max_cpu_percent, previous) {
+                let percent = cpu_percent(usage.cpu_time.saturating_sub(cpu_time), now - at);
+                if percent > limit {
+                    degraded.push(format!("CPU {:.1}% 超过上限 {:.1}%", percent, limit));
+                }
+            }
+        }
+
+        if let (Some(timeout), Some(idle)) = (self.watchdog_timeout_secs, target.output_idle) {
+            if idle.as_secs() >= timeout {
+                unhealthy.push(format!("{} 秒没有任何输出", idle.as_secs()));
+            }
+        }
+
+        if !unhealthy.is_empty() {
+            unhealthy.extend(degraded);
+            HealthStatus::Unhealthy(unhealthy.join("；"))
+        } else if !degraded.is_empty() {
+            HealthStatus::Degraded(degraded.join("；"))
+        } else {
+            HealthStatus::Healthy
+        }
+    }
+}
+
+/// 进程的资源占用
+struct ProcessUsage {
+    /// 工作集（字节）
+    working_set: u64,
+    /// 累计 CPU 时间（内核 + 用户，100ns）
+    cpu_time: u64,
+}
+
+impl ProcessUsage {
+    /// 查询进程的资源占用，进程已退出或无权访问时返回 None
+    fn query(pid: u32) -> Option<Self> {
+        unsafe {
+            let process: HANDLE = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+            if process == 0 {
+                return None;
+            }
+            let mut counters: PROCESS_MEMORY_COUNTERS = std::mem::zeroed();
+            counters.cb = std::mem::size_of::<PROCESS_MEMORY_COUNTERS>() as u32;
+            let memory_ok = GetProcessMemoryInfo(process, &mut counters, counters.cb) != 0;
+            let (mut creation, mut exit, mut kernel, mut user) = std::mem::zeroed();
+            let times_ok =
+                GetProcessTimes(process, &mut creation, &mut exit, &mut kernel, &mut user) != 0;
+            CloseHandle(process);
+            (memory_ok && times_ok).then(|| ProcessUsage {
+                working_set: counters.WorkingSetSize as u64,
+                cpu_time: filetime_to_u64(&kernel) + filetime_to_u64(&user),
+            })
+        }
+    }
+}
+
+fn filetime_to_u64(time: &FILETIME) -> u64 {
+    ((time.dwHighDateTime as u64) << 32) | time.dwLowDateTime as u64
+}
+
+/// `elapsed` 内消耗 `cpu_time`（100ns）对应的 CPU 占用，按全部逻辑核心计算百分比
+fn cpu_percent(cpu_time: u64, elapsed: Duration) -> f64 {
+    let cores = std::thread::available_parallelism().map_or(1, |n| n.get()) as f64;
+    let elapsed = elapsed.as_secs_f64().max(0.001);
+    cpu_time as f64 / 10_000_000.0 / elapsed / cores * 100.0
+}
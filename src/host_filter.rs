@@ -0,0 +1,161 @@
+//! 按主机名启用实例：同一份实例清单部署到多台机器时，`only_on_hosts` / `exclude_hosts` 决定实例在本机是否启用
+//!
+//! 匹配规则：
+//! - 不区分大小写，忽略末尾的 `.`
+//! - 含 `.` 的条目视为 FQDN，与本机的完整 DNS 名比较（如 `gw01.corp.example`）
+//! - 不含 `.` 的条目视为短名，与本机的主机名比较（FQDN 的第一段，如 `gw01`）
+//! - 设置了 `only_on_hosts` 时本机须匹配其中一项；匹配 `exclude_hosts` 任一项的一律不启用（排除优先）
+//!
+//! 过滤只依赖传入的 [`HostNames`]，发现实例时使用 [`HostNames::local`]
+
+use windows_sys::Win32::System::SystemInformation::{
+    ComputerNameDnsFullyQualified, ComputerNameDnsHostname, GetComputerNameExW,
+    COMPUTER_NAME_FORMAT,
+};
+
+use crate::config::FrpcConfigMeta;
+
+/// 参与匹配的主机名（均已转为小写、去掉末尾的 `.`）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostNames {
+    /// 短名，如 `gw01`
+    pub short: String,
+    /// 完整 DNS 名，如 `gw01.corp.example`；不在域中时与短名相同
+    pub fqdn: String,
+}
+
+impl HostNames {
+    /// 由给定的名称构造；`fqdn` 为空时取短名，`short` 为空时取 FQDN 的第一段
+    pub fn new(short: &str, fqdn: &str) -> Self {
+        let fqdn = normalize(fqdn);
+        let short = match normalize(short) {
+            s if s.is_empty() => fqdn.split('.').next().unwrap_or_default().to_string(),
+            s => s,
+        };
+        let fqdn = if fqdn.is_empty() { short.clone() } else { fqdn };
+        HostNames { short, fqdn }
+    }
+
+    /// 本机的主机名；无法获取 DNS 名时退回环境变量 `COMPUTERNAME`（NetBIOS 名）
+    pub fn local() -> Self {
+        let short = computer_name(ComputerNameDnsHostname)
+            .or_else(|| std::env::var("COMPUTERNAME").ok())
+            .unwrap_or_default();
+        let fqdn = computer_name(ComputerNameDnsFullyQualified).unwrap_or_default();
+        HostNames::new(&short, &fqdn)
+    }
+
+    /// 条目是否指本机：含 `.` 时比较 FQDN，否则比较短名
+    pub fn matches(&self, entry: &str) -> bool {
+        let entry = normalize(entry);
+        if entry.is_empty() {
+            return false;
+        }
+        if entry.contains('.') {
+            entry == self.fqdn
+        } else {
+            entry == self.short
+        }
+    }
+}
+
+/// 实例在本机不启用时返回原因，启用时返回 None
+pub fn disabled_reason(meta: &FrpcConfigMeta, host: &HostNames) -> Option<String> {
+    if let Some(entry) = meta.exclude_hosts.iter().find(|e| host.matches(e)) {
+        return Some(format!(
+            "本机（{}）在 exclude_hosts 中（{}）",
+            host.fqdn, entry
+        ));
+    }
+    if !meta.only_on_hosts.is_empty() && !meta.only_on_hosts.iter().any(|e| host.matches(e)) {
+        return Some(format!(
+            "only_on_hosts 中没有本机（{}）: {}",
+            host.fqdn,
+            meta.only_on_hosts.join(", ")
+        ));
+    }
+    None
+}
+
+fn normalize(name: &str) -> String {
+    name.trim().trim_end_matches('.').to_ascii_lowercase()
+}
+
+fn computer_name(format: COMPUTER_NAME_FORMAT) -> Option<String> {
+    let mut size = 0u32;
+    // 第一次调用取得所需长度（含结尾的 0）
+    unsafe { GetComputerNameExW(format, std::ptr::null_mut(), &mut size) };
+    if size == 0 {
+        return None;
+    }
+    let mut buffer = vec![0u16; size as usize];
+    if unsafe { GetComputerNameExW(format, buffer.as_mut_ptr(), &mut size) } == 0 {
+        return None;
+    }
+    buffer.truncate(size as usize);
+    Some(String::from_utf16_lossy(&buffer)).filter(|s| !s.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host() -> HostNames {
+        HostNames::new("GW01", "gw01.Corp.Example.")
+    }
+
+    fn meta(only: &[&str], exclude: &[&str]) -> FrpcConfigMeta {
+        FrpcConfigMeta {
+            only_on_hosts: only.iter().map(|s| s.to_string()).collect(),
+            exclude_hosts: exclude.iter().map(|s| s.to_string()).collect(),
+            ..FrpcConfigMeta::default()
+        }
+    }
+
+    #[test]
+    fn new_normalizes_and_fills_missing_names() {
+        assert_eq!(host().short, "gw01");
+        assert_eq!(host().fqdn, "gw01.corp.example");
+        assert_eq!(HostNames::new("", "gw02.corp").short, "gw02");
+        assert_eq!(HostNames::new("gw03", "").fqdn, "gw03");
+    }
+
+    #[test]
+    fn empty_lists_enable_everywhere() {
+        assert_eq!(disabled_reason(&meta(&[], &[]), &host()), None);
+    }
+
+    #[test]
+    fn only_on_hosts_matches_short_name_or_fqdn() {
+        assert_eq!(disabled_reason(&meta(&["gw01"], &[]), &host()), None);
+        assert_eq!(
+            disabled_reason(&meta(&["gw02", "gw01.corp.example"], &[]), &host()),
+            None
+        );
+        assert!(disabled_reason(&meta(&["gw02"], &[]), &host()).is_some());
+        // 含 `.` 的条目只与 FQDN 比较
+        assert!(disabled_reason(&meta(&["gw01.other.example"], &[]), &host()).is_some());
+    }
+
+    #[test]
+    fn matching_ignores_case_and_trailing_dot() {
+        assert_eq!(disabled_reason(&meta(&[" GW01 "], &[]), &host()), None);
+        assert_eq!(
+            disabled_reason(&meta(&["GW01.CORP.EXAMPLE."], &[]), &host()),
+            None
+        );
+    }
+
+    #[test]
+    fn exclude_hosts_wins_over_only_on_hosts() {
+        assert!(disabled_reason(&meta(&[], &["gw01"]), &host()).is_some());
+        assert!(disabled_reason(&meta(&["gw01"], &["gw01.corp.example"]), &host()).is_some());
+        assert_eq!(disabled_reason(&meta(&[], &["gw02"]), &host()), None);
+    }
+
+    #[test]
+    fn blank_entries_never_match() {
+        assert!(disabled_reason(&meta(&[""], &[]), &host()).is_some());
+        assert_eq!(disabled_reason(&meta(&[], &["", "."]), &host()), None);
+    }
+}
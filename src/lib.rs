@@ -0,0 +1,70 @@
+//! frpdesk 核心库：frpc 实例的配置、发现、启动、监控与 Windows 服务
+//!
+//! 可执行文件（界面、服务与命令行子命令）基于此库构建。在其他程序中嵌入 frpc 管理时使用
+//! [`FrpcSupervisor`]，它不依赖 SCM；库不会初始化全局 logger，日志输出由调用方控制
+
+pub mod account_check;
+mod acl;
+mod admin_proxy;
+mod alert;
+pub mod av_check;
+mod bundle;
+mod certinfo;
+mod circuit_breaker;
+mod cleanup;
+mod cli;
+mod compare_configs;
+pub mod config;
+pub mod constants;
+#[cfg(feature = "debug")]
+mod debug_attach;
+mod disk_guard;
+pub mod download;
+mod etw;
+mod eventlog;
+mod exe_check;
+mod fingerprint;
+pub mod frpc_mg;
+mod health_check;
+mod host_filter;
+mod log_sampling;
+pub mod logger;
+mod metrics;
+mod minidump;
+mod output_pump;
+mod path_check;
+mod policy;
+mod probe;
+mod profile;
+mod restart;
+mod run_as;
+mod run_marker;
+mod self_check;
+mod service;
+mod signal_files;
+mod snapshot;
+mod stop_diagnostics;
+mod supervisor;
+mod tail_errors;
+mod timeline;
+mod watch_logs;
+
+pub use health_check::HealthStatus;
+pub use supervisor::{FrpcSupervisor, InstanceConfig, SupervisorConfig};
+
+/// 可执行文件（界面与命令行入口）使用的接口，不属于库的 API，随时可能变化
+#[doc(hidden)]
+pub mod app_support {
+    pub mod cli {
+        pub use crate::cli::run;
+    }
+
+    pub mod service {
+        pub use crate::service::{
+            check_service_status, discover_running_frpc_processes, install_service,
+            run_service_dispatcher, send_guard_stopped_command, signal_guard_changed,
+            start_frpc_process_with_sender, start_service, uninstall_service, wait_process_changed,
+            PreCheckResult, ServiceAccount,
+        };
+    }
+}
@@ -0,0 +1,173 @@
+//! 日志采样与单行长度限制（settings.json 的 `log_sampling`），写入日志文件前应用
+//!
+//! 采样按规则顺序匹配 target 前缀与级别，匹配的日志每 `one_in` 条只记录第 1 条；
+//! 按计数取舍而非随机，主日志与实例日志各自计数。WARN 与 ERROR 从不采样。
+//! 超长的消息在字符边界处截断，并注明原长度
+
+use log4rs::filter::{Filter, Response};
+use std::borrow::Cow;
+use std::sync::Mutex;
+
+use crate::config::LogSampling;
+
+/// 生效的采样规则
+#[derive(Debug)]
+struct Rule {
+    target: Option<String>,
+    level: log::Level,
+    one_in: u64,
+}
+
+/// 按采样规则丢弃日志的 log4rs 过滤器；每个 appender 各自计数
+#[derive(Debug)]
+pub(crate) struct SamplingFilter {
+    rules: Vec<Rule>,
+    /// 各规则已匹配的条数
+    counters: Mutex<Vec<u64>>,
+}
+
+impl SamplingFilter {
+    /// 按设置创建过滤器；无效的规则（warn/error、无效级别、one_in 为 0）忽略，
+    /// 没有有效规则时返回 None（`--check-only` 会报告无效规则）
+    pub(crate) fn new(sampling: &LogSampling) -> Option<Self> {
+        let rules: Vec<Rule> = sampling
+            .rules
+            .iter()
+            .filter_map(|rule| {
+                Some(Rule {
+                    target: rule.target.clone(),
+                    level: rule.level().ok()?,
+                    one_in: u64::from(rule.one_in),
+                })
+            })
+            .collect();
+        if rules.is_empty() {
+            return None;
+        }
+        Some(Self {
+            counters: Mutex::new(vec![0; rules.len()]),
+            rules,
+        })
+    }
+
+    /// 是否记录这条日志（推进匹配规则的计数）
+    fn keep(&self, level: log::Level, target: &str) -> bool {
+        if level <= log::Level::Warn {
+            return true;
+        }
+        let Some(index) = self.rules.iter().position(|rule| {
+            rule.level == level
+                && rule
+                    .target
+                    .as_deref()
+                    .is_none_or(|prefix| target.starts_with(prefix))
+        }) else {
+            return true;
+        };
+        let mut counters = self.counters.lock().unwrap();
+        let seen = counters[index];
+        counters[index] = seen.wrapping_add(1);
+        seen.is_multiple_of(self.rules[index].one_in)
+    }
+}
+
+impl Filter for SamplingFilter {
+    fn filter(&self, record: &log::Record) -> Response {
+        if self.keep(record.level(), record.target()) {
+            Response::Neutral
+        } else {
+            Response::Reject
+        }
+    }
+}
+
+/// 把消息截断到 `max_bytes` 字节以内（在字符边界处），0 表示不限制
+pub(crate) fn truncate_message(message: &str, max_bytes: usize) -> Cow<'_, str> {
+    if max_bytes == 0 || message.len() <= max_bytes {
+        return Cow::Borrowed(message);
+    }
+    let mut end = max_bytes;
+    while !message.is_char_boundary(end) {
+        end -= 1;
+    }
+    Cow::Owned(format!(
+        "{}…[已截断，原长 {} 字节]",
+        &message[..end],
+        message.len()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LogSampleRule;
+
+    fn rule(target: Option<&str>, level: &str, one_in: u32) -> LogSampleRule {
+        LogSampleRule {
+            target: target.map(str::to_string),
+            level: level.to_string(),
+            one_in,
+        }
+    }
+
+    fn filter(rules: Vec<LogSampleRule>) -> Option<SamplingFilter> {
+        SamplingFilter::new(&LogSampling {
+            max_line_bytes: 0,
+            rules,
+        })
+    }
+
+    /// 连续 `count` 条日志中被记录的序号（从 0 开始）
+    fn kept(filter: &SamplingFilter, level: log::Level, target: &str, count: usize) -> Vec<usize> {
+        (0..count).filter(|_| filter.keep(level, target)).collect()
+    }
+
+    #[test]
+    fn keeps_first_of_every_n() {
+        let filter = filter(vec![rule(Some("frpc::"), "info", 3)]).unwrap();
+        assert_eq!(kept(&filter, log::Level::Info, "frpc::web", 7), [0, 3, 6]);
+        // 其他 target、其他级别与 warn/error 不采样
+        assert_eq!(kept(&filter, log::Level::Info, "frpdesk", 3), [0, 1, 2]);
+        assert_eq!(kept(&filter, log::Level::Debug, "frpc::web", 3), [0, 1, 2]);
+        assert_eq!(kept(&filter, log::Level::Warn, "frpc::web", 3), [0, 1, 2]);
+    }
+
+    #[test]
+    fn first_matching_rule_counts() {
+        let filter = filter(vec![
+            rule(Some("frpc::web"), "debug", 1),
+            rule(None, "debug", 2),
+        ])
+        .unwrap();
+        assert_eq!(
+            kept(&filter, log::Level::Debug, "frpc::web", 4),
+            [0, 1, 2, 3]
+        );
+        // 第二条规则的计数由所有 target 共用
+        assert!(filter.keep(log::Level::Debug, "frpc::ssh"));
+        assert!(!filter.keep(log::Level::Debug, "frpdesk"));
+        assert!(filter.keep(log::Level::Debug, "frpc::ssh"));
+    }
+
+    #[test]
+    fn invalid_rules_are_ignored() {
+        assert!(filter(vec![]).is_none());
+        assert!(filter(vec![
+            rule(None, "warn", 2),
+            rule(None, "error", 2),
+            rule(None, "verbose", 2),
+            rule(None, "info", 0),
+        ])
+        .is_none());
+    }
+
+    #[test]
+    fn truncates_at_char_boundary() {
+        assert_eq!(truncate_message("short", 0), "short");
+        assert_eq!(truncate_message("short", 5), "short");
+        assert_eq!(truncate_message("abcdef", 4), "abcd…[已截断，原长 6 字节]");
+        // “中”占 3 个字节，不能从中间截断
+        assert_eq!(truncate_message("a中文", 2), "a…[已截断，原长 7 字节]");
+        assert_eq!(truncate_message("a中文", 4), "a中…[已截断，原长 7 字节]");
+    }
+}
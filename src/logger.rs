@@ -1,24 +1,299 @@
-//! 日志配置与清理，按天存储日志并自动清理超过 30 天的日志文件
+//! 日志配置与清理，按天存储日志并按保留策略自动清理
+//!
+//! frpc 输出使用 `frpc::<实例名>` 作为 target，除写入主日志外，
+//! 还会分流到 logs/<实例名>/ 下的独立日志文件
+//!
+//! WARN 及以上级别的日志（含 frpc 输出）另外汇总到 logs/errors-<日期>.log，便于快速查看问题
+//!
+//! 实例标识通过 MDC（`log_mdc`，键为 `instance`）传递，输出为独立的一列：
+//! `时间 [级别] [实例名] 消息`；未设置时省略该列。`LogFormat::Json` 时每行为一个 JSON 对象，
+//! 字段为 `time`、`level`、`instance`（可选）、`message` 与结构化字段
+//!
+//! 配置了 [`SyslogConfig`] 时，主日志的记录（含 frpc 输出）另外以 RFC 5424 格式通过 UDP 发送到 syslog 服务器，
+//! 发送失败直接丢弃，不影响文件日志
+//!
+//! 保留策略、行格式、采样与 syslog 通过 [`LoggingOptions`] 传给 [`init_logging`]
 
 use anyhow::{Context, Result};
-use chrono::Local;
+use chrono::{Local, NaiveDate};
+use log::kv::{self, Key, Value, VisitSource};
 use log::LevelFilter;
 use log4rs::{
     append::Append,
-    config::{Appender, Config, Root},
+    config::{Appender, Config, Logger, Root},
+    filter::threshold::ThresholdFilter,
 };
-use std::env;
+use std::collections::HashMap;
 use std::fs::{self, OpenOptions};
 use std::io::Write;
+use std::net::{SocketAddr, UdpSocket};
 use std::path::{Path, PathBuf};
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
 use std::thread;
 
+use crate::config::{
+    self, AppSettings, FrpcConfigMeta, LogFormat, LogRetention, LogSampling, SyslogSettings,
+};
+use crate::eventlog;
+use crate::log_sampling::{self, SamplingFilter};
+
+/// frpc 输出的日志 target 前缀，完整 target 为 `frpc::<实例名>`
+pub const FRPC_TARGET_PREFIX: &str = "frpc::";
+
+/// 错误汇总日志的文件名前缀，完整文件名为 `errors-<日期>.log`
+const ERRORS_FILE_PREFIX: &str = "errors-";
+
+/// MDC 中实例标识的键，对应 log4rs pattern 中的 `{X(instance)}`
+pub const MDC_INSTANCE: &str = "instance";
+
+/// log4rs 句柄，手动轮转时用于切换到新的日志文件
+static LOG_HANDLE: OnceLock<log4rs::Handle> = OnceLock::new();
+
+/// 初始化时的日志选项，零点轮转与手动轮转重建配置时沿用
+static LOG_OPTIONS: OnceLock<LoggingOptions> = OnceLock::new();
+
+/// 日志选项
+///
+/// 默认为文本格式、保留策略每次清理时从 settings.json 读取（界面中修改后次日生效）；
+/// 通过 [`LoggingOptions::builder`] 指定保留策略后固定使用该策略
+#[derive(Debug, Clone, Default)]
+pub struct LoggingOptions {
+    /// 主日志目录的保留策略，None 表示使用 settings.json 中的 `log_retention`
+    retention: Option<LogRetention>,
+    format: LogFormat,
+    /// 服务自身日志（根 logger）的级别，None 表示 INFO
+    level: Option<LevelFilter>,
+    /// frpc 输出（logger `frpc`）的级别，None 表示与 `level` 相同
+    frpc_level: Option<LevelFilter>,
+    /// 采样规则与单行最大长度，默认不采样、不截断
+    sampling: LogSampling,
+    /// 远程 syslog，None 表示不发送
+    syslog: Option<SyslogConfig>,
+}
+
+impl LoggingOptions {
+    pub fn builder() -> LoggingOptionsBuilder {
+        LoggingOptionsBuilder::default()
+    }
+
+    /// 按 settings.json 中的 `log_format`、`log_level`、`frpc_log_level`、`log_sampling`、`syslog`
+    /// 设置行格式、级别、采样与 syslog，保留策略仍在每次清理时读取；
+    /// 无效的级别、采样规则与 syslog 设置忽略（`--check-only` 会报告）
+    pub fn from_settings(settings: &AppSettings) -> Self {
+        Self {
+            retention: None,
+            format: settings.log_format,
+            level: settings.log_level_filter().ok().flatten(),
+            frpc_level: settings.frpc_log_level_filter().ok().flatten(),
+            sampling: settings.log_sampling.clone(),
+            syslog: settings
+                .syslog
+                .as_ref()
+                .and_then(|s| SyslogConfig::from_settings(s).ok()),
+        }
+    }
+
+    /// 当前的全局保留策略
+    fn retention(&self) -> LogRetention {
+        self.retention
+            .clone()
+            .unwrap_or_else(|| config::load_settings().log_retention)
+    }
+}
+
+/// [`LoggingOptions`] 的构建器，未设置的项使用默认值
+#[derive(Debug, Default)]
+pub struct LoggingOptionsBuilder {
+    retention: Option<LogRetention>,
+    format: LogFormat,
+    level: Option<LevelFilter>,
+    frpc_level: Option<LevelFilter>,
+    sampling: LogSampling,
+    syslog: Option<SyslogConfig>,
+}
+
+impl LoggingOptionsBuilder {
+    /// 保留天数，0 表示不按天数清理
+    pub fn retention_days(mut self, days: u32) -> Self {
+        self.retention
+            .get_or_insert_with(LogRetention::default)
+            .max_days = days;
+        self
+    }
+
+    /// 最多保留的文件数，0 表示不限制
+    pub fn max_files(mut self, files: usize) -> Self {
+        self.retention
+            .get_or_insert_with(LogRetention::default)
+            .max_files = files;
+        self
+    }
+
+    /// 目录内日志总大小上限（MB），0 表示不限制
+    pub fn max_size_mb(mut self, mb: u64) -> Self {
+        self.retention
+            .get_or_insert_with(LogRetention::default)
+            .max_total_mb = mb;
+        self
+    }
+
+    pub fn format(mut self, format: LogFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// 服务自身日志的级别
+    pub fn level(mut self, level: LevelFilter) -> Self {
+        self.level = Some(level);
+        self
+    }
+
+    /// frpc 输出的级别，未设置时与 [`level`](Self::level) 相同
+    pub fn frpc_level(mut self, level: LevelFilter) -> Self {
+        self.frpc_level = Some(level);
+        self
+    }
+
+    /// 采样规则与单行最大长度
+    pub fn sampling(mut self, sampling: LogSampling) -> Self {
+        self.sampling = sampling;
+        self
+    }
+
+    /// 同时把日志发送到远程 syslog 服务器
+    pub fn syslog(mut self, syslog: SyslogConfig) -> Self {
+        self.syslog = Some(syslog);
+        self
+    }
+
+    pub fn build(self) -> LoggingOptions {
+        LoggingOptions {
+            retention: self.retention,
+            format: self.format,
+            level: self.level,
+            frpc_level: self.frpc_level,
+            sampling: self.sampling,
+            syslog: self.syslog,
+        }
+    }
+}
+
+/// 远程 syslog 服务器
+///
+/// 字段不公开，只能通过 [`new`](Self::new) 或 [`from_settings`](Self::from_settings) 构造，
+/// 保证 facility 在 0–23 之内（否则 PRI 超出 RFC 5424 的范围）
+#[derive(Debug, Clone)]
+pub struct SyslogConfig {
+    server: SocketAddr,
+    /// facility 编号（0–23，如 16 为 local0）
+    facility: u8,
+    /// 消息中的 HOSTNAME 字段
+    hostname: String,
+}
+
+impl SyslogConfig {
+    pub fn new(server: SocketAddr, facility: u8, hostname: String) -> Result<Self> {
+        anyhow::ensure!(
+            facility <= 23,
+            "syslog.facility 无效: {}（取值 0–23）",
+            facility
+        );
+        Ok(Self {
+            server,
+            facility,
+            hostname,
+        })
+    }
+
+    /// 由 settings.json 的 `syslog` 构造；未设置主机名时使用本机计算机名
+    pub fn from_settings(settings: &SyslogSettings) -> Result<Self> {
+        let server = settings.server.parse().map_err(|_| {
+            anyhow::anyhow!(
+                "syslog.server 无效: {}（应为 IP:端口，如 10.0.0.5:514）",
+                settings.server
+            )
+        })?;
+        let hostname = settings
+            .hostname
+            .clone()
+            .unwrap_or_else(|| std::env::var("COMPUTERNAME").unwrap_or_default());
+        Self::new(server, settings.facility, hostname)
+    }
+}
+
+fn log_options() -> LoggingOptions {
+    LOG_OPTIONS.get().cloned().unwrap_or_default()
+}
+
+/// 磁盘空间严重不足时暂停写入文件日志（记录被丢弃），由磁盘空间检查设置与恢复
+static FILE_LOGGING_PAUSED: AtomicBool = AtomicBool::new(false);
+
+/// 暂停或恢复写入文件日志，返回之前是否已暂停
+pub fn set_file_logging_paused(paused: bool) -> bool {
+    FILE_LOGGING_PAUSED.swap(paused, Ordering::SeqCst)
+}
+
+/// 同一条消息连续出现超过该次数后不再写入，换成其他消息时补一行重复次数汇总
+const REPEAT_THRESHOLD: u64 = 3;
+
 /// 自适应文件写入器：每次写入时以 append + create 模式打开文件，
 /// 文件被外部删除后下次写入自动重建，无需定期检查。
+///
+/// frpc 重连循环时会连续输出大量相同的行，同一文件中连续重复的消息只写入前
+/// [`REPEAT_THRESHOLD`] 次，之后出现不同消息（或刷新）时写入
+/// `last message repeated N times`；超过 `max_line_bytes` 的消息截断后写入
 struct ResilientWriter {
     path: PathBuf,
+    format: LogFormat,
+    /// 单条消息的最大字节数，0 表示不限制
+    max_line_bytes: usize,
     file: Mutex<Option<fs::File>>,
+    repeat: Mutex<RepeatState>,
+}
+
+/// 连续重复消息的计数
+#[derive(Default)]
+struct RepeatState {
+    /// 上一条消息的比较键（级别 + 实例 + 去掉 frpc 时间戳的消息）
+    key: String,
+    /// 上一条消息的级别与实例，汇总行沿用
+    label: LineLabel,
+    /// 连续出现的次数
+    count: u64,
+}
+
+impl RepeatState {
+    /// 记录一条消息，返回 (需要先写入的汇总：(标签, 被省略的次数)，本条是否写入)
+    fn observe(&mut self, key: String, label: LineLabel) -> (Option<(LineLabel, u64)>, bool) {
+        if key == self.key {
+            self.count += 1;
+            return (None, self.count <= REPEAT_THRESHOLD);
+        }
+        let summary = self.take_summary();
+        self.key = key;
+        self.label = label;
+        self.count = 1;
+        (summary, true)
+    }
+
+    /// 取出尚未写入的汇总并清零
+    fn take_summary(&mut self) -> Option<(LineLabel, u64)> {
+        let suppressed = self.count.saturating_sub(REPEAT_THRESHOLD);
+        self.count = self.count.min(REPEAT_THRESHOLD);
+        (suppressed > 0).then(|| (self.label.clone(), suppressed))
+    }
+}
+
+/// 比较重复时忽略 frpc 输出自带的时间戳（`FRPC STDOUT: 2024-01-01 12:00:00.000 [W] ...`）
+fn repeat_key(message: &str) -> &str {
+    let body = message
+        .strip_prefix("FRPC STDOUT: ")
+        .or_else(|| message.strip_prefix("FRPC STDERR: "))
+        .unwrap_or(message);
+    body.trim_start_matches(|c: char| {
+        c.is_ascii_digit() || matches!(c, ' ' | '-' | '/' | ':' | '.')
+    })
 }
 
 impl std::fmt::Debug for ResilientWriter {
@@ -30,18 +305,27 @@ impl std::fmt::Debug for ResilientWriter {
 }
 
 impl ResilientWriter {
-    fn new(path: PathBuf) -> Self {
+    fn new(path: PathBuf, format: LogFormat, max_line_bytes: usize) -> Self {
         Self {
             path,
+            format,
+            max_line_bytes,
             file: Mutex::new(None),
+            repeat: Mutex::new(RepeatState::default()),
         }
     }
 
     fn write_log(&self, record: &log::Record) {
+        if FILE_LOGGING_PAUSED.load(Ordering::Relaxed) {
+            return;
+        }
         let mut guard = self.file.lock().unwrap();
 
-        // 如果没有文件句柄，尝试打开（不存在则创建）
+        // 如果没有文件句柄，尝试打开（不存在则创建，目录被删除时一并重建）
         if guard.is_none() {
+            if let Some(parent) = self.path.parent() {
+                let _ = fs::create_dir_all(parent);
+            }
             match OpenOptions::new()
                 .create(true)
                 .append(true)
@@ -56,16 +340,106 @@ impl ResilientWriter {
         }
 
         if let Some(ref mut file) = *guard {
-            let timestamp = Local::now().format("%Y-%m-%d %H:%M:%S");
-            let level = record.level();
-            let args = record.args();
-            let line = format!("{} [{}] {}\n", timestamp, level, args);
-            if file.write_all(line.as_bytes()).is_err() {
+            let timestamp = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+            let message = record.args().to_string();
+            let message = log_sampling::truncate_message(&message, self.max_line_bytes);
+            let mut kvs = Vec::new();
+            let _ = record.key_values().visit(&mut KvCollector(&mut kvs));
+            let level = record.level().to_string();
+            let instance = log_mdc::get(MDC_INSTANCE, |instance| instance.map(String::from));
+            let label = LineLabel { level, instance };
+
+            // 结构化字段（如 seq）每行不同，不参与重复比较
+            let key = format!("{} {}", label.text(), repeat_key(&message));
+            let (summary, write) = self.repeat.lock().unwrap().observe(key, label.clone());
+            let mut lines = String::new();
+            if let Some((summary_label, n)) = summary {
+                lines.push_str(&self.format_line(
+                    &timestamp,
+                    &summary_label,
+                    &format!("last message repeated {} times", n),
+                    &[],
+                ));
+            }
+            if write {
+                lines.push_str(&self.format_line(&timestamp, &label, &message, &kvs));
+            }
+            if !lines.is_empty() && file.write_all(lines.as_bytes()).is_err() {
                 // 写入失败（文件可能被删除），丢弃句柄，下次重建
                 *guard = None;
             }
         }
     }
+
+    /// 按行格式生成一行（含换行符）
+    fn format_line(
+        &self,
+        timestamp: &str,
+        label: &LineLabel,
+        message: &str,
+        kvs: &[(String, String)],
+    ) -> String {
+        match self.format {
+            LogFormat::Text => {
+                // 结构化字段（log::kv）以 ` key=value` 形式追加在消息之后
+                let fields: String = kvs.iter().map(|(k, v)| format!(" {}={}", k, v)).collect();
+                format!("{} {} {}{}\n", timestamp, label.text(), message, fields)
+            }
+            LogFormat::Json => {
+                let mut object = serde_json::Map::new();
+                object.insert("time".into(), timestamp.into());
+                object.insert("level".into(), label.level.as_str().into());
+                if let Some(ref instance) = label.instance {
+                    object.insert("instance".into(), instance.as_str().into());
+                }
+                object.insert("message".into(), message.into());
+                for (k, v) in kvs {
+                    object.entry(k.clone()).or_insert_with(|| v.as_str().into());
+                }
+                format!("{}\n", serde_json::Value::Object(object))
+            }
+        }
+    }
+
+    /// 写入尚未输出的重复汇总（刷新/退出时调用，避免最后一段重复被悄悄吞掉）
+    fn write_pending_summary(&self, file: &mut fs::File) {
+        if let Some((label, n)) = self.repeat.lock().unwrap().take_summary() {
+            let line = self.format_line(
+                &Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+                &label,
+                &format!("last message repeated {} times", n),
+                &[],
+            );
+            let _ = file.write_all(line.as_bytes());
+        }
+    }
+}
+
+/// 一行日志的级别与实例列
+#[derive(Debug, Clone, Default)]
+struct LineLabel {
+    level: String,
+    instance: Option<String>,
+}
+
+impl LineLabel {
+    /// 文本格式中的 `[级别] [实例]` 列
+    fn text(&self) -> String {
+        match self.instance {
+            Some(ref instance) => format!("[{}] [{}]", self.level, instance),
+            None => format!("[{}]", self.level),
+        }
+    }
+}
+
+/// 收集日志记录中的键值对
+struct KvCollector<'a>(&'a mut Vec<(String, String)>);
+
+impl<'kvs> VisitSource<'kvs> for KvCollector<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        self.0.push((key.to_string(), value.to_string()));
+        Ok(())
+    }
 }
 
 impl Append for ResilientWriter {
@@ -75,31 +449,231 @@ impl Append for ResilientWriter {
     }
 
     fn flush(&self) {
-        let guard = self.file.lock().unwrap();
-        if let Some(ref file) = *guard {
+        let mut guard = self.file.lock().unwrap();
+        if let Some(ref mut file) = *guard {
+            self.write_pending_summary(file);
             let _ = file.sync_all();
         }
     }
 }
 
-/// 初始化日志系统，并启动后台线程在每天零点自动切换日志文件
-pub fn init_logging() -> Result<()> {
-    let exe_path = env::current_exe().context("无法获取可执行文件路径")?;
-    let exe_dir = exe_path.parent().context("无法获取可执行文件目录")?;
+/// 实例日志写入器：按 target 中的实例名分流到 logs/<实例名>/<日期>.log
+struct InstanceLogWriter {
+    logs_dir: PathBuf,
+    date: String,
+    format: LogFormat,
+    max_line_bytes: usize,
+    writers: Mutex<HashMap<String, Arc<ResilientWriter>>>,
+}
+
+impl std::fmt::Debug for InstanceLogWriter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InstanceLogWriter")
+            .field("logs_dir", &self.logs_dir)
+            .field("date", &self.date)
+            .finish()
+    }
+}
+
+impl InstanceLogWriter {
+    fn new(logs_dir: PathBuf, date: String, format: LogFormat, max_line_bytes: usize) -> Self {
+        Self {
+            logs_dir,
+            date,
+            format,
+            max_line_bytes,
+            writers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 获取实例对应的写入器，首次使用时创建
+    fn writer_for(&self, instance: &str) -> Arc<ResilientWriter> {
+        let mut writers = self.writers.lock().unwrap();
+        writers
+            .entry(instance.to_string())
+            .or_insert_with(|| {
+                let path = self
+                    .logs_dir
+                    .join(instance)
+                    .join(format!("{}.log", self.date));
+                Arc::new(ResilientWriter::new(path, self.format, self.max_line_bytes))
+            })
+            .clone()
+    }
+}
+
+impl Append for InstanceLogWriter {
+    fn append(&self, record: &log::Record) -> anyhow::Result<()> {
+        if let Some(instance) = record.target().strip_prefix(FRPC_TARGET_PREFIX) {
+            if !instance.is_empty() {
+                self.writer_for(instance).write_log(record);
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&self) {
+        for writer in self.writers.lock().unwrap().values() {
+            writer.flush();
+        }
+    }
+}
+
+/// syslog 消息中的 APP-NAME
+const SYSLOG_APP_NAME: &str = "frpdesk";
+
+/// 单个 syslog UDP 报文的最大字节数（RFC 5426 要求接收方至少支持 2048 字节），超出的消息截断
+const SYSLOG_MAX_DATAGRAM: usize = 2048;
+
+/// 结构化数据的 SD-ID，使用 RFC 5612 为文档保留的企业编号
+const SYSLOG_SD_ID: &str = "frpdesk@32473";
+
+/// syslog 写入器：每条记录按 RFC 5424 格式化后作为一个 UDP 报文发送
+///
+/// 套接字在首次发送时创建，创建或发送失败时丢弃该条记录，下次重试
+struct SyslogWriter {
+    config: SyslogConfig,
+    socket: Mutex<Option<UdpSocket>>,
+}
+
+impl std::fmt::Debug for SyslogWriter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SyslogWriter")
+            .field("server", &self.config.server)
+            .finish()
+    }
+}
+
+impl SyslogWriter {
+    fn new(config: SyslogConfig) -> Self {
+        Self {
+            config,
+            socket: Mutex::new(None),
+        }
+    }
+
+    /// `<PRI>1 TIMESTAMP HOSTNAME APP-NAME PROCID MSGID [SD] BOM MSG`；
+    /// 实例标识放在结构化数据的 `instance` 参数中（实例名可能含非 ASCII 字符，不适合作为 MSGID）
+    fn format(&self, record: &log::Record) -> Vec<u8> {
+        let severity = match record.level() {
+            log::Level::Error => 3,
+            log::Level::Warn => 4,
+            log::Level::Info => 6,
+            log::Level::Debug | log::Level::Trace => 7,
+        };
+        let instance =
+            log_mdc::get(MDC_INSTANCE, |instance| instance.map(String::from)).or_else(|| {
+                record
+                    .target()
+                    .strip_prefix(FRPC_TARGET_PREFIX)
+                    .filter(|s| !s.is_empty())
+                    .map(str::to_string)
+            });
+        let structured = match instance {
+            Some(instance) => format!(
+                "[{} instance=\"{}\"]",
+                SYSLOG_SD_ID,
+                escape_sd_value(&instance)
+            ),
+            None => "-".to_string(),
+        };
+        let header = format!(
+            "<{}>1 {} {} {} {} - {} \u{feff}",
+            u16::from(self.config.facility) * 8 + severity,
+            Local::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, false),
+            syslog_field(&self.config.hostname, 255),
+            SYSLOG_APP_NAME,
+            std::process::id(),
+            structured
+        );
+        let mut message = header.into_bytes();
+        let body = record.args().to_string();
+        let room = SYSLOG_MAX_DATAGRAM.saturating_sub(message.len());
+        message.extend_from_slice(truncate_at_char_boundary(&body, room).as_bytes());
+        message
+    }
+
+    fn send(&self, datagram: &[u8]) {
+        let mut guard = self.socket.lock().unwrap();
+        if guard.is_none() {
+            let local: SocketAddr = if self.config.server.is_ipv4() {
+                ([0, 0, 0, 0], 0).into()
+            } else {
+                ([0u16; 8], 0).into()
+            };
+            *guard = UdpSocket::bind(local).ok();
+        }
+        if let Some(ref socket) = *guard {
+            if socket.send_to(datagram, self.config.server).is_err() {
+                // 网络变化后重新创建套接字
+                *guard = None;
+            }
+        }
+    }
+}
 
-    let logs_dir = exe_dir.join("logs");
+impl Append for SyslogWriter {
+    fn append(&self, record: &log::Record) -> anyhow::Result<()> {
+        self.send(&self.format(record));
+        Ok(())
+    }
+
+    fn flush(&self) {}
+}
+
+/// HOSTNAME 等头部字段只能是不含空格的可打印 ASCII，空值写为 `-`
+fn syslog_field(value: &str, max_len: usize) -> String {
+    let field: String = value
+        .chars()
+        .filter(|c| c.is_ascii_graphic())
+        .take(max_len)
+        .collect();
+    if field.is_empty() {
+        "-".to_string()
+    } else {
+        field
+    }
+}
+
+/// SD-PARAM 值中的 `"`、`\`、`]` 需要转义
+fn escape_sd_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace(']', "\\]")
+}
+
+/// 截断到报文剩余空间（在字符边界处），不追加截断说明以免再次超出
+fn truncate_at_char_boundary(s: &str, max_bytes: usize) -> &str {
+    if s.len() <= max_bytes {
+        return s;
+    }
+    let mut end = max_bytes;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+/// 初始化日志系统，并启动后台线程在每天零点自动切换日志文件
+///
+/// 只能调用一次；选项在之后的轮转中沿用
+pub fn init_logging(options: LoggingOptions) -> Result<log4rs::Handle> {
+    let logs_dir = config::logs_dir()?;
     fs::create_dir_all(&logs_dir).context("无法创建日志目录")?;
+    let _ = LOG_OPTIONS.set(options);
 
     // 构建今天的日志配置
     let config = build_log_config(&logs_dir)?;
 
     let handle = log4rs::init_config(config).context("无法初始化日志")?;
+    let _ = LOG_HANDLE.set(handle.clone());
 
     // 确认日志文件已创建并写入首条记录
     log::info!("日志系统初始化完成，日志目录: {:?}", logs_dir);
 
-    // 首次启动时清理超过 30 天的旧日志
-    let _ = clean_old_logs(&logs_dir);
+    // 首次启动时按保留策略清理旧日志
+    clean_all_logs(&logs_dir);
 
     // 启动后台线程：在每天零点切换到新的日志文件并清理过期日志
     let handle_clone = handle.clone();
@@ -107,22 +681,130 @@ pub fn init_logging() -> Result<()> {
         log_rotation_loop(handle_clone, &logs_dir);
     });
 
-    Ok(())
+    Ok(handle)
+}
+
+/// 日志初始化失败时的回退诊断：写入 `%TEMP%\frpdesk-logging-error.log` 并记录一条事件日志
+///
+/// 此时 log4rs 尚未初始化，log 宏的输出会被丢弃；程序为 windows 子系统没有控制台，
+/// 不留下记录的话用户完全看不到服务为何退出
+pub fn report_init_failure(err: &anyhow::Error) {
+    let logs_dir = config::logs_dir()
+        .map(|p| p.display().to_string())
+        .unwrap_or_else(|_| "未知".to_string());
+    let message = format!(
+        "frpdesk 无法初始化日志（日志目录: {}），程序退出。请检查该目录是否存在且当前账户可写。原因: {:?}",
+        logs_dir, err
+    );
+
+    let temp_file = std::env::temp_dir().join("frpdesk-logging-error.log");
+    let _ = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&temp_file)
+        .and_then(|mut f| {
+            writeln!(
+                f,
+                "{} [ERROR] {}",
+                Local::now().format("%Y-%m-%d %H:%M:%S"),
+                message
+            )
+        });
+    eventlog::report_error(&message);
 }
 
 /// 构建指向当天日志文件的 Config
 fn build_log_config(logs_dir: &Path) -> Result<Config> {
+    let configs = config::load_configs().unwrap_or_default();
+    log_config(logs_dir, &log_options(), &configs)
+}
+
+/// 按日志选项与实例清单（各实例的 `log_level`）构建 Config
+fn log_config(
+    logs_dir: &Path,
+    options: &LoggingOptions,
+    configs: &[FrpcConfigMeta],
+) -> Result<Config> {
     let today = Local::now().format("%Y-%m-%d").to_string();
     let log_file = logs_dir.join(format!("{}.log", today));
 
-    let writer = ResilientWriter::new(log_file);
+    let errors_file = logs_dir.join(format!("{}{}.log", ERRORS_FILE_PREFIX, today));
+
+    let format = options.format;
+    let level = options.level.unwrap_or(LevelFilter::Info);
+    let frpc_level = options.frpc_level.unwrap_or(level);
+    let max_line_bytes = options.sampling.max_line_bytes;
+    let writer = ResilientWriter::new(log_file, format, max_line_bytes);
+    let errors_writer = ResilientWriter::new(errors_file, format, max_line_bytes);
+    let instance_writer =
+        InstanceLogWriter::new(logs_dir.to_path_buf(), today, format, max_line_bytes);
+
+    // 采样只影响 INFO 及以下级别，错误汇总日志只接收 WARN 及以上，不需要采样；
+    // 主日志与实例日志各自计数
+    let sampled = |name: &str, append: Box<dyn Append>| {
+        let builder = Appender::builder();
+        match SamplingFilter::new(&options.sampling) {
+            Some(filter) => builder.filter(Box::new(filter)).build(name, append),
+            None => builder.build(name, append),
+        }
+    };
+
+    let mut root = Root::builder().appender("logfile").appender("errors");
+    let mut builder = Config::builder();
+    if let Some(ref syslog) = options.syslog {
+        builder = builder.appender(sampled(
+            "syslog",
+            Box::new(SyslogWriter::new(syslog.clone())),
+        ));
+        root = root.appender("syslog");
+    }
 
-    Config::builder()
-        .appender(Appender::builder().build("logfile", Box::new(writer)))
-        .build(Root::builder().appender("logfile").build(LevelFilter::Info))
+    builder
+        .appender(sampled("logfile", Box::new(writer)))
+        .appender(
+            Appender::builder()
+                .filter(Box::new(ThresholdFilter::new(LevelFilter::Warn)))
+                .build("errors", Box::new(errors_writer)),
+        )
+        .appender(sampled("instances", Box::new(instance_writer)))
+        // frpc::<实例名> 同时写入主日志（additive）与实例日志；级别由 frpc 的 logger 决定，
+        // 不受根 logger 的级别影响
+        .logger(
+            Logger::builder()
+                .appender("instances")
+                .build("frpc", frpc_level),
+        )
+        .loggers(instance_loggers(configs))
+        .build(root.build(level))
         .context("无法构建日志配置")
 }
 
+/// 单独设置了 `log_level` 的实例的 logger（`frpc::<实例名>`，沿用上级 `frpc` 的输出），
+/// 未设置的实例继承 `frpc` 的级别（settings.json 的 `frpc_log_level`，默认与 `log_level` 相同）；
+/// 无效的级别忽略（`--check-only` 会报告）
+fn instance_loggers(configs: &[FrpcConfigMeta]) -> Vec<Logger> {
+    configs
+        .iter()
+        .filter_map(|meta| {
+            let level = meta.log_level_filter().ok().flatten()?;
+            Some(Logger::builder().build(format!("{}{}", FRPC_TARGET_PREFIX, meta.name), level))
+        })
+        .collect()
+}
+
+/// 按实例清单重新应用各实例的日志级别（服务重新加载实例清单时调用）
+///
+/// 重建日志配置，日志文件不变；日志尚未初始化时不做任何事
+pub fn reload_instance_levels() {
+    let Some(handle) = LOG_HANDLE.get() else {
+        return;
+    };
+    match config::logs_dir().and_then(|logs_dir| build_log_config(&logs_dir)) {
+        Ok(new_config) => handle.set_config(new_config),
+        Err(e) => log::error!("重新应用实例日志级别失败: {:#}", e),
+    }
+}
+
 /// 后台日志轮转循环：每天零点切换到新的日志文件并清理过期日志
 fn log_rotation_loop(handle: log4rs::Handle, logs_dir: &Path) {
     let mut last_date = Local::now().format("%Y-%m-%d").to_string();
@@ -147,7 +829,7 @@ fn log_rotation_loop(handle: log4rs::Handle, logs_dir: &Path) {
                 Ok(new_config) => {
                     handle.set_config(new_config);
                     log::info!("日志文件已切换到 {}", today);
-                    let _ = clean_old_logs(logs_dir);
+                    clean_all_logs(logs_dir);
                     last_date = today;
                 }
                 Err(e) => eprintln!("日志轮转失败: {:?}", e),
@@ -156,33 +838,614 @@ fn log_rotation_loop(handle: log4rs::Handle, logs_dir: &Path) {
     }
 }
 
-/// 清理超过 30 天的日志文件（按文件名中的日期判断，格式 YYYY-MM-DD.log）
-fn clean_old_logs(logs_dir: &Path) -> Result<()> {
-    let cutoff = (Local::now() - chrono::Duration::days(30)).date_naive();
+/// 立即轮转日志：将当天的主日志与各实例日志重命名为 `<日期>-manual-<时分秒>.log`
+/// （错误汇总日志为 `errors-<日期>-manual-<时分秒>.log`），
+/// 之后的日志写入新的当天日志文件，返回归档后的文件列表
+///
+/// 在已初始化日志的进程（服务）中调用时切换 log4rs 到新文件；
+/// 否则（命令行直接轮转）只重命名并创建空的当天日志文件
+pub fn rotate_logs_now() -> Result<Vec<PathBuf>> {
+    let logs_dir = config::logs_dir()?;
+    let now = Local::now();
+    let today = now.format("%Y-%m-%d").to_string();
+    let archived_name = format!("{}-manual-{}.log", today, now.format("%H%M%S"));
+    let current_name = format!("{}.log", today);
 
-    let entries = fs::read_dir(logs_dir).context("无法列出日志目录")?;
+    let mut dirs = vec![logs_dir.clone()];
+    if let Ok(entries) = fs::read_dir(&logs_dir) {
+        dirs.extend(entries.flatten().map(|e| e.path()).filter(|p| p.is_dir()));
+    }
+    let mut files: Vec<(PathBuf, PathBuf)> = dirs
+        .iter()
+        .map(|dir| (dir.join(&current_name), dir.join(&archived_name)))
+        .collect();
+    files.push((
+        logs_dir.join(format!("{}{}", ERRORS_FILE_PREFIX, current_name)),
+        logs_dir.join(format!("{}{}", ERRORS_FILE_PREFIX, archived_name)),
+    ));
+    let mut archived = Vec::new();
+    for (current, target) in files {
+        if !current.exists() {
+            continue;
+        }
+        // 打开的日志句柄允许重命名，切换配置前的日志仍写入归档文件
+        fs::rename(&current, &target).context(format!("无法重命名日志文件 {:?}", current))?;
+        archived.push(target);
+    }
 
+    match LOG_HANDLE.get() {
+        Some(handle) => {
+            handle.set_config(build_log_config(&logs_dir)?);
+            log::info!("日志已手动轮转，归档 {} 个文件", archived.len());
+        }
+        None => {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(logs_dir.join(&current_name))
+                .context("无法创建新的日志文件")?;
+        }
+    }
+    Ok(archived)
+}
+
+/// 磁盘空间不足时的清理：主目录与各实例目录的普通日志、错误汇总日志都只保留最新的一个文件，返回删除的字节数
+pub fn clean_logs_aggressively(logs_dir: &Path) -> u64 {
+    let policy = LogRetention {
+        max_days: 0,
+        max_files: 1,
+        max_total_mb: 0,
+    };
+    let mut dirs = vec![logs_dir.to_path_buf()];
+    if let Ok(entries) = fs::read_dir(logs_dir) {
+        dirs.extend(entries.flatten().map(|e| e.path()).filter(|p| p.is_dir()));
+    }
+    let size_of = |dir: &Path| -> u64 {
+        fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .filter_map(|e| e.metadata().ok())
+                    .filter(|m| m.is_file())
+                    .map(|m| m.len())
+                    .sum()
+            })
+            .unwrap_or(0)
+    };
+    let mut freed = 0u64;
+    for dir in dirs {
+        let before = size_of(&dir);
+        if let Err(e) = clean_logs(&dir, &policy) {
+            eprintln!("清理日志目录 {:?} 失败: {:?}", dir, e);
+        }
+        freed += before.saturating_sub(size_of(&dir));
+    }
+    freed
+}
+
+/// 按保留策略清理所有日志目录
+///
+/// 主目录 logs/ 使用全局策略；logs/<实例名>/ 使用该实例的策略，未设置时使用全局策略
+fn clean_all_logs(logs_dir: &Path) {
+    let configs = config::load_configs().unwrap_or_default();
+    clean_log_dirs(logs_dir, &log_options().retention(), &configs);
+}
+
+/// 按全局策略 `retention` 清理 logs/，按 `configs` 中各实例的策略清理 logs/<实例名>/
+fn clean_log_dirs(logs_dir: &Path, retention: &LogRetention, configs: &[FrpcConfigMeta]) {
+    if let Err(e) = clean_logs(logs_dir, retention) {
+        eprintln!("清理日志目录 {:?} 失败: {:?}", logs_dir, e);
+    }
+
+    let entries = match fs::read_dir(logs_dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
     for entry in entries.flatten() {
-        let file_name = entry.file_name();
-        let name = file_name.to_string_lossy();
+        let path = entry.path();
+        if !path.is_dir() {
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let policy = configs
+            .iter()
+            .find(|c| c.name == name)
+            .and_then(|c| c.log_retention.clone())
+            .unwrap_or_else(|| retention.clone());
+        if let Err(e) = clean_logs(&path, &policy) {
+            eprintln!("清理日志目录 {:?} 失败: {:?}", path, e);
+        }
+    }
+}
 
-        // 只处理 YYYY-MM-DD.log 格式的文件
-        let date_str = match name.strip_suffix(".log") {
-            Some(s) => s,
+/// 按保留策略清理单个目录中的日志文件（文件名以 YYYY-MM-DD 开头、以 .log 结尾）
+///
+/// 错误汇总日志（`errors-YYYY-MM-DD*.log`）与普通日志分别按同一策略清理，互不占用文件数与大小配额。
+/// 从新到旧依次判断：超过保留天数、超出文件数、累计大小超限的文件被删除。
+/// 每组中最新的文件（当前正在写入）始终保留。
+pub fn clean_logs(dir: &Path, policy: &LogRetention) -> Result<()> {
+    let mut logs: Vec<(NaiveDate, String, PathBuf, u64)> = Vec::new();
+    let mut errors: Vec<(NaiveDate, String, PathBuf, u64)> = Vec::new();
+    for entry in fs::read_dir(dir).context("无法列出日志目录")?.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !name.ends_with(".log") {
+            continue;
+        }
+        let (group, dated) = match name.strip_prefix(ERRORS_FILE_PREFIX) {
+            Some(rest) => (&mut errors, rest),
+            None => (&mut logs, name.as_str()),
+        };
+        let date = match dated
+            .get(..10)
+            .and_then(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d").ok())
+        {
+            Some(d) => d,
             None => continue,
         };
+        let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+        group.push((date, name.clone(), entry.path(), size));
+    }
 
-        let file_date = match chrono::NaiveDate::parse_from_str(date_str, "%Y-%m-%d") {
-            Ok(d) => d,
-            Err(_) => continue,
-        };
+    clean_group(logs, policy);
+    clean_group(errors, policy);
+    Ok(())
+}
 
-        if file_date < cutoff {
-            if let Err(e) = fs::remove_file(entry.path()) {
-                eprintln!("删除旧日志 {:?} 失败: {}", entry.path(), e);
-            }
+fn clean_group(mut files: Vec<(NaiveDate, String, PathBuf, u64)>, policy: &LogRetention) {
+    // 新的在前
+    files.sort_by(|a, b| (b.0, &b.1).cmp(&(a.0, &a.1)));
+
+    let cutoff = (Local::now() - chrono::Duration::days(policy.max_days as i64)).date_naive();
+    let limit_bytes = policy.max_total_mb * 1024 * 1024;
+    let mut kept = 0usize;
+    let mut total = 0u64;
+    let mut over_size = false;
+
+    for (i, (date, _, path, size)) in files.iter().enumerate() {
+        if i > 0 && limit_bytes > 0 && total + size > limit_bytes {
+            over_size = true;
+        }
+        let expired = policy.max_days > 0 && *date < cutoff;
+        let over_count = policy.max_files > 0 && kept >= policy.max_files;
+        if i == 0 || !(expired || over_count || over_size) {
+            kept += 1;
+            total += size;
+        } else if let Err(e) = fs::remove_file(path) {
+            eprintln!("删除旧日志 {:?} 失败: {}", path, e);
         }
     }
+}
 
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::{Level, Log, Metadata};
+
+    /// 按给定选项与实例清单构建的 logger 是否会记录该 target 与级别的日志
+    fn enabled(logger: &log4rs::Logger, target: &str, level: Level) -> bool {
+        logger.enabled(&Metadata::builder().target(target).level(level).build())
+    }
+
+    fn logger(options: LoggingOptions, configs: &[FrpcConfigMeta]) -> log4rs::Logger {
+        let logs_dir = std::env::temp_dir().join("frpdesk-logger-test");
+        log4rs::Logger::new(log_config(&logs_dir, &options, configs).unwrap())
+    }
+
+    #[test]
+    fn service_and_frpc_levels_are_independent() {
+        let logger = self::logger(
+            LoggingOptions::builder()
+                .level(LevelFilter::Debug)
+                .frpc_level(LevelFilter::Warn)
+                .build(),
+            &[],
+        );
+        assert!(enabled(&logger, "frpdesk::service", Level::Debug));
+        assert!(!enabled(&logger, "frpdesk::service", Level::Trace));
+        assert!(enabled(&logger, "frpc::web", Level::Warn));
+        assert!(!enabled(&logger, "frpc::web", Level::Info));
+
+        let logger = self::logger(
+            LoggingOptions::builder()
+                .level(LevelFilter::Warn)
+                .frpc_level(LevelFilter::Debug)
+                .build(),
+            &[],
+        );
+        assert!(!enabled(&logger, "frpdesk::service", Level::Info));
+        assert!(enabled(&logger, "frpc::web", Level::Debug));
+    }
+
+    #[test]
+    fn frpc_level_defaults_to_service_level() {
+        let logger = self::logger(
+            LoggingOptions::builder().level(LevelFilter::Error).build(),
+            &[],
+        );
+        assert!(enabled(&logger, "frpc::web", Level::Error));
+        assert!(!enabled(&logger, "frpc::web", Level::Warn));
+
+        let logger = self::logger(LoggingOptions::default(), &[]);
+        assert!(enabled(&logger, "frpdesk::service", Level::Info));
+        assert!(!enabled(&logger, "frpdesk::service", Level::Debug));
+        assert!(enabled(&logger, "frpc::web", Level::Info));
+        assert!(!enabled(&logger, "frpc::web", Level::Debug));
+    }
+
+    fn instance(name: &str, log_level: Option<&str>) -> FrpcConfigMeta {
+        FrpcConfigMeta {
+            name: name.to_string(),
+            log_level: log_level.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn instance_level_overrides_frpc_level() {
+        let logger = self::logger(
+            LoggingOptions::builder()
+                .frpc_level(LevelFilter::Warn)
+                .build(),
+            &[
+                instance("web", Some("debug")),
+                instance("ssh", Some("error")),
+                instance("rdp", None),
+                instance("bad", Some("verbose")),
+            ],
+        );
+        assert!(enabled(&logger, "frpc::web", Level::Debug));
+        assert!(!enabled(&logger, "frpc::web", Level::Trace));
+        assert!(enabled(&logger, "frpc::ssh", Level::Error));
+        assert!(!enabled(&logger, "frpc::ssh", Level::Warn));
+        // 未设置或级别无效的实例继承 frpc 的级别
+        for name in ["frpc::rdp", "frpc::bad"] {
+            assert!(enabled(&logger, name, Level::Warn));
+            assert!(!enabled(&logger, name, Level::Info));
+        }
+        // 名称为前缀的其他实例不受影响
+        assert!(!enabled(&logger, "frpc::webapp", Level::Info));
+    }
+
+    #[test]
+    fn warnings_and_errors_are_collected_in_errors_file() {
+        let logs_dir =
+            std::env::temp_dir().join(format!("frpdesk-logger-errors-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&logs_dir);
+        let logger =
+            log4rs::Logger::new(log_config(&logs_dir, &LoggingOptions::default(), &[]).unwrap());
+        for (target, level, message) in [
+            ("frpdesk::service", Level::Info, "服务已启动"),
+            ("frpdesk::service", Level::Warn, "磁盘空间不足"),
+            ("frpc::web", Level::Info, "login to server success"),
+            ("frpc::web", Level::Error, "进程意外退出"),
+        ] {
+            logger.log(
+                &log::Record::builder()
+                    .target(target)
+                    .level(level)
+                    .args(format_args!("{}", message))
+                    .build(),
+            );
+        }
+        Log::flush(&logger);
+
+        let today = Local::now().format("%Y-%m-%d").to_string();
+        let read = |name: String| fs::read_to_string(logs_dir.join(name)).unwrap();
+        let main = read(format!("{}.log", today));
+        let errors = read(format!("{}{}.log", ERRORS_FILE_PREFIX, today));
+        assert_eq!(main.lines().count(), 4);
+        let collected: Vec<_> = errors.lines().collect();
+        assert_eq!(collected.len(), 2);
+        assert!(collected[0].contains("[WARN]") && collected[0].ends_with("磁盘空间不足"));
+        assert!(collected[1].contains("[ERROR]") && collected[1].ends_with("进程意外退出"));
+        let _ = fs::remove_dir_all(&logs_dir);
+    }
+
+    fn record(writer: &ResilientWriter, message: &str) {
+        writer.write_log(
+            &log::Record::builder()
+                .target("frpc::web")
+                .level(Level::Info)
+                .args(format_args!("{}", message))
+                .build(),
+        );
+    }
+
+    #[test]
+    fn instance_column_comes_from_scoped_mdc() {
+        let dir = std::env::temp_dir().join(format!("frpdesk-logger-mdc-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let text = ResilientWriter::new(dir.join("text.log"), LogFormat::Text, 0);
+        let json = ResilientWriter::new(dir.join("json.log"), LogFormat::Json, 0);
+        {
+            let _instance = log_mdc::insert_scoped(MDC_INSTANCE, "web");
+            record(&text, "login to server success");
+            record(&json, "login to server success");
+        }
+        // 作用域结束后不再带实例列
+        record(&text, "服务已启动");
+        record(&json, "服务已启动");
+
+        let text = fs::read_to_string(dir.join("text.log")).unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        assert!(
+            lines[0].ends_with(" [INFO] [web] login to server success"),
+            "{}",
+            lines[0]
+        );
+        assert!(lines[1].ends_with(" [INFO] 服务已启动"), "{}", lines[1]);
+        let json: Vec<serde_json::Value> = fs::read_to_string(dir.join("json.log"))
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(json[0]["instance"], "web");
+        assert_eq!(json[0]["message"], "login to server success");
+        assert!(json[1].get("instance").is_none());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    fn written_lines(dir: &Path, file: &str) -> Vec<String> {
+        fs::read_to_string(dir.join(file))
+            .unwrap()
+            .lines()
+            .map(|line| line.split_once(" [").unwrap().1.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn repeated_lines_are_suppressed_after_threshold() {
+        let dir =
+            std::env::temp_dir().join(format!("frpdesk-logger-repeat-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let writer = ResilientWriter::new(dir.join("repeat.log"), LogFormat::Text, 0);
+        for _ in 0..5 {
+            record(&writer, "connect to server error");
+        }
+        // 换成其他消息时补一行汇总
+        record(&writer, "login to server success");
+        for _ in 0..4 {
+            record(&writer, "connect to server error");
+        }
+        // 刷新时写入最后一段重复的汇总，汇总后计数不再重复输出
+        Append::flush(&writer);
+        Append::flush(&writer);
+
+        assert_eq!(
+            written_lines(&dir, "repeat.log"),
+            [
+                "INFO] connect to server error",
+                "INFO] connect to server error",
+                "INFO] connect to server error",
+                "INFO] last message repeated 2 times",
+                "INFO] login to server success",
+                "INFO] connect to server error",
+                "INFO] connect to server error",
+                "INFO] connect to server error",
+                "INFO] last message repeated 1 times",
+            ]
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn repeat_key_ignores_frpc_timestamps() {
+        assert_eq!(
+            repeat_key("FRPC STDOUT: 2024-01-01 12:00:00.000 [W] [web] connect to server error"),
+            "[W] [web] connect to server error"
+        );
+        assert_eq!(
+            repeat_key("FRPC STDERR: 2024/01/02 08:30:01 [E] login failed"),
+            repeat_key("FRPC STDERR: 2024/01/02 08:30:07 [E] login failed")
+        );
+        // 不是 frpc 输出的消息原样比较
+        assert_eq!(repeat_key("服务已启动"), "服务已启动");
+    }
+
+    #[test]
+    fn repeats_are_counted_per_instance_and_ignore_timestamps() {
+        let dir = std::env::temp_dir().join(format!(
+            "frpdesk-logger-repeat-instance-{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        let writer = ResilientWriter::new(dir.join("repeat.log"), LogFormat::Text, 0);
+        for second in 0..5 {
+            record(
+                &writer,
+                &format!(
+                    "FRPC STDOUT: 2024-01-01 12:00:0{}.000 [W] connect to server error",
+                    second
+                ),
+            );
+        }
+        // 相同内容但实例不同，不算重复
+        for instance in ["web", "ssh"] {
+            let _instance = log_mdc::insert_scoped(MDC_INSTANCE, instance);
+            record(&writer, "connect to server error");
+        }
+        Append::flush(&writer);
+
+        let lines = written_lines(&dir, "repeat.log");
+        assert_eq!(lines.len(), 6, "{:?}", lines);
+        assert!(lines[2].ends_with("12:00:02.000 [W] connect to server error"));
+        assert_eq!(lines[3], "INFO] last message repeated 2 times");
+        assert_eq!(lines[4], "INFO] [web] connect to server error");
+        assert_eq!(lines[5], "INFO] [ssh] connect to server error");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    fn syslog_writer(facility: u8) -> SyslogWriter {
+        let server = ([127, 0, 0, 1], 514).into();
+        SyslogWriter::new(SyslogConfig::new(server, facility, "my host".to_string()).unwrap())
+    }
+
+    fn syslog_datagram(writer: &SyslogWriter, level: Level, message: &str) -> String {
+        let datagram = writer.format(
+            &log::Record::builder()
+                .target("frpc::web")
+                .level(level)
+                .args(format_args!("{}", message))
+                .build(),
+        );
+        String::from_utf8(datagram).unwrap()
+    }
+
+    #[test]
+    fn syslog_pri_combines_facility_and_severity() {
+        let local0 = syslog_writer(16);
+        assert!(syslog_datagram(&local0, Level::Info, "x").starts_with("<134>1 "));
+        assert!(syslog_datagram(&local0, Level::Error, "x").starts_with("<131>1 "));
+        assert!(syslog_datagram(&local0, Level::Trace, "x").starts_with("<135>1 "));
+        assert!(syslog_datagram(&syslog_writer(23), Level::Warn, "x").starts_with("<188>1 "));
+
+        // 主机名中的空格去掉，消息前有 BOM
+        let datagram = syslog_datagram(&local0, Level::Info, "login to server success");
+        let fields: Vec<_> = datagram.splitn(7, ' ').collect();
+        assert_eq!(fields[2], "myhost");
+        assert_eq!(fields[3], SYSLOG_APP_NAME);
+        assert!(datagram.ends_with("\u{feff}login to server success"));
+    }
+
+    #[test]
+    fn syslog_facility_out_of_range_is_rejected() {
+        let server = ([127, 0, 0, 1], 514).into();
+        assert!(SyslogConfig::new(server, 24, String::new()).is_err());
+        assert!(SyslogConfig::new(server, 0, String::new()).is_ok());
+    }
+
+    #[test]
+    fn syslog_instance_is_escaped_in_structured_data() {
+        let writer = syslog_writer(16);
+        // 没有 MDC 时实例取自 target
+        assert!(syslog_datagram(&writer, Level::Info, "x")
+            .contains(" - [frpdesk@32473 instance=\"web\"] "));
+
+        let _instance = log_mdc::insert_scoped(MDC_INSTANCE, r#"a"b]c\d"#);
+        assert!(syslog_datagram(&writer, Level::Info, "x")
+            .contains(r#" - [frpdesk@32473 instance="a\"b\]c\\d"] "#));
+    }
+
+    #[test]
+    fn syslog_datagram_is_truncated_at_char_boundary() {
+        let writer = syslog_writer(16);
+        let short = syslog_datagram(&writer, Level::Info, "");
+        // 每个字符 3 字节，截断点落在字符中间时向前退到字符边界
+        let body = "连".repeat(1000);
+        let datagram = syslog_datagram(&writer, Level::Info, &body);
+        assert!(datagram.len() <= SYSLOG_MAX_DATAGRAM);
+        assert!(datagram.len() > SYSLOG_MAX_DATAGRAM - 3);
+        assert!(datagram[short.len()..].chars().all(|c| c == '连'));
+
+        let fits = "x".repeat(SYSLOG_MAX_DATAGRAM - short.len());
+        assert!(syslog_datagram(&writer, Level::Info, &fits).ends_with(&fits));
+    }
+
+    /// 在 `dir` 中创建最近 `days` 天（含今天）每天一个日志文件，返回从新到旧的文件名
+    fn daily_logs(dir: &Path, prefix: &str, days: i64, size: usize) -> Vec<String> {
+        fs::create_dir_all(dir).unwrap();
+        (0..days)
+            .map(|ago| {
+                let date = Local::now().date_naive() - chrono::Duration::days(ago);
+                let name = format!("{}{}.log", prefix, date.format("%Y-%m-%d"));
+                fs::write(dir.join(&name), vec![b'x'; size]).unwrap();
+                name
+            })
+            .collect()
+    }
+
+    fn remaining(dir: &Path) -> Vec<String> {
+        let mut names: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .flatten()
+            .filter(|e| e.path().is_file())
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    fn retention(max_days: u32, max_files: usize, max_total_mb: u64) -> LogRetention {
+        LogRetention {
+            max_days,
+            max_files,
+            max_total_mb,
+        }
+    }
+
+    fn temp_logs_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("frpdesk-retention-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn errors_and_main_logs_are_pruned_separately() {
+        let dir = temp_logs_dir("groups");
+        let logs = daily_logs(&dir, "", 4, 1);
+        let errors = daily_logs(&dir, ERRORS_FILE_PREFIX, 4, 1);
+        fs::write(dir.join("status.json"), "{}").unwrap();
+        fs::write(dir.join("notes.log"), "").unwrap();
+
+        clean_logs(&dir, &retention(0, 2, 0)).unwrap();
+        let mut expected = vec![
+            logs[0].clone(),
+            logs[1].clone(),
+            errors[0].clone(),
+            errors[1].clone(),
+            "notes.log".to_string(),
+            "status.json".to_string(),
+        ];
+        expected.sort();
+        assert_eq!(remaining(&dir), expected);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn expired_logs_are_pruned_but_newest_is_kept() {
+        let dir = temp_logs_dir("days");
+        let logs = daily_logs(&dir, "", 5, 1);
+        clean_logs(&dir, &retention(2, 0, 0)).unwrap();
+        let mut kept = logs[..3].to_vec();
+        kept.sort();
+        assert_eq!(remaining(&dir), kept);
+
+        // 最新的文件（正在写入）即使超过保留天数也保留
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("2000-01-01.log"), "").unwrap();
+        fs::write(dir.join("1999-12-31.log"), "").unwrap();
+        clean_logs(&dir, &retention(1, 0, 0)).unwrap();
+        assert_eq!(remaining(&dir), ["2000-01-01.log"]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn instance_dirs_use_their_own_policy() {
+        let logs_dir = temp_logs_dir("instances");
+        let main = daily_logs(&logs_dir, "", 3, 1);
+        // web 单个文件就超过了自己的大小上限：保留最新的一个，其余删除
+        let web = daily_logs(&logs_dir.join("web"), "", 3, 1024 * 1024 + 1);
+        let ssh = daily_logs(&logs_dir.join("ssh"), "", 3, 1);
+        let configs = [
+            FrpcConfigMeta {
+                name: "web".to_string(),
+                log_retention: Some(retention(0, 0, 1)),
+                ..Default::default()
+            },
+            instance("ssh", None),
+        ];
+
+        clean_log_dirs(&logs_dir, &retention(0, 2, 0), &configs);
+        let sorted = |mut names: Vec<String>| {
+            names.sort();
+            names
+        };
+        assert_eq!(remaining(&logs_dir), sorted(main[..2].to_vec()));
+        assert_eq!(remaining(&logs_dir.join("web")), [web[0].clone()]);
+        // 没有单独策略的实例使用全局策略
+        assert_eq!(remaining(&logs_dir.join("ssh")), sorted(ssh[..2].to_vec()));
+        let _ = fs::remove_dir_all(&logs_dir);
+    }
 }
@@ -1,19 +1,19 @@
-//! 程序入口，根据命令行参数分发到服务模式或交互模式
+//! 程序入口，根据命令行参数分发到子命令、服务模式或交互模式
 
 #![windows_subsystem = "windows"]
 mod app;
-mod config;
-mod download;
-mod frpc_mg;
 mod icons;
-mod logger;
 mod message;
 mod pages;
-mod service;
 mod sidebar;
 mod theme;
+mod toast;
 
-use crate::logger::init_logging;
+// 核心功能位于库中，界面模块通过 crate::config 等路径使用
+use frpdesk::app_support::{cli, service};
+use frpdesk::{account_check, av_check, config, constants, download, frpc_mg, logger};
+
+use crate::logger::{init_logging, LoggingOptions};
 use anyhow::{Context, Result};
 use std::env;
 
@@ -38,9 +38,23 @@ fn ensure_single_instance() -> Option<windows_sys::Win32::Foundation::HANDLE> {
     }
 }
 
+/// 检查服务状态并启动 GUI
+fn check_and_run_app() -> Result<()> {
+    let pre_check = service::check_service_status()?;
+    app::run_app(pre_check);
+    Ok(())
+}
+
 fn main() -> Result<()> {
+    let args: Vec<String> = env::args().collect();
+
+    // 命令行子命令：执行后直接退出，不检查单实例、不初始化日志
+    if let Some(code) = cli::run(&args) {
+        std::process::exit(code);
+    }
+
     // 交互模式下检查单实例
-    let _mutex_guard = if !env::args().any(|a| a == service::SERVICE_ARG) {
+    let _mutex_guard = if !args.iter().any(|a| a == constants::SERVICE_ARG) {
         match ensure_single_instance() {
             Some(h) => Some(h),
             None => return Ok(()),
@@ -49,14 +63,31 @@ fn main() -> Result<()> {
         None
     };
 
-    init_logging().context("无法初始化日志")?;
+    // 需在初始化日志之前生效：日志清理会读取实例清单中的保留策略
+    let instances_file = config::apply_instances_file_arg(&args);
 
-    let args: Vec<String> = env::args().collect();
-    if args.contains(&service::SERVICE_ARG.to_string()) {
+    let options = LoggingOptions::from_settings(&config::load_settings());
+    if let Err(e) = init_logging(options) {
+        // log4rs 不可用，写到 %TEMP% 与事件日志，否则没有任何提示
+        logger::report_init_failure(&e);
+        return Err(e.context("无法初始化日志"));
+    }
+
+    if let Err(e) = instances_file {
+        log::error!("实例清单参数无效: {:?}", e);
+        return Err(e);
+    }
+    if let Some(path) = config::instances_file() {
+        log::info!("使用外部实例清单: {}", path.display());
+    }
+
+    if args.contains(&constants::SERVICE_ARG.to_string()) {
         log::info!("在服务模式下启动，即将进入服务调度器");
         service::run_service_dispatcher().context("服务调度器启动失败")
     } else {
         log::info!("在交互模式下启动");
-        service::check_and_run_app().context("交互模式运行失败")
+        // frpc 崩溃（panic / fatal error）时推送系统通知
+        frpc_mg::set_panic_notifier(toast::notify);
+        check_and_run_app().context("交互模式运行失败")
     }
 }
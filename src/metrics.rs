@@ -0,0 +1,421 @@
+//! Prometheus 指标导出：服务在本地端口提供 `GET /metrics`（文本暴露格式）
+//!
+//! 指标由服务提供的采集函数在每次抓取时从内存中的进程列表与健康状态生成，不额外记录数据。
+//! 抓取频率低，连接在单个线程中逐个处理，请求处理完即关闭连接。
+//!
+//! 同一端口还提供 `GET /health`：所有自启动实例都在运行且健康时返回 200，否则返回 503 并列出原因，
+//! 判断规则与 `frpdesk health` 相同，可用作存活探测。
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use crate::config::MetricsSettings;
+use crate::health_check::HealthStatus;
+
+/// 读取请求、写入响应的超时
+const IO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 请求头大小上限（只需要请求行）
+const MAX_HEADER_BYTES: u64 = 16 * 1024;
+
+/// 单个实例的指标
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InstanceMetrics {
+    pub instance: String,
+    /// 进程正在运行
+    pub up: bool,
+    /// 本次服务运行期间进程守护重启该实例的次数
+    pub restarts_total: u64,
+    /// 最近一次退出的退出码（尚未退出过或无法获取时为 None，不输出）
+    pub last_exit_code: Option<i32>,
+    /// 当前进程已运行的秒数（未运行时为 0）
+    pub uptime_seconds: u64,
+    /// 当前进程自启动以来输出的日志行数（没有跟踪的进程或无法获取时为 None，不输出）
+    #[serde(default)]
+    pub log_lines: Option<u64>,
+    /// 自启动实例，健康检查要求其运行
+    #[serde(default)]
+    pub auto_start: bool,
+    /// 启动探测已通过（未配置探测的实例启动即通过）
+    #[serde(default)]
+    pub ready: bool,
+    /// 最近一次运行期间健康检查的结果（未配置 `health_check` 或尚未检查时为 None）
+    #[serde(default)]
+    pub health: Option<HealthStatus>,
+}
+
+/// 一次抓取的全部指标（`export-metrics` 通过管道获取时以 JSON 传输）
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ServiceMetrics {
+    pub instances: Vec<InstanceMetrics>,
+    /// 本次服务运行期间进程守护重启的总次数（含已移除的实例）
+    pub total_restarts: u64,
+    /// 向 SCM 上报服务状态连续失败的次数，上报成功后归零
+    pub status_report_failures: u32,
+}
+
+/// 按设置启动指标线程；未启用、设置无效或端口被占用时记录日志，不影响服务运行
+pub fn start<F>(settings: &MetricsSettings, collect: F)
+where
+    F: Fn() -> ServiceMetrics + Send + 'static,
+{
+    if !settings.enabled {
+        log::info!("Prometheus 指标导出已在设置中关闭");
+        return;
+    }
+    let listener = match bind(&settings.listen) {
+        Ok(l) => l,
+        Err(e) => {
+            log::error!("Prometheus 指标导出未启动: {:#}", e);
+            return;
+        }
+    };
+    log::info!(
+        "Prometheus 指标导出已启动: http://{}/metrics",
+        settings.listen
+    );
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { continue };
+            if let Err(e) = handle_connection(stream, &collect) {
+                log::debug!("Prometheus 指标请求处理失败: {:#}", e);
+            }
+        }
+    });
+}
+
+fn bind(listen: &str) -> Result<TcpListener> {
+    let addr: SocketAddr = listen
+        .parse()
+        .context(format!("监听地址无效: {}", listen))?;
+    TcpListener::bind(addr).context(format!("无法监听 {}", listen))
+}
+
+fn handle_connection<F>(mut stream: TcpStream, collect: &F) -> Result<()>
+where
+    F: Fn() -> ServiceMetrics,
+{
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+
+    // 只解析请求行，其余请求头读到空行为止后丢弃
+    let mut reader = BufReader::new(stream.try_clone()?).take(MAX_HEADER_BYTES);
+    let mut line = String::new();
+    reader.read_line(&mut line).context("无法读取请求行")?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default().to_string();
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim_end().is_empty() {
+            break;
+        }
+    }
+
+    let path = path.split('?').next().unwrap_or_default();
+    if path != "/metrics" && path != "/health" {
+        return respond(
+            &mut stream,
+            404,
+            "Not Found",
+            TEXT_CONTENT_TYPE,
+            "只提供 /metrics 与 /health\n",
+        );
+    }
+    if method != "GET" {
+        let body = format!("{} 只支持 GET\n", path);
+        return respond(
+            &mut stream,
+            405,
+            "Method Not Allowed",
+            TEXT_CONTENT_TYPE,
+            &body,
+        );
+    }
+    if path == "/health" {
+        let problems = health_problems(&collect());
+        if problems.is_empty() {
+            return respond(&mut stream, 200, "OK", TEXT_CONTENT_TYPE, "ok\n");
+        }
+        let body: String = problems.iter().map(|p| format!("{}\n", p)).collect();
+        return respond(
+            &mut stream,
+            503,
+            "Service Unavailable",
+            TEXT_CONTENT_TYPE,
+            &body,
+        );
+    }
+    let body = render(&collect());
+    respond(&mut stream, 200, "OK", METRICS_CONTENT_TYPE, &body)
+}
+
+/// 不健康的实例及原因：自启动实例未运行、启动探测未通过，或最近一次健康检查为降级/不健康；
+/// 全部健康时为空。非自启动的实例只在运行中且检查结果不健康时计入
+pub fn health_problems(metrics: &ServiceMetrics) -> Vec<String> {
+    let mut instances: Vec<&InstanceMetrics> = metrics.instances.iter().collect();
+    instances.sort_by(|a, b| a.instance.cmp(&b.instance));
+    instances
+        .into_iter()
+        .filter_map(|inst| {
+            let reason = if !inst.up {
+                inst.auto_start.then(|| "未运行".to_string())
+            } else if !inst.ready {
+                Some("启动探测尚未通过".to_string())
+            } else {
+                match &inst.health {
+                    Some(status @ (HealthStatus::Degraded(_) | HealthStatus::Unhealthy(_))) => {
+                        Some(status.to_string())
+                    }
+                    _ => None,
+                }
+            };
+            reason.map(|reason| format!("{}: {}", inst.instance, reason))
+        })
+        .collect()
+}
+
+/// 指标的内容类型（Prometheus 文本暴露格式）
+const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// 其他响应（错误信息、健康检查结果）的内容类型
+const TEXT_CONTENT_TYPE: &str = "text/plain; charset=utf-8";
+
+fn respond(
+    stream: &mut TcpStream,
+    status: u16,
+    reason: &str,
+    content_type: &str,
+    body: &str,
+) -> Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// 按 Prometheus 文本暴露格式输出：每个指标一组 HELP/TYPE，样本按实例名排序
+pub fn render(metrics: &ServiceMetrics) -> String {
+    let mut instances: Vec<&InstanceMetrics> = metrics.instances.iter().collect();
+    instances.sort_by(|a, b| a.instance.cmp(&b.instance));
+
+    let mut out = String::new();
+    write_family(
+        &mut out,
+        "frpc_instance_up",
+        "gauge",
+        "frpc 实例进程是否正在运行（1 运行，0 未运行）",
+        instances.iter().map(|i| (*i, Some(i.up as i64))),
+    );
+    write_family(
+        &mut out,
+        "frpc_instance_restart_total",
+        "counter",
+        "本次服务运行期间进程守护重启该实例的次数",
+        instances
+            .iter()
+            .map(|i| (*i, Some(i.restarts_total as i64))),
+    );
+    write_family(
+        &mut out,
+        "frpc_instance_last_exit_code",
+        "gauge",
+        "frpc 实例最近一次退出的退出码",
+        instances
+            .iter()
+            .map(|i| (*i, i.last_exit_code.map(i64::from))),
+    );
+    write_family(
+        &mut out,
+        "frpc_instance_uptime_seconds",
+        "gauge",
+        "frpc 实例当前进程已运行的秒数",
+        instances
+            .iter()
+            .map(|i| (*i, Some(i.uptime_seconds as i64))),
+    );
+    write_family(
+        &mut out,
+        "frpc_instance_log_lines_total",
+        "counter",
+        "frpc 实例当前进程自启动以来 stdout 与 stderr 输出的行数，重启后从 0 开始",
+        instances
+            .iter()
+            .map(|i| (*i, i.log_lines.map(|n| n as i64))),
+    );
+
+    let _ = writeln!(
+        out,
+        "# HELP frpc_service_restarts_total 本次服务运行期间进程守护重启的总次数"
+    );
+    let _ = writeln!(out, "# TYPE frpc_service_restarts_total counter");
+    let _ = writeln!(
+        out,
+        "frpc_service_restarts_total {}",
+        metrics.total_restarts
+    );
+    let _ = writeln!(out, "# HELP frpc_service_instances 当前跟踪的实例数");
+    let _ = writeln!(out, "# TYPE frpc_service_instances gauge");
+    let _ = writeln!(out, "frpc_service_instances {}", instances.len());
+    let _ = writeln!(
+        out,
+        "# HELP frpc_service_status_report_failures 向 SCM 上报服务状态连续失败的次数"
+    );
+    let _ = writeln!(out, "# TYPE frpc_service_status_report_failures gauge");
+    let _ = writeln!(
+        out,
+        "frpc_service_status_report_failures {}",
+        metrics.status_report_failures
+    );
+    out
+}
+
+/// 输出一组带 `instance` 标签的样本，值为 None 的实例跳过
+fn write_family<'a>(
+    out: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    samples: impl Iterator<Item = (&'a InstanceMetrics, Option<i64>)>,
+) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    for (inst, value) in samples {
+        if let Some(value) = value {
+            let _ = writeln!(
+                out,
+                "{}{{instance=\"{}\"}} {}",
+                name,
+                escape_label(&inst.instance),
+                value
+            );
+        }
+    }
+}
+
+/// 标签值中的反斜杠、双引号与换行需要转义
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instance(name: &str, up: bool) -> InstanceMetrics {
+        InstanceMetrics {
+            instance: name.to_string(),
+            up,
+            restarts_total: 0,
+            last_exit_code: None,
+            uptime_seconds: 0,
+            log_lines: None,
+            auto_start: true,
+            ready: up,
+            health: None,
+        }
+    }
+
+    #[test]
+    fn renders_prometheus_text_format() {
+        let metrics = ServiceMetrics {
+            instances: vec![
+                InstanceMetrics {
+                    restarts_total: 2,
+                    uptime_seconds: 3600,
+                    log_lines: Some(120),
+                    ..instance("web", true)
+                },
+                InstanceMetrics {
+                    restarts_total: 1,
+                    last_exit_code: Some(-1073741819),
+                    ..instance("ssh \"lab\"", false)
+                },
+            ],
+            total_restarts: 5,
+            status_report_failures: 2,
+        };
+        let expected = "\
+# HELP frpc_instance_up frpc 实例进程是否正在运行（1 运行，0 未运行）
+# TYPE frpc_instance_up gauge
+frpc_instance_up{instance=\"ssh \\\"lab\\\"\"} 0
+frpc_instance_up{instance=\"web\"} 1
+# HELP frpc_instance_restart_total 本次服务运行期间进程守护重启该实例的次数
+# TYPE frpc_instance_restart_total counter
+frpc_instance_restart_total{instance=\"ssh \\\"lab\\\"\"} 1
+frpc_instance_restart_total{instance=\"web\"} 2
+# HELP frpc_instance_last_exit_code frpc 实例最近一次退出的退出码
+# TYPE frpc_instance_last_exit_code gauge
+frpc_instance_last_exit_code{instance=\"ssh \\\"lab\\\"\"} -1073741819
+# HELP frpc_instance_uptime_seconds frpc 实例当前进程已运行的秒数
+# TYPE frpc_instance_uptime_seconds gauge
+frpc_instance_uptime_seconds{instance=\"ssh \\\"lab\\\"\"} 0
+frpc_instance_uptime_seconds{instance=\"web\"} 3600
+# HELP frpc_instance_log_lines_total frpc 实例当前进程自启动以来 stdout 与 stderr 输出的行数，重启后从 0 开始
+# TYPE frpc_instance_log_lines_total counter
+frpc_instance_log_lines_total{instance=\"web\"} 120
+# HELP frpc_service_restarts_total 本次服务运行期间进程守护重启的总次数
+# TYPE frpc_service_restarts_total counter
+frpc_service_restarts_total 5
+# HELP frpc_service_instances 当前跟踪的实例数
+# TYPE frpc_service_instances gauge
+frpc_service_instances 2
+# HELP frpc_service_status_report_failures 向 SCM 上报服务状态连续失败的次数
+# TYPE frpc_service_status_report_failures gauge
+frpc_service_status_report_failures 2
+";
+        assert_eq!(render(&metrics), expected);
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        assert_eq!(escape_label("a\\b\"c\nd"), "a\\\\b\\\"c\\nd");
+    }
+
+    #[test]
+    fn unhealthy_instances() {
+        let metrics = ServiceMetrics {
+            instances: vec![
+                instance("web", true),
+                instance("ssh", false),
+                InstanceMetrics {
+                    auto_start: false,
+                    ..instance("manual", false)
+                },
+                InstanceMetrics {
+                    ready: false,
+                    ..instance("rdp", true)
+                },
+                InstanceMetrics {
+                    health: Some(HealthStatus::Unhealthy("CPU 过高".to_string())),
+                    ..instance("db", true)
+                },
+            ],
+            total_restarts: 0,
+            status_report_failures: 0,
+        };
+        assert_eq!(
+            health_problems(&metrics),
+            [
+                "db: 不健康（CPU 过高）",
+                "rdp: 启动探测尚未通过",
+                "ssh: 未运行"
+            ]
+        );
+    }
+}
@@ -0,0 +1,158 @@
+//! 进程转储（`snapshot`）：为运行中的 frpc 写入 minidump，用于排查内存泄漏等问题
+//!
+//! 使用 `MiniDumpWriteDump`（`MiniDumpNormal`），转储文件可用 WinDbg 或 Visual Studio 打开。
+//! 服务启动的 frpc 以 LocalSystem 运行，打开它需要 SeDebugPrivilege（以管理员身份运行命令行时可以启用）；
+//! 缺少该特权时仍会尝试，界面以当前用户启动的 frpc 不需要它
+
+use anyhow::{Context, Result};
+use chrono::Local;
+use std::fs::File;
+use std::os::windows::io::AsRawHandle;
+use std::path::{Path, PathBuf};
+use windows_sys::Win32::Foundation::{
+    CloseHandle, GetLastError, ERROR_ACCESS_DENIED, ERROR_NOT_ALL_ASSIGNED, HANDLE, LUID,
+};
+use windows_sys::Win32::Security::{
+    AdjustTokenPrivileges, LookupPrivilegeValueW, LUID_AND_ATTRIBUTES, SE_DEBUG_NAME,
+    SE_PRIVILEGE_ENABLED, TOKEN_ADJUST_PRIVILEGES, TOKEN_PRIVILEGES, TOKEN_QUERY,
+};
+use windows_sys::Win32::System::Diagnostics::Debug::{MiniDumpNormal, MiniDumpWriteDump};
+use windows_sys::Win32::System::Threading::{
+    GetCurrentProcess, OpenProcess, OpenProcessToken, PROCESS_ACCESS_RIGHTS,
+    PROCESS_QUERY_INFORMATION, PROCESS_VM_READ,
+};
+
+use crate::config;
+use crate::service;
+
+/// 为实例当前运行的 frpc 写入转储，返回转储文件路径
+///
+/// `output` 未指定时为数据根目录下的 `<配置名>-<时间>.dmp`
+pub fn run(identifier: &str, output: Option<PathBuf>) -> Result<PathBuf> {
+    let (pid, process) =
+        open_instance_process(identifier, PROCESS_QUERY_INFORMATION | PROCESS_VM_READ)?;
+    let output = match output {
+        Some(path) => Ok(path),
+        None => default_output(&format!("{}-", identifier), "dmp"),
+    };
+    let result = output.and_then(|output| write_dump(process, pid, &output).map(|_| output));
+    unsafe { CloseHandle(process) };
+    result
+}
+
+/// 打开实例当前运行的 frpc 进程，返回 PID 与进程句柄（由调用方关闭）
+///
+/// 先尝试启用 SeDebugPrivilege，失败时打印警告后继续（当前用户启动的 frpc 不需要它）
+pub(crate) fn open_instance_process(
+    identifier: &str,
+    access: PROCESS_ACCESS_RIGHTS,
+) -> Result<(u32, HANDLE)> {
+    if config::find_config(identifier).is_none() {
+        anyhow::bail!("实例 '{}' 不存在", identifier);
+    }
+    let (_, pid) = service::discover_running_frpc_processes()
+        .into_iter()
+        .find(|(name, _)| name == identifier)
+        .context(format!("实例 '{}' 没有正在运行的 frpc 进程", identifier))?;
+
+    let debug_privilege = enable_debug_privilege();
+    if let Err(ref e) = debug_privilege {
+        eprintln!("警告: {:#}，仅能访问当前用户启动的 frpc", e);
+    }
+
+    let process = unsafe { OpenProcess(access, 0, pid) };
+    if process == 0 {
+        let code = unsafe { GetLastError() };
+        if code == ERROR_ACCESS_DENIED && debug_privilege.is_err() {
+            anyhow::bail!(
+                "无法打开 frpc 进程（PID: {}）：拒绝访问。该进程由服务以 LocalSystem 启动，需要 SeDebugPrivilege，请以管理员身份运行命令行",
+                pid
+            );
+        }
+        anyhow::bail!("无法打开 frpc 进程（PID: {}），错误码: {}", pid, code);
+    }
+    Ok((pid, process))
+}
+
+/// 数据根目录（默认为程序目录）下的 `<前缀><时间>.<扩展名>`
+pub(crate) fn default_output(prefix: &str, extension: &str) -> Result<PathBuf> {
+    Ok(config::data_dir()?.join(format!(
+        "{}{}.{}",
+        prefix,
+        Local::now().format("%Y%m%d-%H%M%S"),
+        extension
+    )))
+}
+
+fn write_dump(process: HANDLE, pid: u32, output: &Path) -> Result<()> {
+    let file = File::create(output).context(format!("无法创建转储文件: {}", output.display()))?;
+    let ok = unsafe {
+        MiniDumpWriteDump(
+            process,
+            pid,
+            file.as_raw_handle() as HANDLE,
+            MiniDumpNormal,
+            std::ptr::null(),
+            std::ptr::null(),
+            std::ptr::null(),
+        )
+    };
+    if ok == 0 {
+        // MiniDumpWriteDump 返回的是 HRESULT
+        let code = unsafe { GetLastError() };
+        drop(file);
+        let _ = std::fs::remove_file(output);
+        anyhow::bail!(
+            "MiniDumpWriteDump 失败（PID: {}），错误码: {:#x}",
+            pid,
+            code
+        );
+    }
+    Ok(())
+}
+
+/// 在当前进程令牌中启用 SeDebugPrivilege；令牌中没有该特权（未以管理员身份运行）时返回错误
+fn enable_debug_privilege() -> Result<()> {
+    unsafe {
+        let mut token: HANDLE = 0;
+        if OpenProcessToken(
+            GetCurrentProcess(),
+            TOKEN_ADJUST_PRIVILEGES | TOKEN_QUERY,
+            &mut token,
+        ) == 0
+        {
+            anyhow::bail!("无法打开进程令牌，错误码: {}", GetLastError());
+        }
+        let mut luid: LUID = std::mem::zeroed();
+        if LookupPrivilegeValueW(std::ptr::null(), SE_DEBUG_NAME, &mut luid) == 0 {
+            let code = GetLastError();
+            CloseHandle(token);
+            anyhow::bail!("无法查询 SeDebugPrivilege，错误码: {}", code);
+        }
+        let privileges = TOKEN_PRIVILEGES {
+            PrivilegeCount: 1,
+            Privileges: [LUID_AND_ATTRIBUTES {
+                Luid: luid,
+                Attributes: SE_PRIVILEGE_ENABLED,
+            }],
+        };
+        let ok = AdjustTokenPrivileges(
+            token,
+            0,
+            &privileges,
+            0,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        );
+        // 调用成功但特权不在令牌中时 GetLastError 为 ERROR_NOT_ALL_ASSIGNED
+        let code = GetLastError();
+        CloseHandle(token);
+        if ok == 0 {
+            anyhow::bail!("无法启用 SeDebugPrivilege，错误码: {}", code);
+        }
+        if code == ERROR_NOT_ALL_ASSIGNED {
+            anyhow::bail!("当前账户没有 SeDebugPrivilege（未以管理员身份运行）");
+        }
+    }
+    Ok(())
+}
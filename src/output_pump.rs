@@ -0,0 +1,862 @@
+//! frpc 输出泵：所有实例的 stdout/stderr 汇入同一个有界队列，由单个分发线程统一处理
+//!
+//! 输出管道为重叠 I/O 的命名管道（[`output_pipe`]），读端关联到同一个 I/O 完成端口，由单个完成线程
+//! 按 64KB 分块读取后在本进程中切分成行并投递到队列，高输出量时一次唤醒可处理多行；匿名管道不支持重叠 I/O，
+//! 以其他用户身份启动的实例与命名管道创建失败时仍为每个输出流使用一个阻塞读取线程（使用小栈空间）。
+//! 去除 ANSI、写日志、连接信号检测都在分发线程中完成。
+//!
+//! - 同一实例同一输出流的行按读取顺序处理（单一读取线程 + FIFO 队列）
+//! - 队列满时丢弃新行并计数，绝不阻塞读取线程，避免 frpc 因管道写满而卡住
+//! - 服务停止时调用 [`shutdown`]，处理完队列中已有的行后再退出
+//! - 每行在读取时分配实例内单调递增的序号（日志 `seq` 字段），stdout/stderr 共用一个计数，
+//!   时间戳相同时也能看出真实先后顺序
+//! - 通过 [`OutputSubscribers`] 可订阅实例的实时输出（`FrpcProcess::subscribe_output`）
+//! - stderr 中的崩溃行（Go 的 `panic:`/`fatal error:`、Rust 的 `panicked at`、访问违例信号）
+//!   记为 ERROR 并附带 `tag=FRPC_PANIC`，同一进程首次出现时调用 [`set_panic_notifier`] 注册的通知
+//! - 实例配置了输出规则（`on_output`）时按顺序匹配每行，命中的动作放入队列，由进程守护在下一轮检查中执行
+//!   （[`take_output_matches`]）；正则在启动时编译，每行最多匹配一遍规则列表
+
+use log::Level;
+use regex::Regex;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, BufRead, BufReader, Read};
+use std::os::windows::io::{AsRawHandle, FromRawHandle, OwnedHandle};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+use strip_ansi_escapes::strip;
+use windows_sys::Win32::Foundation::{
+    GetLastError, ERROR_IO_PENDING, GENERIC_WRITE, HANDLE, INVALID_HANDLE_VALUE,
+};
+use windows_sys::Win32::Storage::FileSystem::{
+    CreateFileW, ReadFile, FILE_FLAG_FIRST_PIPE_INSTANCE, FILE_FLAG_OVERLAPPED, OPEN_EXISTING,
+    PIPE_ACCESS_INBOUND,
+};
+use windows_sys::Win32::System::Pipes::{
+    CreateNamedPipeW, PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_WAIT,
+};
+use windows_sys::Win32::System::Threading::INFINITE;
+use windows_sys::Win32::System::IO::{
+    CreateIoCompletionPort, GetQueuedCompletionStatus, OVERLAPPED,
+};
+
+use crate::config::{self, OutputAction};
+use crate::logger::{FRPC_TARGET_PREFIX, MDC_INSTANCE};
+
+/// 队列容量（行）
+const QUEUE_CAPACITY: usize = 4096;
+
+/// 读取线程只做按行读取，不需要默认的 2MB 栈
+const READER_STACK_SIZE: usize = 64 * 1024;
+
+/// 完成端口每次读取的字节数，同时作为命名管道的输入缓冲区大小
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// 每个输出订阅者最多缓存的行数，订阅者处理不及时超出后丢弃新行
+const SUBSCRIBER_CAPACITY: usize = 1024;
+
+/// 每个实例保留的最近输出行数（停止诊断等场景使用）
+const RECENT_LINES: usize = 20;
+
+/// 停止时等待分发线程处理完剩余输出的最长时间
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// 输出流类型
+#[derive(Clone, Copy, Debug)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl Stream {
+    fn label(self) -> &'static str {
+        match self {
+            Stream::Stdout => "stdout",
+            Stream::Stderr => "stderr",
+        }
+    }
+}
+
+/// 单个实例的输出去向，由该实例的读取线程共享
+///
+/// 两个读取线程结束且队列中的行处理完后被释放，`on_connected` 随之断开，
+/// 启动探测据此判断进程已退出
+pub struct InstanceSink {
+    identifier: String,
+    pid: u32,
+    /// 日志 target 为 frpc::<实例名>，同时写入实例独立日志
+    log_target: String,
+    on_connected: Option<Sender<()>>,
+    /// 就绪规则（实例配置的 `ready_pattern`），未设置时使用 [`is_default_ready_line`]
+    ready: Option<Regex>,
+    /// 因队列已满被丢弃、尚未报告的行数
+    dropped: AtomicU64,
+    /// 输出行序号计数（已分配的最大序号）
+    seq: Arc<AtomicU64>,
+    /// 实时输出订阅者，由 `FrpcProcess` 另外持有（不能持有整个 sink，否则 on_connected 不会断开）
+    subscribers: Arc<OutputSubscribers>,
+    /// 已通知过崩溃，同一进程只通知一次（崩溃输出通常有多行匹配）
+    panic_notified: AtomicBool,
+    /// 输出规则（已编译），按顺序匹配
+    rules: Vec<(Regex, OutputAction)>,
+    /// 各规则是否已触发，同一进程每条规则只触发一次
+    rules_fired: Vec<AtomicBool>,
+}
+
+/// 输出规则命中，等待进程守护执行
+#[derive(Debug)]
+pub(crate) struct OutputMatch {
+    pub instance: String,
+    /// 产生该输出的进程，执行时进程已退出或已被替换则忽略
+    pub pid: u32,
+    pub pattern: String,
+    pub action: OutputAction,
+    pub line: String,
+}
+
+/// 已命中、尚未执行的输出规则，由分发线程写入、进程守护取出
+static OUTPUT_MATCHES: Mutex<Vec<OutputMatch>> = Mutex::new(Vec::new());
+
+/// 取出所有已命中、尚未执行的输出规则
+pub(crate) fn take_output_matches() -> Vec<OutputMatch> {
+    std::mem::take(&mut *OUTPUT_MATCHES.lock().unwrap())
+}
+
+/// 实例实时输出的订阅者列表：每行（已去除 ANSI）发送给所有订阅者
+///
+/// 订阅者各自拥有独立的接收端；接收端被丢弃后在下一行时自动移除
+#[derive(Default)]
+pub struct OutputSubscribers {
+    senders: Mutex<Vec<SyncSender<String>>>,
+    /// 本次启动以来 stdout 与 stderr 读取到的行数（含队列满时丢弃的行），健康检查的输出看门狗据此判断
+    lines: AtomicUsize,
+    /// 最近的 [`RECENT_LINES`] 行输出
+    recent: Mutex<VecDeque<String>>,
+}
+
+impl OutputSubscribers {
+    /// 新增一个订阅者
+    pub fn subscribe(&self) -> Receiver<String> {
+        let (tx, rx) = mpsc::sync_channel(SUBSCRIBER_CAPACITY);
+        self.senders.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// 本次启动以来读取到的行数
+    pub fn line_count(&self) -> usize {
+        self.lines.load(Ordering::Relaxed)
+    }
+
+    /// 最近的输出行（已去除 ANSI），按先后顺序
+    pub fn recent_lines(&self) -> Vec<String> {
+        self.recent.lock().unwrap().iter().cloned().collect()
+    }
+
+    fn publish(&self, line: &str) {
+        {
+            let mut recent = self.recent.lock().unwrap();
+            if recent.len() == RECENT_LINES {
+                recent.pop_front();
+            }
+            recent.push_back(line.to_string());
+        }
+        let mut senders = self.senders.lock().unwrap();
+        if senders.is_empty() {
+            return;
+        }
+        senders.retain(|tx| match tx.try_send(line.to_string()) {
+            // 订阅者处理不及时时丢弃该行，不阻塞分发线程
+            Ok(()) | Err(TrySendError::Full(_)) => true,
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+    }
+}
+
+/// 获取实例的行序号计数：设置了跨重启延续时同一实例共用一个计数，否则每次启动新建
+fn seq_counter(identifier: &str) -> Arc<AtomicU64> {
+    if !config::load_settings().output_seq_across_restarts {
+        return Arc::new(AtomicU64::new(0));
+    }
+    static COUNTERS: OnceLock<Mutex<HashMap<String, Arc<AtomicU64>>>> = OnceLock::new();
+    let counters = COUNTERS.get_or_init(|| Mutex::new(HashMap::new()));
+    Arc::clone(
+        counters
+            .lock()
+            .unwrap()
+            .entry(identifier.to_string())
+            .or_default(),
+    )
+}
+
+impl InstanceSink {
+    pub fn new(
+        identifier: &str,
+        pid: u32,
+        on_connected: Option<Sender<()>>,
+        ready: Option<Regex>,
+        rules: Vec<(Regex, OutputAction)>,
+        subscribers: Arc<OutputSubscribers>,
+    ) -> Arc<Self> {
+        Arc::new(InstanceSink {
+            identifier: identifier.to_string(),
+            pid,
+            log_target: format!("{}{}", FRPC_TARGET_PREFIX, identifier),
+            on_connected,
+            ready,
+            dropped: AtomicU64::new(0),
+            seq: seq_counter(identifier),
+            subscribers,
+            panic_notified: AtomicBool::new(false),
+            rules_fired: rules.iter().map(|_| AtomicBool::new(false)).collect(),
+            rules,
+        })
+    }
+}
+
+enum Event {
+    Line {
+        sink: Arc<InstanceSink>,
+        stream: Stream,
+        seq: u64,
+        line: Vec<u8>,
+    },
+    /// 队列中此前的行处理完毕后应答并退出
+    Shutdown(Sender<()>),
+}
+
+static PUMP: OnceLock<SyncSender<Event>> = OnceLock::new();
+
+/// frpc 崩溃时的通知（标题, 正文），由交互模式注册为系统通知；服务中不注册
+static PANIC_NOTIFIER: OnceLock<fn(&str, &str)> = OnceLock::new();
+
+/// 注册 frpc 崩溃通知，只能注册一次
+pub fn set_panic_notifier(notify: fn(&str, &str)) {
+    let _ = PANIC_NOTIFIER.set(notify);
+}
+
+/// 获取队列发送端，首次调用时启动分发线程
+fn pump() -> &'static SyncSender<Event> {
+    PUMP.get_or_init(|| {
+        let (tx, rx) = mpsc::sync_channel(QUEUE_CAPACITY);
+        thread::Builder::new()
+            .name("frpc-output".to_string())
+            .spawn(move || dispatch_loop(rx))
+            .expect("无法创建 frpc 输出分发线程");
+        tx
+    })
+}
+
+/// 子进程输出流的读取方式
+pub enum OutputSource {
+    /// [`output_pipe`] 创建的命名管道读端，由完成端口读取
+    Pipe(PipeReader),
+    /// 匿名管道等只能阻塞读取的流，由独立的读取线程读取
+    Reader(Box<dyn Read + Send>),
+}
+
+/// 开始读取一个输出流
+pub fn start_reading(source: OutputSource, sink: Arc<InstanceSink>, stream: Stream) {
+    match source {
+        OutputSource::Pipe(reader) => PendingRead::start(reader, sink, stream),
+        OutputSource::Reader(reader) => spawn_reader(reader, sink, stream),
+    }
+}
+
+/// 为一个输出流启动读取线程
+fn spawn_reader<R>(reader: R, sink: Arc<InstanceSink>, stream: Stream)
+where
+    R: Read + Send + 'static,
+{
+    let tx = pump().clone();
+    let identifier = sink.identifier.clone();
+    let result = thread::Builder::new()
+        .name(format!("frpc-{}-{}", identifier, stream.label()))
+        .stack_size(READER_STACK_SIZE)
+        .spawn(move || {
+            let mut reader = BufReader::new(reader);
+            let mut buf = Vec::new();
+            loop {
+                buf.clear();
+                match reader.read_until(b'\n', &mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => {}
+                }
+                queue_line(&tx, &sink, stream, std::mem::take(&mut buf));
+            }
+        });
+    if let Err(e) = result {
+        log::error!(
+            "[{}] 无法创建 {} 读取线程: {}",
+            identifier,
+            stream.label(),
+            e
+        );
+    }
+}
+
+/// 为读取到的一行分配序号并投递到队列（去除行尾换行符）；队列满时丢弃该行并计数，不阻塞读取
+fn queue_line(tx: &SyncSender<Event>, sink: &Arc<InstanceSink>, stream: Stream, mut line: Vec<u8>) {
+    while matches!(line.last(), Some(b'\n' | b'\r')) {
+        line.pop();
+    }
+    // 读取时分配序号，丢弃的行同样占用序号，日志中可看出缺口
+    let seq = sink.seq.fetch_add(1, Ordering::Relaxed) + 1;
+    sink.subscribers.lines.fetch_add(1, Ordering::Relaxed);
+    let event = Event::Line {
+        sink: Arc::clone(sink),
+        stream,
+        seq,
+        line,
+    };
+    match tx.try_send(event) {
+        Ok(()) => {}
+        Err(TrySendError::Full(_)) => {
+            sink.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        // 分发线程已停止（服务正在退出），继续读取以免 frpc 写管道阻塞
+        Err(TrySendError::Disconnected(_)) => {}
+    }
+}
+
+/// 命名管道的读端，已关联到输出完成端口
+pub struct PipeReader(OwnedHandle);
+
+/// 子进程的输出管道：`writer` 作为子进程的 stdout/stderr，`reader` 交给 [`start_reading`]
+pub struct OutputPipe {
+    pub reader: PipeReader,
+    pub writer: OwnedHandle,
+}
+
+/// 创建一个由完成端口读取的输出管道
+///
+/// 读端为重叠 I/O 的命名管道（只允许一个实例、拒绝远程客户端，创建后立即由本进程连接，
+/// 其他进程无法再连接），写端为普通的同步句柄
+pub fn output_pipe() -> io::Result<OutputPipe> {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+    let port = completion_port()?;
+    let name: Vec<u16> = format!(
+        r"\\.\pipe\frpdesk-output-{}-{}",
+        std::process::id(),
+        NEXT_ID.fetch_add(1, Ordering::Relaxed)
+    )
+    .encode_utf16()
+    .chain(std::iter::once(0))
+    .collect();
+    unsafe {
+        let server = CreateNamedPipeW(
+            name.as_ptr(),
+            PIPE_ACCESS_INBOUND | FILE_FLAG_OVERLAPPED | FILE_FLAG_FIRST_PIPE_INSTANCE,
+            PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+            1,
+            0,
+            READ_CHUNK_SIZE as u32,
+            0,
+            std::ptr::null(),
+        );
+        if server == INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error());
+        }
+        let reader = OwnedHandle::from_raw_handle(server as _);
+        let client = CreateFileW(
+            name.as_ptr(),
+            GENERIC_WRITE,
+            0,
+            std::ptr::null(),
+            OPEN_EXISTING,
+            0,
+            0,
+        );
+        if client == INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error());
+        }
+        let writer = OwnedHandle::from_raw_handle(client as _);
+        if CreateIoCompletionPort(server, port, 0, 0) == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(OutputPipe {
+            reader: PipeReader(reader),
+            writer,
+        })
+    }
+}
+
+/// 输出完成端口，首次调用时创建并启动完成线程；创建失败时记录一次错误，之后始终返回错误
+fn completion_port() -> io::Result<HANDLE> {
+    static PORT: OnceLock<Option<HANDLE>> = OnceLock::new();
+    let port = PORT.get_or_init(|| {
+        let port = unsafe { CreateIoCompletionPort(INVALID_HANDLE_VALUE, 0, 0, 1) };
+        if port == 0 {
+            log::error!(
+                "无法创建 frpc 输出完成端口，改用读取线程: {}",
+                io::Error::last_os_error()
+            );
+            return None;
+        }
+        let spawned = thread::Builder::new()
+            .name("frpc-output-iocp".to_string())
+            .stack_size(READER_STACK_SIZE)
+            .spawn(move || completion_loop(port));
+        if let Err(e) = spawned {
+            log::error!("无法创建 frpc 输出完成线程，改用读取线程: {}", e);
+            return None;
+        }
+        Some(port)
+    });
+    port.ok_or_else(|| io::Error::other("frpc 输出完成端口不可用"))
+}
+
+/// 一个输出流正在进行（或下一次）的重叠读取，连同读取缓冲区与未完成的行
+///
+/// 读取期间由完成端口持有（`Box::into_raw`），完成包中的 OVERLAPPED 指针即本结构的指针
+#[repr(C)]
+struct PendingRead {
+    /// 必须是第一个字段
+    overlapped: OVERLAPPED,
+    handle: OwnedHandle,
+    buf: Box<[u8]>,
+    /// 尚未遇到换行符的部分行
+    partial: Vec<u8>,
+    sink: Arc<InstanceSink>,
+    stream: Stream,
+}
+
+impl PendingRead {
+    fn start(reader: PipeReader, sink: Arc<InstanceSink>, stream: Stream) {
+        Self::issue(Box::new(PendingRead {
+            overlapped: unsafe { std::mem::zeroed() },
+            handle: reader.0,
+            buf: vec![0; READ_CHUNK_SIZE].into_boxed_slice(),
+            partial: Vec::new(),
+            sink,
+            stream,
+        }));
+    }
+
+    /// 发起下一次读取，所有权交给完成端口；管道已关闭时输出剩余的部分行并释放
+    fn issue(mut read: Box<Self>) {
+        read.overlapped = unsafe { std::mem::zeroed() };
+        let ptr = Box::into_raw(read);
+        let ok = unsafe {
+            let read = &mut *ptr;
+            ReadFile(
+                read.handle.as_raw_handle() as HANDLE,
+                read.buf.as_mut_ptr(),
+                read.buf.len() as u32,
+                std::ptr::null_mut(),
+                &mut read.overlapped,
+            )
+        };
+        // 同步完成时同样会投递完成包，只有失败且不是 ERROR_IO_PENDING 时不会
+        if ok == 0 && unsafe { GetLastError() } != ERROR_IO_PENDING {
+            unsafe { Box::from_raw(ptr) }.finish();
+        }
+    }
+
+    /// 读取完成：把缓冲区中完整的行投递到队列，剩余部分留到下一次
+    fn complete(&mut self, len: usize) {
+        let tx = pump();
+        self.partial.extend_from_slice(&self.buf[..len]);
+        let mut start = 0;
+        while let Some(pos) = self.partial[start..].iter().position(|&b| b == b'\n') {
+            let line = self.partial[start..start + pos].to_vec();
+            queue_line(tx, &self.sink, self.stream, line);
+            start += pos + 1;
+        }
+        self.partial.drain(..start);
+    }
+
+    /// 写端已全部关闭（进程退出）：最后一行没有换行符时同样投递，随后关闭读端
+    fn finish(&mut self) {
+        if !self.partial.is_empty() {
+            let line = std::mem::take(&mut self.partial);
+            queue_line(pump(), &self.sink, self.stream, line);
+        }
+    }
+}
+
+fn completion_loop(port: HANDLE) {
+    loop {
+        let mut len = 0u32;
+        let mut key = 0usize;
+        let mut overlapped: *mut OVERLAPPED = std::ptr::null_mut();
+        let ok = unsafe {
+            GetQueuedCompletionStatus(port, &mut len, &mut key, &mut overlapped, INFINITE)
+        };
+        if overlapped.is_null() {
+            // 完成端口本身的错误，正常运行中不会出现；稍后重试，避免空转
+            log::error!("等待 frpc 输出失败: {}", io::Error::last_os_error());
+            thread::sleep(Duration::from_secs(1));
+            continue;
+        }
+        let mut read = unsafe { Box::from_raw(overlapped as *mut PendingRead) };
+        if ok == 0 {
+            // ERROR_BROKEN_PIPE：写端已全部关闭
+            read.finish();
+            continue;
+        }
+        read.complete(len as usize);
+        PendingRead::issue(read);
+    }
+}
+
+/// 处理完队列中已有的输出后停止分发线程，确保停止前的最后几行日志不丢失
+pub fn shutdown() {
+    let Some(tx) = PUMP.get() else {
+        return;
+    };
+    let (ack_tx, ack_rx) = mpsc::channel();
+    if tx.send(Event::Shutdown(ack_tx)).is_ok() {
+        let _ = ack_rx.recv_timeout(SHUTDOWN_TIMEOUT);
+    }
+}
+
+fn dispatch_loop(rx: Receiver<Event>) {
+    for event in rx {
+        match event {
+            Event::Line {
+                sink,
+                stream,
+                seq,
+                line,
+            } => handle_line(&sink, stream, seq, &line),
+            Event::Shutdown(ack) => {
+                let _ = ack.send(());
+                break;
+            }
+        }
+    }
+}
+
+fn handle_line(sink: &InstanceSink, stream: Stream, seq: u64, line: &[u8]) {
+    // 实例标识作为独立字段（MDC）输出，不再拼入消息文本
+    let _instance = log_mdc::insert_scoped(MDC_INSTANCE, sink.identifier.as_str());
+
+    let dropped = sink.dropped.swap(0, Ordering::Relaxed);
+    if dropped > 0 {
+        log::warn!(
+            target: sink.log_target.as_str(),
+            "输出过快，日志队列已满，丢弃了 {} 行",
+            dropped
+        );
+    }
+
+    let cleaned_bytes = strip(line);
+    let cleaned_line = String::from_utf8_lossy(&cleaned_bytes);
+    sink.subscribers.publish(&cleaned_line);
+    match_output_rules(sink, &cleaned_line);
+    // 按 frpc 自身的日志级别标记输出（frpc 也会把正常信息写到 stderr），
+    // 无法识别时 stdout 记为 Info、stderr 记为 Warn
+    let level = frpc_level(&cleaned_line).unwrap_or(match stream {
+        Stream::Stdout => Level::Info,
+        Stream::Stderr => Level::Warn,
+    });
+    match stream {
+        Stream::Stdout => {
+            log::log!(
+                target: sink.log_target.as_str(),
+                level,
+                seq = seq;
+                "FRPC STDOUT: {}",
+                cleaned_line
+            );
+            let ready = match sink.ready {
+                Some(ref re) => re.is_match(&cleaned_line),
+                None => is_default_ready_line(&cleaned_line),
+            };
+            if ready {
+                if let Some(ref tx) = sink.on_connected {
+                    let _ = tx.send(());
+                }
+            }
+        }
+        Stream::Stderr if is_panic_line(&cleaned_line) => {
+            log::error!(
+                target: sink.log_target.as_str(),
+                seq = seq,
+                tag = "FRPC_PANIC";
+                "FRPC STDERR: {}",
+                cleaned_line
+            );
+            if !sink.panic_notified.swap(true, Ordering::Relaxed) {
+                if let Some(notify) = PANIC_NOTIFIER.get() {
+                    notify(&format!("{} 崩溃", sink.identifier), &cleaned_line);
+                }
+            }
+        }
+        Stream::Stderr => {
+            log::log!(
+                target: sink.log_target.as_str(),
+                level,
+                seq = seq;
+                "FRPC STDERR: {}",
+                cleaned_line
+            );
+        }
+    }
+}
+
+/// 按顺序匹配输出规则，第一条命中的规则生效；每条规则在同一进程中只触发一次
+fn match_output_rules(sink: &InstanceSink, line: &str) {
+    let Some(index) = sink.rules.iter().position(|(re, _)| re.is_match(line)) else {
+        return;
+    };
+    let (regex, action) = &sink.rules[index];
+    if *action == OutputAction::Ignore || sink.rules_fired[index].swap(true, Ordering::Relaxed) {
+        return;
+    }
+    log::warn!(
+        target: sink.log_target.as_str(),
+        "输出匹配规则 {:?}，执行动作 {:?}",
+        regex.as_str(),
+        action
+    );
+    OUTPUT_MATCHES.lock().unwrap().push(OutputMatch {
+        instance: sink.identifier.clone(),
+        pid: sink.pid,
+        pattern: regex.as_str().to_string(),
+        action: *action,
+        line: line.to_string(),
+    });
+}
+
+/// frpc 崩溃输出的标志行：Go 的 `panic:` / `fatal error:` 与信号行，Rust 的 `thread '...' panicked at`，
+/// Windows 访问违例（Go 报告为 `Exception 0xc0000005`）
+fn is_panic_line(line: &str) -> bool {
+    let line = line.trim_start();
+    line.starts_with("panic: ")
+        || line.starts_with("fatal error: ")
+        || line.starts_with("[signal ")
+        || line.starts_with("Exception 0xc0000005")
+        || (line.starts_with("thread '") && line.contains("' panicked"))
+        || line.contains("SIGSEGV")
+}
+
+/// 默认就绪输出：登录服务器成功或代理启动成功
+fn is_default_ready_line(line: &str) -> bool {
+    line.contains("login to server success") || line.contains("start proxy success")
+}
+
+/// 识别 frpc 输出行的日志级别标记：`[I]`/`[INFO]`、`[W]`/`[WARN]`、`[E]`/`[ERROR]`、`[D]`/`[DEBUG]`、`[T]`/`[TRACE]`
+///
+/// 标记可位于行首，或位于 frpc 自带的时间戳之后（如 `2024-01-01 12:00:00.000 [W] ...`）
+fn frpc_level(line: &str) -> Option<Level> {
+    let start = line.find('[')?;
+    let prefix = &line[..start];
+    if !prefix
+        .chars()
+        .all(|c| c.is_ascii_digit() || matches!(c, ' ' | '-' | '/' | ':' | '.'))
+    {
+        return None;
+    }
+    let rest = &line[start + 1..];
+    let tag = &rest[..rest.find(']')?];
+    match tag {
+        "I" | "INFO" => Some(Level::Info),
+        "W" | "WARN" => Some(Level::Warn),
+        "E" | "ERROR" => Some(Level::Error),
+        "D" | "DEBUG" => Some(Level::Debug),
+        "T" | "TRACE" => Some(Level::Trace),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sink(identifier: &str, pid: u32, rules: &[(&str, OutputAction)]) -> InstanceSink {
+        let rules: Vec<(Regex, OutputAction)> = rules
+            .iter()
+            .map(|(pattern, action)| (Regex::new(pattern).unwrap(), *action))
+            .collect();
+        InstanceSink {
+            identifier: identifier.to_string(),
+            pid,
+            log_target: format!("{}{}", FRPC_TARGET_PREFIX, identifier),
+            on_connected: None,
+            ready: None,
+            dropped: AtomicU64::new(0),
+            seq: Arc::new(AtomicU64::new(0)),
+            subscribers: Arc::default(),
+            panic_notified: AtomicBool::new(false),
+            rules_fired: rules.iter().map(|_| AtomicBool::new(false)).collect(),
+            rules,
+        }
+    }
+
+    /// 取出某个实例的命中记录，其他实例（并行的测试）的留在队列中
+    fn take_matches(instance: &str) -> Vec<(String, OutputAction)> {
+        let mut matches = OUTPUT_MATCHES.lock().unwrap();
+        let (own, others) = std::mem::take(&mut *matches)
+            .into_iter()
+            .partition::<Vec<_>, _>(|m| m.instance == instance);
+        *matches = others;
+        own.into_iter().map(|m| (m.pattern, m.action)).collect()
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let sink = sink(
+            "rules-first",
+            1,
+            &[
+                ("login to server failed", OutputAction::Restart),
+                ("failed", OutputAction::Alert),
+            ],
+        );
+        match_output_rules(&sink, "[W] login to server failed: i/o timeout");
+        match_output_rules(&sink, "[W] start proxy failed");
+        assert_eq!(
+            take_matches("rules-first"),
+            vec![
+                ("login to server failed".to_string(), OutputAction::Restart),
+                ("failed".to_string(), OutputAction::Alert),
+            ]
+        );
+        match_output_rules(&sink, "[I] login to server success");
+        assert!(take_matches("rules-first").is_empty());
+    }
+
+    #[test]
+    fn ignore_rule_stops_matching() {
+        let sink = sink(
+            "rules-ignore",
+            1,
+            &[
+                ("proxy \\[ssh\\]", OutputAction::Ignore),
+                ("error", OutputAction::Stop),
+            ],
+        );
+        match_output_rules(&sink, "[E] proxy [ssh] error: port already used");
+        assert!(take_matches("rules-ignore").is_empty());
+        match_output_rules(&sink, "[E] proxy [web] error: port already used");
+        assert_eq!(
+            take_matches("rules-ignore"),
+            vec![("error".to_string(), OutputAction::Stop)]
+        );
+    }
+
+    #[test]
+    fn rules_fire_once_per_process() {
+        let rules = [("connection refused", OutputAction::Alert)];
+        let first = sink("rules-once", 1, &rules);
+        match_output_rules(&first, "[W] dial tcp: connection refused");
+        match_output_rules(&first, "[W] dial tcp: connection refused");
+        let matches = take_matches("rules-once");
+        assert_eq!(matches.len(), 1);
+
+        // 重启后的新进程重新计数
+        let restarted = sink("rules-once", 2, &rules);
+        match_output_rules(&restarted, "[W] dial tcp: connection refused");
+        let matches: Vec<_> = OUTPUT_MATCHES
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|m| m.instance == "rules-once")
+            .map(|m| m.pid)
+            .collect();
+        assert_eq!(matches, vec![2]);
+        take_matches("rules-once");
+    }
+
+    #[test]
+    fn panic_lines() {
+        for line in [
+            "panic: runtime error: invalid memory address or nil pointer dereference",
+            "  fatal error: concurrent map writes",
+            "[signal 0xc0000005 code=0x0 addr=0x0 pc=0x5d1a2b]",
+            "Exception 0xc0000005 0x0 0x0 0x7ff6",
+            "thread 'main' panicked at src/main.rs:10:5:",
+            "unexpected signal: SIGSEGV",
+        ] {
+            assert!(is_panic_line(line), "{}", line);
+        }
+        for line in [
+            "2024-01-01 12:00:00.000 [E] login to server failed: EOF",
+            "[W] [proxy.go:123] panic recovered in proxy [ssh]",
+            "thread 'main' started",
+        ] {
+            assert!(!is_panic_line(line), "{}", line);
+        }
+    }
+
+    #[test]
+    fn level_tags() {
+        assert_eq!(
+            frpc_level("[I] [service.go:301] login to server success"),
+            Some(Level::Info)
+        );
+        assert_eq!(
+            frpc_level("2024-01-01 12:00:00.000 [W] [proxy] dial failed"),
+            Some(Level::Warn)
+        );
+        assert_eq!(
+            frpc_level("2024/01/01 12:00:00 [ERROR] start error"),
+            Some(Level::Error)
+        );
+        assert_eq!(frpc_level("[DEBUG] heartbeat"), Some(Level::Debug));
+        assert_eq!(frpc_level("[T] trace"), Some(Level::Trace));
+        // 标记前有时间戳以外的内容、标记未知或方括号不闭合时不识别
+        assert_eq!(frpc_level("proxy [W] ssh"), None);
+        assert_eq!(frpc_level("[ssh] start proxy success"), None);
+        assert_eq!(frpc_level("2024-01-01 12:00:00 [W login"), None);
+        assert_eq!(frpc_level("["), None);
+        assert_eq!(frpc_level("no tag"), None);
+    }
+
+    /// 带就绪通知的实例，返回通知的接收端
+    fn ready_sink(identifier: &str, ready: Option<&str>) -> (InstanceSink, Receiver<()>) {
+        let (tx, rx) = mpsc::channel();
+        let sink = InstanceSink {
+            on_connected: Some(tx),
+            ready: ready.map(|pattern| Regex::new(pattern).unwrap()),
+            ..sink(identifier, 1, &[])
+        };
+        (sink, rx)
+    }
+
+    #[test]
+    fn ready_pattern_replaces_default_ready_lines() {
+        let (sink, rx) = ready_sink("ready-pattern", Some(r"proxy \[web\] .*success"));
+        handle_line(&sink, Stream::Stdout, 1, b"[I] login to server success");
+        handle_line(
+            &sink,
+            Stream::Stdout,
+            2,
+            b"[I] proxy [ssh] start proxy success",
+        );
+        assert!(rx.try_recv().is_err());
+        // 就绪规则只匹配 stdout
+        handle_line(
+            &sink,
+            Stream::Stderr,
+            3,
+            b"[I] proxy [web] start proxy success",
+        );
+        assert!(rx.try_recv().is_err());
+        // 匹配时已去除 ANSI 转义
+        handle_line(
+            &sink,
+            Stream::Stdout,
+            4,
+            b"\x1b[1;34m[I] proxy [web] start proxy success\x1b[0m",
+        );
+        assert!(rx.try_recv().is_ok());
+    }
+
+    #[test]
+    fn default_ready_lines() {
+        let (sink, rx) = ready_sink("ready-default", None);
+        handle_line(&sink, Stream::Stdout, 1, b"[I] try to connect to server...");
+        assert!(rx.try_recv().is_err());
+        handle_line(
+            &sink,
+            Stream::Stdout,
+            2,
+            b"[I] login to server success, get run id [abc]",
+        );
+        assert!(rx.try_recv().is_ok());
+    }
+}
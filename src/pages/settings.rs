@@ -10,6 +10,7 @@ use gpui_component::spinner::Spinner;
 use gpui_component::{ActiveTheme, Disableable, Sizable, Size};
 
 use crate::app::AppView;
+use crate::config;
 use crate::download;
 
 /// 分割线
@@ -186,8 +187,8 @@ pub fn render(view: &mut AppView, cx: &mut Context<AppView>) -> gpui::AnyElement
                                 .with_size(Size::Small)
                                 .primary()
                                 .label("注册服务")
-                                .on_click(cx.listener(|view, _event, _window, cx| {
-                                    view.install_service(cx);
+                                .on_click(cx.listener(|view, _event, window, cx| {
+                                    view.install_service(window, cx);
                                 }))
                                 .into_any_element()
                         })
@@ -319,6 +320,64 @@ pub fn render(view: &mut AppView, cx: &mut Context<AppView>) -> gpui::AnyElement
                 ),
         )
         .child(div().mx(px(24.0)).child(separator(cx.theme())))
+        // ========== 系统通知 ==========
+        .child(
+            div()
+                .mx(px(24.0))
+                .py(px(16.0))
+                .flex()
+                .flex_col()
+                .gap_y(px(12.0))
+                // 标题行：标题 + 开关
+                .child(
+                    div()
+                        .flex()
+                        .items_center()
+                        .gap_x(px(12.0))
+                        .child(
+                            div()
+                                .text_sm()
+                                .font_weight(FontWeight::SEMIBOLD)
+                                .text_color(cx.theme().foreground)
+                                .child("系统通知"),
+                        )
+                        .child(
+                            div()
+                                .id("switch-toast-notifications")
+                                .w(px(36.0))
+                                .h(px(20.0))
+                                .rounded(px(10.0))
+                                .bg(if view.toast_notifications {
+                                    cx.theme().primary
+                                } else {
+                                    cx.theme().border
+                                })
+                                .cursor_pointer()
+                                .flex()
+                                .items_center()
+                                .px(px(2.0))
+                                .child(
+                                    div()
+                                        .w(px(16.0))
+                                        .h(px(16.0))
+                                        .rounded_full()
+                                        .bg(gpui::rgb(0xffffff))
+                                        .when(view.toast_notifications, |el| el.ml_auto()),
+                                )
+                                .on_click(cx.listener(|view, _event, _window, cx| {
+                                    view.toggle_toast_notifications(cx);
+                                })),
+                        ),
+                )
+                // 说明
+                .child(
+                    div()
+                        .text_xs()
+                        .text_color(cx.theme().muted_foreground)
+                        .child("开启后，实例掉线或恢复时在 Windows 通知中心弹出提示。"),
+                ),
+        )
+        .child(div().mx(px(24.0)).child(separator(cx.theme())))
         // ========== 日志 ==========
         .child(
             div()
@@ -345,10 +404,7 @@ pub fn render(view: &mut AppView, cx: &mut Context<AppView>) -> gpui::AnyElement
                                 .with_size(Size::Small)
                                 .label("打开日志目录")
                                 .on_click(cx.listener(|_view, _event, _window, _cx| {
-                                    let logs_dir = std::env::current_exe()
-                                        .ok()
-                                        .and_then(|p| p.parent().map(|p| p.join("logs")));
-                                    if let Some(dir) = logs_dir {
+                                    if let Ok(dir) = config::logs_dir() {
                                         let _ =
                                             std::process::Command::new("explorer").arg(dir).spawn();
                                     }
@@ -0,0 +1,185 @@
+//! 运行中实例的路径自检：定期确认各实例的 frpc 程序与配置文件仍在磁盘上
+//!
+//! 服务长时间运行期间，配置文件可能被删除或移动，而实例仍按启动时的路径运行，
+//! 直到崩溃重启时才以含糊的错误失败。每隔 `path_check_interval_secs` 秒检查一次，
+//! 路径消失时记录警告并按实例告警策略告警（每次消失只告警一次），恢复后记录日志；
+//! 重启前同样检查，路径已消失时明确报告，而不是把不存在的路径交给 frpc
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// 路径自检状态；间隔为 0 时不检查
+pub(crate) struct PathCheck {
+    interval: Duration,
+    next_check: Option<Instant>,
+    /// 已报告的实例及消失路径的说明，恢复后移除
+    missing: HashMap<String, Vec<String>>,
+}
+
+impl PathCheck {
+    pub(crate) fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            next_check: None,
+            missing: HashMap::new(),
+        }
+    }
+
+    /// 修改检查间隔，下一轮按新间隔重新计时
+    pub(crate) fn set_interval(&mut self, interval: Duration) {
+        if interval != self.interval {
+            self.interval = interval;
+            self.next_check = None;
+        }
+    }
+
+    /// 到了检查时间时检查 `running` 中的实例（名称, 程序, 配置），返回本次新发现路径消失的实例
+    pub(crate) fn poll(
+        &mut self,
+        now: Instant,
+        running: impl FnOnce() -> Vec<(String, PathBuf, PathBuf)>,
+    ) -> Vec<(String, Vec<String>)> {
+        if self.interval.is_zero() {
+            self.missing.clear();
+            return Vec::new();
+        }
+        if self.next_check.is_some_and(|next| now < next) {
+            return Vec::new();
+        }
+        self.next_check = Some(now + self.interval);
+
+        let running = running();
+        // 已不在运行的实例不再跟踪，重新启动后消失会再次报告
+        self.missing
+            .retain(|name, _| running.iter().any(|(n, _, _)| n == name));
+        let mut newly_missing = Vec::new();
+        for (name, exe, conf) in running {
+            let gone = missing_paths(&exe, &conf);
+            if gone.is_empty() {
+                if self.missing.remove(&name).is_some() {
+                    log::info!("[{}] 程序与配置文件已恢复", name);
+                }
+                continue;
+            }
+            if self.missing.get(&name) == Some(&gone) {
+                continue;
+            }
+            log::warn!(
+                "[{}] {}，实例仍在运行，但退出后将无法重启",
+                name,
+                gone.join("；")
+            );
+            self.missing.insert(name.clone(), gone.clone());
+            newly_missing.push((name, gone));
+        }
+        newly_missing
+    }
+}
+
+/// `exe` 与 `conf` 中已不存在的路径的说明，如 `配置文件已消失: C:\...\frpc.toml`
+pub(crate) fn missing_paths(exe: &Path, conf: &Path) -> Vec<String> {
+    [("frpc 程序", exe), ("配置文件", conf)]
+        .into_iter()
+        .filter(|(_, path)| !path.exists())
+        .map(|(kind, path)| format!("{}已消失: {}", kind, path.display()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    struct Paths {
+        dir: PathBuf,
+        exe: PathBuf,
+        conf: PathBuf,
+    }
+
+    impl Paths {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "frpdesk-path-check-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            let (exe, conf) = (dir.join("frpc.exe"), dir.join("web.toml"));
+            fs::write(&exe, "").unwrap();
+            fs::write(&conf, "").unwrap();
+            Self { dir, exe, conf }
+        }
+
+        fn running(&self) -> Vec<(String, PathBuf, PathBuf)> {
+            vec![("web".to_string(), self.exe.clone(), self.conf.clone())]
+        }
+    }
+
+    impl Drop for Paths {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    #[test]
+    fn missing_paths_are_reported_once() {
+        let paths = Paths::new("once");
+        let mut check = PathCheck::new(Duration::from_secs(60));
+        let start = Instant::now();
+        assert!(check.poll(start, || paths.running()).is_empty());
+
+        fs::remove_file(&paths.conf).unwrap();
+        // 间隔内不检查
+        let early = start + Duration::from_secs(59);
+        assert!(check.poll(early, || panic!("间隔内不应检查")).is_empty());
+        let first = start + Duration::from_secs(60);
+        let reported = check.poll(first, || paths.running());
+        assert_eq!(reported.len(), 1);
+        assert_eq!(reported[0].0, "web");
+        assert_eq!(
+            reported[0].1,
+            [format!("配置文件已消失: {}", paths.conf.display())]
+        );
+        // 同样的路径仍消失时不再报告
+        assert!(check
+            .poll(first + Duration::from_secs(60), || paths.running())
+            .is_empty());
+
+        // 又有路径消失时按新的说明再次报告
+        fs::remove_file(&paths.exe).unwrap();
+        let reported = check.poll(first + Duration::from_secs(120), || paths.running());
+        assert_eq!(reported[0].1.len(), 2);
+    }
+
+    #[test]
+    fn restored_or_stopped_instances_are_reported_again() {
+        let paths = Paths::new("again");
+        let mut check = PathCheck::new(Duration::from_secs(1));
+        let mut now = Instant::now();
+        let mut tick = |check: &mut PathCheck, running: Vec<_>| {
+            now += Duration::from_secs(1);
+            check.poll(now, || running).len()
+        };
+
+        fs::remove_file(&paths.conf).unwrap();
+        assert_eq!(tick(&mut check, paths.running()), 1);
+        fs::write(&paths.conf, "").unwrap();
+        assert_eq!(tick(&mut check, paths.running()), 0);
+        fs::remove_file(&paths.conf).unwrap();
+        assert_eq!(tick(&mut check, paths.running()), 1);
+
+        // 实例停止后不再跟踪，重新启动后仍消失时再次报告
+        assert_eq!(tick(&mut check, Vec::new()), 0);
+        assert_eq!(tick(&mut check, paths.running()), 1);
+    }
+
+    #[test]
+    fn zero_interval_disables_checks() {
+        let mut check = PathCheck::new(Duration::ZERO);
+        assert!(check
+            .poll(Instant::now(), || panic!("间隔为 0 时不应检查"))
+            .is_empty());
+    }
+}
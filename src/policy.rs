@@ -0,0 +1,223 @@
+//! 注册表中的服务级配置（`HKLM\SOFTWARE\FrpcService`），便于企业通过组策略下发
+//!
+//! 值名与 settings.json 中的键相同（如 `process_guard`、`log_level`），作为默认值与
+//! settings.json 之间的一层；另有只能在注册表中设置的 `ServiceName`、`ConfigDir`。
+//! 键或值不存在时不产生任何影响，类型不符的值记录警告后忽略
+
+use std::sync::Mutex;
+use windows_sys::Win32::Foundation::{ERROR_FILE_NOT_FOUND, ERROR_SUCCESS};
+use windows_sys::Win32::System::Registry::{
+    RegGetValueW, HKEY_LOCAL_MACHINE, REG_DWORD, REG_QWORD, REG_SZ, RRF_RT_ANY,
+    RRF_SUBKEY_WOW6464KEY,
+};
+
+/// 策略所在的注册表键（HKLM 下，始终读取 64 位视图）
+pub(crate) const POLICY_KEY: &str = "SOFTWARE\\FrpcService";
+
+/// 服务名（REG_SZ），未设置时为 `FrpcService`
+pub(crate) const SERVICE_NAME_VALUE: &str = "ServiceName";
+
+/// 数据根目录（REG_SZ / REG_EXPAND_SZ），优先级低于环境变量 `FRPC_SERVICE_CONFIG_DIR`
+pub(crate) const CONFIG_DIR_VALUE: &str = "ConfigDir";
+
+/// 非 0 时注册表中的设置覆盖 settings.json（REG_DWORD），默认 settings.json 优先
+const OVERRIDES_SETTINGS_VALUE: &str = "PolicyOverridesSettings";
+
+/// 注册表中读到的值
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum RegValue {
+    Dword(u32),
+    Qword(u64),
+    /// REG_SZ；REG_EXPAND_SZ 读取时已展开环境变量
+    String(String),
+    /// 其他类型，附带类型编号
+    Other(u32),
+}
+
+impl RegValue {
+    fn type_name(&self) -> String {
+        match self {
+            RegValue::Dword(_) => "REG_DWORD".to_string(),
+            RegValue::Qword(_) => "REG_QWORD".to_string(),
+            RegValue::String(_) => "REG_SZ".to_string(),
+            RegValue::Other(kind) => format!("类型 {}", kind),
+        }
+    }
+}
+
+/// 策略值的来源；正常运行时为 [`MachinePolicy`]，可替换为其他实现以便在没有注册表的环境中验证合并逻辑
+pub(crate) trait PolicySource {
+    /// 读取一个值，键或值不存在时返回 None
+    fn value(&self, name: &str) -> Option<RegValue>;
+}
+
+/// `HKLM\SOFTWARE\FrpcService`
+pub(crate) struct MachinePolicy;
+
+impl PolicySource for MachinePolicy {
+    fn value(&self, name: &str) -> Option<RegValue> {
+        let key: Vec<u16> = format!("{}\0", POLICY_KEY).encode_utf16().collect();
+        let value: Vec<u16> = format!("{}\0", name).encode_utf16().collect();
+        let flags = RRF_RT_ANY | RRF_SUBKEY_WOW6464KEY;
+        let mut kind = 0u32;
+        let mut size = 0u32;
+        let status = unsafe {
+            RegGetValueW(
+                HKEY_LOCAL_MACHINE,
+                key.as_ptr(),
+                value.as_ptr(),
+                flags,
+                &mut kind,
+                std::ptr::null_mut(),
+                &mut size,
+            )
+        };
+        if status == ERROR_FILE_NOT_FOUND {
+            return None;
+        }
+        if status != ERROR_SUCCESS {
+            warn_once(
+                name,
+                format!("读取注册表值 {} 失败（错误码 {}），已忽略", name, status),
+            );
+            return None;
+        }
+        let mut data = vec![0u8; size as usize];
+        let status = unsafe {
+            RegGetValueW(
+                HKEY_LOCAL_MACHINE,
+                key.as_ptr(),
+                value.as_ptr(),
+                flags,
+                &mut kind,
+                data.as_mut_ptr() as *mut _,
+                &mut size,
+            )
+        };
+        if status != ERROR_SUCCESS {
+            warn_once(
+                name,
+                format!("读取注册表值 {} 失败（错误码 {}），已忽略", name, status),
+            );
+            return None;
+        }
+        data.truncate(size as usize);
+        Some(match kind {
+            REG_DWORD if data.len() == 4 => {
+                RegValue::Dword(u32::from_le_bytes(data[..4].try_into().unwrap()))
+            }
+            REG_QWORD if data.len() == 8 => {
+                RegValue::Qword(u64::from_le_bytes(data[..8].try_into().unwrap()))
+            }
+            // RegGetValueW 会展开 REG_EXPAND_SZ，返回的类型为 REG_SZ
+            REG_SZ => {
+                let wide: Vec<u16> = data
+                    .chunks_exact(2)
+                    .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                    .take_while(|&c| c != 0)
+                    .collect();
+                RegValue::String(String::from_utf16_lossy(&wide))
+            }
+            other => RegValue::Other(other),
+        })
+    }
+}
+
+/// 注册表中可设置的 settings.json 项应有的类型
+#[derive(Debug, Clone, Copy)]
+enum Expected {
+    /// REG_DWORD，0 为 false，非 0 为 true
+    Bool,
+    /// REG_SZ
+    String,
+}
+
+/// 可在注册表中设置的 settings.json 项；嵌套的对象（如 `log_retention`）只能在 settings.json 中设置
+const SETTINGS_VALUES: &[(&str, Expected)] = &[
+    ("log_level", Expected::String),
+    ("frpc_log_level", Expected::String),
+    ("log_format", Expected::String),
+    ("process_guard", Expected::Bool),
+    ("monitor_panic_recovery", Expected::Bool),
+    ("no_update_check", Expected::Bool),
+    ("strict_unique_configs", Expected::Bool),
+    ("alert_webhook_url", Expected::String),
+    ("output_seq_across_restarts", Expected::Bool),
+    ("toast_notifications", Expected::Bool),
+];
+
+/// 注册表中设置的 settings.json 项，按 settings.json 的键组织；类型不符的值记录警告后跳过
+pub(crate) fn settings_layer(
+    source: &dyn PolicySource,
+) -> serde_json::Map<String, serde_json::Value> {
+    let mut layer = serde_json::Map::new();
+    for &(name, expected) in SETTINGS_VALUES {
+        let Some(value) = source.value(name) else {
+            continue;
+        };
+        let converted = match (expected, &value) {
+            (Expected::Bool, RegValue::Dword(v)) => Some(serde_json::Value::Bool(*v != 0)),
+            (Expected::String, RegValue::String(s)) => Some(serde_json::Value::String(s.clone())),
+            _ => None,
+        };
+        match converted {
+            Some(json) => {
+                layer.insert(name.to_string(), json);
+            }
+            None => warn_mismatch(name, expected, &value),
+        }
+    }
+    layer
+}
+
+/// 注册表中的设置是否覆盖 settings.json（`PolicyOverridesSettings` 非 0）
+pub(crate) fn overrides_settings(source: &dyn PolicySource) -> bool {
+    match source.value(OVERRIDES_SETTINGS_VALUE) {
+        None => false,
+        Some(RegValue::Dword(v)) => v != 0,
+        Some(other) => {
+            warn_mismatch(OVERRIDES_SETTINGS_VALUE, Expected::Bool, &other);
+            false
+        }
+    }
+}
+
+/// 读取字符串值（如 `ServiceName`、`ConfigDir`），未设置、为空或类型不符时返回 None
+pub(crate) fn string_value(source: &dyn PolicySource, name: &str) -> Option<String> {
+    match source.value(name)? {
+        RegValue::String(s) => Some(s.trim().to_string()).filter(|s| !s.is_empty()),
+        other => {
+            warn_mismatch(name, Expected::String, &other);
+            None
+        }
+    }
+}
+
+fn warn_mismatch(name: &str, expected: Expected, value: &RegValue) {
+    let expected = match expected {
+        Expected::Bool => "REG_DWORD",
+        Expected::String => "REG_SZ",
+    };
+    warn_once(
+        name,
+        format!(
+            "注册表值 HKLM\\{}\\{} 的类型为 {}，应为 {}，已忽略",
+            POLICY_KEY,
+            name,
+            value.type_name(),
+            expected
+        ),
+    );
+}
+
+/// 已警告过的值名；设置会被频繁读取，同一个值只警告一次
+static WARNED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// 记录警告，同一个 `name` 在进程内只记录一次
+pub(crate) fn warn_once(name: &str, message: String) {
+    let mut warned = WARNED.lock().unwrap();
+    if !warned.iter().any(|n| n == name) {
+        warned.push(name.to_string());
+        log::warn!("{}", message);
+    }
+}
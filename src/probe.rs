@@ -0,0 +1,176 @@
+//! 启动探测：进程启动成功不代表隧道已建立，探测通过后才视为健康
+//!
+//! 两种探测方式同时进行，任一通过即可：
+//! - 监听 frpc 输出中的就绪行：实例配置的 `ready_pattern`，默认为
+//!   "login to server success" / "start proxy success"
+//! - 配置了 webServer（admin API）时轮询 `/api/status`，至少一个代理为 running
+//!
+//! 目录模式（`config_dir`）下一个进程运行多份配置，需要收到与配置文件数相同次数的就绪信号，
+//! 不轮询 admin API（各文件的 webServer 互不相同）
+
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// admin API 轮询间隔
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// frpc admin API 连接信息（来自配置文件的 webServer 段）
+pub struct AdminApi {
+    /// 形如 `http://127.0.0.1:7400`，不含路径
+    pub base_url: String,
+    pub user: Option<String>,
+    pub password: Option<String>,
+}
+
+/// 从 frpc 配置中解析 admin API 地址，未配置 webServer.port 或解析失败时返回 None
+fn admin_api_from_config(config_path: &Path) -> Option<AdminApi> {
+    admin_api_lookup(config_path).ok().flatten()
+}
+
+/// 从 frpc 配置中解析 admin API 地址
+///
+/// 没有 webServer 段时返回 Ok(None)；配置无法读取/解析，或 webServer 中端口缺失、无效时返回错误
+pub fn admin_api_lookup(config_path: &Path) -> anyhow::Result<Option<AdminApi>> {
+    use anyhow::Context;
+    let content = std::fs::read_to_string(config_path)
+        .context(format!("无法读取配置文件: {:?}", config_path))?;
+    let value: toml::Value = toml::from_str(&content).context("配置文件不是有效的 TOML")?;
+    let Some(web) = value.get("webServer").or_else(|| value.get("web_server")) else {
+        return Ok(None);
+    };
+    let port = web
+        .get("port")
+        .and_then(|v| v.as_integer())
+        .filter(|p| (1..=65535).contains(p))
+        .context("webServer.port 缺失或不是有效的端口号")?;
+    let addr = web
+        .get("addr")
+        .and_then(|v| v.as_str())
+        .filter(|s| !s.is_empty() && *s != "0.0.0.0")
+        .unwrap_or("127.0.0.1");
+    Ok(Some(AdminApi {
+        base_url: format!("http://{}:{}", addr, port),
+        user: web.get("user").and_then(|v| v.as_str()).map(String::from),
+        password: web
+            .get("password")
+            .and_then(|v| v.as_str())
+            .map(String::from),
+    }))
+}
+
+/// 配置文件是否启用了 admin API（webServer.port），`frpc reload` 依赖它
+pub fn has_admin_api(config_path: &Path) -> bool {
+    admin_api_from_config(config_path).is_some()
+}
+
+/// 查询 admin API，至少一个代理状态为 running 时返回 true
+pub(crate) fn admin_api_has_running_proxy(
+    client: &reqwest::blocking::Client,
+    api: &AdminApi,
+) -> bool {
+    let mut req = client.get(format!("{}/api/status", api.base_url));
+    if let Some(ref user) = api.user {
+        req = req.basic_auth(user, api.password.as_ref());
+    }
+    let json: serde_json::Value = match req.send().and_then(|r| r.json()) {
+        Ok(v) => v,
+        Err(_) => return false,
+    };
+    // 格式: {"tcp": [{"name": "...", "status": "running", ...}], "udp": [...]}
+    json.as_object()
+        .map(|types| {
+            types.values().filter_map(|v| v.as_array()).any(|proxies| {
+                proxies
+                    .iter()
+                    .any(|p| p.get("status").and_then(|s| s.as_str()) == Some("running"))
+            })
+        })
+        .unwrap_or(false)
+}
+
+/// 在后台线程执行启动探测，不阻塞调用方
+///
+/// - `connected`: 输出泵在检测到就绪输出时发送信号
+/// - `ready_pattern`: 实例配置的就绪规则，仅用于超时日志
+/// - `on_done`: 探测结束后回调，参数为是否通过
+pub fn spawn_startup_probe<F>(
+    identifier: String,
+    config_path: PathBuf,
+    connected: Receiver<()>,
+    ready_pattern: Option<String>,
+    timeout: Duration,
+    on_done: F,
+) where
+    F: FnOnce(bool) + Send + 'static,
+{
+    thread::spawn(move || {
+        let expected = if config_path.is_dir() {
+            crate::config::config_dir_files(&config_path)
+                .map_or(1, |files| files.len())
+                .max(1)
+        } else {
+            1
+        };
+        let mut ready = 0;
+        let api = admin_api_from_config(&config_path);
+        let client = api.as_ref().and_then(|_| {
+            reqwest::blocking::Client::builder()
+                .timeout(Duration::from_secs(2))
+                .build()
+                .ok()
+        });
+        let deadline = Instant::now() + timeout;
+
+        let passed = loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break false;
+            }
+            match connected.recv_timeout(remaining.min(POLL_INTERVAL)) {
+                Ok(()) => {
+                    ready += 1;
+                    if ready >= expected {
+                        break true;
+                    }
+                }
+                // 输出线程已结束，说明进程已退出
+                Err(RecvTimeoutError::Disconnected) => break false,
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+            if let (Some(api), Some(client)) = (&api, &client) {
+                if admin_api_has_running_proxy(client, api) {
+                    break true;
+                }
+            }
+        };
+
+        if passed {
+            log::info!("[{}] 启动探测通过，隧道已建立", identifier);
+        } else if expected > 1 {
+            log::warn!(
+                "[{}] 启动探测超时（{} 秒内只有 {}/{} 个配置就绪），请检查目录中各配置的 token/服务器地址",
+                identifier,
+                timeout.as_secs(),
+                ready,
+                expected
+            );
+        } else {
+            match ready_pattern {
+                Some(pattern) => log::warn!(
+                    "[{}] 启动探测超时（{} 秒内没有输出匹配就绪规则 `{}`），请检查 ready_pattern 或 token/服务器地址",
+                    identifier,
+                    timeout.as_secs(),
+                    pattern
+                ),
+                None => log::warn!(
+                    "[{}] 启动探测超时（{} 秒内未确认隧道建立），请检查 token/服务器地址",
+                    identifier,
+                    timeout.as_secs()
+                ),
+            }
+        }
+        on_done(passed);
+    });
+}
@@ -0,0 +1,494 @@
+//! 采样分析（`profile`）：定期抓取运行中 frpc 各线程的调用栈并汇总热点，用于现场排查 CPU 占用过高
+//!
+//! 每轮枚举 frpc 的线程，只采样自上一轮以来消耗了 CPU 的线程：挂起线程、读取寄存器上下文，
+//! 用 `StackWalk64`（通过 `ReadProcessMemory` 读取目标进程的栈）回溯调用栈后立即恢复。
+//! 比完整的 WinDbg 会话轻量，适合现场诊断；结果写入文本报告。
+//!
+//! - frpc 发布版不带符号，没有符号的帧输出为 `模块+偏移`，可用同版本源码构建的 frpc 配合
+//!   `go tool addr2line` 解析
+//! - 只支持 64 位 frpc；打开服务启动的 frpc 与 `snapshot` 一样需要 SeDebugPrivilege
+
+use anyhow::{Context, Result};
+use chrono::Local;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::ffi::c_void;
+use std::fmt::Write as _;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use windows_sys::Win32::Foundation::{
+    CloseHandle, GetLastError, BOOL, FILETIME, HANDLE, INVALID_HANDLE_VALUE, WAIT_OBJECT_0,
+};
+use windows_sys::Win32::System::Diagnostics::Debug::{
+    AddrModeFlat, GetThreadContext, ReadProcessMemory, StackWalk64, SymCleanup, SymFromAddrW,
+    SymFunctionTableAccess64, SymGetModuleBase64, SymGetModuleInfoW64, SymInitializeW,
+    SymSetOptions, ADDRESS64, CONTEXT, CONTEXT_FULL_AMD64, IMAGEHLP_MODULEW64, STACKFRAME64,
+    SYMBOL_INFOW, SYMOPT_DEFERRED_LOADS, SYMOPT_UNDNAME,
+};
+use windows_sys::Win32::System::Diagnostics::ToolHelp::{
+    CreateToolhelp32Snapshot, Thread32First, Thread32Next, TH32CS_SNAPTHREAD, THREADENTRY32,
+};
+use windows_sys::Win32::System::SystemInformation::IMAGE_FILE_MACHINE_AMD64;
+use windows_sys::Win32::System::Threading::{
+    GetProcessTimes, GetThreadTimes, IsWow64Process, OpenThread, ResumeThread, SuspendThread,
+    WaitForSingleObject, PROCESS_QUERY_INFORMATION, PROCESS_SYNCHRONIZE, PROCESS_VM_READ,
+    THREAD_GET_CONTEXT, THREAD_QUERY_INFORMATION, THREAD_SUSPEND_RESUME,
+};
+
+use crate::minidump;
+
+/// 两轮采样的间隔
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(20);
+
+/// 每个调用栈最多回溯的帧数
+const MAX_FRAMES: usize = 64;
+
+/// 报告中列出的热点函数数量
+const TOP_FUNCTIONS: usize = 30;
+
+/// 报告中列出的调用栈数量
+const TOP_STACKS: usize = 20;
+
+/// 符号名的最大长度（UTF-16 字符）
+const MAX_SYMBOL_NAME: usize = 512;
+
+/// 对实例当前运行的 frpc 采样 `duration`，返回报告文件路径
+///
+/// `output` 未指定时为数据根目录下的 `<配置名>-profile-<时间>.txt`
+pub fn run(identifier: &str, duration: Duration, output: Option<PathBuf>) -> Result<PathBuf> {
+    let (pid, process) = minidump::open_instance_process(
+        identifier,
+        PROCESS_QUERY_INFORMATION | PROCESS_VM_READ | PROCESS_SYNCHRONIZE,
+    )?;
+    let result = output
+        .map_or_else(
+            || minidump::default_output(&format!("{}-profile-", identifier), "txt"),
+            Ok,
+        )
+        .and_then(|output| {
+            let report = profile_process(identifier, pid, process, duration)?;
+            std::fs::write(&output, report)
+                .context(format!("无法写入采样报告: {}", output.display()))?;
+            Ok(output)
+        });
+    unsafe { CloseHandle(process) };
+    result
+}
+
+/// 采样并生成报告文本
+fn profile_process(
+    identifier: &str,
+    pid: u32,
+    process: HANDLE,
+    duration: Duration,
+) -> Result<String> {
+    let mut wow64: BOOL = 0;
+    if unsafe { IsWow64Process(process, &mut wow64) } != 0 && wow64 != 0 {
+        anyhow::bail!("frpc（PID: {}）是 32 位进程，暂不支持采样", pid);
+    }
+    // 符号会话需在回溯前初始化：StackWalk64 通过它查找 x64 函数的展开信息
+    let mut symbols = SymbolSession::new(process)?;
+
+    let started_at = Local::now();
+    let start = Instant::now();
+    let cpu_before = process_cpu_time(process);
+    let mut sampler = Sampler::new(pid, process);
+    let mut exited = false;
+    while start.elapsed() < duration {
+        if unsafe { WaitForSingleObject(process, 0) } == WAIT_OBJECT_0 {
+            exited = true;
+            break;
+        }
+        sampler.sample_once();
+        std::thread::sleep(SAMPLE_INTERVAL);
+    }
+    let elapsed = start.elapsed();
+    let cpu_time = process_cpu_time(process).saturating_sub(cpu_before);
+
+    let profile = &sampler.profile;
+    let mut report = String::new();
+    let _ = writeln!(report, "frpc 采样报告");
+    let _ = writeln!(report, "实例: {}", identifier);
+    let _ = writeln!(report, "PID: {}", pid);
+    let _ = writeln!(
+        report,
+        "开始时间: {}",
+        started_at.format("%Y-%m-%d %H:%M:%S")
+    );
+    let _ = writeln!(
+        report,
+        "采样时长: {:.1} 秒（间隔 {} ms，共 {} 轮）",
+        elapsed.as_secs_f64(),
+        SAMPLE_INTERVAL.as_millis(),
+        profile.rounds
+    );
+    let cpu_secs = cpu_time as f64 / 10_000_000.0;
+    let _ = writeln!(
+        report,
+        "进程 CPU 时间: {:.2} 秒（平均 {:.1}% 单核）",
+        cpu_secs,
+        cpu_secs / elapsed.as_secs_f64().max(0.001) * 100.0
+    );
+    let _ = writeln!(
+        report,
+        "样本数: {}（只采样两轮之间消耗了 CPU 的线程）",
+        profile.samples
+    );
+    if exited {
+        let _ = writeln!(report, "注意: frpc 在采样期间退出，报告只包含退出前的样本");
+    }
+    profile.write_summary(&mut report, &mut symbols);
+    Ok(report)
+}
+
+/// 采样结果
+#[derive(Default)]
+struct Profile {
+    rounds: u64,
+    samples: u64,
+    /// 线程 ID → 样本数
+    threads: HashMap<u32, u64>,
+    /// 调用栈（栈顶在前）→ 样本数
+    stacks: HashMap<Vec<u64>, u64>,
+}
+
+impl Profile {
+    fn write_summary(&self, report: &mut String, symbols: &mut SymbolSession) {
+        if self.samples == 0 {
+            let _ = writeln!(report, "\n采样期间 frpc 没有消耗 CPU 的线程");
+            return;
+        }
+        let percent = |count: u64| count as f64 * 100.0 / self.samples as f64;
+
+        let _ = writeln!(report, "\n== 线程 ==");
+        let _ = writeln!(report, "{:>8}  {:>8}  {:>7}", "TID", "样本数", "占比");
+        for (tid, count) in sorted_by_count(self.threads.iter().map(|(t, c)| (*t, *c))) {
+            let _ = writeln!(report, "{:>8}  {:>8}  {:>6.1}%", tid, count, percent(count));
+        }
+
+        // 自身：位于栈顶的样本；包含：出现在栈中任意位置的样本（同一栈中只计一次）
+        let mut self_counts: HashMap<String, u64> = HashMap::new();
+        let mut total_counts: HashMap<String, u64> = HashMap::new();
+        for (stack, count) in &self.stacks {
+            let names: Vec<String> = stack.iter().map(|&pc| symbols.name(pc)).collect();
+            *self_counts.entry(names[0].clone()).or_default() += count;
+            for name in names.into_iter().collect::<HashSet<_>>() {
+                *total_counts.entry(name).or_default() += count;
+            }
+        }
+        let _ = writeln!(report, "\n== 热点函数（自身） ==");
+        for (name, count) in sorted_by_count(self_counts).into_iter().take(TOP_FUNCTIONS) {
+            let _ = writeln!(report, "{:>8}  {:>6.1}%  {}", count, percent(count), name);
+        }
+        let _ = writeln!(report, "\n== 热点函数（包含调用） ==");
+        for (name, count) in sorted_by_count(total_counts)
+            .into_iter()
+            .take(TOP_FUNCTIONS)
+        {
+            let _ = writeln!(report, "{:>8}  {:>6.1}%  {}", count, percent(count), name);
+        }
+
+        let _ = writeln!(report, "\n== 热点调用栈 ==");
+        let stacks = sorted_by_count(self.stacks.iter().map(|(s, c)| (s, *c)));
+        for (i, (stack, count)) in stacks.into_iter().take(TOP_STACKS).enumerate() {
+            let _ = writeln!(
+                report,
+                "\n#{} 样本数 {}（{:.1}%）",
+                i + 1,
+                count,
+                percent(count)
+            );
+            for &pc in stack {
+                let _ = writeln!(report, "    {}", symbols.name(pc));
+            }
+        }
+    }
+}
+
+/// 按样本数从多到少排序
+fn sorted_by_count<K: Ord>(counts: impl IntoIterator<Item = (K, u64)>) -> Vec<(K, u64)> {
+    let mut sorted: Vec<(K, u64)> = counts.into_iter().collect();
+    sorted.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    sorted
+}
+
+/// 已打开的 frpc 线程与其上一轮的 CPU 时间（100ns）
+struct SampledThread {
+    handle: HANDLE,
+    cpu_time: u64,
+}
+
+struct Sampler {
+    pid: u32,
+    process: HANDLE,
+    threads: HashMap<u32, SampledThread>,
+    profile: Profile,
+}
+
+impl Sampler {
+    fn new(pid: u32, process: HANDLE) -> Self {
+        Self {
+            pid,
+            process,
+            threads: HashMap::new(),
+            profile: Profile::default(),
+        }
+    }
+
+    /// 一轮采样：同步线程列表，对上一轮以来消耗了 CPU 的线程回溯调用栈
+    fn sample_once(&mut self) {
+        self.profile.rounds += 1;
+        let alive = thread_ids(self.pid);
+        self.threads.retain(|tid, thread| {
+            let keep = alive.contains(tid);
+            if !keep {
+                unsafe { CloseHandle(thread.handle) };
+            }
+            keep
+        });
+        for tid in alive {
+            let thread = match self.threads.entry(tid) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let handle = unsafe {
+                        OpenThread(
+                            THREAD_SUSPEND_RESUME | THREAD_GET_CONTEXT | THREAD_QUERY_INFORMATION,
+                            0,
+                            tid,
+                        )
+                    };
+                    if handle != 0 {
+                        // 新线程以当前 CPU 时间为起点，从下一轮开始参与采样
+                        entry.insert(SampledThread {
+                            handle,
+                            cpu_time: thread_cpu_time(handle),
+                        });
+                    }
+                    continue;
+                }
+            };
+            let cpu_time = thread_cpu_time(thread.handle);
+            if cpu_time <= thread.cpu_time {
+                continue;
+            }
+            thread.cpu_time = cpu_time;
+            if let Some(stack) = walk_stack(self.process, thread.handle) {
+                self.profile.samples += 1;
+                *self.profile.threads.entry(tid).or_default() += 1;
+                *self.profile.stacks.entry(stack).or_default() += 1;
+            }
+        }
+    }
+}
+
+impl Drop for Sampler {
+    fn drop(&mut self) {
+        for thread in self.threads.values() {
+            unsafe { CloseHandle(thread.handle) };
+        }
+    }
+}
+
+/// 进程当前的线程 ID
+fn thread_ids(pid: u32) -> HashSet<u32> {
+    let mut ids = HashSet::new();
+    unsafe {
+        let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPTHREAD, 0);
+        if snapshot == INVALID_HANDLE_VALUE {
+            return ids;
+        }
+        let mut entry: THREADENTRY32 = std::mem::zeroed();
+        entry.dwSize = std::mem::size_of::<THREADENTRY32>() as u32;
+        let mut ok = Thread32First(snapshot, &mut entry);
+        while ok != 0 {
+            if entry.th32OwnerProcessID == pid {
+                ids.insert(entry.th32ThreadID);
+            }
+            ok = Thread32Next(snapshot, &mut entry);
+        }
+        CloseHandle(snapshot);
+    }
+    ids
+}
+
+fn filetime_to_u64(time: &FILETIME) -> u64 {
+    ((time.dwHighDateTime as u64) << 32) | time.dwLowDateTime as u64
+}
+
+/// 线程已消耗的 CPU 时间（内核 + 用户，100ns）
+fn thread_cpu_time(thread: HANDLE) -> u64 {
+    unsafe {
+        let (mut creation, mut exit, mut kernel, mut user) = std::mem::zeroed();
+        if GetThreadTimes(thread, &mut creation, &mut exit, &mut kernel, &mut user) == 0 {
+            return 0;
+        }
+        filetime_to_u64(&kernel) + filetime_to_u64(&user)
+    }
+}
+
+/// 进程已消耗的 CPU 时间（内核 + 用户，100ns）
+fn process_cpu_time(process: HANDLE) -> u64 {
+    unsafe {
+        let (mut creation, mut exit, mut kernel, mut user) = std::mem::zeroed();
+        if GetProcessTimes(process, &mut creation, &mut exit, &mut kernel, &mut user) == 0 {
+            return 0;
+        }
+        filetime_to_u64(&kernel) + filetime_to_u64(&user)
+    }
+}
+
+/// `GetThreadContext` 要求 CONTEXT 按 16 字节对齐
+#[repr(C, align(16))]
+struct AlignedContext(CONTEXT);
+
+/// 挂起线程并回溯调用栈（栈顶在前），完成后恢复线程
+fn walk_stack(process: HANDLE, thread: HANDLE) -> Option<Vec<u64>> {
+    unsafe {
+        if SuspendThread(thread) == u32::MAX {
+            return None;
+        }
+        let stack = walk_suspended(process, thread);
+        ResumeThread(thread);
+        stack
+    }
+}
+
+unsafe fn walk_suspended(process: HANDLE, thread: HANDLE) -> Option<Vec<u64>> {
+    let mut context: AlignedContext = std::mem::zeroed();
+    context.0.ContextFlags = CONTEXT_FULL_AMD64;
+    if GetThreadContext(thread, &mut context.0) == 0 {
+        return None;
+    }
+    let flat = |offset: u64| ADDRESS64 {
+        Offset: offset,
+        Segment: 0,
+        Mode: AddrModeFlat,
+    };
+    let mut frame: STACKFRAME64 = std::mem::zeroed();
+    frame.AddrPC = flat(context.0.Rip);
+    frame.AddrFrame = flat(context.0.Rbp);
+    frame.AddrStack = flat(context.0.Rsp);
+
+    let mut stack = Vec::new();
+    while stack.len() < MAX_FRAMES {
+        let ok = StackWalk64(
+            IMAGE_FILE_MACHINE_AMD64 as u32,
+            process,
+            thread,
+            &mut frame,
+            &mut context.0 as *mut CONTEXT as *mut c_void,
+            Some(read_process_memory),
+            Some(SymFunctionTableAccess64),
+            Some(SymGetModuleBase64),
+            None,
+        );
+        if ok == 0 || frame.AddrPC.Offset == 0 {
+            break;
+        }
+        stack.push(frame.AddrPC.Offset);
+    }
+    (!stack.is_empty()).then_some(stack)
+}
+
+/// `StackWalk64` 读取目标进程内存的回调
+unsafe extern "system" fn read_process_memory(
+    process: HANDLE,
+    address: u64,
+    buffer: *mut c_void,
+    size: u32,
+    bytes_read: *mut u32,
+) -> BOOL {
+    let mut read = 0usize;
+    let ok = ReadProcessMemory(
+        process,
+        address as *const c_void,
+        buffer,
+        size as usize,
+        &mut read,
+    );
+    if !bytes_read.is_null() {
+        *bytes_read = read as u32;
+    }
+    ok
+}
+
+/// SYMBOL_INFOW 之后紧跟符号名缓冲区
+#[repr(C)]
+struct SymbolBuffer {
+    info: SYMBOL_INFOW,
+    name: [u16; MAX_SYMBOL_NAME],
+}
+
+/// dbghelp 符号会话，结束时调用 SymCleanup；地址到名称的解析结果会缓存
+struct SymbolSession {
+    process: HANDLE,
+    names: HashMap<u64, String>,
+}
+
+impl SymbolSession {
+    fn new(process: HANDLE) -> Result<Self> {
+        unsafe {
+            SymSetOptions(SYMOPT_UNDNAME | SYMOPT_DEFERRED_LOADS);
+            // fInvadeProcess 为 TRUE：加载目标进程已载入的全部模块
+            if SymInitializeW(process, std::ptr::null(), 1) == 0 {
+                anyhow::bail!("SymInitialize 失败，错误码: {}", GetLastError());
+            }
+        }
+        Ok(Self {
+            process,
+            names: HashMap::new(),
+        })
+    }
+
+    /// 地址的可读名称：有符号时为 `模块!函数+偏移`，否则为 `模块+偏移`，不在任何模块中时为地址本身
+    fn name(&mut self, address: u64) -> String {
+        if let Some(name) = self.names.get(&address) {
+            return name.clone();
+        }
+        let name = self.resolve(address);
+        self.names.insert(address, name.clone());
+        name
+    }
+
+    fn resolve(&self, address: u64) -> String {
+        unsafe {
+            let mut module: IMAGEHLP_MODULEW64 = std::mem::zeroed();
+            module.SizeOfStruct = std::mem::size_of::<IMAGEHLP_MODULEW64>() as u32;
+            if SymGetModuleInfoW64(self.process, address, &mut module) == 0 {
+                return format!("{:#x}", address);
+            }
+            let module_name = wide_to_string(&module.ModuleName);
+
+            let mut symbol: SymbolBuffer = std::mem::zeroed();
+            symbol.info.SizeOfStruct = std::mem::size_of::<SYMBOL_INFOW>() as u32;
+            symbol.info.MaxNameLen = MAX_SYMBOL_NAME as u32 + 1;
+            let mut displacement = 0u64;
+            if SymFromAddrW(self.process, address, &mut displacement, &mut symbol.info) != 0 {
+                // 名称从 Name 字段开始，延伸到其后的缓冲区
+                let len = (symbol.info.NameLen as usize).min(MAX_SYMBOL_NAME + 1);
+                let start = (&symbol as *const SymbolBuffer as *const u8)
+                    .add(std::mem::offset_of!(SYMBOL_INFOW, Name))
+                    as *const u16;
+                let name = std::slice::from_raw_parts(start, len);
+                return format!(
+                    "{}!{}+{:#x}",
+                    module_name,
+                    String::from_utf16_lossy(name),
+                    displacement
+                );
+            }
+            format!("{}+{:#x}", module_name, address - module.BaseOfImage)
+        }
+    }
+}
+
+impl Drop for SymbolSession {
+    fn drop(&mut self) {
+        unsafe { SymCleanup(self.process) };
+    }
+}
+
+fn wide_to_string(wide: &[u16]) -> String {
+    let len = wide.iter().position(|&c| c == 0).unwrap_or(wide.len());
+    String::from_utf16_lossy(&wide[..len])
+}
@@ -0,0 +1,346 @@
+//! 滚动重启队列：实例逐个重启，上一个就绪（或等待超时）后才开始下一个
+//!
+//! 由进程守护每轮推进（[`RestartQueue::advance`]），等待新进程就绪期间不阻塞监控循环。
+//! 按实例的 `restart_strategy` 选择方式：
+//! - `stop_first`：停止旧进程、启动新进程，等待启动探测通过
+//! - `blue_green`：先启动备用进程，就绪后替换并停止旧进程；未就绪时停止备用进程，旧进程继续运行
+
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::config::RestartStrategy;
+
+/// 重启的各个步骤：进程守护直接操作进程列表，测试中记录调用顺序
+pub(crate) trait RestartSteps {
+    /// 蓝绿重启中已启动、尚未加入进程列表的新进程
+    type Standby;
+
+    /// 本次重启的方式与等待新进程就绪的时间
+    fn plan(&self, name: &str) -> (RestartStrategy, Duration);
+    /// 停止并移出实例当前的进程
+    fn stop_old(&self, name: &str);
+    /// 启动新进程并加入进程列表，失败时返回 false
+    fn start_new(&self, name: &str, entry: &(PathBuf, PathBuf)) -> bool;
+    /// 实例当前的进程是否已通过启动探测
+    fn is_ready(&self, name: &str) -> bool;
+    /// 启动备用进程，不加入进程列表；失败时返回 None
+    fn start_standby(&self, name: &str, entry: &(PathBuf, PathBuf)) -> Option<Self::Standby>;
+    /// 备用进程是否就绪：Some(true) 已就绪，Some(false) 确定不会就绪（探测失败或已退出），None 仍在等待
+    fn standby_ready(&self, standby: &mut Self::Standby) -> Option<bool>;
+    /// 备用进程替换旧进程：加入进程列表后停止旧进程
+    fn promote(&self, name: &str, standby: Self::Standby);
+    /// 回滚：停止备用进程，旧进程保持运行
+    fn discard(&self, name: &str, standby: Self::Standby);
+}
+
+/// 正在进行的重启
+enum InFlight<T> {
+    /// 新进程已在进程列表中，等待启动探测通过
+    StopFirst { name: String, deadline: Instant },
+    /// 旧进程仍在进程列表中，等待备用进程就绪
+    BlueGreen {
+        name: String,
+        standby: T,
+        deadline: Instant,
+    },
+}
+
+/// 排队的重启与正在进行的重启，同一时间只进行一个
+pub(crate) struct RestartQueue<T> {
+    queued: VecDeque<(String, (PathBuf, PathBuf))>,
+    in_flight: Option<InFlight<T>>,
+}
+
+impl<T> RestartQueue<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            queued: VecDeque::new(),
+            in_flight: None,
+        }
+    }
+
+    /// 排队重启实例；已在排队的实例不重复加入（使用最新的 exe/配置）
+    pub(crate) fn push(&mut self, name: &str, entry: (PathBuf, PathBuf)) {
+        match self.queued.iter_mut().find(|(n, _)| n == name) {
+            Some((_, queued)) => *queued = entry,
+            None => self.queued.push_back((name.to_string(), entry)),
+        }
+    }
+
+    /// 推进重启：正在进行的重启就绪或超时后，开始排队中的下一个
+    pub(crate) fn advance<S: RestartSteps<Standby = T>>(&mut self, steps: &S, now: Instant) {
+        loop {
+            if let Some(in_flight) = self.in_flight.take() {
+                self.in_flight = Self::poll(steps, in_flight, now);
+                if self.in_flight.is_some() {
+                    return;
+                }
+            }
+            let Some((name, entry)) = self.queued.pop_front() else {
+                return;
+            };
+            self.in_flight = Self::begin(steps, name, &entry, now);
+        }
+    }
+
+    /// 服务停止：丢弃排队的重启，进行中的蓝绿重启回滚（旧进程继续运行）
+    pub(crate) fn abort<S: RestartSteps<Standby = T>>(&mut self, steps: &S) {
+        if !self.queued.is_empty() {
+            log::info!("服务正在停止，跳过 {} 个排队的滚动重启", self.queued.len());
+            self.queued.clear();
+        }
+        if let Some(InFlight::BlueGreen { name, standby, .. }) = self.in_flight.take() {
+            log::info!("[{}] 服务正在停止，不再等待新进程就绪", name);
+            steps.discard(&name, standby);
+        }
+    }
+
+    fn begin<S: RestartSteps<Standby = T>>(
+        steps: &S,
+        name: String,
+        entry: &(PathBuf, PathBuf),
+        now: Instant,
+    ) -> Option<InFlight<T>> {
+        let (strategy, ready_timeout) = steps.plan(&name);
+        let deadline = now + ready_timeout;
+        match strategy {
+            RestartStrategy::BlueGreen => {
+                log::info!("[{}] 蓝绿重启：启动新进程，就绪后停止旧进程", name);
+                let standby = steps.start_standby(&name, entry)?;
+                Some(InFlight::BlueGreen {
+                    name,
+                    standby,
+                    deadline,
+                })
+            }
+            RestartStrategy::StopFirst => {
+                steps.stop_old(&name);
+                steps
+                    .start_new(&name, entry)
+                    .then_some(InFlight::StopFirst { name, deadline })
+            }
+        }
+    }
+
+    /// 检查进行中的重启，仍需等待时返回 Some
+    fn poll<S: RestartSteps<Standby = T>>(
+        steps: &S,
+        in_flight: InFlight<T>,
+        now: Instant,
+    ) -> Option<InFlight<T>> {
+        match in_flight {
+            InFlight::StopFirst { name, deadline } => {
+                if steps.is_ready(&name) {
+                    log::info!("[{}] 滚动重启完成，启动探测已通过", name);
+                    None
+                } else if now >= deadline {
+                    log::warn!("[{}] 滚动重启：等待启动探测超时，继续处理下一个实例", name);
+                    None
+                } else {
+                    Some(InFlight::StopFirst { name, deadline })
+                }
+            }
+            InFlight::BlueGreen {
+                name,
+                mut standby,
+                deadline,
+            } => match steps.standby_ready(&mut standby) {
+                Some(true) => {
+                    steps.promote(&name, standby);
+                    None
+                }
+                Some(false) => {
+                    steps.discard(&name, standby);
+                    None
+                }
+                None if now >= deadline => {
+                    log::warn!("[{}] 蓝绿重启：等待新进程就绪超时", name);
+                    steps.discard(&name, standby);
+                    None
+                }
+                None => Some(InFlight::BlueGreen {
+                    name,
+                    standby,
+                    deadline,
+                }),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    /// 记录各步骤的调用顺序；备用进程用实例名表示
+    #[derive(Default)]
+    struct Recorder {
+        strategies: HashMap<String, RestartStrategy>,
+        events: RefCell<Vec<String>>,
+        ready: RefCell<Vec<String>>,
+        standby_results: RefCell<HashMap<String, bool>>,
+        fail_start: bool,
+    }
+
+    impl Recorder {
+        fn with(strategies: &[(&str, RestartStrategy)]) -> Self {
+            Self {
+                strategies: strategies
+                    .iter()
+                    .map(|(name, s)| (name.to_string(), *s))
+                    .collect(),
+                ..Default::default()
+            }
+        }
+
+        fn event(&self, event: &str, name: &str) {
+            self.events.borrow_mut().push(format!("{} {}", event, name));
+        }
+
+        fn take_events(&self) -> Vec<String> {
+            std::mem::take(&mut *self.events.borrow_mut())
+        }
+    }
+
+    impl RestartSteps for Recorder {
+        type Standby = String;
+
+        fn plan(&self, name: &str) -> (RestartStrategy, Duration) {
+            let strategy = self.strategies.get(name).copied().unwrap_or_default();
+            (strategy, Duration::from_secs(30))
+        }
+        fn stop_old(&self, name: &str) {
+            self.event("stop", name);
+        }
+        fn start_new(&self, name: &str, _: &(PathBuf, PathBuf)) -> bool {
+            self.event("start", name);
+            !self.fail_start
+        }
+        fn is_ready(&self, name: &str) -> bool {
+            self.ready.borrow().iter().any(|n| n == name)
+        }
+        fn start_standby(&self, name: &str, _: &(PathBuf, PathBuf)) -> Option<String> {
+            self.event("standby", name);
+            (!self.fail_start).then(|| name.to_string())
+        }
+        fn standby_ready(&self, standby: &mut String) -> Option<bool> {
+            self.standby_results.borrow().get(standby.as_str()).copied()
+        }
+        fn promote(&self, name: &str, _: String) {
+            self.event("promote", name);
+        }
+        fn discard(&self, name: &str, _: String) {
+            self.event("discard", name);
+        }
+    }
+
+    fn entry() -> (PathBuf, PathBuf) {
+        (PathBuf::from("frpc.exe"), PathBuf::from("frpc.toml"))
+    }
+
+    #[test]
+    fn stop_first_stops_old_before_starting_new_and_waits_for_ready() {
+        let steps = Recorder::default();
+        let mut queue = RestartQueue::new();
+        let now = Instant::now();
+        queue.push("web", entry());
+        queue.push("ssh", entry());
+        queue.advance(&steps, now);
+        assert_eq!(steps.take_events(), ["stop web", "start web"]);
+
+        // web 就绪前不开始下一个
+        queue.advance(&steps, now + Duration::from_secs(1));
+        assert!(steps.take_events().is_empty());
+
+        steps.ready.borrow_mut().push("web".into());
+        queue.advance(&steps, now + Duration::from_secs(2));
+        assert_eq!(steps.take_events(), ["stop ssh", "start ssh"]);
+    }
+
+    #[test]
+    fn stop_first_moves_on_after_ready_timeout_or_failed_start() {
+        let steps = Recorder {
+            fail_start: true,
+            ..Default::default()
+        };
+        let mut queue = RestartQueue::new();
+        let now = Instant::now();
+        queue.push("web", entry());
+        queue.push("ssh", entry());
+        // 启动失败时不等待，直接处理下一个
+        queue.advance(&steps, now);
+        assert_eq!(
+            steps.take_events(),
+            ["stop web", "start web", "stop ssh", "start ssh"]
+        );
+
+        let steps = Recorder::default();
+        let mut queue = RestartQueue::new();
+        queue.push("web", entry());
+        queue.push("ssh", entry());
+        queue.advance(&steps, now);
+        steps.take_events();
+        queue.advance(&steps, now + Duration::from_secs(30));
+        assert_eq!(steps.take_events(), ["stop ssh", "start ssh"]);
+    }
+
+    #[test]
+    fn blue_green_keeps_old_process_until_new_one_is_ready() {
+        let steps = Recorder::with(&[("web", RestartStrategy::BlueGreen)]);
+        let mut queue = RestartQueue::new();
+        let now = Instant::now();
+        queue.push("web", entry());
+        queue.advance(&steps, now);
+        queue.advance(&steps, now + Duration::from_secs(5));
+        // 等待期间旧进程不停止
+        assert_eq!(steps.take_events(), ["standby web"]);
+
+        steps
+            .standby_results
+            .borrow_mut()
+            .insert("web".into(), true);
+        queue.advance(&steps, now + Duration::from_secs(6));
+        assert_eq!(steps.take_events(), ["promote web"]);
+    }
+
+    #[test]
+    fn blue_green_rolls_back_when_new_process_is_not_ready() {
+        let steps = Recorder::with(&[
+            ("web", RestartStrategy::BlueGreen),
+            ("ssh", RestartStrategy::BlueGreen),
+        ]);
+        let mut queue = RestartQueue::new();
+        let now = Instant::now();
+        queue.push("web", entry());
+        queue.push("ssh", entry());
+        steps
+            .standby_results
+            .borrow_mut()
+            .insert("web".into(), false);
+        queue.advance(&steps, now);
+        assert_eq!(
+            steps.take_events(),
+            ["standby web", "discard web", "standby ssh"]
+        );
+        // 超时未就绪同样回滚
+        queue.advance(&steps, now + Duration::from_secs(30));
+        assert_eq!(steps.take_events(), ["discard ssh"]);
+    }
+
+    #[test]
+    fn abort_discards_standby_and_queued_restarts() {
+        let steps = Recorder::with(&[("web", RestartStrategy::BlueGreen)]);
+        let mut queue = RestartQueue::new();
+        let now = Instant::now();
+        queue.push("web", entry());
+        queue.push("ssh", entry());
+        queue.push("web", entry());
+        queue.advance(&steps, now);
+        queue.abort(&steps);
+        queue.advance(&steps, now + Duration::from_secs(60));
+        assert_eq!(steps.take_events(), ["standby web", "discard web"]);
+    }
+}
@@ -0,0 +1,318 @@
+//! 以指定用户身份启动 frpc（实例配置中的 `run_as`）
+//!
+//! - 服务（LocalSystem）中：`LogonUserW` + `CreateProcessAsUserW`
+//! - 交互模式（普通用户，没有替换进程令牌的权限）：回退到 `CreateProcessWithLogonW`
+//!
+//! 密码使用 DPAPI（本机范围）加密后保存在 metadata.json 中，仅在启动进程时解密，
+//! 用完立即清零，不写入日志
+
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs::File;
+use std::os::windows::ffi::OsStrExt;
+use std::os::windows::io::FromRawHandle;
+use std::path::Path;
+use windows_sys::Win32::Foundation::{
+    CloseHandle, GetLastError, LocalFree, SetHandleInformation, ERROR_PRIVILEGE_NOT_HELD, HANDLE,
+    HANDLE_FLAG_INHERIT,
+};
+use windows_sys::Win32::Security::Cryptography::{
+    CryptProtectData, CryptUnprotectData, CRYPTPROTECT_LOCAL_MACHINE, CRYPTPROTECT_UI_FORBIDDEN,
+    CRYPT_INTEGER_BLOB,
+};
+use windows_sys::Win32::Security::{
+    LogonUserW, LOGON32_LOGON_INTERACTIVE, LOGON32_PROVIDER_DEFAULT, SECURITY_ATTRIBUTES,
+};
+use windows_sys::Win32::System::Pipes::CreatePipe;
+use windows_sys::Win32::System::Threading::{
+    CreateProcessAsUserW, CreateProcessWithLogonW, CREATE_NO_WINDOW, CREATE_UNICODE_ENVIRONMENT,
+    LOGON_WITH_PROFILE, PROCESS_INFORMATION, STARTF_USESTDHANDLES, STARTUPINFOW,
+};
+
+use crate::config::RunAs;
+use crate::frpc_mg;
+
+/// 以其他用户身份启动的进程：PID 与输出管道读端
+pub struct SpawnedProcess {
+    pub pid: u32,
+    pub stdout: File,
+    pub stderr: File,
+}
+
+fn to_wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+fn path_to_wide(p: &Path) -> Vec<u16> {
+    p.as_os_str()
+        .encode_wide()
+        .chain(std::iter::once(0))
+        .collect()
+}
+
+/// 使用 DPAPI（本机范围）加密密码，返回十六进制字符串
+///
+/// 本机范围使服务（LocalSystem）与交互用户都能解密，但拷贝到其他机器无法解密
+pub fn protect_password(password: &str) -> Result<String> {
+    let mut plain: Vec<u8> = password.as_bytes().to_vec();
+    let input = CRYPT_INTEGER_BLOB {
+        cbData: plain.len() as u32,
+        pbData: plain.as_mut_ptr(),
+    };
+    let mut output = CRYPT_INTEGER_BLOB {
+        cbData: 0,
+        pbData: std::ptr::null_mut(),
+    };
+    let ok = unsafe {
+        CryptProtectData(
+            &input,
+            std::ptr::null(),
+            std::ptr::null(),
+            std::ptr::null(),
+            std::ptr::null(),
+            CRYPTPROTECT_LOCAL_MACHINE | CRYPTPROTECT_UI_FORBIDDEN,
+            &mut output,
+        )
+    };
+    plain.iter_mut().for_each(|b| *b = 0);
+    if ok == 0 {
+        return Err(anyhow::anyhow!("加密密码失败，错误码: {}", unsafe {
+            GetLastError()
+        }));
+    }
+    let encrypted = unsafe { std::slice::from_raw_parts(output.pbData, output.cbData as usize) };
+    let hex = encrypted.iter().map(|b| format!("{:02x}", b)).collect();
+    unsafe { LocalFree(output.pbData as _) };
+    Ok(hex)
+}
+
+/// 解密密码，返回以 0 结尾的 UTF-16 缓冲区（调用方用完后需清零）
+fn unprotect_password(hex: &str) -> Result<Vec<u16>> {
+    if !hex.len().is_multiple_of(2) {
+        return Err(anyhow::anyhow!("run_as 密码格式无效"));
+    }
+    let mut encrypted = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+        .collect::<std::result::Result<Vec<u8>, _>>()
+        .context("run_as 密码格式无效")?;
+    let input = CRYPT_INTEGER_BLOB {
+        cbData: encrypted.len() as u32,
+        pbData: encrypted.as_mut_ptr(),
+    };
+    let mut output = CRYPT_INTEGER_BLOB {
+        cbData: 0,
+        pbData: std::ptr::null_mut(),
+    };
+    let ok = unsafe {
+        CryptUnprotectData(
+            &input,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            std::ptr::null(),
+            std::ptr::null(),
+            CRYPTPROTECT_UI_FORBIDDEN,
+            &mut output,
+        )
+    };
+    if ok == 0 {
+        return Err(anyhow::anyhow!(
+            "解密 run_as 密码失败（是否在其他机器上设置？），错误码: {}",
+            unsafe { GetLastError() }
+        ));
+    }
+    let plain = unsafe { std::slice::from_raw_parts_mut(output.pbData, output.cbData as usize) };
+    let wide = to_wide(&String::from_utf8_lossy(plain));
+    plain.iter_mut().for_each(|b| *b = 0);
+    unsafe { LocalFree(output.pbData as _) };
+    Ok(wide)
+}
+
+/// 拆分用户名：`DOMAIN\user` -> (user, Some(DOMAIN))；`user@domain` 与纯用户名原样传入
+fn split_user(user: &str) -> (String, Option<String>) {
+    match user.split_once('\\') {
+        Some((domain, name)) => (name.to_string(), Some(domain.to_string())),
+        None if user.contains('@') => (user.to_string(), None),
+        // 本地账户
+        None => (user.to_string(), Some(".".to_string())),
+    }
+}
+
+/// 创建输出管道：写端可继承（交给子进程），读端不可继承
+unsafe fn create_output_pipe() -> Result<(HANDLE, HANDLE)> {
+    let sa = SECURITY_ATTRIBUTES {
+        nLength: std::mem::size_of::<SECURITY_ATTRIBUTES>() as u32,
+        lpSecurityDescriptor: std::ptr::null_mut(),
+        bInheritHandle: 1,
+    };
+    let mut read: HANDLE = 0;
+    let mut write: HANDLE = 0;
+    if CreatePipe(&mut read, &mut write, &sa, 0) == 0 {
+        return Err(anyhow::anyhow!(
+            "无法创建输出管道，错误码: {}",
+            GetLastError()
+        ));
+    }
+    SetHandleInformation(read, HANDLE_FLAG_INHERIT, 0);
+    Ok((read, write))
+}
+
+/// 以 `run_as` 指定的用户身份启动 `exe -c config`（目录模式下为 `--config_dir`），标准输出/错误重定向到管道
+///
+/// `env` 为额外注入的环境变量（`set-env`），与本进程的环境合并后传给新进程
+pub fn spawn_as_user(
+    run_as: &RunAs,
+    exe: &Path,
+    config: &Path,
+    env: &BTreeMap<String, String>,
+) -> Result<SpawnedProcess> {
+    let mut password = unprotect_password(&run_as.password_protected)?;
+    let env_block = environment_block(env);
+    let result = unsafe { spawn_inner(&run_as.user, &password, exe, config, env_block.as_deref()) };
+    password.iter_mut().for_each(|c| *c = 0);
+    result
+}
+
+/// 本进程的环境叠加 `overrides` 后的 Unicode 环境块（`名=值\0...\0\0`，按名称不区分大小写排序），
+/// 没有额外变量时为 None（新进程继承本进程的环境）
+fn environment_block(overrides: &BTreeMap<String, String>) -> Option<Vec<u16>> {
+    if overrides.is_empty() {
+        return None;
+    }
+    // Windows 的环境变量名不区分大小写，按大写名去重，后设置的覆盖先前的
+    let mut vars: BTreeMap<String, (OsString, OsString)> = std::env::vars_os()
+        .map(|(k, v)| (k.to_string_lossy().to_uppercase(), (k, v)))
+        .collect();
+    for (key, value) in overrides {
+        vars.insert(key.to_uppercase(), (key.into(), value.into()));
+    }
+    let mut block = Vec::new();
+    for (key, value) in vars.values() {
+        block.extend(key.encode_wide());
+        block.push(u16::from(b'='));
+        block.extend(value.encode_wide());
+        block.push(0);
+    }
+    block.push(0);
+    Some(block)
+}
+
+unsafe fn spawn_inner(
+    user: &str,
+    password: &[u16],
+    exe: &Path,
+    config: &Path,
+    env_block: Option<&[u16]>,
+) -> Result<SpawnedProcess> {
+    let (name, domain) = split_user(user);
+    let name_w = to_wide(&name);
+    let domain_w = domain.as_deref().map(to_wide);
+    let domain_ptr = domain_w.as_ref().map_or(std::ptr::null(), |d| d.as_ptr());
+    let exe_w = path_to_wide(exe);
+    let mut cmdline = to_wide(&format!(
+        "\"{}\" {} \"{}\"",
+        exe.display(),
+        frpc_mg::config_flag(config),
+        config.display()
+    ));
+    let env_ptr = env_block.map_or(std::ptr::null(), |b| b.as_ptr().cast());
+    let cwd_w = exe.parent().map(path_to_wide);
+    let cwd_ptr = cwd_w.as_ref().map_or(std::ptr::null(), |d| d.as_ptr());
+
+    let (out_read, out_write) = create_output_pipe()?;
+    let (err_read, err_write) = match create_output_pipe() {
+        Ok(p) => p,
+        Err(e) => {
+            CloseHandle(out_read);
+            CloseHandle(out_write);
+            return Err(e);
+        }
+    };
+    let close_all = || {
+        CloseHandle(out_read);
+        CloseHandle(err_read);
+    };
+
+    let mut si: STARTUPINFOW = std::mem::zeroed();
+    si.cb = std::mem::size_of::<STARTUPINFOW>() as u32;
+    si.dwFlags = STARTF_USESTDHANDLES;
+    si.hStdOutput = out_write;
+    si.hStdError = err_write;
+    let mut pi: PROCESS_INFORMATION = std::mem::zeroed();
+    let flags = CREATE_NO_WINDOW | CREATE_UNICODE_ENVIRONMENT;
+
+    let mut token: HANDLE = 0;
+    let mut created = 0;
+    let mut error = 0;
+    if LogonUserW(
+        name_w.as_ptr(),
+        domain_ptr,
+        password.as_ptr(),
+        LOGON32_LOGON_INTERACTIVE,
+        LOGON32_PROVIDER_DEFAULT,
+        &mut token,
+    ) == 0
+    {
+        error = GetLastError();
+    } else {
+        created = CreateProcessAsUserW(
+            token,
+            exe_w.as_ptr(),
+            cmdline.as_mut_ptr(),
+            std::ptr::null(),
+            std::ptr::null(),
+            1,
+            flags,
+            env_ptr,
+            cwd_ptr,
+            &si,
+            &mut pi,
+        );
+        if created == 0 {
+            error = GetLastError();
+        }
+        CloseHandle(token);
+    }
+
+    // 非服务进程没有替换进程令牌的权限，改用 CreateProcessWithLogonW
+    if created == 0 && error == ERROR_PRIVILEGE_NOT_HELD {
+        created = CreateProcessWithLogonW(
+            name_w.as_ptr(),
+            domain_ptr,
+            password.as_ptr(),
+            LOGON_WITH_PROFILE,
+            exe_w.as_ptr(),
+            cmdline.as_mut_ptr(),
+            flags,
+            env_ptr,
+            cwd_ptr,
+            &si,
+            &mut pi,
+        );
+        if created == 0 {
+            error = GetLastError();
+        }
+    }
+
+    // 写端已交给子进程（或启动失败），本进程不再持有，否则读端收不到 EOF
+    CloseHandle(out_write);
+    CloseHandle(err_write);
+
+    if created == 0 {
+        close_all();
+        return Err(anyhow::anyhow!(
+            "以用户 {} 启动 frpc 失败，错误码: {}（1326 表示用户名或密码错误，1385 表示该用户没有登录权限）",
+            user,
+            error
+        ));
+    }
+
+    CloseHandle(pi.hThread);
+    CloseHandle(pi.hProcess);
+    Ok(SpawnedProcess {
+        pid: pi.dwProcessId,
+        stdout: File::from_raw_handle(out_read as _),
+        stderr: File::from_raw_handle(err_read as _),
+    })
+}
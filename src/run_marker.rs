@@ -0,0 +1,145 @@
+//! 运行标记：服务运行期间在 logs/.running 记录 PID 与启动时间，正常停止时删除
+//!
+//! 下次启动时如果标记仍然存在，说明上次会话非正常结束（蓝屏、被强制终止等）
+
+use anyhow::{Context, Result};
+use chrono::Local;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config;
+
+/// 上次未正常结束的会话信息
+#[derive(Debug, Clone)]
+pub struct PreviousRun {
+    pub pid: u32,
+    pub started_at: String,
+}
+
+/// 标记文件路径: logs/.running
+fn marker_path() -> Result<PathBuf> {
+    Ok(config::logs_dir()?.join(".running"))
+}
+
+/// 读取已有的标记文件（格式: pid=<PID> / started_at=<时间> 每行一项）
+fn read_marker(path: &Path) -> Option<PreviousRun> {
+    let content = fs::read_to_string(path).ok()?;
+    let mut pid = 0;
+    let mut started_at = String::from("未知");
+    for line in content.lines() {
+        if let Some(v) = line.strip_prefix("pid=") {
+            pid = v.trim().parse().unwrap_or(0);
+        } else if let Some(v) = line.strip_prefix("started_at=") {
+            started_at = v.trim().to_string();
+        }
+    }
+    Some(PreviousRun { pid, started_at })
+}
+
+/// 服务启动时调用：检查上次的标记并写入本次的标记
+///
+/// 返回 Some 表示上次会话未正常结束
+pub fn begin() -> Option<PreviousRun> {
+    let path = match marker_path() {
+        Ok(p) => p,
+        Err(e) => {
+            log::error!("无法获取运行标记路径: {:?}", e);
+            return None;
+        }
+    };
+    begin_at(&path)
+}
+
+/// [`begin`]，标记文件位于 `path`
+fn begin_at(path: &Path) -> Option<PreviousRun> {
+    let previous = read_marker(path);
+    if let Err(e) = write_marker(path) {
+        log::error!("写入运行标记失败: {:?}", e);
+    }
+    previous
+}
+
+fn write_marker(path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("无法创建日志目录")?;
+    }
+    let content = format!(
+        "pid={}\nstarted_at={}\n",
+        std::process::id(),
+        Local::now().format("%Y-%m-%d %H:%M:%S")
+    );
+    fs::write(path, content).context("无法写入运行标记")
+}
+
+/// 服务正常停止时调用：删除标记
+pub fn end() {
+    if let Ok(path) = marker_path() {
+        end_at(&path);
+    }
+}
+
+/// [`end`]，标记文件位于 `path`；只删除本进程写入的标记（启动早期出错时保留上次会话遗留的标记）
+fn end_at(path: &Path) {
+    if read_marker(path).is_some_and(|run| run.pid == std::process::id()) {
+        if let Err(e) = fs::remove_file(path) {
+            log::error!("删除运行标记失败: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_marker(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "frpdesk-run-marker-{}-{}",
+            test,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        dir.join("logs").join(".running")
+    }
+
+    #[test]
+    fn orderly_session_leaves_no_marker() {
+        let path = temp_marker("orderly");
+        assert!(begin_at(&path).is_none());
+        let written = read_marker(&path).unwrap();
+        assert_eq!(written.pid, std::process::id());
+        end_at(&path);
+        assert!(!path.exists());
+        // 下次启动不会报告上次未正常结束
+        assert!(begin_at(&path).is_none());
+        end_at(&path);
+        let _ = fs::remove_dir_all(path.parent().unwrap().parent().unwrap());
+    }
+
+    #[test]
+    fn leftover_marker_reports_previous_session() {
+        let path = temp_marker("leftover");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "pid=4242\nstarted_at=2026-01-02 03:04:05\n").unwrap();
+        let previous = begin_at(&path).unwrap();
+        assert_eq!(previous.pid, 4242);
+        assert_eq!(previous.started_at, "2026-01-02 03:04:05");
+        // 本次会话的标记覆盖旧标记
+        assert_eq!(read_marker(&path).unwrap().pid, std::process::id());
+        end_at(&path);
+        let _ = fs::remove_dir_all(path.parent().unwrap().parent().unwrap());
+    }
+
+    #[test]
+    fn malformed_marker_uses_placeholders() {
+        let path = temp_marker("malformed");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "pid=abc\n").unwrap();
+        let previous = read_marker(&path).unwrap();
+        assert_eq!(previous.pid, 0);
+        assert_eq!(previous.started_at, "未知");
+        // 不是本进程写入的标记保留，留给下次启动报告
+        end_at(&path);
+        assert!(path.exists());
+        let _ = fs::remove_dir_all(path.parent().unwrap().parent().unwrap());
+    }
+}
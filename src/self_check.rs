@@ -0,0 +1,419 @@
+//! 服务自检（`--check-only`）：按服务启动时的流程逐项检查后退出
+//!
+//! 复用服务实际使用的发现、校验代码，不注册服务、不启动 frpc、不常驻。
+//! 所有检查都会执行并输出结果，退出码为第一个失败项的错误码，全部通过为 0
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::acl;
+use crate::config;
+use crate::exe_check;
+use crate::logger::SyslogConfig;
+use crate::probe;
+use crate::service::{self, DiscoveredInstance};
+
+/// 各检查项失败时的退出码
+const EXIT_DISCOVERY: i32 = 2;
+const EXIT_CONFIG: i32 = 3;
+const EXIT_PORT: i32 = 4;
+const EXIT_FRPC: i32 = 5;
+const EXIT_PERMISSION: i32 = 6;
+
+/// 逐项检查结果的收集与输出
+struct Checker {
+    exit_code: i32,
+    failures: usize,
+}
+
+impl Checker {
+    fn pass(&self, item: &str, detail: &str) {
+        println!("[通过] {}: {}", item, detail);
+    }
+
+    fn warn(&self, item: &str, detail: &str) {
+        println!("[警告] {}: {}", item, detail);
+    }
+
+    fn fail(&mut self, code: i32, item: &str, detail: &str) {
+        println!("[失败] {}: {}", item, detail);
+        self.failures += 1;
+        if self.exit_code == 0 {
+            self.exit_code = code;
+        }
+    }
+}
+
+/// 执行全部检查，返回退出码
+pub fn run() -> i32 {
+    let mut checker = Checker {
+        exit_code: 0,
+        failures: 0,
+    };
+
+    // 1. 实例发现（与服务启动相同的发现逻辑）
+    let report = match service::discover_instances() {
+        Ok(r) => r,
+        Err(e) => {
+            checker.fail(EXIT_DISCOVERY, "实例发现", &format!("{:#}", e));
+            println!("\n自检未通过（退出码 {}）", checker.exit_code);
+            return checker.exit_code;
+        }
+    };
+    let enabled: Vec<&DiscoveredInstance> = report.instances.iter().filter(|i| i.enabled).collect();
+    if enabled.is_empty() {
+        checker.warn(
+            "实例发现",
+            "没有设置自启动的实例，服务启动后不会运行任何 frpc",
+        );
+    } else {
+        checker.pass(
+            "实例发现",
+            &format!(
+                "{} 个自启动实例: {}",
+                enabled.len(),
+                enabled
+                    .iter()
+                    .map(|i| i.identifier.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        );
+    }
+    for skipped in &report.skipped {
+        let detail = format!(
+            "{}: {}（{}）",
+            skipped.identifier,
+            skipped.reason,
+            skipped.path.display()
+        );
+        // 清单中登记了但无法启动的实例视为失败，未登记的 toml 只提示
+        if config::find_config(&skipped.identifier).is_some() {
+            checker.fail(EXIT_DISCOVERY, "实例发现", &detail);
+        } else {
+            checker.warn("实例发现", &detail);
+        }
+    }
+    for dup in &report.duplicates {
+        checker.fail(
+            EXIT_DISCOVERY,
+            "实例发现",
+            &format!(
+                "{} 与 {} 引用同一配置文件 {}",
+                dup.duplicate,
+                dup.first,
+                dup.config.display()
+            ),
+        );
+    }
+
+    // 2. 配置校验
+    let settings = match config::validate_settings_file() {
+        Ok(s) => {
+            let mut problems: Vec<String> =
+                [s.log_level_filter().err(), s.frpc_log_level_filter().err()]
+                    .into_iter()
+                    .flatten()
+                    .map(|e| format!("{:#}", e))
+                    .collect();
+            for window in &s.maintenance_windows {
+                if let Err(e) = window.bounds() {
+                    problems.push(format!("{:#}", e));
+                }
+            }
+            for rule in &s.log_sampling.rules {
+                if let Err(e) = rule.level() {
+                    problems.push(format!("{:#}", e));
+                }
+            }
+            if let Some(Err(e)) = s.syslog.as_ref().map(SyslogConfig::from_settings) {
+                problems.push(format!("{:#}", e));
+            }
+            if s.stop_diagnostics_percent > 100 {
+                problems.push(format!(
+                    "stop_diagnostics_percent 无效: {}（取值 0–100）",
+                    s.stop_diagnostics_percent
+                ));
+            }
+            if problems.is_empty() {
+                checker.pass("settings.json", "可以加载");
+            }
+            for problem in problems {
+                checker.fail(EXIT_CONFIG, "settings.json", &problem);
+            }
+            Some(s)
+        }
+        Err(e) => {
+            checker.fail(EXIT_CONFIG, "settings.json", &format!("{:#}", e));
+            None
+        }
+    };
+    let mut admin_ports: Vec<(String, u16)> = Vec::new();
+    let mut remote_ports: Vec<(String, String, u16)> = Vec::new();
+    for inst in &enabled {
+        check_instance_config(&mut checker, inst, &mut admin_ports, &mut remote_ports);
+    }
+
+    // 3. 端口冲突
+    let failures_before = checker.failures;
+    check_port_conflicts(&mut checker, &admin_ports, &remote_ports, settings.as_ref());
+    if checker.failures == failures_before {
+        checker.pass("端口冲突", "自启动实例之间没有端口冲突");
+    }
+
+    // 4. frpc 版本（各实例可使用不同的可执行文件，逐个检查）
+    let mut exes: Vec<(&Path, Vec<&str>)> = Vec::new();
+    for inst in &enabled {
+        match exes.iter_mut().find(|(exe, _)| *exe == inst.exe.as_path()) {
+            Some((_, users)) => users.push(&inst.identifier),
+            None => exes.push((&inst.exe, vec![&inst.identifier])),
+        }
+    }
+    if exes.is_empty() {
+        checker.warn("frpc", "没有可启动的实例，跳过版本检查");
+    }
+    for (exe, users) in exes {
+        match exe_check::check_frpc_exe(exe) {
+            Ok(version) => checker.pass(
+                "frpc",
+                &format!(
+                    "{}（{}，用于 {}）",
+                    version,
+                    exe.display(),
+                    users.join(", ")
+                ),
+            ),
+            Err(e) => checker.fail(
+                EXIT_FRPC,
+                "frpc",
+                &format!("{:#}（用于 {}）", e, users.join(", ")),
+            ),
+        }
+    }
+
+    // 5. 路径权限
+    check_permissions(&mut checker);
+
+    if checker.exit_code == 0 {
+        println!("\n自检通过");
+    } else {
+        println!("\n自检未通过（退出码 {}）", checker.exit_code);
+    }
+    checker.exit_code
+}
+
+/// 校验单个自启动实例的配置；目录模式下逐个校验目录中的配置文件
+fn check_instance_config(
+    checker: &mut Checker,
+    inst: &DiscoveredInstance,
+    admin_ports: &mut Vec<(String, u16)>,
+    remote_ports: &mut Vec<(String, String, u16)>,
+) {
+    if !inst.config_dir {
+        let item = format!("配置 {}", inst.identifier);
+        check_config_file(
+            checker,
+            &item,
+            inst,
+            &inst.config,
+            admin_ports,
+            remote_ports,
+        );
+        return;
+    }
+    match config::config_dir_files(&inst.config) {
+        Ok(files) => {
+            for file in files {
+                let item = format!(
+                    "配置 {}/{}",
+                    inst.identifier,
+                    file.file_name().unwrap_or_default().to_string_lossy()
+                );
+                check_config_file(checker, &item, inst, &file, admin_ports, remote_ports);
+            }
+        }
+        Err(e) => checker.fail(
+            EXIT_CONFIG,
+            &format!("配置 {}", inst.identifier),
+            &format!("{:#}", e),
+        ),
+    }
+}
+
+/// 校验一个配置文件：TOML 内容、就绪规则、admin API 端口
+fn check_config_file(
+    checker: &mut Checker,
+    item: &str,
+    inst: &DiscoveredInstance,
+    path: &Path,
+    admin_ports: &mut Vec<(String, u16)>,
+    remote_ports: &mut Vec<(String, String, u16)>,
+) {
+    let content = match std::fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(e) => {
+            checker.fail(EXIT_CONFIG, item, &format!("无法读取配置文件: {}", e));
+            return;
+        }
+    };
+    let (server_addr, proxies) = match config::validate_toml(&content) {
+        Ok(v) => v,
+        Err(e) => {
+            checker.fail(EXIT_CONFIG, item, &format!("{:#}", e));
+            return;
+        }
+    };
+    let mut ok = true;
+    if let Some(meta) = config::find_config(&inst.identifier) {
+        if let Err(e) = meta.ready_regex() {
+            checker.fail(EXIT_CONFIG, item, &format!("{:#}", e));
+            ok = false;
+        }
+        if let Err(e) = meta.log_level_filter() {
+            checker.fail(EXIT_CONFIG, item, &format!("{:#}", e));
+            ok = false;
+        }
+        if let Err(e) = meta.output_rules() {
+            checker.fail(EXIT_CONFIG, item, &format!("{:#}", e));
+            ok = false;
+        }
+    }
+    match probe::admin_api_lookup(path) {
+        Ok(Some(api)) => {
+            if let Some(port) = port_of(&api.base_url) {
+                admin_ports.push((inst.identifier.clone(), port));
+            }
+        }
+        Ok(None) => {}
+        Err(e) => {
+            checker.fail(EXIT_CONFIG, item, &format!("{:#}", e));
+            ok = false;
+        }
+    }
+    for proxy in &proxies {
+        if let Some(port) = proxy.remote_port {
+            remote_ports.push((inst.identifier.clone(), server_addr.clone(), port));
+        }
+    }
+    if ok {
+        checker.pass(
+            item,
+            &format!("服务器 {}，{} 个代理", server_addr, proxies.len()),
+        );
+    }
+}
+
+fn port_of(url: &str) -> Option<u16> {
+    url.rsplit(':').next()?.parse().ok()
+}
+
+/// 检查自启动实例之间的 admin 端口、同一服务器上的远程端口，以及 admin 聚合代理、指标导出端口
+fn check_port_conflicts(
+    checker: &mut Checker,
+    admin_ports: &[(String, u16)],
+    remote_ports: &[(String, String, u16)],
+    settings: Option<&config::AppSettings>,
+) {
+    let mut seen: HashMap<u16, &str> = HashMap::new();
+    for (name, port) in admin_ports {
+        if let Some(first) = seen.insert(*port, name) {
+            checker.fail(
+                EXIT_PORT,
+                "端口冲突",
+                &format!("{} 与 {} 的 admin API 都使用端口 {}", name, first, port),
+            );
+        }
+    }
+    if let Some(proxy) = settings.and_then(|s| s.admin_proxy.as_ref()) {
+        if let Some(port) = port_of(&proxy.listen) {
+            if let Some(name) = seen.get(&port) {
+                checker.fail(
+                    EXIT_PORT,
+                    "端口冲突",
+                    &format!("admin 聚合代理与 {} 的 admin API 都使用端口 {}", name, port),
+                );
+            }
+        }
+    }
+
+    if let Some(metrics) = settings
+        .and_then(|s| s.metrics.as_ref())
+        .filter(|m| m.enabled)
+    {
+        if let Some(port) = port_of(&metrics.listen) {
+            if let Some(name) = seen.get(&port) {
+                checker.fail(
+                    EXIT_PORT,
+                    "端口冲突",
+                    &format!(
+                        "Prometheus 指标导出与 {} 的 admin API 都使用端口 {}",
+                        name, port
+                    ),
+                );
+            }
+            let proxy_port = settings
+                .and_then(|s| s.admin_proxy.as_ref())
+                .and_then(|p| port_of(&p.listen));
+            if proxy_port == Some(port) {
+                checker.fail(
+                    EXIT_PORT,
+                    "端口冲突",
+                    &format!("Prometheus 指标导出与 admin 聚合代理都使用端口 {}", port),
+                );
+            }
+        }
+    }
+
+    let mut seen: HashMap<(&str, u16), &str> = HashMap::new();
+    for (name, server, port) in remote_ports {
+        if let Some(first) = seen.insert((server.as_str(), *port), name) {
+            if first != name {
+                checker.fail(
+                    EXIT_PORT,
+                    "端口冲突",
+                    &format!(
+                        "{} 与 {} 在服务器 {} 上都使用远程端口 {}",
+                        name, first, server, port
+                    ),
+                );
+            }
+        }
+    }
+}
+
+/// 日志目录需要可写；程序目录、配置目录对普通用户可写时给出警告（与服务启动时的检查一致）
+fn check_permissions(checker: &mut Checker) {
+    match config::logs_dir() {
+        Ok(dir) => match check_writable(&dir) {
+            Ok(()) => checker.pass("路径权限", &format!("日志目录可写: {}", dir.display())),
+            Err(e) => checker.fail(
+                EXIT_PERMISSION,
+                "路径权限",
+                &format!("日志目录 {} 不可写: {}", dir.display(), e),
+            ),
+        },
+        Err(e) => checker.fail(EXIT_PERMISSION, "路径权限", &format!("{:#}", e)),
+    }
+    for dir in acl::sensitive_directories() {
+        match acl::check_directory_acl(&dir) {
+            Ok(warning) if !warning.is_empty() => checker.warn(
+                "路径权限",
+                &format!(
+                    "{} 对 {} 可写，可能被用于提权",
+                    warning.path.display(),
+                    warning.writable_by.join("、")
+                ),
+            ),
+            Ok(_) => {}
+            Err(e) => checker.warn("路径权限", &format!("{:#}", e)),
+        }
+    }
+}
+
+/// 在目录中创建并删除一个临时文件（目录不存在时按服务启动时的方式创建）
+fn check_writable(dir: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let test_file = dir.join(".check-only.tmp");
+    std::fs::write(&test_file, b"")?;
+    std::fs::remove_file(&test_file)
+}
@@ -2,39 +2,121 @@
 //!
 
 use anyhow::{Context, Result};
-use std::collections::HashSet;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::ffi::OsString;
-use std::path::PathBuf;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::mpsc::Sender;
-use std::sync::{Arc, Mutex};
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
 use std::thread;
-use std::time::Duration;
-use windows_sys::Win32::Foundation::{CloseHandle, GetLastError, HANDLE, INVALID_HANDLE_VALUE};
+use std::time::{Duration, Instant, SystemTime};
+use windows_sys::Win32::Foundation::{
+    CloseHandle, GetLastError, LocalFree, ERROR_SERVICE_DOES_NOT_EXIST, ERROR_SUCCESS, HANDLE,
+    INVALID_HANDLE_VALUE,
+};
+use windows_sys::Win32::Security::Authorization::{
+    ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1,
+};
 use windows_sys::Win32::Security::{
-    InitializeSecurityDescriptor, SetSecurityDescriptorDacl, SECURITY_ATTRIBUTES,
-    SECURITY_DESCRIPTOR,
+    CheckTokenMembership, CreateWellKnownSid, InitializeSecurityDescriptor, RevertToSelf,
+    SetSecurityDescriptorDacl, WinBuiltinAdministratorsSid, PSECURITY_DESCRIPTOR,
+    SECURITY_ATTRIBUTES, SECURITY_DESCRIPTOR,
 };
 use windows_sys::Win32::Storage::FileSystem::{
     CreateFileW, FlushFileBuffers, ReadFile, WriteFile, FILE_FLAG_FIRST_PIPE_INSTANCE,
-    OPEN_EXISTING, PIPE_ACCESS_INBOUND,
+    OPEN_EXISTING, PIPE_ACCESS_DUPLEX,
 };
 use windows_sys::Win32::System::Pipes::{
-    ConnectNamedPipe, CreateNamedPipeW, DisconnectNamedPipe, PIPE_READMODE_BYTE, PIPE_TYPE_BYTE,
-    PIPE_WAIT,
+    ConnectNamedPipe, CreateNamedPipeW, DisconnectNamedPipe, ImpersonateNamedPipeClient,
+    PIPE_READMODE_BYTE, PIPE_TYPE_BYTE, PIPE_WAIT,
+};
+use windows_sys::Win32::System::Registry::{
+    RegGetValueW, RegSetKeyValueW, HKEY_LOCAL_MACHINE, REG_MULTI_SZ, RRF_RT_REG_DWORD,
+    RRF_RT_REG_MULTI_SZ,
 };
 use windows_sys::Win32::System::Threading::{
     CreateEventW, OpenEventW, SetEvent, WaitForMultipleObjects, WaitForSingleObject,
 };
 
-/// 服务停止信号，由 SCM 停止事件设置
-static SERVICE_STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+/// 收到的 SCM 停止控制（Stop / Shutdown），与停止信号一同设置
+static STOP_CONTROL: Mutex<Option<StopReason>> = Mutex::new(None);
+
+/// 应用期望状态请求，由管道 APPLY 命令设置，监控循环下一轮处理
+static APPLY_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// 重新加载设置与实例清单请求，由 SCM PARAMCHANGE 设置，监控循环下一轮处理
+static RELOAD_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// 分组命令队列，由管道线程写入、监控循环执行
+static GROUP_COMMANDS: Mutex<Vec<GroupCommand>> = Mutex::new(Vec::new());
+
+/// 因 `set-env` 需要重启的实例，由管道线程写入、监控循环执行
+static ENV_RESTARTS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// 服务停止原因，记入停止日志与 `logs/status.json` 的 `last_stop_reason`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum StopReason {
+    /// SCM 发送 Stop（`sc stop`、服务管理器、注销服务）
+    StopControl,
+    /// 系统关机
+    Shutdown,
+    /// 进程守护未开启：启动自启动实例后退出
+    ProcessGuardDisabled,
+    /// 界面关闭了进程守护
+    ProcessGuardTurnedOff,
+    /// 启动过程出错（设置无法加载、无法创建命名事件等）
+    StartupError,
+}
+
+impl StopReason {
+    /// 写入 status.json 的标识
+    pub fn code(self) -> &'static str {
+        match self {
+            StopReason::StopControl => "stop_control",
+            StopReason::Shutdown => "shutdown",
+            StopReason::ProcessGuardDisabled => "process_guard_disabled",
+            StopReason::ProcessGuardTurnedOff => "process_guard_turned_off",
+            StopReason::StartupError => "startup_error",
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            StopReason::StopControl => "收到 SCM 停止命令",
+            StopReason::Shutdown => "系统关机",
+            StopReason::ProcessGuardDisabled => "进程守护未开启，已启动自启动配置",
+            StopReason::ProcessGuardTurnedOff => "进程守护已在界面中关闭",
+            StopReason::StartupError => "服务启动出错",
+        }
+    }
+}
+
+/// 分组操作命令，管道中以 JSON 传输：`{"cmd":"restart","group":"lab"}`
+///
+/// `cmd` 可选 restart / start / stop
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct GroupCommand {
+    pub cmd: String,
+    pub group: String,
+}
+
+/// 设置实例环境变量的命令，管道中以 `SET_ENV:` 加 JSON 传输：
+/// `SET_ENV:{"instance":"web","key":"FRP_TOKEN","value":"..."}`
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct SetEnvCommand {
+    pub instance: String,
+    pub key: String,
+    pub value: String,
+}
 
 // Event access constants
 const EVENT_MODIFY_STATE: u32 = 0x0002;
 const WAIT_OBJECT_0: u32 = 0;
 const WAIT_TIMEOUT: u32 = 0x102;
+const GENERIC_READ: u32 = 0x80000000;
 const GENERIC_WRITE: u32 = 0x40000000;
 
 /// Named pipe for guard_stopped IPC: UI sends STOP/START/CLEAR commands
@@ -129,7 +211,17 @@ pub fn wait_process_changed(timeout_ms: u32) -> bool {
 /// - `START:config_name` — 将配置从手动停止列表移除
 /// - `CLEAR` — 清空手动停止列表
 /// - `TRACK:config_name:pid` — 通知 Service 将 UI 启动的进程纳入守护跟踪
+/// - `APPLY` — 重新发现实例并平滑应用（见 [`apply_desired_state`]）
+/// - `{"cmd":"restart","group":"lab"}` — 分组操作（见 [`GroupCommand`]）
+/// - `GET_METRICS` — 服务通过同一连接返回指标 JSON（见 [`query_pipe`]）
 pub fn send_guard_stopped_command(command: &str) {
+    if let Err(e) = send_pipe_command(command) {
+        log::error!("{:?}", e);
+    }
+}
+
+/// 通过命名管道向 Service 发送一条命令，管道不可用时返回错误
+pub(crate) fn send_pipe_command(command: &str) -> Result<()> {
     // 重试 3 次，每次间隔 50ms，应对管道短暂不可用的情况
     // （DisconnectNamedPipe 到下一次 CreateNamedPipeW 之间的间隙）
     for attempt in 0..3u32 {
@@ -148,12 +240,11 @@ pub fn send_guard_stopped_command(command: &str) {
                     std::thread::sleep(Duration::from_millis(50));
                     continue;
                 }
-                log::error!(
-                    "无法连接到命名管道 {}（已重试 {} 次）",
+                return Err(anyhow::anyhow!(
+                    "无法连接到命名管道 {}（已重试 {} 次），服务是否在运行且开启了进程守护？",
                     PIPE_NAME,
                     attempt + 1
-                );
-                return;
+                ));
             }
             let data = format!("{}\n", command);
             let mut bytes_written = 0u32;
@@ -166,36 +257,104 @@ pub fn send_guard_stopped_command(command: &str) {
             );
             FlushFileBuffers(handle);
             CloseHandle(handle);
-            return;
+            return Ok(());
         }
     }
+    Ok(())
 }
 
-/// 创建命名管道服务器（带 NULL DACL，允许跨会话访问）
-fn create_named_pipe_server() -> Result<HANDLE> {
-    let mut sd: SECURITY_DESCRIPTOR = unsafe { std::mem::zeroed() };
+/// 向 Service 发送一条需要应答的命令（如 `GET_METRICS`），读取应答直到服务关闭连接
+pub(crate) fn query_pipe(command: &str) -> Result<String> {
+    let handle = unsafe {
+        CreateFileW(
+            pipe_name_utf16().as_ptr(),
+            GENERIC_READ | GENERIC_WRITE,
+            0,
+            std::ptr::null(),
+            OPEN_EXISTING,
+            0,
+            0,
+        )
+    };
+    if handle == INVALID_HANDLE_VALUE {
+        anyhow::bail!(
+            "无法连接到命名管道 {}，服务是否在运行且开启了进程守护？",
+            PIPE_NAME
+        );
+    }
+    let data = format!("{}\n", command);
+    let mut bytes_written = 0u32;
+    let mut response = Vec::new();
     unsafe {
-        if InitializeSecurityDescriptor(&mut sd as *mut _ as *mut _, 1) == 0 {
-            return Err(anyhow::anyhow!("无法初始化安全描述符"));
+        WriteFile(
+            handle,
+            data.as_ptr(),
+            data.len() as u32,
+            &mut bytes_written,
+            std::ptr::null_mut(),
+        );
+        FlushFileBuffers(handle);
+        let mut buffer = [0u8; 4096];
+        loop {
+            let mut bytes_read = 0u32;
+            let ok = ReadFile(
+                handle,
+                buffer.as_mut_ptr(),
+                buffer.len() as u32,
+                &mut bytes_read,
+                std::ptr::null_mut(),
+            );
+            // 服务写完应答后断开连接，ReadFile 以 ERROR_BROKEN_PIPE 失败
+            if ok == 0 || bytes_read == 0 {
+                break;
+            }
+            response.extend_from_slice(&buffer[..bytes_read as usize]);
         }
-        if SetSecurityDescriptorDacl(&mut sd as *mut _ as *mut _, 1, std::ptr::null(), 0) == 0 {
-            return Err(anyhow::anyhow!("无法设置安全描述符 DACL"));
+        CloseHandle(handle);
+    }
+    if response.is_empty() {
+        anyhow::bail!("服务没有应答命令 {}（服务版本过旧？）", command);
+    }
+    String::from_utf8(response).context("服务应答不是有效的 UTF-8")
+}
+
+/// 命名管道的 DACL：SYSTEM 与 Administrators 完全控制，交互式登录用户（界面、非提权的命令行）可读写。
+/// 修改服务状态的命令另外检查客户端是否为管理员，见 [`pipe_client_is_admin`]
+const PIPE_SDDL: &str = "D:P(A;;GA;;;SY)(A;;GA;;;BA)(A;;GRGW;;;IU)";
+
+/// 创建命名管道服务器（使用 [`PIPE_SDDL`]，允许其他会话的界面访问）
+fn create_named_pipe_server() -> Result<HANDLE> {
+    let sddl: Vec<u16> = PIPE_SDDL.encode_utf16().chain(std::iter::once(0)).collect();
+    let mut sd: PSECURITY_DESCRIPTOR = std::ptr::null_mut();
+    unsafe {
+        if ConvertStringSecurityDescriptorToSecurityDescriptorW(
+            sddl.as_ptr(),
+            SDDL_REVISION_1,
+            &mut sd,
+            std::ptr::null_mut(),
+        ) == 0
+        {
+            return Err(anyhow::anyhow!(
+                "无法创建管道安全描述符: {}",
+                GetLastError()
+            ));
         }
         let sa = SECURITY_ATTRIBUTES {
             nLength: std::mem::size_of::<SECURITY_ATTRIBUTES>() as u32,
-            lpSecurityDescriptor: &mut sd as *mut _ as *mut _,
+            lpSecurityDescriptor: sd,
             bInheritHandle: 0,
         };
         let handle = CreateNamedPipeW(
             pipe_name_utf16().as_ptr(),
-            PIPE_ACCESS_INBOUND | FILE_FLAG_FIRST_PIPE_INSTANCE,
+            PIPE_ACCESS_DUPLEX | FILE_FLAG_FIRST_PIPE_INSTANCE,
             PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT,
             1,
-            0,
+            PIPE_OUT_BUFFER_SIZE,
             4096,
             0,
             &sa,
         );
+        LocalFree(sd as _);
         if handle == INVALID_HANDLE_VALUE {
             return Err(anyhow::anyhow!("无法创建命名管道"));
         }
@@ -203,11 +362,105 @@ fn create_named_pipe_server() -> Result<HANDLE> {
     }
 }
 
-/// 启动命名管道监听线程，接收 UI 发送的命令（STOP/START/CLEAR/TRACK）
+/// SID 最大长度（SECURITY_MAX_SID_SIZE）
+const MAX_SID_SIZE: usize = 68;
+
+/// 管道客户端是否为管理员：模拟客户端后检查令牌是否启用了 Administrators 组。
+/// UAC 下未提权的管理员账户不算；必须在从管道读取数据之后调用
+fn pipe_client_is_admin(pipe: HANDLE) -> bool {
+    let mut sid = [0u8; MAX_SID_SIZE];
+    let mut sid_len = MAX_SID_SIZE as u32;
+    unsafe {
+        if CreateWellKnownSid(
+            WinBuiltinAdministratorsSid,
+            std::ptr::null_mut(),
+            sid.as_mut_ptr() as _,
+            &mut sid_len,
+        ) == 0
+        {
+            return false;
+        }
+        if ImpersonateNamedPipeClient(pipe) == 0 {
+            log::warn!("无法模拟管道客户端: {}", GetLastError());
+            return false;
+        }
+        let mut is_member = 0;
+        // 令牌句柄为 0 时检查当前线程的模拟令牌
+        let ok = CheckTokenMembership(0, sid.as_mut_ptr() as _, &mut is_member);
+        RevertToSelf();
+        ok != 0 && is_member != 0
+    }
+}
+
+/// 单次请求的最大长度，超出部分丢弃
+const PIPE_MAX_REQUEST: usize = 64 * 1024;
+
+/// 读取客户端的一次请求：客户端写入以换行结尾的命令后等待应答，不会关闭写端，
+/// 因此读到换行结尾（或客户端断开、超过长度上限）即结束，命令较长时分多次读取
+fn read_pipe_request(pipe: HANDLE) -> Vec<u8> {
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 4096];
+    while !request.ends_with(b"\n") && request.len() < PIPE_MAX_REQUEST {
+        let mut bytes_read = 0u32;
+        let ok = unsafe {
+            ReadFile(
+                pipe,
+                buffer.as_mut_ptr(),
+                buffer.len() as u32,
+                &mut bytes_read,
+                std::ptr::null_mut(),
+            )
+        };
+        if ok == 0 || bytes_read == 0 {
+            break;
+        }
+        request.extend_from_slice(&buffer[..bytes_read as usize]);
+    }
+    if request.len() >= PIPE_MAX_REQUEST && !request.ends_with(b"\n") {
+        log::warn!("管道请求超过 {} 字节，已截断", PIPE_MAX_REQUEST);
+        request.truncate(PIPE_MAX_REQUEST);
+    }
+    request
+}
+
+/// 非管理员客户端发送了需要管理员权限的命令
+fn reject_pipe_command(command: &str) {
+    log::warn!(
+        "拒绝管道命令 {}：客户端不是管理员（需在提升权限的命令行中执行）",
+        command
+    );
+}
+
+/// 管道输出缓冲区大小：指标应答通常能一次写入缓冲区，不必等客户端读取
+const PIPE_OUT_BUFFER_SIZE: u32 = 64 * 1024;
+
+/// 把应答写回管道客户端，等客户端读完后由调用方断开连接
+fn write_pipe_response(pipe: HANDLE, body: &[u8]) {
+    let mut written = 0u32;
+    let ok = unsafe {
+        WriteFile(
+            pipe,
+            body.as_ptr(),
+            body.len() as u32,
+            &mut written,
+            std::ptr::null_mut(),
+        )
+    };
+    if ok == 0 {
+        log::warn!("管道应答写入失败: {}", unsafe { GetLastError() });
+        return;
+    }
+    unsafe { FlushFileBuffers(pipe) };
+}
+
+/// 启动命名管道监听线程，接收 UI 发送的命令（STOP/START/CLEAR/TRACK/APPLY/分组命令），
+/// 以及命令行的查询（GET_METRICS）
 fn start_guard_stopped_pipe(
     guard_stopped: Arc<Mutex<HashSet<String>>>,
     processes: Arc<Mutex<Vec<(String, FrpcProcess)>>>,
-    auto_start_map: Arc<std::collections::HashMap<String, (PathBuf, PathBuf)>>,
+    auto_start_map: InstanceMap,
+    monitor_paused: Arc<AtomicBool>,
+    metrics: MetricsSource,
 ) {
     thread::spawn(move || {
         loop {
@@ -231,22 +484,14 @@ fn start_guard_stopped_pipe(
             }
 
             // 读取数据
-            let mut buffer = [0u8; 4096];
-            let mut bytes_read = 0u32;
-            let success = unsafe {
-                ReadFile(
-                    pipe,
-                    buffer.as_mut_ptr(),
-                    buffer.len() as u32,
-                    &mut bytes_read,
-                    std::ptr::null_mut(),
-                )
-            };
-
-            if success != 0 && bytes_read > 0 {
-                let data = String::from_utf8_lossy(&buffer[..bytes_read as usize]);
+            let request = read_pipe_request(pipe);
+            if !request.is_empty() {
+                let is_admin = pipe_client_is_admin(pipe);
+                let data = String::from_utf8_lossy(&request);
                 for line in data.lines() {
                     let line = line.trim();
+                    // STOP/START/CLEAR 只修改手动停止列表（是否在退出后重启），TRACK 只接管经校验的 frpc 进程，
+                    // 界面以交互式用户身份发送，不要求管理员；其余修改服务状态的命令要求管理员
                     if let Some(name) = line.strip_prefix("STOP:") {
                         let mut gs = guard_stopped.lock().unwrap();
                         gs.insert(name.to_string());
@@ -259,23 +504,93 @@ fn start_guard_stopped_pipe(
                         let mut gs = guard_stopped.lock().unwrap();
                         gs.clear();
                         log::info!("手动停止列表已清空（管道）");
+                    } else if (line == "PAUSE_MONITOR" || line == "RESUME_MONITOR") && !is_admin {
+                        reject_pipe_command(line);
+                    } else if line == "PAUSE_MONITOR" {
+                        if !monitor_paused.swap(true, Ordering::SeqCst) {
+                            log::warn!("========== 监控已暂停（管道）：退出的进程不会被重启，执行 resume-monitor 恢复 ==========");
+                        }
+                    } else if line == "RESUME_MONITOR" {
+                        if monitor_paused.swap(false, Ordering::SeqCst) {
+                            log::info!("监控已恢复（管道）");
+                        }
+                    } else if line == "ROTATE_LOGS" && !is_admin {
+                        reject_pipe_command(line);
+                    } else if line == "ROTATE_LOGS" {
+                        match logger::rotate_logs_now() {
+                            Ok(files) => log::info!("已归档日志文件（管道）: {:?}", files),
+                            Err(e) => log::error!("手动轮转日志失败: {:?}", e),
+                        }
+                    } else if line == "GET_METRICS" {
+                        let metrics = metrics.collect();
+                        match serde_json::to_vec(&metrics) {
+                            Ok(body) => write_pipe_response(pipe, &body),
+                            Err(e) => log::error!("无法序列化指标: {}", e),
+                        }
+                    } else if line == "APPLY" && !is_admin {
+                        reject_pipe_command(line);
+                    } else if line == "APPLY" {
+                        APPLY_REQUESTED.store(true, Ordering::SeqCst);
+                        log::info!("收到应用期望状态请求（管道）");
+                    } else if line.starts_with("SET_ENV:") && !is_admin {
+                        reject_pipe_command("SET_ENV");
+                    } else if let Some(json) = line.strip_prefix("SET_ENV:") {
+                        match serde_json::from_str::<SetEnvCommand>(json) {
+                            Ok(command) if frpc_mg::is_valid_env_key(&command.key) => {
+                                // 只记录变量名，值可能是令牌
+                                log::info!(
+                                    "[{}] 已设置环境变量 {}（管道），重启后生效",
+                                    command.instance,
+                                    command.key
+                                );
+                                frpc_mg::set_env_override(
+                                    &command.instance,
+                                    &command.key,
+                                    &command.value,
+                                );
+                                ENV_RESTARTS.lock().unwrap().push(command.instance);
+                            }
+                            Ok(command) => {
+                                log::error!("无效的环境变量名（管道）: {:?}", command.key)
+                            }
+                            Err(e) => log::error!("无法解析 SET_ENV 命令: {}", e),
+                        }
+                    } else if line.starts_with('{') && !is_admin {
+                        reject_pipe_command("分组命令");
+                    } else if line.starts_with('{') {
+                        match serde_json::from_str::<GroupCommand>(line) {
+                            Ok(command) => {
+                                log::info!("收到分组命令（管道）: {:?}", command);
+                                GROUP_COMMANDS.lock().unwrap().push(command);
+                            }
+                            Err(e) => log::error!("无法解析管道命令 {}: {}", line, e),
+                        }
                     } else if let Some(remainder) = line.strip_prefix("TRACK:") {
                         // UI 启动了进程，通知 Service 纳入守护跟踪
                         // 格式: TRACK:config_name:pid
                         if let Some((name, pid_str)) = remainder.split_once(':') {
                             if let Ok(pid) = pid_str.parse::<u32>() {
-                                if let Some((exe, conf)) = auto_start_map.get(name) {
+                                let entry = auto_start_map.read().unwrap().get(name).cloned();
+                                // 服务停止实例时会结束跟踪的 PID：只接管确实运行该配置的 frpc 进程，
+                                // 不能借 TRACK 让服务结束任意进程
+                                let verified = entry.is_some()
+                                    && discover_running_frpc_processes()
+                                        .iter()
+                                        .any(|(n, p)| n == name && *p == pid);
+                                if entry.is_some() && !verified {
+                                    log::warn!(
+                                        "[{}] TRACK 的 PID {} 不是运行该配置的 frpc 进程，忽略",
+                                        name,
+                                        pid
+                                    );
+                                } else if let Some((exe, conf)) = entry {
                                     let mut proc_list = processes.lock().unwrap();
                                     // 已在跟踪列表中，跳过
                                     if proc_list.iter().any(|(n, _)| n == name) {
                                         log::debug!("[{}] 已在守护跟踪列表中，跳过", name);
                                     } else {
-                                        let process = FrpcProcess::from_pid(
-                                            pid,
-                                            name.to_string(),
-                                            exe.clone(),
-                                            conf.clone(),
-                                        );
+                                        let process =
+                                            FrpcProcess::from_pid(pid, name.to_string(), exe, conf);
                                         proc_list.push((name.to_string(), process));
                                         log::info!(
                                             "[{}] UI 通知 TRACK (PID: {})，已纳入守护跟踪",
@@ -300,19 +615,45 @@ fn start_guard_stopped_pipe(
     });
 }
 use windows_service::service::{
-    ServiceAccess, ServiceControlAccept, ServiceErrorControl, ServiceExitCode, ServiceInfo,
-    ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+    Service, ServiceAccess, ServiceConfig, ServiceControlAccept, ServiceErrorControl,
+    ServiceExitCode, ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{
+    self, ServiceControlHandlerResult, ServiceStatusHandle,
 };
-use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
 use windows_service::service_dispatcher;
 use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
 
-use crate::config;
-use crate::frpc_mg::FrpcProcess;
-
-pub const SERVICE_NAME: &str = "FrpcService";
-pub const DISPLAY_NAME: &str = "FRP Client Service";
-pub const SERVICE_ARG: &str = "--service";
+use crate::acl;
+use crate::admin_proxy;
+use crate::cleanup;
+use crate::config::{self, FrpcConfigMeta};
+use crate::constants::{DEFAULT_SERVICE_NAME, DISPLAY_NAME, SERVICE_ARG};
+use crate::disk_guard;
+use crate::etw::EtwProvider;
+use crate::fingerprint;
+use crate::frpc_mg::{self, FrpcProcess};
+use crate::host_filter::{self, HostNames};
+use crate::logger;
+use crate::metrics::{self, InstanceMetrics, ServiceMetrics};
+use crate::output_pump;
+use crate::policy;
+use crate::probe;
+use crate::run_marker;
+use crate::signal_files::{ControlSignal, SignalWatcher};
+use crate::snapshot;
+use crate::stop_diagnostics::{self, PendingStops};
+use crate::supervisor::{self, FrpcSupervisor, HealthMap, InstanceMap, SupervisorConfig};
+use crate::timeline::{self, TimelineEvent};
+
+/// 服务名：注册表 `ServiceName` 的值，未设置时为 [`DEFAULT_SERVICE_NAME`]；进程内只读取一次
+pub fn service_name() -> &'static str {
+    static NAME: OnceLock<String> = OnceLock::new();
+    NAME.get_or_init(|| {
+        policy::string_value(&policy::MachinePolicy, policy::SERVICE_NAME_VALUE)
+            .unwrap_or_else(|| DEFAULT_SERVICE_NAME.to_string())
+    })
+}
 
 // =========================================================================
 //  交互模式入口
@@ -320,23 +661,16 @@ pub const SERVICE_ARG: &str = "--service";
 
 /// 服务预检查结果
 #[derive(Clone, Debug)]
-pub(crate) enum PreCheckResult {
+pub enum PreCheckResult {
     Running,
     Stopped,
     NotRegistered,
 }
 
-/// 检查服务状态并启动 GUI
-pub fn check_and_run_app() -> Result<()> {
-    let pre_check = check_service_status()?;
-    crate::app::run_app(pre_check);
-    Ok(())
-}
-
 /// 检查 Windows 服务当前状态
-pub(crate) fn check_service_status() -> Result<PreCheckResult> {
+pub fn check_service_status() -> Result<PreCheckResult> {
     let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
-    if let Ok(service) = manager.open_service(SERVICE_NAME, ServiceAccess::QUERY_STATUS) {
+    if let Ok(service) = manager.open_service(service_name(), ServiceAccess::QUERY_STATUS) {
         let status = service.query_status()?;
         match status.current_state {
             ServiceState::Running => Ok(PreCheckResult::Running),
@@ -355,16 +689,139 @@ pub(crate) fn check_service_status() -> Result<PreCheckResult> {
 //  服务注册 / 注销
 // =========================================================================
 
-/// 注册 Windows 服务（如果已存在则先删除再重建）
-pub(crate) fn install_service() -> Result<()> {
+/// 服务运行账户
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServiceAccount {
+    /// LocalSystem（默认）
+    LocalSystem,
+    /// 虚拟账户 `NT SERVICE\FrpcService`：每个服务独立的 SID，无需管理密码，
+    /// 只拥有显式授予的文件权限
+    Virtual,
+}
+
+impl ServiceAccount {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "localsystem" | "system" => Ok(ServiceAccount::LocalSystem),
+            "virtual" => Ok(ServiceAccount::Virtual),
+            other => anyhow::bail!("不支持的服务账户: {}（可选 localsystem/virtual）", other),
+        }
+    }
+
+    fn account_name(self) -> Option<OsString> {
+        match self {
+            ServiceAccount::LocalSystem => None,
+            ServiceAccount::Virtual => Some(OsString::from(virtual_account_name())),
+        }
+    }
+}
+
+/// 服务启动类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ServiceStartMode {
+    /// 开机自动启动（默认）
+    Auto,
+    /// 延迟自动启动：其他自动启动服务启动完成后再启动，减轻开机负载
+    DelayedAuto,
+    /// 手动启动
+    Manual,
+}
+
+impl ServiceStartMode {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "auto" => Ok(ServiceStartMode::Auto),
+            "delayed-auto" => Ok(ServiceStartMode::DelayedAuto),
+            "manual" => Ok(ServiceStartMode::Manual),
+            other => anyhow::bail!(
+                "不支持的启动类型: {}（可选 auto/delayed-auto/manual）",
+                other
+            ),
+        }
+    }
+
+    fn start_type(self) -> ServiceStartType {
+        match self {
+            ServiceStartMode::Auto | ServiceStartMode::DelayedAuto => ServiceStartType::AutoStart,
+            ServiceStartMode::Manual => ServiceStartType::OnDemand,
+        }
+    }
+}
+
+fn virtual_account_name() -> String {
+    format!("NT SERVICE\\{}", service_name())
+}
+
+/// 为虚拟账户授予程序目录、配置目录的读取权限与日志目录、信号文件目录的修改权限
+///
+/// 虚拟账户的 SID 在服务创建后才存在，必须在 `create_service` 之后调用
+fn grant_virtual_account_access() -> Result<()> {
+    use std::os::windows::process::CommandExt;
+    const CREATE_NO_WINDOW: u32 = 0x08000000;
+
+    let account = virtual_account_name();
+    let exe_dir = env::current_exe()
+        .ok()
+        .and_then(|p| p.parent().map(|p| p.to_path_buf()))
+        .context("无法获取程序目录")?;
+    let logs_dir = config::logs_dir()?;
+    std::fs::create_dir_all(&logs_dir).context("无法创建日志目录")?;
+    // 服务受理信号文件后需要删除它们，虚拟账户无法在只读的数据目录中创建 control/，注册时预先创建
+    let control_dir = config::control_dir()?;
+    std::fs::create_dir_all(&control_dir).context("无法创建信号文件目录")?;
+    let mut grants = vec![
+        (exe_dir, "(OI)(CI)RX"),
+        (config::conf_dir()?, "(OI)(CI)RX"),
+        (logs_dir, "(OI)(CI)M"),
+        (control_dir, "(OI)(CI)M"),
+    ];
+    let instances_dir = config::instances_dir()?;
+    if !grants.iter().any(|(dir, _)| dir == &instances_dir) {
+        grants.push((instances_dir, "(OI)(CI)RX"));
+    }
+
+    for (dir, rights) in grants {
+        if !dir.exists() {
+            continue;
+        }
+        let output = std::process::Command::new("icacls")
+            .arg(&dir)
+            .arg("/grant")
+            .arg(format!("{}:{}", account, rights))
+            .arg("/Q")
+            .creation_flags(CREATE_NO_WINDOW)
+            .output()
+            .context("无法执行 icacls")?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "icacls 授权 {:?} 失败: {}",
+                dir,
+                String::from_utf8_lossy(&output.stdout).trim()
+            );
+        }
+        log::info!("已授予 {} 对 {:?} 的权限 {}", account, dir, rights);
+    }
+    Ok(())
+}
+
+/// 以 LocalSystem 注册 Windows 服务（如果已存在则先删除再重建）
+pub fn install_service() -> Result<()> {
+    install_service_as(ServiceAccount::LocalSystem, ServiceStartMode::Auto)
+}
+
+/// 以指定账户与启动类型注册 Windows 服务（如果已存在则先删除再重建）
+pub(crate) fn install_service_as(
+    account: ServiceAccount,
+    start_mode: ServiceStartMode,
+) -> Result<()> {
     let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::all())?;
 
     // 如果服务已存在，先停止并删除
     if let Ok(service) = manager.open_service(
-        SERVICE_NAME,
+        service_name(),
         ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
     ) {
-        log::info!("服务 {} 已存在，尝试删除旧服务", SERVICE_NAME);
+        log::info!("服务 {} 已存在，尝试删除旧服务", service_name());
         stop_service_if_running(&service)?;
         service.delete().context("无法删除旧服务")?;
         std::thread::sleep(Duration::from_millis(500));
@@ -373,56 +830,422 @@ pub(crate) fn install_service() -> Result<()> {
     // 创建新服务
     let manager =
         ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CREATE_SERVICE)?;
-    let exe_path = env::current_exe().context("无法获取当前可执行文件路径")?;
-    manager
+    let (exe_path, launch_arguments) = service_launch_command()?;
+    let service = manager
         .create_service(
             &ServiceInfo {
-                name: OsString::from(SERVICE_NAME),
+                name: OsString::from(service_name()),
                 display_name: OsString::from(DISPLAY_NAME),
                 service_type: ServiceType::OWN_PROCESS,
-                start_type: ServiceStartType::AutoStart,
+                start_type: start_mode.start_type(),
                 error_control: ServiceErrorControl::Normal,
                 executable_path: PathBuf::from(&exe_path),
-                launch_arguments: vec![OsString::from(SERVICE_ARG)],
+                launch_arguments,
                 dependencies: vec![],
-                account_name: None,
+                // 虚拟账户不需要密码
+                account_name: account.account_name(),
                 account_password: None,
             },
             ServiceAccess::all(),
         )
         .context("创建服务失败，请确保以管理员身份运行")?;
-    log::info!("服务 {} 已成功注册（重启电脑后生效）", SERVICE_NAME);
+    // 服务已创建，设置延迟启动失败时保留服务（仍为普通自动启动），只记录错误
+    if start_mode == ServiceStartMode::DelayedAuto {
+        if let Err(e) = service.set_delayed_auto_start(true) {
+            log::error!("设置延迟自动启动失败，服务将以普通自动启动运行: {:?}", e);
+        }
+    }
+    if let Err(e) = service.set_preshutdown_timeout(PRESHUTDOWN_TIMEOUT) {
+        log::error!(
+            "设置预关机超时失败，系统关机时 frpc 可能来不及正常退出: {:?}",
+            e
+        );
+    }
+    write_service_environment().context("无法写入服务的环境变量")?;
+    if account == ServiceAccount::Virtual {
+        grant_virtual_account_access().context("为虚拟账户授权失败")?;
+    }
+    log::info!(
+        "服务 {} 已成功注册（账户: {:?}，启动类型: {:?}，重启电脑后生效）",
+        service_name(),
+        account,
+        start_mode
+    );
+
+    Ok(())
+}
+
+/// 注册服务时写入的程序路径与启动参数
+fn service_launch_command() -> Result<(PathBuf, Vec<OsString>)> {
+    let exe_path = env::current_exe().context("无法获取当前可执行文件路径")?;
+    // 使用外部实例清单时，把路径一并写入服务启动参数
+    let mut launch_arguments = vec![OsString::from(SERVICE_ARG)];
+    if let Some(path) = config::instances_file() {
+        launch_arguments.push(OsString::from(config::INSTANCES_FILE_ARG));
+        launch_arguments.push(path.clone().into_os_string());
+    }
+    Ok((exe_path, launch_arguments))
+}
+
+/// 服务在注册表中的键（`HKLM\SYSTEM\CurrentControlSet\Services\FrpcService`），以 NUL 结尾
+fn service_registry_key() -> Vec<u16> {
+    format!("SYSTEM\\CurrentControlSet\\Services\\{}\0", service_name())
+        .encode_utf16()
+        .collect()
+}
 
+/// 服务进程不继承注册时命令行的环境变量：设置了 `FRPC_SERVICE_CONFIG_DIR` 时
+/// 写入服务的 `Environment` 注册表值（REG_MULTI_SZ），服务与其启动的 frpc 都能读到
+fn write_service_environment() -> Result<()> {
+    let Some(dir) = config::config_dir_override() else {
+        return Ok(());
+    };
+    // 每项以 NUL 结尾，整体再以一个 NUL 结尾
+    let data: Vec<u16> = format!("{}={}\0\0", config::CONFIG_DIR_ENV, dir.display())
+        .encode_utf16()
+        .collect();
+    let value: Vec<u16> = "Environment\0".encode_utf16().collect();
+    let status = unsafe {
+        RegSetKeyValueW(
+            HKEY_LOCAL_MACHINE,
+            service_registry_key().as_ptr(),
+            value.as_ptr(),
+            REG_MULTI_SZ,
+            data.as_ptr() as *const _,
+            (data.len() * 2) as u32,
+        )
+    };
+    if status != ERROR_SUCCESS {
+        anyhow::bail!("RegSetKeyValueW 失败，错误码: {}", status);
+    }
+    log::info!("已为服务设置 {}={}", config::CONFIG_DIR_ENV, dir.display());
     Ok(())
 }
 
+/// 已注册服务的 `Environment` 注册表值中 `FRPC_SERVICE_CONFIG_DIR` 的值
+fn installed_config_dir_override() -> Option<String> {
+    let value: Vec<u16> = "Environment\0".encode_utf16().collect();
+    let key = service_registry_key();
+    let mut size = 0u32;
+    unsafe {
+        if RegGetValueW(
+            HKEY_LOCAL_MACHINE,
+            key.as_ptr(),
+            value.as_ptr(),
+            RRF_RT_REG_MULTI_SZ,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            &mut size,
+        ) != ERROR_SUCCESS
+        {
+            return None;
+        }
+        let mut data = vec![0u16; (size as usize).div_ceil(2)];
+        if RegGetValueW(
+            HKEY_LOCAL_MACHINE,
+            key.as_ptr(),
+            value.as_ptr(),
+            RRF_RT_REG_MULTI_SZ,
+            std::ptr::null_mut(),
+            data.as_mut_ptr() as *mut _,
+            &mut size,
+        ) != ERROR_SUCCESS
+        {
+            return None;
+        }
+        let prefix = format!("{}=", config::CONFIG_DIR_ENV);
+        String::from_utf16_lossy(&data)
+            .split('\0')
+            .find_map(|entry| {
+                entry
+                    .get(..prefix.len())
+                    .filter(|p| p.eq_ignore_ascii_case(&prefix))
+                    .map(|_| entry[prefix.len()..].to_string())
+            })
+            .filter(|dir| !dir.is_empty())
+    }
+}
+
+/// 幂等注册的结果
+#[derive(Debug)]
+pub(crate) enum InstallOutcome {
+    /// 服务不存在，已注册
+    Installed,
+    /// 服务已存在且配置一致，未做改动
+    Unchanged,
+    /// 服务已存在但配置不一致，已按本次参数重新注册；附带差异说明
+    Reinstalled(Vec<String>),
+}
+
+/// 幂等注册：服务已存在且配置一致时直接返回，不一致时删除后重新注册
+///
+/// 配置一致指以下各项均与本次要注册的相同（显示名称、描述与恢复策略不参与比较）：
+/// - 启动命令行：程序路径与参数（含 `--instances-file`），不区分大小写
+/// - 登录账户：LocalSystem 或虚拟账户
+/// - 启动类型：自动 / 自动（延迟启动）/ 手动
+/// - 数据根目录：服务环境变量中的 `FRPC_SERVICE_CONFIG_DIR`，不区分大小写
+pub(crate) fn ensure_service_installed(
+    account: ServiceAccount,
+    start_mode: ServiceStartMode,
+) -> Result<InstallOutcome> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+    let differences = match open_existing_service(&manager, ServiceAccess::QUERY_CONFIG)? {
+        Some(service) => {
+            let installed = service.query_config().context("无法读取已注册服务的配置")?;
+            service_config_differences(&installed, account, start_mode)?
+        }
+        None => {
+            install_service_as(account, start_mode)?;
+            return Ok(InstallOutcome::Installed);
+        }
+    };
+    if differences.is_empty() {
+        log::info!("服务 {} 已注册且配置一致，跳过注册", service_name());
+        return Ok(InstallOutcome::Unchanged);
+    }
+    log::info!(
+        "服务 {} 已注册但配置不一致（{}），重新注册",
+        service_name(),
+        differences.join("；")
+    );
+    install_service_as(account, start_mode)?;
+    Ok(InstallOutcome::Reinstalled(differences))
+}
+
+/// 幂等注销：服务不存在时视为成功，返回是否实际删除了服务
+pub(crate) fn ensure_service_uninstalled() -> Result<bool> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+    if open_existing_service(&manager, ServiceAccess::QUERY_STATUS)?.is_none() {
+        log::info!("服务 {} 未注册，无需注销", service_name());
+        return Ok(false);
+    }
+    uninstall_service()?;
+    Ok(true)
+}
+
+/// 打开已注册的服务，服务不存在时返回 None；权限不足等其他错误照常返回
+fn open_existing_service(
+    manager: &ServiceManager,
+    access: ServiceAccess,
+) -> Result<Option<Service>> {
+    match manager.open_service(service_name(), access) {
+        Ok(service) => Ok(Some(service)),
+        Err(windows_service::Error::Winapi(e))
+            if e.raw_os_error() == Some(ERROR_SERVICE_DOES_NOT_EXIST as i32) =>
+        {
+            Ok(None)
+        }
+        Err(e) => Err(e).context("无法打开服务，请确保以管理员身份运行"),
+    }
+}
+
+/// 比较已注册服务的配置与本次要注册的配置，返回每一项差异的说明（`已注册 → 期望`）
+fn service_config_differences(
+    installed: &ServiceConfig,
+    account: ServiceAccount,
+    start_mode: ServiceStartMode,
+) -> Result<Vec<String>> {
+    let (exe_path, launch_arguments) = service_launch_command()?;
+    let expected = ServiceSetup {
+        command: std::iter::once(exe_path.into_os_string())
+            .chain(launch_arguments)
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect(),
+        account,
+        start_mode,
+        config_dir: config::config_dir_override().map(|dir| dir.display().to_string()),
+    };
+    let installed = InstalledService {
+        executable_path: installed.executable_path.to_string_lossy().into_owned(),
+        account_name: installed
+            .account_name
+            .as_ref()
+            .map(|name| name.to_string_lossy().into_owned()),
+        start_type: installed.start_type,
+        delayed_auto_start: delayed_auto_start_enabled(),
+        config_dir: installed_config_dir_override(),
+    };
+    Ok(setup_differences(
+        &installed,
+        &expected,
+        &virtual_account_name(),
+    ))
+}
+
+/// 本次要注册的服务配置中参与比较的各项
+struct ServiceSetup {
+    /// 启动命令行：程序路径与参数
+    command: Vec<String>,
+    account: ServiceAccount,
+    start_mode: ServiceStartMode,
+    /// `FRPC_SERVICE_CONFIG_DIR`，未设置时为 None
+    config_dir: Option<String>,
+}
+
+/// 已注册服务中参与比较的各项（SCM 配置与注册表中的值）
+struct InstalledService {
+    /// SCM 中的启动命令行（含引号）
+    executable_path: String,
+    /// 登录账户，None 表示 LocalSystem
+    account_name: Option<String>,
+    start_type: ServiceStartType,
+    /// 注册表中的 `DelayedAutostart`
+    delayed_auto_start: bool,
+    /// 服务环境变量中的 `FRPC_SERVICE_CONFIG_DIR`
+    config_dir: Option<String>,
+}
+
+/// [`service_config_differences`] 的比较部分；`virtual_account` 为本服务虚拟账户的名称
+fn setup_differences(
+    installed: &InstalledService,
+    expected: &ServiceSetup,
+    virtual_account: &str,
+) -> Vec<String> {
+    let mut differences = Vec::new();
+
+    let actual = split_command_line(&installed.executable_path);
+    let same_command = expected.command.len() == actual.len()
+        && expected
+            .command
+            .iter()
+            .zip(&actual)
+            .all(|(a, b)| a.to_lowercase() == b.to_lowercase());
+    if !same_command {
+        differences.push(format!(
+            "启动命令行: {} → {}",
+            actual.join(" "),
+            expected.command.join(" ")
+        ));
+    }
+
+    let installed_as = match installed.account_name.as_deref() {
+        None => Some(ServiceAccount::LocalSystem),
+        Some(name)
+            if name.eq_ignore_ascii_case("LocalSystem")
+                || name.eq_ignore_ascii_case(".\\LocalSystem")
+                || name.eq_ignore_ascii_case("NT AUTHORITY\\SYSTEM") =>
+        {
+            Some(ServiceAccount::LocalSystem)
+        }
+        Some(name) if name.eq_ignore_ascii_case(virtual_account) => Some(ServiceAccount::Virtual),
+        Some(_) => None,
+    };
+    if installed_as != Some(expected.account) {
+        differences.push(format!(
+            "账户: {} → {:?}",
+            installed.account_name.as_deref().unwrap_or("LocalSystem"),
+            expected.account
+        ));
+    }
+
+    let installed_start = match installed.start_type {
+        ServiceStartType::AutoStart if installed.delayed_auto_start => {
+            Some(ServiceStartMode::DelayedAuto)
+        }
+        ServiceStartType::AutoStart => Some(ServiceStartMode::Auto),
+        ServiceStartType::OnDemand => Some(ServiceStartMode::Manual),
+        _ => None,
+    };
+    if installed_start != Some(expected.start_mode) {
+        differences.push(format!(
+            "启动类型: {} → {:?}",
+            installed_start
+                .map(|mode| format!("{:?}", mode))
+                .unwrap_or_else(|| format!("{:?}", installed.start_type)),
+            expected.start_mode
+        ));
+    }
+
+    if installed.config_dir.as_deref().map(str::to_lowercase)
+        != expected.config_dir.as_deref().map(str::to_lowercase)
+    {
+        differences.push(format!(
+            "{}: {} → {}",
+            config::CONFIG_DIR_ENV,
+            installed.config_dir.as_deref().unwrap_or("（未设置）"),
+            expected.config_dir.as_deref().unwrap_or("（未设置）")
+        ));
+    }
+    differences
+}
+
+/// 按空白拆分服务的启动命令行，双引号内的空白不拆分（注册时写入的参数本身不含引号）
+fn split_command_line(command: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_arg = false;
+    let mut quoted = false;
+    for c in command.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                in_arg = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if in_arg {
+                    args.push(std::mem::take(&mut current));
+                    in_arg = false;
+                }
+            }
+            c => {
+                current.push(c);
+                in_arg = true;
+            }
+        }
+    }
+    if in_arg {
+        args.push(current);
+    }
+    args
+}
+
+/// 服务是否设置了延迟自动启动（windows-service 没有读取接口，直接读注册表）
+fn delayed_auto_start_enabled() -> bool {
+    let key = service_registry_key();
+    let value: Vec<u16> = "DelayedAutostart\0".encode_utf16().collect();
+    let mut data = 0u32;
+    let mut size = std::mem::size_of::<u32>() as u32;
+    let status = unsafe {
+        RegGetValueW(
+            HKEY_LOCAL_MACHINE,
+            key.as_ptr(),
+            value.as_ptr(),
+            RRF_RT_REG_DWORD,
+            std::ptr::null_mut(),
+            &mut data as *mut u32 as *mut _,
+            &mut size,
+        )
+    };
+    status == ERROR_SUCCESS && data != 0
+}
+
 /// 注销 Windows 服务（先停止再删除）
-pub(crate) fn uninstall_service() -> Result<()> {
+pub fn uninstall_service() -> Result<()> {
     let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::all())?;
     let service = manager.open_service(
-        SERVICE_NAME,
+        service_name(),
         ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
     )?;
     stop_service_if_running(&service)?;
     service.delete().context("无法删除服务")?;
-    log::info!("服务 {} 已删除", SERVICE_NAME);
+    log::info!("服务 {} 已删除", service_name());
     Ok(())
 }
 
 /// 启动 Windows 服务
-pub(crate) fn start_service() -> Result<()> {
+pub fn start_service() -> Result<()> {
     let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
     let service = manager.open_service(
-        SERVICE_NAME,
+        service_name(),
         ServiceAccess::START | ServiceAccess::QUERY_STATUS,
     )?;
     let status = service.query_status()?;
     if status.current_state == ServiceState::Running {
-        log::info!("服务 {} 已在运行", SERVICE_NAME);
+        log::info!("服务 {} 已在运行", service_name());
         return Ok(());
     }
     service.start(&[] as &[&str]).context("无法启动服务")?;
-    log::info!("服务 {} 已启动", SERVICE_NAME);
+    log::info!("服务 {} 已启动", service_name());
     Ok(())
 }
 
@@ -431,7 +1254,7 @@ pub(crate) fn start_service() -> Result<()> {
 pub(crate) fn stop_service() -> Result<()> {
     let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
     let service = manager.open_service(
-        SERVICE_NAME,
+        service_name(),
         ServiceAccess::STOP | ServiceAccess::QUERY_STATUS,
     )?;
     stop_service_if_running(&service)
@@ -456,8 +1279,8 @@ pub fn start_frpc_process_with_sender(
     name: &str,
     on_connected: Option<Sender<()>>,
 ) -> Result<FrpcProcess> {
-    let exe_path = config::frpc_exe_path().context("无法获取 frpc.exe 路径")?;
-    let config_path = config::config_toml_path(name).context("无法获取配置文件路径")?;
+    let exe_path = config::instance_exe_path(name).context("无法获取 frpc.exe 路径")?;
+    let config_path = config::instance_config_path(name).context("无法获取配置文件路径")?;
     FrpcProcess::start(name.to_string(), exe_path, config_path, on_connected)
 }
 
@@ -492,115 +1315,332 @@ fn stop_service_if_running(service: &windows_service::service::Service) -> Resul
 extern "system" fn service_main(_arguments: u32, _argv: *mut *mut u16) {
     if let Err(e) = run_service() {
         log::error!("服务运行失败: {:?}", e);
+        log::info!(
+            "服务停止，原因: {}（{}）",
+            StopReason::StartupError.description(),
+            StopReason::StartupError.code()
+        );
+        snapshot::record_stop(StopReason::StartupError.code());
+        // 出错返回也是有序退出（原因已记录），下次启动不应报告会话未正常结束
+        run_marker::end();
+        log::logger().flush();
     }
 }
 
 pub fn run_service_dispatcher() -> Result<()> {
-    service_dispatcher::start(SERVICE_NAME, service_main)?;
+    service_dispatcher::start(service_name(), service_main)?;
     Ok(())
 }
 
+/// 服务运行的阶段，`run_service` 从 Starting 开始逐步推进，直到 Stopped
+///
+/// 每个阶段由对应的函数处理并返回下一阶段，阶段之间的转换只发生在这些函数的返回值中：
+/// Starting → Running / StopRequested，Running → Running / StopRequested，
+/// StopRequested → Stopping，Stopping → Stopped
+enum RunState {
+    /// 加载设置、启动自启动实例
+    Starting,
+    /// 进程守护循环，每次处理一轮后回到 Running 或转为 StopRequested
+    Running { pool: FrpcProcessPool },
+    /// 收到停止请求或无需继续守护；进程守护未开启时没有进程池
+    StopRequested {
+        reason: StopReason,
+        pool: Option<FrpcProcessPool>,
+    },
+    /// 收尾：记录停止原因、排空输出泵、上报 Stopped
+    Stopping { reason: StopReason },
+    /// 已上报 Stopped，`run_service` 返回
+    Stopped,
+}
+
+/// 进程守护期间的全部状态：库的进程守护（跟踪的进程、健康状态、实例映射）、设置与跨进程事件
+///
+/// 进程守护中的进程列表、实例映射与手动停止列表与管道线程共享
+struct FrpcProcessPool {
+    settings: config::AppSettings,
+    supervisor: FrpcSupervisor,
+    guard_event: HANDLE,
+    process_changed_event: HANDLE,
+    /// control/ 下的信号文件，无法获取数据目录时为 None
+    signals: Option<SignalWatcher>,
+    /// 上次读取实例设置时实例清单的修改时间，变化时重新读取
+    instances_modified: Option<SystemTime>,
+}
+
 fn run_service() -> Result<()> {
-    SERVICE_STOP_REQUESTED.store(false, Ordering::SeqCst);
+    // 服务停止信号，由 SCM 停止控制设置；进程守护据此不再等待重启中的实例
+    let stop_requested = Arc::new(AtomicBool::new(false));
+    let stop_flag = Arc::clone(&stop_requested);
+    *STOP_CONTROL.lock().unwrap() = None;
+    // 服务没有控制台，默认 panic 输出会丢失，改为写入日志（含位置信息）
+    panic::set_hook(Box::new(|info| {
+        log::error!("发生 panic: {}", info);
+    }));
     let status_handle =
-        service_control_handler::register(SERVICE_NAME, |control_event| match control_event {
-            windows_service::service::ServiceControl::Stop
-            | windows_service::service::ServiceControl::Shutdown => {
-                SERVICE_STOP_REQUESTED.store(true, Ordering::SeqCst);
-                ServiceControlHandlerResult::NoError
-            }
-            _ => ServiceControlHandlerResult::NotImplemented,
-        })
+        service_control_handler::register(
+            service_name(),
+            move |control_event| match control_event {
+                windows_service::service::ServiceControl::Stop => {
+                    STOP_CONTROL
+                        .lock()
+                        .unwrap()
+                        .get_or_insert(StopReason::StopControl);
+                    stop_flag.store(true, Ordering::SeqCst);
+                    ServiceControlHandlerResult::NoError
+                }
+                // 接受 PRESHUTDOWN 后系统关机时先收到 Preshutdown，SCM 等待服务停止（最长
+                // PRESHUTDOWN_TIMEOUT）后才继续关机；未接受时收到的是时限很短的 Shutdown
+                windows_service::service::ServiceControl::Preshutdown
+                | windows_service::service::ServiceControl::Shutdown => {
+                    *STOP_CONTROL.lock().unwrap() = Some(StopReason::Shutdown);
+                    stop_flag.store(true, Ordering::SeqCst);
+                    ServiceControlHandlerResult::NoError
+                }
+                windows_service::service::ServiceControl::ParamChange => {
+                    RELOAD_REQUESTED.store(true, Ordering::SeqCst);
+                    ServiceControlHandlerResult::NoError
+                }
+                _ => ServiceControlHandlerResult::NotImplemented,
+            },
+        )
         .context("无法注册服务控制处理程序")?;
-    set_service_status(&status_handle, ServiceState::StartPending)?;
+    let mut status = StatusReporter::new(status_handle);
+    status.report(ServiceState::StartPending);
+
+    // ETW 提供程序：服务退出（含出错返回）时写入 service_stop 并注销；
+    // 名称固定为默认服务名，不随注册表中的 ServiceName 变化
+    let _etw = EtwProvider::register(DEFAULT_SERVICE_NAME);
+
+    let mut state = RunState::Starting;
+    loop {
+        state = match state {
+            RunState::Starting => start_pool(&mut status, Arc::clone(&stop_requested))?,
+            RunState::Running { pool } => pool.supervise(&mut status),
+            RunState::StopRequested { reason, pool } => {
+                if let Some(mut pool) = pool {
+                    if reason == StopReason::Shutdown {
+                        pool.stop_for_shutdown(&mut status);
+                    }
+                    pool.close();
+                }
+                RunState::Stopping { reason }
+            }
+            RunState::Stopping { reason } => {
+                finish_service(&mut status, reason);
+                RunState::Stopped
+            }
+            RunState::Stopped => return Ok(()),
+        };
+    }
+}
+
+/// Starting：启动所有自启动实例并上报 Running
+///
+/// 进程守护未开启时直接转为 StopRequested（frpc 进程继续作为孤儿进程运行），
+/// 否则启动 admin 聚合代理、指标导出与管道线程后进入 Running
+fn start_pool(status: &mut StatusReporter, stop_requested: Arc<AtomicBool>) -> Result<RunState> {
+    // 上次会话未正常结束时不信任遗留的状态快照（无论新旧都删除）；
+    // 已运行的 frpc 进程与每次启动一样按命令行重新发现并校验 PID 后才接管
+    let unclean = run_marker::begin();
+    if let Some(prev) = &unclean {
+        log::warn!(
+            "========== 上次服务会话未正常结束（PID: {}，启动于 {}），重置状态快照 ==========",
+            prev.pid,
+            prev.started_at
+        );
+        snapshot::reset();
+    }
+
+    // 上次运行遗留的生成文件（中断的下载、旧的状态快照），本次会重新生成
+    cleanup::clean_generated_files();
 
-    let mut settings = config::load_settings();
+    // 程序目录/配置目录对普通用户可写时记录安全警告，不阻止启动
+    acl::warn_insecure_directories();
+
+    // settings.json 来自更新版本的程序时明确报错，而不是按默认设置运行
+    let settings = config::upgrade_settings_file().context("无法加载 settings.json")?;
 
     // 服务启动时始终启动所有自启动配置（进程守护只负责崩溃后重启）
-    // processes 共享给管道线程（TRACK 命令需要添加进程）
-    let processes: Arc<Mutex<Vec<(String, FrpcProcess)>>> =
-        Arc::new(Mutex::new(start_auto_start_processes()));
+    // 服务停止时不停止 frpc 进程，继续作为孤儿进程运行
+    let supervisor_config = SupervisorConfig::discover().unwrap_or_else(|e| {
+        log::error!("发现自启动配置失败: {:?}", e);
+        SupervisorConfig::default()
+    });
+    warn_invalid_maintenance_windows(&settings);
+    let mut supervisor = FrpcSupervisor::new(SupervisorConfig {
+        panic_recovery: settings.monitor_panic_recovery,
+        stop_processes_on_shutdown: false,
+        maintenance_windows: settings.maintenance_windows.clone(),
+        circuit_breaker: settings.circuit_breaker.clone(),
+        path_check_interval: Duration::from_secs(settings.path_check_interval_secs),
+        ..supervisor_config
+    })
+    .with_stop_flag(stop_requested);
+    let instances_modified = instances_file_modified();
+    // 含程序或配置缺失、未被发现的自启动实例，指标与健康检查中显示为未运行
+    supervisor.set_instance_settings(load_instance_settings());
+    supervisor.start();
 
     {
-        let proc_list = processes.lock().unwrap();
+        let proc_list = supervisor.processes.lock().unwrap();
         log::info!(
             "服务已启动，进程守护: {}，已跟踪 {} 个进程",
             settings.process_guard,
             proc_list.len()
         );
-    }
-    set_service_status(&status_handle, ServiceState::Running)?;
+        if let Some(prev) = &unclean {
+            log::warn!(
+                "启动报告: 上次服务会话未正常结束（PID: {}，启动于 {}）",
+                prev.pid,
+                prev.started_at
+            );
+        }
+        // 启动报告：各实例实际运行的程序与配置（哈希），便于事后追溯
+        for (name, proc) in proc_list.iter() {
+            match &proc.launch {
+                Some(launch) => log::info!(
+                    "[{}] 启动报告: PID={} exe_sha256={} config_sha256={} argv={:?} env={:?}",
+                    name,
+                    proc.pid(),
+                    launch.exe_sha256,
+                    launch.config_sha256,
+                    launch.argv,
+                    launch.env_keys
+                ),
+                None => log::info!(
+                    "[{}] 启动报告: PID={}（沿用已运行的进程）",
+                    name,
+                    proc.pid()
+                ),
+            }
+        }
+        snapshot::update(
+            &proc_list,
+            &supervisor.health.lock().unwrap(),
+            supervisor.monitor_paused.load(Ordering::SeqCst),
+            None,
+            status.consecutive_failures(),
+        );
+        let configured = supervisor.instances.read().unwrap().len();
+        check_min_successful(
+            proc_list.len(),
+            configured,
+            settings.min_successful_instances,
+        )?;
+    }
+    status.report(ServiceState::Running);
 
     // 进程守护未开启时：启动自启动配置后立即退出
     // frpc 进程会继续作为孤儿进程运行
     if !settings.process_guard {
-        log::info!("进程守护未开启，服务退出（已启动自启动配置）");
-        set_service_status(&status_handle, ServiceState::Stopped)?;
-        return Ok(());
+        return Ok(RunState::StopRequested {
+            reason: StopReason::ProcessGuardDisabled,
+            pool: None,
+        });
     }
 
-    // auto_start_map 共享给管道线程（TRACK 命令需要查找 exe/conf）
-    let auto_start_map = Arc::new(discover_auto_start_map());
+    // admin API 聚合代理（可选），随服务进程退出
+    if let Some(ref proxy) = settings.admin_proxy {
+        admin_proxy::start(proxy);
+    }
+
+    // 日志磁盘剩余空间检查，空间不足时清理旧日志、暂停文件日志
+    match config::logs_dir() {
+        Ok(logs_dir) => disk_guard::start(&settings.disk_guard, logs_dir),
+        Err(e) => log::warn!("无法获取日志目录，不启动磁盘空间检查: {:?}", e),
+    }
+
+    // Prometheus 指标导出（可选），每次抓取时从进程列表与健康状态生成
+    if let Some(ref metrics_settings) = settings.metrics {
+        let source = MetricsSource::new(&supervisor, status);
+        metrics::start(metrics_settings, move || source.collect());
+    }
 
     // 创建跨进程命名事件，UI 可通过信号通知服务
     let guard_event = create_named_event(&guard_event_name(), "进程守护")?;
     let process_changed_event = create_named_event(&process_changed_event_name(), "进程状态变更")?;
 
     // 通过命名管道接收 UI 的命令（STOP/START/CLEAR/TRACK）
-    let guard_stopped: Arc<Mutex<HashSet<String>>> = Arc::new(Mutex::new(HashSet::new()));
+    // 实例映射共享给管道线程（TRACK 命令需要查找 exe/conf），应用期望状态时替换
     start_guard_stopped_pipe(
-        Arc::clone(&guard_stopped),
-        Arc::clone(&processes),
-        Arc::clone(&auto_start_map),
+        Arc::clone(&supervisor.guard_stopped),
+        Arc::clone(&supervisor.processes),
+        Arc::clone(&supervisor.instances),
+        Arc::clone(&supervisor.monitor_paused),
+        MetricsSource::new(&supervisor, status),
     );
 
-    loop {
-        if SERVICE_STOP_REQUESTED.load(Ordering::SeqCst) {
-            log::info!("收到服务停止信号");
-            unsafe {
-                CloseHandle(guard_event);
-                CloseHandle(process_changed_event);
-            }
-            set_service_status(&status_handle, ServiceState::Stopped)?;
-            return Ok(());
+    // 信号文件：没有 SCM 权限的用户或脚本在 control/ 下创建文件请求 reload/apply/status
+    let signals = match config::control_dir() {
+        Ok(dir) => Some(SignalWatcher::new(dir)),
+        Err(e) => {
+            log::warn!("无法获取信号文件目录，不监听信号文件: {:?}", e);
+            None
+        }
+    };
+
+    Ok(RunState::Running {
+        pool: FrpcProcessPool {
+            settings,
+            supervisor,
+            guard_event,
+            process_changed_event,
+            signals,
+            instances_modified,
+        },
+    })
+}
+
+impl FrpcProcessPool {
+    /// Running：进程守护的一轮（最多等待 1 秒），返回下一阶段
+    fn supervise(mut self, status: &mut StatusReporter) -> RunState {
+        // 上一次状态上报失败时在此重试，不中断进程守护
+        status.retry();
+
+        if self.supervisor.stop_requested() {
+            let reason = STOP_CONTROL
+                .lock()
+                .unwrap()
+                .unwrap_or(StopReason::StopControl);
+            return RunState::StopRequested {
+                reason,
+                pool: Some(self),
+            };
         }
 
         // 使用命名事件等待 1 秒，替代 thread::sleep
         // - WAIT_OBJECT_0: guard_event 信号化（进程守护开关切换）
         // - WAIT_TIMEOUT: 超时，继续检查进程状态
-        let wait_result = unsafe { WaitForMultipleObjects(1, [guard_event].as_ptr(), 0, 1000) };
+        let wait_result =
+            unsafe { WaitForMultipleObjects(1, [self.guard_event].as_ptr(), 0, 1000) };
         match wait_result {
             WAIT_OBJECT_0 => {
-                settings.process_guard = !settings.process_guard;
+                self.settings.process_guard = !self.settings.process_guard;
                 log::info!(
                     "收到进程守护变更信号，process_guard={}",
-                    settings.process_guard
+                    self.settings.process_guard
                 );
-                if settings.process_guard {
-                    // 开启进程守护：清理已在守护关闭期间退出的进程
-                    // 只监控开启后存活的进程，避免重启之前已死的进程
-                    let mut proc_list = processes.lock().unwrap();
-                    let before = proc_list.len();
-                    proc_list.retain(|(_, proc)| FrpcProcess::is_pid_running(proc.pid()));
-                    let after = proc_list.len();
-                    if before != after {
-                        log::info!(
-                            "进程守护已开启，清理 {} 个已退出进程，当前跟踪 {} 个",
-                            before - after,
-                            after
-                        );
-                    } else {
-                        log::info!("进程守护已开启，当前跟踪 {} 个进程", after);
-                    }
-                } else {
+                if !self.settings.process_guard {
                     // 关闭进程守护：退出服务，frpc 进程继续作为孤儿进程运行
-                    log::info!("进程守护已关闭，服务退出");
-                    unsafe {
-                        CloseHandle(guard_event);
-                        CloseHandle(process_changed_event);
-                    }
-                    set_service_status(&status_handle, ServiceState::Stopped)?;
-                    return Ok(());
+                    return RunState::StopRequested {
+                        reason: StopReason::ProcessGuardTurnedOff,
+                        pool: Some(self),
+                    };
+                }
+                // 开启进程守护：清理已在守护关闭期间退出的进程
+                // 只监控开启后存活的进程，避免重启之前已死的进程
+                let mut proc_list = self.supervisor.processes.lock().unwrap();
+                let before = proc_list.len();
+                proc_list.retain(|(_, proc)| FrpcProcess::is_pid_running(proc.pid()));
+                let after = proc_list.len();
+                if before != after {
+                    log::info!(
+                        "进程守护已开启，清理 {} 个已退出进程，当前跟踪 {} 个",
+                        before - after,
+                        after
+                    );
+                } else {
+                    log::info!("进程守护已开启，当前跟踪 {} 个进程", after);
                 }
             }
             WAIT_TIMEOUT => {} // 超时，继续检查进程状态
@@ -609,97 +1649,798 @@ fn run_service() -> Result<()> {
             }
         }
 
-        // 进程守护开启：检查是否有进程退出并重启
-        // Phase 1: 检测已退出的进程，构建重启候选列表
-        let mut restart_list = Vec::new();
-        {
-            let gs = guard_stopped.lock().unwrap();
-            let mut proc_list = processes.lock().unwrap();
-            proc_list.retain(|(name, proc)| {
-                if FrpcProcess::is_pid_running(proc.pid()) {
-                    true
-                } else {
-                    if gs.contains(name) {
-                        log::info!("[{}] 进程已退出（UI 手动停止，不重启）", name);
-                    } else {
-                        // 暂不重启，等 grace period 后再确认
-                        log::info!("[{}] 进程已退出，等待确认后重启", name);
-                        restart_list.push(name.clone());
-                    }
-                    false
+        // 信号文件：与对应的 SCM 控制或管道命令走同一流程
+        if let Some(signals) = &mut self.signals {
+            for signal in signals.poll(Instant::now()) {
+                match signal {
+                    ControlSignal::Reload => RELOAD_REQUESTED.store(true, Ordering::SeqCst),
+                    ControlSignal::Apply => APPLY_REQUESTED.store(true, Ordering::SeqCst),
+                    ControlSignal::Status => snapshot::force_next_write(),
                 }
-            });
+            }
+        }
+
+        // 实例清单变化（界面修改实例设置等）时重新读取各实例的设置
+        let modified = instances_file_modified();
+        if modified != self.instances_modified {
+            self.instances_modified = modified;
+            self.supervisor
+                .set_instance_settings(load_instance_settings());
+        }
+
+        // 应用期望状态：重新发现实例并平滑应用增删改，服务全程保持 Running
+        let supervisor = &mut self.supervisor;
+        if APPLY_REQUESTED.swap(false, Ordering::SeqCst) {
+            logger::reload_instance_levels();
+            supervisor.set_instance_settings(load_instance_settings());
+            match discover_auto_start_map() {
+                Ok(desired) => apply_desired_state(supervisor, desired, ApplyMode::Graceful),
+                Err(e) => log::error!("发现自启动配置失败，本次不应用期望状态: {:?}", e),
+            }
         }
 
-        // Phase 2: 等待 500ms 给 STOP 命令到达的时间，然后重新检查 guard_stopped
-        if !restart_list.is_empty() {
-            std::thread::sleep(std::time::Duration::from_millis(500));
-            let gs = guard_stopped.lock().unwrap();
-            let mut proc_list = processes.lock().unwrap();
-            for name in &restart_list {
-                if gs.contains(name) {
-                    log::info!("[{}] 等待期间收到停止命令，取消重启", name);
+        // 重新加载设置与实例清单：只启动新增、停止移除的实例，已运行的实例不受影响
+        if RELOAD_REQUESTED.swap(false, Ordering::SeqCst) {
+            log::info!("收到 PARAMCHANGE 或 control/reload，重新加载设置与实例清单");
+            let mut reloaded = config::load_settings();
+            if reloaded.process_guard != self.settings.process_guard {
+                // 进程守护开关由界面通过事件切换（关闭时服务退出），重载时不改变
+                log::info!("进程守护开关需在界面中切换，本次重载保持不变");
+                reloaded.process_guard = self.settings.process_guard;
+            }
+            supervisor.panic_recovery = reloaded.monitor_panic_recovery;
+            warn_invalid_maintenance_windows(&reloaded);
+            supervisor.maintenance_windows = reloaded.maintenance_windows.clone();
+            supervisor
+                .path_check
+                .set_interval(Duration::from_secs(reloaded.path_check_interval_secs));
+            supervisor.set_circuit_breaker(reloaded.circuit_breaker.clone());
+            self.settings = reloaded;
+            logger::reload_instance_levels();
+            supervisor.set_instance_settings(load_instance_settings());
+            // 发现失败时不能当作实例全部移除，已运行的实例保持不变
+            match discover_auto_start_map() {
+                Ok(desired) => apply_desired_state(supervisor, desired, ApplyMode::AddRemoveOnly),
+                Err(e) => log::error!("发现自启动配置失败，本次不启动或停止任何实例: {:?}", e),
+            }
+        }
+
+        // 管道收到的分组命令
+        let group_commands = std::mem::take(&mut *GROUP_COMMANDS.lock().unwrap());
+        for command in group_commands {
+            if let Err(e) = run_group_command(&command, supervisor) {
+                log::error!("分组命令 {:?} 执行失败: {:?}", command, e);
+            }
+        }
+
+        // set-env 后重启正在运行的实例，使新的环境变量生效
+        let mut env_restarts = std::mem::take(&mut *ENV_RESTARTS.lock().unwrap());
+        env_restarts.dedup();
+        for name in env_restarts {
+            let entry = supervisor.instances.read().unwrap().get(&name).cloned();
+            let running = supervisor
+                .processes
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|(n, _)| n == &name);
+            match entry {
+                Some(entry) if running => {
+                    log::info!("[{}] 重启以应用新的环境变量", name);
+                    supervisor.rolling_restart(&name, &entry);
+                    signal_process_changed();
+                }
+                _ => log::info!("[{}] 未在运行，新的环境变量在下次启动时生效", name),
+            }
+        }
+
+        // 进程守护开启：检查是否有进程退出并重启（监控暂停时只更新状态快照）
+        supervisor.supervise_once();
+        let paused = supervisor.monitor_paused.load(Ordering::SeqCst);
+
+        // 状态快照（frpc 版本、配置哈希），内容变化时才写文件
+        snapshot::update(
+            &supervisor.processes.lock().unwrap(),
+            &supervisor.health.lock().unwrap(),
+            paused,
+            supervisor.circuit_breaker.status(),
+            status.consecutive_failures(),
+        );
+        RunState::Running { pool: self }
+    }
+
+    /// 系统关机或重启（含 Windows 更新触发的重启）时并行地正常停止所有 frpc 进程
+    ///
+    /// 平时服务停止后 frpc 继续作为孤儿进程运行，但关机时系统会直接结束它们；
+    /// 趁预关机阶段让 frpc 按各自的宽限时间退出，断开连接并写完日志
+    fn stop_for_shutdown(&mut self, status: &mut StatusReporter) {
+        status.report(ServiceState::StopPending);
+        self.supervisor.abort_restarts();
+        let stopped = std::mem::take(&mut *self.supervisor.processes.lock().unwrap());
+        log::info!("系统关机，正常停止 {} 个 frpc 进程", stopped.len());
+        let pending = PendingStops::new(&stopped);
+        let watchdog = stop_diagnostics::arm(
+            PRESHUTDOWN_TIMEOUT,
+            config::load_settings().stop_diagnostics_percent,
+            Arc::clone(&pending),
+        );
+        // 各批实例的宽限时间可能累加到接近预关机超时，期间持续上报进度
+        stop_service_and_wait(
+            &self.supervisor,
+            stopped,
+            Some(&pending),
+            Some(&status.handle),
+        );
+        if let Some(watchdog) = watchdog {
+            watchdog.finish();
+        }
+    }
+
+    /// StopRequested：关闭跨进程事件句柄；跟踪的 frpc 进程不停止，继续作为孤儿进程运行
+    fn close(self) {
+        // 蓝绿重启中尚未就绪的新进程不能留下，与旧进程同时作为孤儿进程运行
+        self.supervisor.abort_restarts();
+        unsafe {
+            CloseHandle(self.guard_event);
+            CloseHandle(self.process_changed_event);
+        }
+    }
+}
+
+/// 按依赖的逆序停止进程（见 [`FrpcSupervisor::stop_in_dependency_order`]）并等待所有实例退出；
+/// 在服务内调用时传入 `status_handle`，等待期间每 2 秒向 SCM 上报 StopPending 与递增的检查点，避免 SCM 判定停止超时
+fn stop_service_and_wait(
+    supervisor: &FrpcSupervisor,
+    processes: Vec<(String, FrpcProcess)>,
+    pending: Option<&Arc<PendingStops>>,
+    status_handle: Option<&ServiceStatusHandle>,
+) {
+    let _ticker = status_handle.map(|handle| CheckpointTicker::start(*handle));
+    supervisor.stop_in_dependency_order(processes, pending);
+}
+
+/// 服务退出前的收尾：记录停止原因、写入状态快照、排空输出泵、清除运行标记、刷新日志并上报 Stopped
+///
+/// 除系统关机外 frpc 进程不会被停止，继续作为孤儿进程运行
+fn finish_service(status: &mut StatusReporter, reason: StopReason) {
+    log::info!(
+        "服务停止，原因: {}（{}），{}",
+        reason.description(),
+        reason.code(),
+        if reason == StopReason::Shutdown {
+            "frpc 进程已停止"
+        } else {
+            "frpc 进程继续运行"
+        }
+    );
+    snapshot::record_stop(reason.code());
+    output_pump::shutdown();
+    run_marker::end();
+    // 上报 Stopped 后 SCM 可能随时结束进程：先写出重复汇总并落盘，避免丢失最后几行日志
+    log::logger().flush();
+    status.report_stopped();
+}
+
+/// 启动后运行中的实例数是否达到 `min_successful_instances`（不超过自启动实例数），未达到时返回错误，
+/// 服务以失败退出（未上报 Stopped，SCM 视为意外终止并执行恢复操作）；已启动的 frpc 进程继续运行，服务重启后按 PID 接管
+fn check_min_successful(started: usize, configured: usize, min: usize) -> Result<()> {
+    let required = min.min(configured);
+    if started < required {
+        anyhow::bail!(
+            "只有 {} 个 frpc 实例成功启动，少于 min_successful_instances 要求的 {} 个（共 {} 个自启动实例）",
+            started,
+            required,
+            configured
+        );
+    }
+    if started < configured {
+        log::warn!(
+            "{}/{} 个 frpc 实例成功启动，达到 min_successful_instances（{}），服务继续运行",
+            started,
+            configured,
+            min
+        );
+    }
+    Ok(())
+}
+
+/// 时间无效的维护窗口不生效，记录警告
+fn warn_invalid_maintenance_windows(settings: &config::AppSettings) {
+    for window in &settings.maintenance_windows {
+        if let Err(e) = window.bounds() {
+            log::warn!("{:#}，该维护窗口不生效", e);
+        }
+    }
+}
+
+/// 应用期望状态的方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ApplyMode {
+    /// `frpdesk apply`：变更的实例热重载或滚动重启
+    Graceful,
+    /// `sc control paramchange`：只启动新增、停止移除的实例，
+    /// 已运行实例的变更在其下次重启时生效
+    AddRemoveOnly,
+}
+
+/// 应用期望状态时对单个实例的操作
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ApplyAction {
+    /// 新增的实例：启动
+    Start(String),
+    /// 移除的实例：停止
+    Stop(String),
+    /// 仅配置变更且配置了 admin API：`frpc reload` 热重载，不中断已有连接
+    Reload(String),
+    /// 程序/路径变更或无法热重载：滚动重启
+    Restart(String),
+}
+
+/// 比较当前与期望的实例集合，生成操作列表（不执行）
+///
+/// - 期望中有、当前没有：Start
+/// - 当前有、期望中没有：正在运行时 Stop
+/// - 两者都有且正在运行：exe/配置路径变化或 frpc.exe 内容变化时 Restart；
+///   仅配置内容变化时，新旧配置都启用了 admin API 则 Reload，否则 Restart
+/// - 未在运行（手动停止或等待守护重启）的实例只更新映射，不做操作
+///
+/// 操作顺序：先停止移除的实例，再启动新增实例，然后热重载，最后逐个重启
+fn plan_desired_state(
+    current: &HashMap<String, (PathBuf, PathBuf)>,
+    desired: &HashMap<String, (PathBuf, PathBuf)>,
+    running: &[(String, FrpcProcess)],
+) -> Vec<ApplyAction> {
+    let mut stops = Vec::new();
+    let mut starts = Vec::new();
+    let mut reloads = Vec::new();
+    let mut restarts = Vec::new();
+
+    // HashMap 遍历顺序不固定，按名称排序使执行顺序稳定
+    let mut current_names: Vec<&String> = current.keys().collect();
+    current_names.sort();
+    let mut desired_names: Vec<&String> = desired.keys().collect();
+    desired_names.sort();
+
+    for name in current_names {
+        if !desired.contains_key(name) && running.iter().any(|(n, _)| n == name) {
+            stops.push(ApplyAction::Stop(name.clone()));
+        }
+    }
+    for name in desired_names {
+        let (exe, conf) = &desired[name];
+        if !current.contains_key(name) {
+            if !running.iter().any(|(n, _)| n == name) {
+                starts.push(ApplyAction::Start(name.clone()));
+            }
+            continue;
+        }
+        let Some((_, proc)) = running.iter().find(|(n, _)| n == name) else {
+            continue;
+        };
+        if &proc.exe_path != exe || &proc.config_path != conf {
+            restarts.push(ApplyAction::Restart(name.clone()));
+            continue;
+        }
+        // 从 PID 恢复跟踪的进程没有启动记录，无法判断是否变更
+        let Some(launch) = &proc.launch else {
+            log::info!("[{}] 缺少启动记录，无法判断配置是否变更，保持不变", name);
+            continue;
+        };
+        let exe_changed = fingerprint::file_sha256(exe).ok().as_ref() != Some(&launch.exe_sha256);
+        let conf_changed =
+            fingerprint::config_sha256(conf).ok().as_ref() != Some(&launch.config_sha256);
+        if exe_changed {
+            restarts.push(ApplyAction::Restart(name.clone()));
+        } else if conf_changed {
+            if probe::has_admin_api(conf) {
+                reloads.push(ApplyAction::Reload(name.clone()));
+            } else {
+                restarts.push(ApplyAction::Restart(name.clone()));
+            }
+        }
+    }
+
+    stops
+        .into_iter()
+        .chain(starts)
+        .chain(reloads)
+        .chain(restarts)
+        .collect()
+}
+
+/// 平滑应用期望的实例集合：能热重载的走 reload，必须重启的逐个滚动重启，
+/// 新增实例启动，移除实例停止，服务状态始终保持 Running
+fn apply_desired_state(
+    supervisor: &FrpcSupervisor,
+    desired: HashMap<String, (PathBuf, PathBuf)>,
+    mode: ApplyMode,
+) {
+    let (processes, health) = (&supervisor.processes, &supervisor.health);
+    let mut actions = {
+        let proc_list = processes.lock().unwrap();
+        plan_desired_state(&supervisor.instances.read().unwrap(), &desired, &proc_list)
+    };
+    if mode == ApplyMode::AddRemoveOnly {
+        actions.retain(|action| match action {
+            ApplyAction::Reload(name) | ApplyAction::Restart(name) => {
+                log::info!("[{}] 已变更，保持运行，下次重启时生效", name);
+                false
+            }
+            ApplyAction::Start(_) | ApplyAction::Stop(_) => true,
+        });
+    }
+    log::info!(
+        "应用期望状态：期望 {} 个实例，计划 {} 项操作 {:?}",
+        desired.len(),
+        actions.len(),
+        actions
+    );
+
+    for action in &actions {
+        if supervisor.stop_requested() {
+            log::info!("服务正在停止，中止应用期望状态");
+            return;
+        }
+        match action {
+            ApplyAction::Stop(name) => {
+                let removed = {
+                    let mut proc_list = processes.lock().unwrap();
+                    let pos = proc_list.iter().position(|(n, _)| n == name);
+                    pos.map(|i| proc_list.remove(i))
+                };
+                if let Some((_, mut proc)) = removed {
+                    if let Err(e) = proc.stop(supervisor.graceful_stop_timeout(name)) {
+                        log::error!("[{}] 停止已移除的实例失败: {:?}", name, e);
+                    }
+                }
+                health.lock().unwrap().remove(name);
+            }
+            ApplyAction::Start(name) => {
+                if supervisor.guard_stopped.lock().unwrap().contains(name) {
+                    log::info!("[{}] 在手动停止列表中，不启动", name);
                     continue;
                 }
-                if let Some((exe, conf)) = auto_start_map.get(name) {
-                    match FrpcProcess::start(name.clone(), exe.clone(), conf.clone(), None) {
-                        Ok(p) => {
-                            log::info!("[{}] 进程守护重启成功", name);
-                            proc_list.push((name.clone(), p));
-                        }
-                        Err(e) => log::error!("[{}] 进程守护重启失败: {:?}", name, e),
+                let (exe, conf) = &desired[name];
+                match supervisor.start_instance(name, exe, conf) {
+                    Ok(p) => {
+                        timeline::record(name, TimelineEvent::Start { pid: p.pid() });
+                        processes.lock().unwrap().push((name.clone(), p));
                     }
+                    Err(e) => log::error!("[{}] 启动新增实例失败: {:?}", name, e),
+                }
+            }
+            ApplyAction::Reload(name) | ApplyAction::Restart(name) => {
+                let entry = &desired[name];
+                let outcome = reload_or_restart(
+                    name,
+                    matches!(action, ApplyAction::Reload(_)),
+                    || FrpcProcess::verify_config(&entry.0, &entry.1),
+                    || reload_running(processes, name),
+                );
+                if outcome == ReloadOutcome::Restart {
+                    supervisor.rolling_restart(name, entry);
                 }
             }
-            // 通知 UI 更新界面显示
-            signal_process_changed();
         }
     }
+
+    *supervisor.instances.write().unwrap() = desired;
+    signal_process_changed();
+    log::info!("期望状态已应用，需要重启的实例在后台逐个滚动重启");
 }
 
-/// 启动所有自启动配置（跳过已运行的），返回进程列表
-fn start_auto_start_processes() -> Vec<(String, FrpcProcess)> {
-    let running_frpc = discover_running_frpc_processes();
-    let instances = match discover_auto_start_instances() {
-        Ok(v) => v,
+/// 变更实例的处理结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ReloadOutcome {
+    /// 新配置未通过校验：不重载也不重启，旧进程继续运行
+    Rejected,
+    /// 已热重载
+    Reloaded,
+    /// 需要滚动重启（计划如此，或热重载失败）
+    Restart,
+}
+
+/// 热重载/重启前先用 `verify`（`frpc verify`）校验新配置，未通过时记录错误，不调用 `reload`，也不重启；
+/// 校验通过后计划热重载（`try_reload`）的实例执行 `reload`，失败时改为重启
+///
+/// 实例映射仍会更新为新配置，旧进程的启动记录不变，下次 `apply` 会再次校验
+fn reload_or_restart(
+    name: &str,
+    try_reload: bool,
+    verify: impl FnOnce() -> Result<()>,
+    reload: impl FnOnce() -> Result<()>,
+) -> ReloadOutcome {
+    if let Err(e) = verify() {
+        log::error!(
+            "[{}] 新配置校验失败，不重载也不重启，旧进程继续运行: {:#}",
+            name,
+            e
+        );
+        return ReloadOutcome::Rejected;
+    }
+    if !try_reload {
+        return ReloadOutcome::Restart;
+    }
+    match reload() {
+        Ok(()) => ReloadOutcome::Reloaded,
         Err(e) => {
-            log::error!("发现自启动配置失败: {:?}", e);
-            return Vec::new();
+            log::warn!("[{}] 热重载失败，改为重启: {:?}", name, e);
+            ReloadOutcome::Restart
         }
+    }
+}
+
+/// 热重载正在运行的实例；未在运行时不做操作
+///
+/// frpc reload 是子进程调用，执行期间不持有进程列表锁，完成后按 PID 更新启动记录
+fn reload_running(processes: &Mutex<Vec<(String, FrpcProcess)>>, name: &str) -> Result<()> {
+    let target = processes
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|(n, _)| n == name)
+        .map(|(_, p)| (p.pid(), p.exe_path.clone(), p.config_path.clone()));
+    let Some((pid, exe, conf)) = target else {
+        return Ok(());
     };
-    let mut processes = Vec::new();
-    for (id, exe, conf) in instances {
-        if let Some((_, pid)) = running_frpc.iter().find(|(n, _)| n == &id) {
-            if FrpcProcess::is_pid_running(*pid) {
-                let process = FrpcProcess::from_pid(*pid, id.clone(), exe, conf);
-                log::info!("[{}] 检测到已运行的进程 (PID: {})", id, pid);
-                processes.push((id, process));
-                continue;
+    FrpcProcess::reload(name, &exe, &conf)?;
+    let mut proc_list = processes.lock().unwrap();
+    if let Some((_, proc)) = proc_list
+        .iter_mut()
+        .find(|(n, p)| n == name && p.pid() == pid)
+    {
+        proc.record_reload();
+    }
+    Ok(())
+}
+
+/// 执行分组命令，只作用于组内的自启动实例
+///
+/// - restart：逐个滚动重启（含已停止的实例）
+/// - start：启动组内未运行的实例
+/// - stop：停止组内实例并加入手动停止列表，进程守护不会重启
+fn run_group_command(command: &GroupCommand, supervisor: &FrpcSupervisor) -> Result<()> {
+    if !matches!(command.cmd.as_str(), "restart" | "start" | "stop") {
+        anyhow::bail!("未知的分组命令: {}（可选 restart/start/stop）", command.cmd);
+    }
+    // 每次执行时读取最新的分组成员，修改 metadata.json 后无需重启服务
+    let members = config::group_members(&command.group)?;
+    let map = supervisor.instances.read().unwrap().clone();
+    let (processes, guard_stopped) = (&supervisor.processes, &supervisor.guard_stopped);
+    log::info!(
+        "执行分组命令 {} [{}]，成员: {:?}",
+        command.cmd,
+        command.group,
+        members
+    );
+
+    for name in &members {
+        let Some(entry) = map.get(name) else {
+            log::info!("[{}] 不是自启动实例，跳过分组命令", name);
+            continue;
+        };
+        let running = processes.lock().unwrap().iter().any(|(n, _)| n == name);
+        match command.cmd.as_str() {
+            "restart" => {
+                guard_stopped.lock().unwrap().remove(name);
+                supervisor.rolling_restart(name, entry);
             }
-        }
-        match FrpcProcess::start(id.clone(), exe, conf, None) {
-            Ok(p) => {
-                log::info!("[{}] frpc 进程已启动", id);
-                processes.push((id, p));
+            "start" => {
+                guard_stopped.lock().unwrap().remove(name);
+                if !running {
+                    match supervisor.start_instance(name, &entry.0, &entry.1) {
+                        Ok(p) => {
+                            timeline::record(name, TimelineEvent::Start { pid: p.pid() });
+                            processes.lock().unwrap().push((name.clone(), p));
+                        }
+                        Err(e) => log::error!("[{}] 分组启动失败: {:?}", name, e),
+                    }
+                }
+            }
+            _ => {
+                guard_stopped.lock().unwrap().insert(name.clone());
+                let removed = {
+                    let mut proc_list = processes.lock().unwrap();
+                    let pos = proc_list.iter().position(|(n, _)| n == name);
+                    pos.map(|i| proc_list.remove(i))
+                };
+                if let Some((_, mut proc)) = removed {
+                    if let Err(e) = proc.stop(supervisor.graceful_stop_timeout(name)) {
+                        log::error!("[{}] 分组停止失败: {:?}", name, e);
+                    }
+                }
             }
-            Err(e) => log::error!("启动 frpc 实例失败: {:?}", e),
         }
     }
-    if processes.is_empty() {
-        log::warn!("没有任何 frpc 进程成功启动");
-    } else {
-        log::info!("成功启动 {} 个 frpc 实例", processes.len());
+    signal_process_changed();
+    Ok(())
+}
+
+/// 生成 Prometheus 指标所需的共享状态，指标线程与管道线程（GET_METRICS）各持有一份
+#[derive(Clone)]
+struct MetricsSource {
+    processes: Arc<Mutex<Vec<(String, FrpcProcess)>>>,
+    health: HealthMap,
+    /// 设置了自启动的实例名，实例设置变化时由进程守护更新，抓取时不读取 metadata.json
+    auto_start: Arc<RwLock<HashSet<String>>>,
+    total_restarts: Arc<AtomicU64>,
+    status_report_failures: Arc<AtomicU32>,
+}
+
+impl MetricsSource {
+    fn new(supervisor: &FrpcSupervisor, status: &StatusReporter) -> Self {
+        Self {
+            processes: Arc::clone(&supervisor.processes),
+            health: Arc::clone(&supervisor.health),
+            auto_start: Arc::clone(&supervisor.auto_start),
+            total_restarts: Arc::clone(&supervisor.total_restarts),
+            status_report_failures: status.failures(),
+        }
+    }
+
+    /// 按当前跟踪的进程与健康状态生成 Prometheus 指标
+    ///
+    /// 实例集合为健康状态中记录过的实例、当前跟踪的进程与自启动实例的并集。
+    /// 进程列表锁内只复制 PID 与输出行数，进程是否存活在锁外检查，不阻塞进程守护
+    fn collect(&self) -> ServiceMetrics {
+        // (名称, PID, 输出行数)；从 PID 恢复跟踪的进程没有输出管道，行数未知
+        let tracked: Vec<(String, u32, Option<u64>)> = self
+            .processes
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, p)| {
+                let lines = p.launch.is_some().then(|| p.log_line_count() as u64);
+                (name.clone(), p.pid(), lines)
+            })
+            .collect();
+        let running: Vec<(&String, Option<u64>)> = tracked
+            .iter()
+            .filter(|(_, pid, _)| FrpcProcess::is_pid_running(*pid))
+            .map(|(name, _, lines)| (name, *lines))
+            .collect();
+        let auto_start = self.auto_start.read().unwrap().clone();
+        let h = self.health.lock().unwrap();
+        let mut names: Vec<&String> = h.keys().collect();
+        for (name, _, _) in &tracked {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        // 从未启动成功的自启动实例也要计入健康检查
+        for name in &auto_start {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        let instances = names
+            .into_iter()
+            .map(|name| {
+                let process = running.iter().find(|(n, _)| *n == name);
+                let up = process.is_some();
+                let entry = h.get(name);
+                InstanceMetrics {
+                    instance: name.clone(),
+                    up,
+                    restarts_total: entry.map_or(0, |e| e.restarts_total),
+                    last_exit_code: entry.and_then(|e| e.last_exit_code),
+                    uptime_seconds: entry
+                        .and_then(|e| e.started_at)
+                        .filter(|_| up)
+                        .map_or(0, |t| t.elapsed().as_secs()),
+                    log_lines: process.and_then(|(_, lines)| *lines),
+                    auto_start: auto_start.contains(name),
+                    // 没有健康记录的进程（UI 通知跟踪的）没有启动探测
+                    ready: up && entry.is_none_or(|e| e.healthy),
+                    health: entry
+                        .and_then(|e| e.last_health_check.as_ref())
+                        .filter(|_| up)
+                        .map(|(_, status)| status.clone()),
+                }
+            })
+            .collect();
+        ServiceMetrics {
+            instances,
+            total_restarts: self.total_restarts.load(Ordering::SeqCst),
+            status_report_failures: self.status_report_failures.load(Ordering::SeqCst),
+        }
     }
-    processes
 }
 
-fn set_service_status(
-    handle: &windows_service::service_control_handler::ServiceStatusHandle,
+/// 向 SCM 上报服务状态，失败不终止服务
+///
+/// 状态句柄可能暂时失效（例如系统更新期间），若直接返回错误，服务会退出并中断正常运行的隧道。
+/// StartPending/Running 上报失败时记录日志，在下一轮循环重试；最终的 Stopped 上报失败直接忽略
+struct StatusReporter<S: StatusSink = ServiceStatusHandle> {
+    handle: S,
+    /// 最近一次期望上报的状态
     state: ServiceState,
-) -> Result<()> {
+    /// 上次上报失败，等待重试
+    pending: bool,
+    /// 连续上报失败次数，与指标导出线程共享
+    consecutive_failures: Arc<AtomicU32>,
+    /// 停止期间上报的检查点，0 表示上报的是普通状态
+    checkpoint: u32,
+}
+
+/// 服务状态的上报目标；正常运行时为 SCM 的状态句柄，可替换为其他实现以便验证上报失败时的行为
+trait StatusSink {
+    fn set_status(&self, state: ServiceState) -> Result<()>;
+
+    /// 上报 StopPending 与递增的检查点
+    fn set_stop_progress(&self, checkpoint: u32) -> Result<()>;
+}
+
+impl StatusSink for ServiceStatusHandle {
+    fn set_status(&self, state: ServiceState) -> Result<()> {
+        set_service_status(self, state)
+    }
+
+    fn set_stop_progress(&self, checkpoint: u32) -> Result<()> {
+        report_stop_progress(self, checkpoint)
+    }
+}
+
+impl<S: StatusSink> StatusReporter<S> {
+    fn new(handle: S) -> Self {
+        Self {
+            handle,
+            state: ServiceState::StartPending,
+            pending: false,
+            consecutive_failures: Arc::new(AtomicU32::new(0)),
+            checkpoint: 0,
+        }
+    }
+
+    /// 连续上报失败次数，写入状态快照
+    fn consecutive_failures(&self) -> u32 {
+        self.consecutive_failures.load(Ordering::SeqCst)
+    }
+
+    /// 连续上报失败次数的共享计数，供指标导出读取
+    fn failures(&self) -> Arc<AtomicU32> {
+        Arc::clone(&self.consecutive_failures)
+    }
+
+    /// 上报新状态，失败时留待 [`retry`](Self::retry) 重试
+    fn report(&mut self, state: ServiceState) {
+        self.state = state;
+        self.checkpoint = 0;
+        self.try_report();
+    }
+
+    /// 上报停止进度（StopPending 与检查点），失败时同样计数并留待重试
+    fn report_progress(&mut self, checkpoint: u32) {
+        self.state = ServiceState::StopPending;
+        self.checkpoint = checkpoint;
+        self.try_report();
+    }
+
+    /// 上次上报失败时重新上报
+    fn retry(&mut self) {
+        if self.pending {
+            self.try_report();
+        }
+    }
+
+    fn try_report(&mut self) {
+        let result = if self.checkpoint > 0 {
+            self.handle.set_stop_progress(self.checkpoint)
+        } else {
+            self.handle.set_status(self.state)
+        };
+        match result {
+            Ok(()) => {
+                let failures = self.consecutive_failures.swap(0, Ordering::SeqCst);
+                if failures > 0 {
+                    log::info!(
+                        "服务状态 {:?} 上报已恢复（此前连续失败 {} 次）",
+                        self.state,
+                        failures
+                    );
+                }
+                self.pending = false;
+            }
+            Err(e) => {
+                self.pending = true;
+                let failures = self.consecutive_failures.fetch_add(1, Ordering::SeqCst) + 1;
+                log::warn!(
+                    "服务状态 {:?} 上报失败（连续第 {} 次），下一轮重试，进程守护继续运行: {:?}",
+                    self.state,
+                    failures,
+                    e
+                );
+            }
+        }
+    }
+
+    /// 最终的 Stopped 上报，服务即将退出，失败时仅记录
+    fn report_stopped(&mut self) {
+        if let Err(e) = self.handle.set_status(ServiceState::Stopped) {
+            log::warn!(
+                "服务状态 Stopped 上报失败（此前连续失败 {} 次），忽略: {:?}",
+                self.consecutive_failures(),
+                e
+            );
+        }
+    }
+}
+
+/// StartPending 上报的等待提示：延迟自动启动时服务在开机负载较高时启动，
+/// 加载配置、检查权限可能较慢，避免 SCM 过早判定启动超时
+const START_WAIT_HINT: Duration = Duration::from_secs(30);
+
+/// 预关机超时：系统关机时 SCM 最多等待服务停止的时间，注册服务时写入。
+/// 关机时需在此时间内正常停止所有 frpc 进程（并行停止，取各实例宽限时间的最大值）
+const PRESHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// 停止期间上报检查点的间隔
+const STOP_CHECKPOINT_INTERVAL: Duration = Duration::from_secs(2);
+
+/// 停止期间在后台线程中按 [`STOP_CHECKPOINT_INTERVAL`] 上报 StopPending 与递增的检查点，
+/// 让 SCM 知道停止仍在推进；drop 时结束线程
+///
+/// 经由 [`StatusReporter`] 上报：失败时记录连续失败次数，下一个检查点即为重试
+struct CheckpointTicker {
+    stop: Option<Sender<()>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl CheckpointTicker {
+    fn start<S: StatusSink + Send + 'static>(handle: S) -> Self {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread = thread::Builder::new()
+            .name("stop-checkpoint".into())
+            .spawn(move || {
+                let mut status = StatusReporter::new(handle);
+                let mut checkpoint = 0u32;
+                // 发送端被 drop 时 recv_timeout 返回 Disconnected，结束上报
+                while let Err(mpsc::RecvTimeoutError::Timeout) =
+                    stopped.recv_timeout(STOP_CHECKPOINT_INTERVAL)
+                {
+                    checkpoint += 1;
+                    status.report_progress(checkpoint);
+                }
+            })
+            .map_err(|e| log::warn!("无法启动检查点上报线程: {}", e))
+            .ok();
+        CheckpointTicker {
+            stop: Some(stop),
+            thread,
+        }
+    }
+}
+
+impl Drop for CheckpointTicker {
+    fn drop(&mut self) {
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// 上报 StopPending 进度：检查点递增表示停止仍在推进，SCM 在等待提示内等待下一次上报
+fn report_stop_progress(handle: &ServiceStatusHandle, checkpoint: u32) -> Result<()> {
+    handle.set_service_status(ServiceStatus {
+        service_type: ServiceType::OWN_PROCESS,
+        current_state: ServiceState::StopPending,
+        controls_accepted: ServiceControlAccept::empty(),
+        exit_code: ServiceExitCode::Win32(0),
+        checkpoint,
+        wait_hint: STOP_CHECKPOINT_INTERVAL * 3,
+        process_id: None,
+    })?;
+    Ok(())
+}
+
+fn set_service_status(handle: &ServiceStatusHandle, state: ServiceState) -> Result<()> {
     let mut controls = ServiceControlAccept::empty();
     if state == ServiceState::Running {
-        controls = ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN;
+        controls = ServiceControlAccept::STOP
+            | ServiceControlAccept::SHUTDOWN
+            | ServiceControlAccept::PRESHUTDOWN
+            | ServiceControlAccept::PARAM_CHANGE;
     }
     handle.set_service_status(ServiceStatus {
         service_type: ServiceType::OWN_PROCESS,
@@ -707,71 +2448,347 @@ fn set_service_status(
         controls_accepted: controls,
         exit_code: ServiceExitCode::Win32(0),
         checkpoint: 0,
-        wait_hint: Duration::ZERO,
+        wait_hint: match state {
+            ServiceState::StartPending => START_WAIT_HINT,
+            ServiceState::StopPending => PRESHUTDOWN_TIMEOUT,
+            _ => Duration::ZERO,
+        },
         process_id: None,
     })?;
     Ok(())
 }
 
-fn discover_auto_start_instances() -> Result<Vec<(String, PathBuf, PathBuf)>> {
-    let frpc_exe = config::frpc_exe_path().context("无法获取 frpc.exe 路径")?;
-    if !frpc_exe.exists() {
-        return Ok(Vec::new());
+/// 发现到的实例
+#[derive(Debug, Serialize)]
+pub(crate) struct DiscoveredInstance {
+    pub identifier: String,
+    pub exe: PathBuf,
+    /// 配置文件；目录模式下为配置目录，整个目录由一个 frpc 进程运行
+    pub config: PathBuf,
+    /// 是否为目录模式（`config_dir`）的聚合实例
+    pub config_dir: bool,
+    /// 是否设置了自启动（服务只启动自启动的实例）
+    pub enabled: bool,
+    pub group: String,
+}
+
+/// 被跳过的候选配置及原因
+#[derive(Debug, Serialize)]
+pub(crate) struct SkippedCandidate {
+    pub identifier: String,
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+/// 实例发现结果，用于诊断"服务没有启动某个隧道"
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct DiscoveryReport {
+    pub instances: Vec<DiscoveredInstance>,
+    pub skipped: Vec<SkippedCandidate>,
+    pub duplicates: Vec<DuplicateConfig>,
+}
+
+/// 多个实例引用同一配置文件
+#[derive(Debug, Serialize)]
+pub(crate) struct DuplicateConfig {
+    pub config: PathBuf,
+    /// 最先引用该配置的实例
+    pub first: String,
+    pub duplicate: String,
+}
+
+/// 配置文件的比较键：优先使用规范化路径，失败时退回原始路径（Windows 路径不区分大小写）
+fn config_key(path: &Path) -> String {
+    std::fs::canonicalize(path)
+        .unwrap_or_else(|_| path.to_path_buf())
+        .to_string_lossy()
+        .to_lowercase()
+}
+
+/// 找出引用同一配置文件的实例（经不同的相对路径、大小写等指向同一文件也能识别）
+///
+/// 两个 frpc 使用同一配置会争抢同一组代理，frps 端不断重新注册
+fn find_duplicate_configs<'a>(
+    instances: impl IntoIterator<Item = (&'a str, &'a Path)>,
+) -> Vec<DuplicateConfig> {
+    let mut seen: HashMap<String, &'a str> = HashMap::new();
+    let mut duplicates = Vec::new();
+    for (identifier, config) in instances {
+        match seen.entry(config_key(config)) {
+            std::collections::hash_map::Entry::Occupied(first) => {
+                duplicates.push(DuplicateConfig {
+                    config: config.to_path_buf(),
+                    first: first.get().to_string(),
+                    duplicate: identifier.to_string(),
+                });
+            }
+            std::collections::hash_map::Entry::Vacant(slot) => {
+                slot.insert(identifier);
+            }
+        }
+    }
+    duplicates
+}
+
+fn warn_duplicate_config(dup: &DuplicateConfig, strict: bool) {
+    log::warn!(
+        "实例 {} 与 {} 引用同一配置文件 {:?}，两个 frpc 将争抢同一组代理{}",
+        dup.duplicate,
+        dup.first,
+        dup.config,
+        if strict {
+            "，已跳过后者（strict_unique_configs）"
+        } else {
+            "，请检查实例清单"
+        }
+    );
+}
+
+/// 检查自启动实例是否引用同一配置文件：记录警告，开启 `strict_unique_configs` 时只保留第一个
+fn check_unique_configs(instances: &mut Vec<(String, PathBuf, PathBuf)>) {
+    let duplicates = find_duplicate_configs(
+        instances
+            .iter()
+            .map(|(id, _, conf)| (id.as_str(), conf.as_path())),
+    );
+    if duplicates.is_empty() {
+        return;
+    }
+    let strict = config::load_settings().strict_unique_configs;
+    for dup in &duplicates {
+        warn_duplicate_config(dup, strict);
+    }
+    if strict {
+        instances.retain(|(id, _, _)| !duplicates.iter().any(|d| &d.duplicate == id));
+    }
+}
+
+/// 按实例清单发现所有实例（不启动任何进程），同时记录被跳过的候选及原因
+///
+/// - 清单中有记录但缺少 `<配置名>.toml`
+/// - 配置目录中有 `.toml` 但清单中没有记录
+/// - 未找到该实例使用的 frpc 可执行文件（默认 `bin/frpc.exe`，或配置中的 `exe`）
+/// - 目录模式（`config_dir`）的配置目录无效
+/// - 按 `only_on_hosts` / `exclude_hosts` 不在本机启用
+///
+/// 目录模式的实例作为一个聚合实例返回，`config` 为配置目录
+pub(crate) fn discover_instances() -> Result<DiscoveryReport> {
+    let configs = config::load_configs()?;
+    let host = HostNames::local();
+    let mut report = DiscoveryReport::default();
+
+    for meta in &configs {
+        let conf = meta.config_path()?;
+        let frpc_exe = meta.exe_path().context("无法获取 frpc.exe 路径")?;
+        if let Some(reason) = host_filter::disabled_reason(meta, &host) {
+            report.skipped.push(SkippedCandidate {
+                identifier: meta.name.clone(),
+                path: conf,
+                reason,
+            });
+        } else if !conf.exists() {
+            let reason = if meta.config_dir.is_some() {
+                "实例清单中有记录，但 config_dir 指定的配置目录不存在"
+            } else {
+                "实例清单中有记录，但配置文件不存在"
+            };
+            report.skipped.push(SkippedCandidate {
+                identifier: meta.name.clone(),
+                path: conf,
+                reason: reason.to_string(),
+            });
+        } else if let Some(reason) = config_dir_problem(meta, &conf) {
+            report.skipped.push(SkippedCandidate {
+                identifier: meta.name.clone(),
+                path: conf,
+                reason,
+            });
+        } else if !frpc_exe.exists() {
+            let reason = if meta.exe.is_some() {
+                "未找到配置中 exe 指定的 frpc 可执行文件".to_string()
+            } else {
+                "未找到 frpc.exe，请先在设置页面下载".to_string()
+            };
+            report.skipped.push(SkippedCandidate {
+                identifier: meta.name.clone(),
+                path: frpc_exe,
+                reason,
+            });
+        } else {
+            report.instances.push(DiscoveredInstance {
+                identifier: meta.name.clone(),
+                exe: frpc_exe,
+                config: conf,
+                config_dir: meta.config_dir.is_some(),
+                enabled: meta.auto_start,
+                group: meta.group.clone(),
+            });
+        }
+    }
+
+    // 配置目录中未登记到清单的 toml 文件
+    if let Ok(entries) = std::fs::read_dir(config::instances_dir()?) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("toml") {
+                continue;
+            }
+            let Some(stem) = path.file_stem().and_then(|s| s.to_str()) else {
+                continue;
+            };
+            if !configs.iter().any(|c| c.name == stem) {
+                report.skipped.push(SkippedCandidate {
+                    identifier: stem.to_string(),
+                    path,
+                    reason: "配置文件未登记到实例清单（metadata.json）".to_string(),
+                });
+            }
+        }
+    }
+
+    report.duplicates = find_duplicate_configs(
+        report
+            .instances
+            .iter()
+            .map(|i| (i.identifier.as_str(), i.config.as_path())),
+    );
+    if config::load_settings().strict_unique_configs {
+        for dup in &report.duplicates {
+            if let Some(pos) = report
+                .instances
+                .iter()
+                .position(|i| i.identifier == dup.duplicate)
+            {
+                let inst = report.instances.remove(pos);
+                report.skipped.push(SkippedCandidate {
+                    identifier: inst.identifier,
+                    path: inst.config,
+                    reason: format!("与 {} 引用同一配置文件（strict_unique_configs）", dup.first),
+                });
+            }
+        }
     }
+
+    Ok(report)
+}
+
+pub(crate) fn discover_auto_start_instances() -> Result<Vec<(String, PathBuf, PathBuf)>> {
+    let host = HostNames::local();
     let mut instances = Vec::new();
     for meta in config::get_auto_start_configs().unwrap_or_default() {
-        let conf = config::config_toml_path(&meta.name)?;
+        if let Some(reason) = host_filter::disabled_reason(&meta, &host) {
+            log::info!("[{}] {}，本机不启用", meta.name, reason);
+            continue;
+        }
+        let conf = meta.config_path()?;
+        if let Some(reason) = conf
+            .exists()
+            .then(|| config_dir_problem(&meta, &conf))
+            .flatten()
+        {
+            log::warn!("[{}] {}，跳过", meta.name, reason);
+            continue;
+        }
+        let frpc_exe = meta.exe_path().context("无法获取 frpc.exe 路径")?;
+        if !frpc_exe.exists() {
+            log::warn!(
+                "[{}] 未找到 frpc 可执行文件 {:?}，跳过",
+                meta.name,
+                frpc_exe
+            );
+            continue;
+        }
         if conf.exists() {
-            instances.push((meta.name.clone(), frpc_exe.clone(), conf));
+            instances.push((meta.name.clone(), frpc_exe, conf));
         }
     }
+    check_unique_configs(&mut instances);
     Ok(instances)
 }
 
-/// 发现自启动配置，返回 name -> (exe, conf) 的映射
-fn discover_auto_start_map() -> std::collections::HashMap<String, (PathBuf, PathBuf)> {
-    let mut map = std::collections::HashMap::new();
-    let frpc_exe = match config::frpc_exe_path() {
-        Ok(p) if p.exists() => p,
-        _ => return map,
-    };
-    for meta in config::get_auto_start_configs().unwrap_or_default() {
-        if let Ok(conf) = config::config_toml_path(&meta.name) {
-            if conf.exists() {
-                map.insert(meta.name.clone(), (frpc_exe.clone(), conf));
-            }
+/// 目录模式的配置目录无法使用时返回原因（不是目录、没有配置文件，或就是实例清单所在目录）
+fn config_dir_problem(meta: &config::FrpcConfigMeta, conf: &Path) -> Option<String> {
+    meta.config_dir.as_ref()?;
+    if !conf.is_dir() {
+        return Some("config_dir 指定的路径不是目录".to_string());
+    }
+    // 清单所在目录中是其他实例的 <name>.toml，整体交给一个 frpc 会重复运行它们
+    let instances_dir = config::instances_dir().ok()?;
+    if let (Ok(a), Ok(b)) = (conf.canonicalize(), instances_dir.canonicalize()) {
+        if a == b {
+            return Some("config_dir 不能是实例清单所在目录".to_string());
+        }
+    }
+    match config::config_dir_files(conf) {
+        Ok(files) if files.is_empty() => {
+            Some("config_dir 指定的配置目录中没有配置文件".to_string())
         }
+        Ok(_) => None,
+        Err(e) => Some(format!("{:#}", e)),
     }
-    map
+}
+
+/// 发现自启动配置，返回 name -> (exe, conf) 的映射
+///
+/// 发现失败时返回错误而不是空映射：空映射会被当作“所有实例已移除”而停止全部实例
+fn discover_auto_start_map() -> Result<HashMap<String, (PathBuf, PathBuf)>> {
+    Ok(discover_auto_start_instances()?
+        .into_iter()
+        .map(|(name, exe, conf)| (name, (exe, conf)))
+        .collect())
+}
+
+/// 读取实例清单中各实例的设置（按名称），无法读取时为空
+fn load_instance_settings() -> HashMap<String, FrpcConfigMeta> {
+    supervisor::instance_settings_by_name(config::load_configs().unwrap_or_else(|e| {
+        log::warn!("无法读取实例设置: {:?}", e);
+        Vec::new()
+    }))
+}
+
+/// 实例清单的修改时间，文件不存在或无法读取时为 None
+fn instances_file_modified() -> Option<SystemTime> {
+    let path = config::metadata_path().ok()?;
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
 }
 
 /// 发现当前正在运行的 frpc 进程，匹配到已有配置
 ///
 /// 返回 (配置名, PID) 的列表。优先使用 wmic（快速），失败则回退到 PowerShell。
+/// 按各配置使用的可执行文件名查询（默认 frpc.exe，配置了 `exe` 时包括其文件名）
 pub fn discover_running_frpc_processes() -> Vec<(String, u32)> {
     use std::os::windows::process::CommandExt;
     const CREATE_NO_WINDOW: u32 = 0x08000000;
 
-    let frpc_exe = match config::frpc_exe_path() {
-        Ok(p) => p,
-        Err(_) => return Vec::new(),
-    };
-    if !frpc_exe.exists() {
+    let configs = config::load_configs().unwrap_or_default();
+    if configs.is_empty() {
         return Vec::new();
     }
 
-    let configs = config::load_configs().unwrap_or_default();
-    if configs.is_empty() {
+    // 进程名条件，如 Name='frpc.exe' OR Name='frpc-0.52.exe'（单引号转义，避免拼坏 WQL）
+    let mut exe_names: Vec<String> = configs
+        .iter()
+        .filter_map(|meta| meta.exe_path().ok())
+        .filter(|exe| exe.exists())
+        .filter_map(|exe| exe.file_name().map(|n| n.to_string_lossy().into_owned()))
+        .collect();
+    exe_names.sort_by_key(|n| n.to_lowercase());
+    exe_names.dedup_by_key(|n| n.to_lowercase());
+    if exe_names.is_empty() {
         return Vec::new();
     }
-    let conf_dir = config::conf_dir().unwrap_or_default();
+    let name_filter = exe_names
+        .iter()
+        .map(|n| format!("Name='{}'", n.replace('\'', "\\'")))
+        .collect::<Vec<_>>()
+        .join(" OR ");
 
     // 尝试 wmic（快速），失败或无输出则回退到 PowerShell
     let stdout = match std::process::Command::new("wmic")
         .args([
             "process",
             "where",
-            "name='frpc.exe'",
+            name_filter.as_str(),
             "get",
             "ProcessId,CommandLine",
             "/FORMAT:CSV",
@@ -782,9 +2799,12 @@ pub fn discover_running_frpc_processes() -> Vec<(String, u32)> {
         Ok(o) if !o.stdout.is_empty() => String::from_utf8_lossy(&o.stdout).into_owned(),
         _ => {
             log::debug!("wmic 不可用或无输出，尝试 PowerShell");
+            let script = format!(
+                "Get-CimInstance Win32_Process -Filter \"{}\" | Select-Object ProcessId,CommandLine | ConvertTo-CSV -NoTypeInformation",
+                name_filter
+            );
             match std::process::Command::new("powershell")
-                .args(["-NoProfile", "-NonInteractive", "-Command",
-                    "Get-CimInstance Win32_Process -Filter \"Name='frpc.exe'\" | Select-Object ProcessId,CommandLine | ConvertTo-CSV -NoTypeInformation"])
+                .args(["-NoProfile", "-NonInteractive", "-Command", script.as_str()])
                 .creation_flags(CREATE_NO_WINDOW)
                 .output()
             {
@@ -839,7 +2859,7 @@ pub fn discover_running_frpc_processes() -> Vec<(String, u32)> {
 
         // 匹配配置
         for config_meta in &configs {
-            let config_path = conf_dir.join(format!("{}.toml", config_meta.name));
+            let config_path = config_meta.config_path().unwrap_or_default();
             let config_path_str = config_path.to_string_lossy();
             if cmd_line.contains(&*config_path_str) {
                 result.push((config_meta.name.clone(), pid));
@@ -869,3 +2889,485 @@ fn parse_csv_line(line: &str) -> Vec<String> {
     fields.push(current.trim().to_string());
     fields
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn min_successful_met_exactly() {
+        assert!(check_min_successful(2, 3, 2).is_ok());
+    }
+
+    #[test]
+    fn min_successful_not_met() {
+        assert!(check_min_successful(1, 3, 2).is_err());
+    }
+
+    #[test]
+    fn min_successful_capped_by_configured_instances() {
+        assert!(check_min_successful(1, 1, 3).is_ok());
+        assert!(check_min_successful(0, 0, 1).is_ok());
+    }
+
+    #[test]
+    fn min_successful_default_keeps_running_with_no_instances() {
+        let min = config::AppSettings::default().min_successful_instances;
+        assert!(check_min_successful(0, 3, min).is_ok());
+    }
+
+    #[test]
+    fn split_command_line_handles_quotes_and_backslashes() {
+        let cases: &[(&str, &[&str])] = &[
+            ("", &[]),
+            ("   ", &[]),
+            (
+                r"C:\frpdesk\frpdesk.exe --service",
+                &[r"C:\frpdesk\frpdesk.exe", "--service"],
+            ),
+            (
+                r#""C:\Program Files\frpdesk\frpdesk.exe" --service"#,
+                &[r"C:\Program Files\frpdesk\frpdesk.exe", "--service"],
+            ),
+            // 路径末尾的反斜杠不转义结尾的引号
+            (
+                r#""C:\Program Files\" --instances-file "D:\conf dir\metadata.json""#,
+                &[
+                    r"C:\Program Files\",
+                    "--instances-file",
+                    r"D:\conf dir\metadata.json",
+                ],
+            ),
+            // 引号可出现在参数中间，多个空白只作一次分隔
+            (r#"a"b c"d    e"#, &["ab cd", "e"]),
+            // 空的引号是一个空参数
+            (r#"a "" b"#, &["a", "", "b"]),
+            ("a\tb", &["a", "b"]),
+        ];
+        for (command, expected) in cases {
+            assert_eq!(split_command_line(command), *expected, "{}", command);
+        }
+    }
+
+    const VIRTUAL: &str = r"NT SERVICE\FrpcService";
+
+    fn expected_setup() -> ServiceSetup {
+        ServiceSetup {
+            command: vec![
+                r"C:\Program Files\frpdesk\frpdesk.exe".to_string(),
+                SERVICE_ARG.to_string(),
+            ],
+            account: ServiceAccount::LocalSystem,
+            start_mode: ServiceStartMode::Auto,
+            config_dir: None,
+        }
+    }
+
+    fn installed_service() -> InstalledService {
+        InstalledService {
+            executable_path: format!(r#""C:\Program Files\frpdesk\frpdesk.exe" {}"#, SERVICE_ARG),
+            account_name: Some("LocalSystem".to_string()),
+            start_type: ServiceStartType::AutoStart,
+            delayed_auto_start: false,
+            config_dir: None,
+        }
+    }
+
+    fn differences(installed: InstalledService, expected: ServiceSetup) -> Vec<String> {
+        setup_differences(&installed, &expected, VIRTUAL)
+    }
+
+    #[test]
+    fn identical_setup_has_no_differences() {
+        assert!(differences(installed_service(), expected_setup()).is_empty());
+        // 路径与数据目录不区分大小写，LocalSystem 有多种写法
+        for account in [None, Some(r"NT AUTHORITY\SYSTEM"), Some(r".\localsystem")] {
+            let installed = InstalledService {
+                executable_path: format!(
+                    r#""c:\program files\FRPDESK\frpdesk.exe" {}"#,
+                    SERVICE_ARG
+                ),
+                account_name: account.map(str::to_string),
+                config_dir: Some(r"D:\Data".to_string()),
+                ..installed_service()
+            };
+            let expected = ServiceSetup {
+                config_dir: Some(r"d:\data".to_string()),
+                ..expected_setup()
+            };
+            assert!(differences(installed, expected).is_empty(), "{:?}", account);
+        }
+    }
+
+    #[test]
+    fn command_line_difference() {
+        let installed = InstalledService {
+            executable_path: r"C:\old\frpdesk.exe --service".to_string(),
+            ..installed_service()
+        };
+        let found = differences(installed, expected_setup());
+        assert_eq!(found.len(), 1);
+        assert!(found[0].starts_with("启动命令行"), "{:?}", found);
+
+        // 多出的参数（如 --instances-file）同样算作不同
+        let expected = ServiceSetup {
+            command: [
+                expected_setup().command,
+                vec!["--instances-file".into(), r"D:\m.json".into()],
+            ]
+            .concat(),
+            ..expected_setup()
+        };
+        assert_eq!(differences(installed_service(), expected).len(), 1);
+    }
+
+    #[test]
+    fn account_difference() {
+        let expected = ServiceSetup {
+            account: ServiceAccount::Virtual,
+            ..expected_setup()
+        };
+        let found = differences(installed_service(), expected);
+        assert_eq!(found, vec!["账户: LocalSystem → Virtual".to_string()]);
+
+        let installed = InstalledService {
+            account_name: Some(VIRTUAL.to_lowercase()),
+            ..installed_service()
+        };
+        let expected = ServiceSetup {
+            account: ServiceAccount::Virtual,
+            ..expected_setup()
+        };
+        assert!(differences(installed, expected).is_empty());
+
+        // 其他账户与两者都不同
+        let installed = InstalledService {
+            account_name: Some(r".\frpuser".to_string()),
+            ..installed_service()
+        };
+        assert_eq!(differences(installed, expected_setup()).len(), 1);
+    }
+
+    #[test]
+    fn start_type_difference() {
+        let delayed = InstalledService {
+            delayed_auto_start: true,
+            ..installed_service()
+        };
+        assert_eq!(
+            differences(delayed, expected_setup()),
+            vec!["启动类型: DelayedAuto → Auto".to_string()]
+        );
+        let manual = InstalledService {
+            start_type: ServiceStartType::OnDemand,
+            ..installed_service()
+        };
+        let expected = ServiceSetup {
+            start_mode: ServiceStartMode::Manual,
+            ..expected_setup()
+        };
+        assert!(differences(manual, expected).is_empty());
+        let disabled = InstalledService {
+            start_type: ServiceStartType::Disabled,
+            ..installed_service()
+        };
+        assert_eq!(
+            differences(disabled, expected_setup()),
+            vec!["启动类型: Disabled → Auto".to_string()]
+        );
+    }
+
+    #[test]
+    fn config_dir_difference() {
+        let expected = ServiceSetup {
+            config_dir: Some(r"D:\frp".to_string()),
+            ..expected_setup()
+        };
+        assert_eq!(
+            differences(installed_service(), expected),
+            vec![format!(r"{}: （未设置） → D:\frp", config::CONFIG_DIR_ENV)]
+        );
+    }
+
+    #[test]
+    fn aliased_config_paths_are_duplicates() {
+        let dir = std::env::temp_dir().join(format!("frpdesk-dup-configs-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("web.toml"), "").unwrap();
+        std::fs::write(dir.join("ssh.toml"), "").unwrap();
+
+        let web = dir.join("web.toml");
+        let via_parent = dir.join("sub").join("..").join("web.toml");
+        let upper_case = dir.join("WEB.TOML");
+        let ssh = dir.join("ssh.toml");
+        let duplicates = find_duplicate_configs([
+            ("web", web.as_path()),
+            ("ssh", ssh.as_path()),
+            ("web-copy", via_parent.as_path()),
+            ("web-upper", upper_case.as_path()),
+        ]);
+        let pairs: Vec<_> = duplicates
+            .iter()
+            .map(|d| (d.first.as_str(), d.duplicate.as_str()))
+            .collect();
+        assert_eq!(pairs, [("web", "web-copy"), ("web", "web-upper")]);
+        assert_eq!(duplicates[0].config, via_parent);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    /// 按预设结果依次上报的状态目标，记录每次上报的状态
+    struct FlakySink {
+        results: std::cell::RefCell<std::collections::VecDeque<bool>>,
+        reported: std::cell::RefCell<Vec<ServiceState>>,
+        checkpoints: std::cell::RefCell<Vec<u32>>,
+    }
+
+    impl FlakySink {
+        fn new(results: &[bool]) -> Self {
+            Self {
+                results: std::cell::RefCell::new(results.iter().copied().collect()),
+                reported: std::cell::RefCell::new(Vec::new()),
+                checkpoints: std::cell::RefCell::new(Vec::new()),
+            }
+        }
+    }
+
+    impl StatusSink for FlakySink {
+        fn set_status(&self, state: ServiceState) -> Result<()> {
+            self.reported.borrow_mut().push(state);
+            match self.results.borrow_mut().pop_front() {
+                Some(false) => anyhow::bail!("状态句柄无效"),
+                _ => Ok(()),
+            }
+        }
+
+        fn set_stop_progress(&self, checkpoint: u32) -> Result<()> {
+            self.checkpoints.borrow_mut().push(checkpoint);
+            self.set_status(ServiceState::StopPending)
+        }
+    }
+
+    #[test]
+    fn failed_status_reports_are_retried() {
+        let mut status = StatusReporter::new(FlakySink::new(&[true, false, false, true]));
+        status.report(ServiceState::StartPending);
+        // 成功后不重复上报
+        status.retry();
+        status.report(ServiceState::Running);
+        assert!(status.pending);
+        status.retry();
+        assert_eq!(status.consecutive_failures(), 2);
+        status.retry();
+        assert!(!status.pending);
+        assert_eq!(status.consecutive_failures(), 0);
+        status.retry();
+        assert_eq!(
+            *status.handle.reported.borrow(),
+            [
+                ServiceState::StartPending,
+                ServiceState::Running,
+                ServiceState::Running,
+                ServiceState::Running
+            ]
+        );
+    }
+
+    #[test]
+    fn newer_state_replaces_pending_report() {
+        let mut status = StatusReporter::new(FlakySink::new(&[false, false, false]));
+        status.report(ServiceState::StartPending);
+        status.report(ServiceState::Running);
+        status.retry();
+        assert_eq!(status.consecutive_failures(), 3);
+        assert_eq!(
+            status.handle.reported.borrow().last(),
+            Some(&ServiceState::Running)
+        );
+        // 最终的 Stopped 上报失败也不报错，只上报一次
+        let mut stopping = StatusReporter::new(FlakySink::new(&[false]));
+        stopping.report_stopped();
+        assert_eq!(*stopping.handle.reported.borrow(), [ServiceState::Stopped]);
+    }
+
+    #[test]
+    fn failed_stop_progress_is_counted_and_retried() {
+        let mut status = StatusReporter::new(FlakySink::new(&[true, false, false]));
+        status.report(ServiceState::StopPending);
+        status.report_progress(1);
+        assert_eq!(status.consecutive_failures(), 1);
+        // 重试时重发同一检查点
+        status.retry();
+        status.report_progress(2);
+        assert!(!status.pending);
+        assert_eq!(status.consecutive_failures(), 0);
+        assert_eq!(*status.handle.checkpoints.borrow(), [1, 1, 2]);
+        // 之后的普通状态上报不带检查点
+        status.report(ServiceState::Stopped);
+        assert_eq!(status.handle.checkpoints.borrow().len(), 3);
+    }
+
+    /// 期望状态测试用的临时目录：frpc.exe 与两份配置（web 启用 admin API，ssh 未启用）
+    fn plan_fixture(test: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("frpdesk-plan-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("frpc.exe"), "frpc 0.61").unwrap();
+        std::fs::write(dir.join("web.toml"), "[webServer]\nport = 7400\n").unwrap();
+        std::fs::write(dir.join("ssh.toml"), "serverPort = 7000\n").unwrap();
+        dir
+    }
+
+    fn entry(dir: &Path, conf: &str) -> (PathBuf, PathBuf) {
+        (dir.join("frpc.exe"), dir.join(conf))
+    }
+
+    /// 按文件当前内容记录启动哈希的运行中实例
+    fn launched(name: &str, (exe, conf): &(PathBuf, PathBuf)) -> (String, FrpcProcess) {
+        let mut proc = FrpcProcess::from_pid(0, name.to_string(), exe.clone(), conf.clone());
+        proc.launch = Some(frpc_mg::LaunchRecord {
+            argv: Vec::new(),
+            env_keys: Vec::new(),
+            exe_sha256: fingerprint::file_sha256(exe).unwrap(),
+            config_sha256: fingerprint::config_sha256(conf).unwrap(),
+            frpc_version: None,
+            working_dir: None,
+            command_line: String::new(),
+        });
+        (name.to_string(), proc)
+    }
+
+    fn map(entries: &[(&str, &(PathBuf, PathBuf))]) -> HashMap<String, (PathBuf, PathBuf)> {
+        entries
+            .iter()
+            .map(|(name, entry)| (name.to_string(), (*entry).clone()))
+            .collect()
+    }
+
+    #[test]
+    fn plan_starts_added_and_stops_removed_running_instances() {
+        let dir = plan_fixture("add-remove");
+        let (web, ssh) = (entry(&dir, "web.toml"), entry(&dir, "ssh.toml"));
+        let current = map(&[("web", &web), ("ssh", &ssh), ("idle", &ssh)]);
+        let desired = map(&[("web", &web), ("new", &ssh)]);
+        // idle 已移除但未在运行，不需要停止
+        let running = [launched("web", &web), launched("ssh", &ssh)];
+        assert_eq!(
+            plan_desired_state(&current, &desired, &running),
+            [
+                ApplyAction::Stop("ssh".into()),
+                ApplyAction::Start("new".into())
+            ]
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn plan_leaves_unchanged_and_stopped_instances_alone() {
+        let dir = plan_fixture("unchanged");
+        let (web, ssh) = (entry(&dir, "web.toml"), entry(&dir, "ssh.toml"));
+        let current = map(&[("web", &web), ("ssh", &ssh), ("recovered", &ssh)]);
+        let desired = current.clone();
+        let mut recovered = launched("recovered", &ssh);
+        recovered.1.launch = None;
+        // ssh 未在运行（手动停止或等待重启），即使配置变化也只更新映射
+        std::fs::write(dir.join("ssh.toml"), "serverPort = 17001\n").unwrap();
+        let running = [launched("web", &web), recovered];
+        assert!(plan_desired_state(&current, &desired, &running).is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn plan_restarts_on_exe_change_and_reloads_config_with_admin_api() {
+        let dir = plan_fixture("changed");
+        let (web, ssh) = (entry(&dir, "web.toml"), entry(&dir, "ssh.toml"));
+        let moved = (dir.join("frpc-new.exe"), dir.join("ssh.toml"));
+        std::fs::write(&moved.0, "frpc 0.62").unwrap();
+        let current = map(&[("web", &web), ("ssh", &ssh), ("moved", &ssh)]);
+        let desired = map(&[("web", &web), ("ssh", &ssh), ("moved", &moved)]);
+        let running = [
+            launched("web", &web),
+            launched("ssh", &ssh),
+            launched("moved", &ssh),
+        ];
+        std::fs::write(dir.join("web.toml"), "[webServer]\nport = 17401\n").unwrap();
+        std::fs::write(dir.join("ssh.toml"), "serverPort = 17001\n").unwrap();
+        // 配置变更：web 启用了 admin API 可热重载，ssh 只能重启；moved 的 exe 路径变化需要重启
+        assert_eq!(
+            plan_desired_state(&current, &desired, &running),
+            [
+                ApplyAction::Reload("web".into()),
+                ApplyAction::Restart("moved".into()),
+                ApplyAction::Restart("ssh".into())
+            ]
+        );
+
+        // frpc.exe 内容变化时即使配置可热重载也要重启
+        let running = [launched("web", &web)];
+        std::fs::write(dir.join("frpc.exe"), "frpc 0.62.1").unwrap();
+        assert_eq!(
+            plan_desired_state(&map(&[("web", &web)]), &map(&[("web", &web)]), &running),
+            [ApplyAction::Restart("web".into())]
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn plan_orders_stops_starts_reloads_then_restarts() {
+        let dir = plan_fixture("mixed");
+        let (web, ssh) = (entry(&dir, "web.toml"), entry(&dir, "ssh.toml"));
+        let current = map(&[("a-web", &web), ("b-ssh", &ssh), ("z-old", &ssh)]);
+        let desired = map(&[("a-web", &web), ("b-ssh", &ssh), ("c-new", &web)]);
+        let running = [
+            launched("b-ssh", &ssh),
+            launched("a-web", &web),
+            launched("z-old", &ssh),
+        ];
+        std::fs::write(dir.join("web.toml"), "[webServer]\nport = 17401\n").unwrap();
+        std::fs::write(dir.join("ssh.toml"), "serverPort = 17001\n").unwrap();
+        assert_eq!(
+            plan_desired_state(&current, &desired, &running),
+            [
+                ApplyAction::Stop("z-old".into()),
+                ApplyAction::Start("c-new".into()),
+                ApplyAction::Reload("a-web".into()),
+                ApplyAction::Restart("b-ssh".into())
+            ]
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn failed_verify_neither_reloads_nor_restarts() {
+        for try_reload in [true, false] {
+            let reloaded = std::cell::Cell::new(false);
+            let outcome = reload_or_restart(
+                "web",
+                try_reload,
+                || anyhow::bail!("配置无效"),
+                || {
+                    reloaded.set(true);
+                    Ok(())
+                },
+            );
+            assert_eq!(outcome, ReloadOutcome::Rejected);
+            assert!(!reloaded.get());
+        }
+    }
+
+    #[test]
+    fn verified_config_reloads_or_falls_back_to_restart() {
+        assert_eq!(
+            reload_or_restart("web", true, || Ok(()), || Ok(())),
+            ReloadOutcome::Reloaded
+        );
+        assert_eq!(
+            reload_or_restart("web", true, || Ok(()), || anyhow::bail!("admin API 不可用")),
+            ReloadOutcome::Restart
+        );
+        // 计划重启的实例不尝试热重载
+        let outcome = reload_or_restart("web", false, || Ok(()), || panic!("不应热重载"));
+        assert_eq!(outcome, ReloadOutcome::Restart);
+    }
+}
@@ -0,0 +1,180 @@
+//! 信号文件：在数据目录的 control/ 下创建约定名称的文件即可请求服务执行动作，不需要 SCM 权限
+//!
+//! 服务每轮检查扫描一次目录。识别的文件在受理时删除，删除即表示服务已收到；
+//! 未知的文件名记录一次警告后忽略，不删除。谁能发出请求由 control/ 目录的 ACL 决定；
+//! 命名管道上的同类命令要求管理员，信号文件用于把这些动作委派给单独授权的用户。
+//! 同一动作距上次执行不足 [`DEBOUNCE`] 时文件保留到间隔结束再受理，期间重复创建的文件自然合并为一次
+
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// 同一动作两次执行的最小间隔
+const DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// 信号文件对应的动作
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) enum ControlSignal {
+    /// `reload`：重新加载设置与实例清单，与 `sc control <服务名> paramchange` 相同
+    Reload,
+    /// `apply`：应用期望状态，与 `frpdesk apply` 相同
+    Apply,
+    /// `status`：立即重写 logs/status.json（即使内容没有变化）
+    Status,
+}
+
+impl ControlSignal {
+    const ALL: [ControlSignal; 3] = [
+        ControlSignal::Reload,
+        ControlSignal::Apply,
+        ControlSignal::Status,
+    ];
+
+    fn file_name(self) -> &'static str {
+        match self {
+            ControlSignal::Reload => "reload",
+            ControlSignal::Apply => "apply",
+            ControlSignal::Status => "status",
+        }
+    }
+
+    /// 按文件名识别，不区分大小写
+    fn from_file_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|s| s.file_name().eq_ignore_ascii_case(name))
+    }
+}
+
+/// control/ 目录的扫描状态
+pub(crate) struct SignalWatcher {
+    dir: PathBuf,
+    /// 各动作上次受理的时间
+    last_run: HashMap<ControlSignal, Instant>,
+    /// 已警告过的未知文件名
+    warned: HashSet<String>,
+}
+
+impl SignalWatcher {
+    /// 监听 `dir`，目录不存在时创建（新目录继承上级目录的 ACL）
+    pub(crate) fn new(dir: PathBuf) -> Self {
+        if let Err(e) = std::fs::create_dir_all(&dir) {
+            log::warn!("无法创建信号文件目录 {:?}: {}", dir, e);
+        }
+        Self {
+            dir,
+            last_run: HashMap::new(),
+            warned: HashSet::new(),
+        }
+    }
+
+    /// 扫描一次，返回本轮受理的动作（已删除对应文件）
+    pub(crate) fn poll(&mut self, now: Instant) -> Vec<ControlSignal> {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        let mut found: Vec<(ControlSignal, PathBuf)> = Vec::new();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if !path.is_file() {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().into_owned();
+            match ControlSignal::from_file_name(&name) {
+                Some(signal) => found.push((signal, path)),
+                None => {
+                    if self.warned.insert(name.clone()) {
+                        log::warn!("忽略未知的信号文件: {:?}", path);
+                    }
+                }
+            }
+        }
+        found.sort();
+
+        let mut accepted = Vec::new();
+        for (signal, path) in found {
+            if accepted.contains(&signal) {
+                continue;
+            }
+            if self
+                .last_run
+                .get(&signal)
+                .is_some_and(|&at| now.duration_since(at) < DEBOUNCE)
+            {
+                continue;
+            }
+            // 写入方可能还没关闭文件，删除失败时下一轮重试，避免同一请求执行两次
+            if let Err(e) = std::fs::remove_file(&path) {
+                log::debug!("信号文件 {:?} 暂时无法删除，下一轮重试: {}", path, e);
+                continue;
+            }
+            log::info!("收到信号文件 control/{}", signal.file_name());
+            self.last_run.insert(signal, now);
+            accepted.push(signal);
+        }
+        accepted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn watcher(name: &str) -> SignalWatcher {
+        let dir =
+            std::env::temp_dir().join(format!("frpdesk-signals-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        SignalWatcher::new(dir)
+    }
+
+    fn touch(watcher: &SignalWatcher, name: &str) {
+        fs::write(watcher.dir.join(name), "").unwrap();
+    }
+
+    #[test]
+    fn known_files_are_accepted_and_removed() {
+        let mut watcher = watcher("known");
+        touch(&watcher, "status");
+        touch(&watcher, "RELOAD");
+        touch(&watcher, "Apply");
+        touch(&watcher, "restart-all");
+        fs::create_dir(watcher.dir.join("apply.d")).unwrap();
+
+        let accepted = watcher.poll(Instant::now());
+        assert_eq!(
+            accepted,
+            [
+                ControlSignal::Reload,
+                ControlSignal::Apply,
+                ControlSignal::Status
+            ]
+        );
+        // 未知文件与目录保留
+        let mut left: Vec<_> = fs::read_dir(&watcher.dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        left.sort();
+        assert_eq!(left, ["apply.d", "restart-all"]);
+        assert!(watcher.warned.contains("restart-all"));
+        let _ = fs::remove_dir_all(&watcher.dir);
+    }
+
+    #[test]
+    fn repeated_signals_are_debounced() {
+        let mut watcher = watcher("debounce");
+        let start = Instant::now();
+        touch(&watcher, "reload");
+        assert_eq!(watcher.poll(start), [ControlSignal::Reload]);
+
+        // 间隔内再次创建的文件保留到间隔结束再受理
+        touch(&watcher, "reload");
+        assert!(watcher.poll(start + Duration::from_secs(1)).is_empty());
+        assert!(watcher.dir.join("reload").exists());
+        assert_eq!(watcher.poll(start + DEBOUNCE), [ControlSignal::Reload]);
+        assert!(!watcher.dir.join("reload").exists());
+        assert!(watcher.poll(start + DEBOUNCE * 2).is_empty());
+        let _ = fs::remove_dir_all(&watcher.dir);
+    }
+}
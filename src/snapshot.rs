@@ -0,0 +1,274 @@
+//! 状态快照：服务运行期间将各实例的状态写入 logs/status.json
+//!
+//! 记录每个实例启动时使用的 frpc 版本、frpc.exe 与配置文件的哈希，以及配置文件当前的哈希。
+//! 两个配置哈希不一致说明配置已修改但运行中的进程尚未重载/重启。
+
+use anyhow::{Context, Result};
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use crate::config;
+use crate::fingerprint;
+use crate::frpc_mg::FrpcProcess;
+use crate::metrics::{InstanceMetrics, ServiceMetrics};
+use crate::supervisor::InstanceHealth;
+
+/// 单个实例的状态
+#[derive(Debug, Serialize, PartialEq)]
+pub struct InstanceStatus {
+    pub identifier: String,
+    pub pid: u32,
+    pub config: PathBuf,
+    /// 启动时的 frpc 版本（从 PID 恢复跟踪的进程为 None，下同）
+    pub frpc_version: Option<String>,
+    pub exe_sha256: Option<String>,
+    /// 启动时配置文件的哈希
+    pub config_sha256: Option<String>,
+    /// 配置文件当前的哈希
+    pub current_config_sha256: Option<String>,
+    /// 配置文件在启动后被修改，尚未生效
+    pub config_changed: bool,
+    /// 本次服务运行期间进程守护重启该实例的次数
+    pub restarts_total: u64,
+    /// 最近一次退出的退出码（尚未退出过或无法获取时为 None）
+    pub last_exit_code: Option<i32>,
+}
+
+/// 全局熔断状态，熔断期间写入快照
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct CircuitBreakerStatus {
+    /// 熔断开始时间
+    pub opened_at: String,
+    /// 触发熔断时统计窗口内的崩溃次数
+    pub crashes: usize,
+    /// 最早恢复时间，此后探测 frps 成功即恢复
+    pub probe_after: String,
+}
+
+#[derive(Debug, Serialize)]
+struct StatusSnapshot<'a> {
+    updated_at: String,
+    service_pid: u32,
+    /// 监控已暂停（`frpdesk pause-monitor`）：进程继续运行，但退出后不会被重启
+    monitor_paused: bool,
+    /// 全局熔断中：暂停所有重启，恢复后统一拉起未运行的实例
+    #[serde(skip_serializing_if = "Option::is_none")]
+    circuit_breaker: Option<&'a CircuitBreakerStatus>,
+    /// 向 SCM 上报服务状态连续失败的次数，上报成功后归零
+    status_report_failures: u32,
+    /// 服务停止时写入的停止原因（如 `stop_control`、`shutdown`），运行期间不输出
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_stop_reason: Option<&'a str>,
+    instances: &'a [InstanceStatus],
+}
+
+/// 上次写入的（监控暂停状态, 熔断状态, 状态上报失败次数, 实例状态），未变化时不重复写文件
+type WrittenState = (bool, Option<CircuitBreakerStatus>, u32, Vec<InstanceStatus>);
+
+static LAST_WRITTEN: Mutex<WrittenState> = Mutex::new((false, None, 0, Vec::new()));
+
+/// 下次 [`update`] 即使内容未变化也写文件（信号文件 `control/status`）
+static FORCE_WRITE: AtomicBool = AtomicBool::new(false);
+
+/// 快照文件名（位于 logs/）
+pub(crate) const SNAPSHOT_FILE: &str = "status.json";
+
+/// 写入快照时使用的临时文件名，写入中断时可能遗留
+pub(crate) const SNAPSHOT_TEMP_FILE: &str = "status.json.tmp";
+
+/// 快照文件路径: logs/status.json
+fn status_path() -> Result<PathBuf> {
+    Ok(config::logs_dir()?.join(SNAPSHOT_FILE))
+}
+
+fn instance_status(
+    name: &str,
+    proc: &FrpcProcess,
+    health: Option<&InstanceHealth>,
+) -> InstanceStatus {
+    let current_config_sha256 = fingerprint::config_sha256(&proc.config_path).ok();
+    let launch = proc.launch.as_ref();
+    let config_sha256 = launch.map(|l| l.config_sha256.clone());
+    InstanceStatus {
+        identifier: name.to_string(),
+        pid: proc.pid(),
+        config: proc.config_path.clone(),
+        frpc_version: launch.and_then(|l| l.frpc_version.clone()),
+        exe_sha256: launch.map(|l| l.exe_sha256.clone()),
+        config_changed: config_sha256.is_some() && config_sha256 != current_config_sha256,
+        config_sha256,
+        current_config_sha256,
+        restarts_total: health.map_or(0, |h| h.restarts_total),
+        last_exit_code: health.and_then(|h| h.last_exit_code),
+    }
+}
+
+/// 按当前跟踪的进程、健康状态、监控暂停、熔断状态与状态上报失败次数更新状态快照，内容未变化时跳过
+pub fn update(
+    processes: &[(String, FrpcProcess)],
+    health: &HashMap<String, InstanceHealth>,
+    monitor_paused: bool,
+    circuit_breaker: Option<&CircuitBreakerStatus>,
+    status_report_failures: u32,
+) {
+    let instances: Vec<InstanceStatus> = processes
+        .iter()
+        .map(|(name, proc)| instance_status(name, proc, health.get(name)))
+        .collect();
+    let mut last = LAST_WRITTEN.lock().unwrap();
+    if !FORCE_WRITE.swap(false, Ordering::SeqCst)
+        && last.0 == monitor_paused
+        && last.1.as_ref() == circuit_breaker
+        && last.2 == status_report_failures
+        && last.3 == instances
+        && status_path().is_ok_and(|p| p.exists())
+    {
+        return;
+    }
+    for inst in &instances {
+        let was_changed = last
+            .3
+            .iter()
+            .any(|l| l.identifier == inst.identifier && l.config_changed);
+        if inst.config_changed && !was_changed {
+            log::warn!(
+                "[{}] 配置文件已修改但尚未生效（启动时 {}，当前 {}），可执行 frpdesk apply 应用",
+                inst.identifier,
+                inst.config_sha256.as_deref().unwrap_or("-"),
+                inst.current_config_sha256.as_deref().unwrap_or("-")
+            );
+        }
+    }
+    if let Err(e) = write_snapshot(
+        &instances,
+        monitor_paused,
+        circuit_breaker,
+        status_report_failures,
+        None,
+    ) {
+        log::error!("写入状态快照失败: {:?}", e);
+        return;
+    }
+    *last = (
+        monitor_paused,
+        circuit_breaker.cloned(),
+        status_report_failures,
+        instances,
+    );
+}
+
+/// 丢弃上次会话的状态快照：上次会话未正常结束时其内容不可信，本次运行重新生成
+pub fn reset() {
+    let Ok(path) = status_path() else {
+        return;
+    };
+    for file in [path.with_file_name(SNAPSHOT_TEMP_FILE), path] {
+        match fs::remove_file(&file) {
+            Ok(()) => log::info!("已重置上次会话的状态快照: {:?}", file),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => log::warn!("重置状态快照失败 {:?}: {}", file, e),
+        }
+    }
+    *LAST_WRITTEN.lock().unwrap() = (false, None, 0, Vec::new());
+}
+
+/// 请求下次更新时重写快照
+pub fn force_next_write() {
+    FORCE_WRITE.store(true, Ordering::SeqCst);
+}
+
+/// 服务停止时写入停止原因；实例列表沿用最后一次写入的内容（frpc 进程在服务停止后继续运行）
+pub fn record_stop(reason: &str) {
+    let last = LAST_WRITTEN.lock().unwrap();
+    if let Err(e) = write_snapshot(&last.3, last.0, last.1.as_ref(), last.2, Some(reason)) {
+        log::error!("写入停止原因到状态快照失败: {:?}", e);
+    }
+}
+
+fn write_snapshot(
+    instances: &[InstanceStatus],
+    monitor_paused: bool,
+    circuit_breaker: Option<&CircuitBreakerStatus>,
+    status_report_failures: u32,
+    last_stop_reason: Option<&str>,
+) -> Result<()> {
+    let path = status_path()?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("无法创建日志目录")?;
+    }
+    let snapshot = StatusSnapshot {
+        updated_at: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        service_pid: std::process::id(),
+        monitor_paused,
+        circuit_breaker,
+        status_report_failures,
+        last_stop_reason,
+        instances,
+    };
+    let content = serde_json::to_string_pretty(&snapshot).context("无法序列化状态快照")?;
+    // 先写临时文件再替换，读取方不会看到写了一半的内容
+    let tmp = path.with_file_name(SNAPSHOT_TEMP_FILE);
+    fs::write(&tmp, content).context("无法写入状态快照")?;
+    fs::rename(&tmp, &path).context("无法替换状态快照")?;
+    Ok(())
+}
+
+/// 从快照文件读取指标时使用的字段（旧版本写入的快照没有重启次数与退出码）
+#[derive(Deserialize)]
+struct SavedSnapshot {
+    updated_at: String,
+    #[serde(default)]
+    status_report_failures: u32,
+    #[serde(default)]
+    instances: Vec<SavedInstance>,
+}
+
+#[derive(Deserialize)]
+struct SavedInstance {
+    identifier: String,
+    pid: u32,
+    #[serde(default)]
+    restarts_total: u64,
+    #[serde(default)]
+    last_exit_code: Option<i32>,
+}
+
+/// 服务未运行时从快照文件还原指标，同时返回快照的写入时间
+///
+/// 实例是否运行按记录的 PID 判断，重启次数与退出码为服务最后一次写入的值，运行时长无法得知（为 0）
+pub fn load_metrics() -> Result<(ServiceMetrics, String)> {
+    let path = status_path()?;
+    let content =
+        fs::read_to_string(&path).context(format!("无法读取状态快照 {}", path.display()))?;
+    let saved: SavedSnapshot =
+        serde_json::from_str(&content).context(format!("状态快照 {} 无效", path.display()))?;
+    let instances: Vec<InstanceMetrics> = saved
+        .instances
+        .into_iter()
+        .map(|inst| InstanceMetrics {
+            up: FrpcProcess::is_pid_running(inst.pid),
+            instance: inst.identifier,
+            restarts_total: inst.restarts_total,
+            last_exit_code: inst.last_exit_code,
+            uptime_seconds: 0,
+            log_lines: None,
+            auto_start: false,
+            ready: false,
+            health: None,
+        })
+        .collect();
+    let total_restarts = instances.iter().map(|i| i.restarts_total).sum();
+    Ok((
+        ServiceMetrics {
+            instances,
+            total_restarts,
+            status_report_failures: saved.status_report_failures,
+        },
+        saved.updated_at,
+    ))
+}
@@ -0,0 +1,172 @@
+//! 停止超时前的临界诊断：系统关机时正常停止 frpc 的耗时达到预关机时限的一定比例
+//! （settings.json 的 `stop_diagnostics_percent`）仍未完成时，记录一次尚未退出的实例、PID 与最近的输出
+//!
+//! 诊断写入 logs/stop-diagnostics-<时间>.json，摘要写入主日志与 Windows 事件日志。诊断在独立线程中进行，
+//! 不阻塞停止流程；停止完成时若诊断正在写入，最多再等待 [`WRITE_TIMEOUT`]
+
+use anyhow::{Context, Result};
+use chrono::Local;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config;
+use crate::eventlog;
+use crate::frpc_mg::FrpcProcess;
+use crate::output_pump::OutputSubscribers;
+
+/// 停止完成后等待正在写入的诊断的最长时间
+const WRITE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// 停止过程中尚未退出的实例，各停止线程在实例退出后移除
+pub(crate) struct PendingStops(Mutex<BTreeMap<String, PendingInstance>>);
+
+struct PendingInstance {
+    pid: u32,
+    /// 从 PID 恢复跟踪的进程没有输出
+    output: Option<Arc<OutputSubscribers>>,
+}
+
+impl PendingStops {
+    pub(crate) fn new(processes: &[(String, FrpcProcess)]) -> Arc<Self> {
+        Arc::new(PendingStops(Mutex::new(
+            processes
+                .iter()
+                .map(|(name, proc)| {
+                    let instance = PendingInstance {
+                        pid: proc.pid(),
+                        output: proc.output_subscribers(),
+                    };
+                    (name.clone(), instance)
+                })
+                .collect(),
+        )))
+    }
+
+    /// 实例已停止（正常退出或被强制终止）
+    pub(crate) fn done(&self, name: &str) {
+        self.0.lock().unwrap().remove(name);
+    }
+}
+
+/// logs/stop-diagnostics-<时间>.json 的内容
+#[derive(Debug, Serialize)]
+struct StopDiagnostics {
+    time: String,
+    /// 开始停止以来的时间
+    elapsed_ms: u64,
+    /// 预关机时限
+    budget_ms: u64,
+    pending: Vec<PendingReport>,
+}
+
+#[derive(Debug, Serialize)]
+struct PendingReport {
+    instance: String,
+    pid: u32,
+    recent_output: Vec<String>,
+}
+
+/// 已设定的诊断，停止完成后调用 [`Watchdog::finish`]
+pub(crate) struct Watchdog {
+    disarm: Sender<()>,
+    /// 诊断写完时收到消息；未触发时发送端随线程退出而断开
+    done: Receiver<()>,
+}
+
+/// 从现在起经过 `budget` 的 `percent`% 后，若停止仍未完成则记录诊断；`percent` 为 0 时不设定
+pub(crate) fn arm(budget: Duration, percent: u8, pending: Arc<PendingStops>) -> Option<Watchdog> {
+    if percent == 0 {
+        return None;
+    }
+    let delay = budget * u32::from(percent.min(100)) / 100;
+    let started = Instant::now();
+    let (disarm_tx, disarm_rx) = mpsc::channel();
+    let (done_tx, done_rx) = mpsc::channel();
+    let spawned = thread::Builder::new()
+        .name("stop-diagnostics".to_string())
+        .spawn(move || {
+            if disarm_rx.recv_timeout(delay) != Err(RecvTimeoutError::Timeout) {
+                return;
+            }
+            report(&pending, started.elapsed(), budget);
+            let _ = done_tx.send(());
+        });
+    match spawned {
+        Ok(_) => Some(Watchdog {
+            disarm: disarm_tx,
+            done: done_rx,
+        }),
+        Err(e) => {
+            log::warn!("无法创建停止诊断线程，本次不记录诊断: {}", e);
+            None
+        }
+    }
+}
+
+impl Watchdog {
+    /// 停止已完成：尚未触发的诊断取消，正在写入的最多等待 [`WRITE_TIMEOUT`]
+    pub(crate) fn finish(self) {
+        let _ = self.disarm.send(());
+        let _ = self.done.recv_timeout(WRITE_TIMEOUT);
+    }
+}
+
+fn report(pending: &PendingStops, elapsed: Duration, budget: Duration) {
+    let instances: Vec<PendingReport> = pending
+        .0
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(name, instance)| PendingReport {
+            instance: name.clone(),
+            pid: instance.pid,
+            recent_output: instance
+                .output
+                .as_ref()
+                .map_or_else(Vec::new, |output| output.recent_lines()),
+        })
+        .collect();
+    if instances.is_empty() {
+        return;
+    }
+    let mut summary = format!(
+        "系统关机停止 frpc 已用时 {:.1} 秒（预关机时限 {} 秒），仍有 {} 个实例未退出: {}",
+        elapsed.as_secs_f64(),
+        budget.as_secs(),
+        instances.len(),
+        instances
+            .iter()
+            .map(|i| format!("{}（PID {}）", i.instance, i.pid))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    let diagnostics = StopDiagnostics {
+        time: Local::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, false),
+        elapsed_ms: elapsed.as_millis() as u64,
+        budget_ms: budget.as_millis() as u64,
+        pending: instances,
+    };
+    match write(&diagnostics) {
+        Ok(path) => summary.push_str(&format!("，诊断已写入 {}", path.display())),
+        Err(e) => log::warn!("写入停止诊断失败: {:#}", e),
+    }
+    log::error!("{}", summary);
+    eventlog::report_error(&summary);
+    // 随后可能被 SCM 强制结束，立即落盘
+    log::logger().flush();
+}
+
+fn write(diagnostics: &StopDiagnostics) -> Result<PathBuf> {
+    let path = config::logs_dir()?.join(format!(
+        "stop-diagnostics-{}.json",
+        Local::now().format("%Y%m%d-%H%M%S")
+    ));
+    let content = serde_json::to_vec_pretty(diagnostics)?;
+    std::fs::write(&path, content).context(format!("无法写入 {:?}", path))?;
+    Ok(path)
+}
@@ -0,0 +1,1092 @@
+//! 可嵌入的进程守护：在其他 Rust 程序中启动、监控并停止 frpc 实例，不依赖 SCM
+//!
+//! 守护的实例及其设置（启动探测、健康检查、告警、依赖等）由 [`SupervisorConfig`] 提供，不要求在实例清单中登记；
+//! [`SupervisorConfig::discover`] 与服务一样从实例清单（程序目录的 conf/ 或 `--instances-file`）读取。
+//! 服务模式在它之上另外处理 SCM 控制、界面命名管道与 `apply` 等命令。
+//! 库不会初始化全局 logger，日志输出由调用方决定（未设置 logger 时日志被丢弃）
+//!
+//! ```no_run
+//! use std::sync::mpsc;
+//! use frpdesk::{FrpcSupervisor, SupervisorConfig};
+//!
+//! let (shutdown_tx, shutdown_rx) = mpsc::channel();
+//! let supervisor = FrpcSupervisor::new(SupervisorConfig::discover()?);
+//! let worker = std::thread::spawn(move || supervisor.run(shutdown_rx));
+//! // ...
+//! shutdown_tx.send(()).ok();
+//! worker.join().unwrap()?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use anyhow::Result;
+use std::collections::{HashMap, HashSet};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, TryRecvError};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::Local;
+
+use crate::alert::{self, AlertEvent};
+use crate::circuit_breaker::CircuitBreaker;
+use crate::config::{
+    self, CircuitBreakerSettings, FrpcConfigMeta, HealthCheckConfig, MaintenanceWindow,
+    OutputAction, RestartStrategy,
+};
+use crate::etw::{self, EtwEvent};
+use crate::exe_check;
+use crate::frpc_mg::{self, FrpcProcess};
+use crate::health_check::{HealthStatus, HealthTarget};
+use crate::output_pump;
+use crate::path_check::{self, PathCheck};
+use crate::probe;
+use crate::restart::{RestartQueue, RestartSteps};
+use crate::service;
+use crate::stop_diagnostics::PendingStops;
+use crate::timeline::{self, TimelineEvent};
+
+/// 实例映射：名称 -> (frpc 可执行文件, 配置文件)
+pub(crate) type InstanceMap = Arc<RwLock<HashMap<String, (PathBuf, PathBuf)>>>;
+
+/// 进程守护的配置
+#[derive(Debug, Clone)]
+pub struct SupervisorConfig {
+    /// 要守护的实例：名称 -> 实例
+    pub instances: HashMap<String, InstanceConfig>,
+    /// 两轮检查之间的间隔
+    pub poll_interval: Duration,
+    /// 单轮检查发生 panic 时恢复并在下一轮继续，为 false 时 panic 向上传播
+    pub panic_recovery: bool,
+    /// [`FrpcSupervisor::run`] 收到停止信号后是否停止所有 frpc 进程
+    pub stop_processes_on_shutdown: bool,
+    /// 维护时间窗口：窗口内实例退出只记录，不重启、不告警；窗口结束后拉起未运行的实例
+    pub maintenance_windows: Vec<MaintenanceWindow>,
+    /// 全局熔断：短时间内崩溃过多时暂停全部重启，None 表示不启用
+    pub circuit_breaker: Option<CircuitBreakerSettings>,
+    /// 检查运行中实例的程序与配置文件是否仍存在的间隔，为 0 时不检查
+    pub path_check_interval: Duration,
+}
+
+/// 一个受守护的实例
+#[derive(Debug, Clone, Default)]
+pub struct InstanceConfig {
+    /// frpc 可执行文件
+    pub exe: PathBuf,
+    /// 配置文件；目录模式下为配置目录
+    pub config: PathBuf,
+    /// 实例设置：启动探测、就绪与输出规则、健康检查、告警、停止宽限时间、依赖、重启策略等，
+    /// 字段与实例清单中的条目相同（名称与路径字段不使用）
+    pub settings: FrpcConfigMeta,
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> Self {
+        Self {
+            instances: HashMap::new(),
+            poll_interval: Duration::from_secs(1),
+            panic_recovery: true,
+            stop_processes_on_shutdown: true,
+            maintenance_windows: Vec::new(),
+            circuit_breaker: None,
+            path_check_interval: Duration::from_secs(60),
+        }
+    }
+}
+
+impl SupervisorConfig {
+    /// 与服务启动时相同：实例清单中所有设置了自启动、且程序与配置文件存在的实例，设置取自实例清单
+    pub fn discover() -> Result<Self> {
+        let mut settings = instance_settings_by_name(config::load_configs().unwrap_or_default());
+        let instances = service::discover_auto_start_instances()?
+            .into_iter()
+            .map(|(name, exe, config)| {
+                let settings = settings.remove(&name).unwrap_or_default();
+                (
+                    name,
+                    InstanceConfig {
+                        exe,
+                        config,
+                        settings,
+                    },
+                )
+            })
+            .collect();
+        Ok(Self {
+            instances,
+            ..Self::default()
+        })
+    }
+}
+
+/// 按名称索引实例设置
+pub(crate) fn instance_settings_by_name(
+    configs: Vec<FrpcConfigMeta>,
+) -> HashMap<String, FrpcConfigMeta> {
+    configs
+        .into_iter()
+        .map(|meta| (meta.name.clone(), meta))
+        .collect()
+}
+
+/// 单个实例的健康状态
+#[derive(Debug, Default)]
+pub(crate) struct InstanceHealth {
+    /// 启动探测已通过（未配置探测的实例启动即视为通过）
+    pub(crate) healthy: bool,
+    /// 进程守护连续重启次数，启动探测通过后清零
+    consecutive_restarts: u32,
+    /// 本次运行期间进程守护重启的次数（不清零）
+    pub(crate) restarts_total: u64,
+    /// 最近一次退出的退出码（无法获取时为 None）
+    pub(crate) last_exit_code: Option<i32>,
+    /// 当前进程的启动时间（从 PID 恢复跟踪的进程为开始跟踪的时间），已退出时为 None
+    pub(crate) started_at: Option<Instant>,
+    /// 上一次运行期间健康检查的时间与结果
+    pub(crate) last_health_check: Option<(Instant, HealthStatus)>,
+    /// 运行期间健康检查连续不健康的次数
+    unhealthy_checks: u32,
+}
+
+pub(crate) type HealthMap = Arc<Mutex<HashMap<String, InstanceHealth>>>;
+
+/// 蓝绿重启时未配置启动探测超时的实例等待新进程就绪的时间
+const BLUE_GREEN_READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// 蓝绿重启等待新进程就绪的时间：启动探测超时，未配置时为 [`BLUE_GREEN_READY_TIMEOUT`]
+fn blue_green_ready_timeout(meta: &FrpcConfigMeta) -> Duration {
+    match meta.probe_timeout_secs {
+        0 => BLUE_GREEN_READY_TIMEOUT,
+        secs => Duration::from_secs(secs),
+    }
+}
+
+/// frpc 进程守护：启动实例，进程退出后重启，直到收到停止信号
+pub struct FrpcSupervisor {
+    pub(crate) processes: Arc<Mutex<Vec<(String, FrpcProcess)>>>,
+    pub(crate) health: HealthMap,
+    pub(crate) instances: InstanceMap,
+    /// 各实例的设置（按名称），没有设置的实例使用默认值
+    instance_settings: HashMap<String, FrpcConfigMeta>,
+    /// 设置了自启动的实例名（含程序或配置缺失、未能启动的），随实例设置更新；指标导出据此计入从未启动成功的实例
+    pub(crate) auto_start: Arc<RwLock<HashSet<String>>>,
+    /// 手动停止、不再重启的实例
+    pub(crate) guard_stopped: Arc<Mutex<HashSet<String>>>,
+    /// 监控暂停：检查照常跳过，退出的进程不重启，启动探测超时与健康检查不通过也不处理
+    pub(crate) monitor_paused: Arc<AtomicBool>,
+    /// 进程守护重启的总次数
+    pub(crate) total_restarts: Arc<AtomicU64>,
+    /// 停止请求：滚动重启与蓝绿重启不再等待启动探测
+    stop_requested: Arc<AtomicBool>,
+    pub(crate) panic_recovery: bool,
+    /// 已退出、等待重启的实例，跨轮次保留（本轮 panic 时下一轮继续处理）
+    pending_restarts: Vec<String>,
+    poll_interval: Duration,
+    stop_processes_on_shutdown: bool,
+    pub(crate) maintenance_windows: Vec<MaintenanceWindow>,
+    /// 当前所在的维护窗口，用于检测进入与离开
+    maintenance: Option<MaintenanceWindow>,
+    pub(crate) circuit_breaker: CircuitBreaker,
+    pub(crate) path_check: PathCheck,
+    /// 排队与进行中的滚动重启，每轮进程守护推进
+    restarts: Mutex<RestartQueue<Standby>>,
+}
+
+impl FrpcSupervisor {
+    /// 创建进程守护，不启动任何进程
+    pub fn new(config: SupervisorConfig) -> Self {
+        let mut instances = HashMap::new();
+        let mut instance_settings = HashMap::new();
+        for (name, instance) in config.instances {
+            instances.insert(name.clone(), (instance.exe, instance.config));
+            instance_settings.insert(name, instance.settings);
+        }
+        Self {
+            processes: Arc::new(Mutex::new(Vec::new())),
+            health: Arc::new(Mutex::new(HashMap::new())),
+            auto_start: Arc::new(RwLock::new(instances.keys().cloned().collect())),
+            instances: Arc::new(RwLock::new(instances)),
+            instance_settings,
+            guard_stopped: Arc::new(Mutex::new(HashSet::new())),
+            monitor_paused: Arc::new(AtomicBool::new(false)),
+            total_restarts: Arc::new(AtomicU64::new(0)),
+            stop_requested: Arc::new(AtomicBool::new(false)),
+            panic_recovery: config.panic_recovery,
+            pending_restarts: Vec::new(),
+            poll_interval: config.poll_interval,
+            stop_processes_on_shutdown: config.stop_processes_on_shutdown,
+            maintenance_windows: config.maintenance_windows,
+            maintenance: None,
+            circuit_breaker: CircuitBreaker::new(config.circuit_breaker),
+            path_check: PathCheck::new(config.path_check_interval),
+            restarts: Mutex::new(RestartQueue::new()),
+        }
+    }
+
+    /// 使用外部的停止请求标志（服务模式下由 SCM 控制处理程序设置）
+    pub(crate) fn with_stop_flag(mut self, stop_requested: Arc<AtomicBool>) -> Self {
+        self.stop_requested = stop_requested;
+        self
+    }
+
+    /// 是否已收到停止请求
+    pub(crate) fn stop_requested(&self) -> bool {
+        self.stop_requested.load(Ordering::SeqCst)
+    }
+
+    /// 替换各实例的设置（服务模式下实例清单变化时调用），下一次启动、检查或停止时生效
+    pub(crate) fn set_instance_settings(&mut self, settings: HashMap<String, FrpcConfigMeta>) {
+        *self.auto_start.write().unwrap() = settings
+            .values()
+            .filter(|meta| meta.auto_start)
+            .map(|meta| meta.name.clone())
+            .collect();
+        self.instance_settings = settings;
+    }
+
+    /// 实例设置，没有设置的实例使用默认值
+    fn settings(&self, name: &str) -> FrpcConfigMeta {
+        self.instance_settings
+            .get(name)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// 实例停止时的宽限时间
+    pub(crate) fn graceful_stop_timeout(&self, name: &str) -> Duration {
+        self.instance_settings.get(name).map_or(
+            config::DEFAULT_GRACEFUL_STOP_TIMEOUT,
+            FrpcConfigMeta::graceful_stop_timeout,
+        )
+    }
+
+    /// 启动所有实例；已在运行的 frpc 进程按 PID 接管，不重复启动
+    pub fn start(&mut self) {
+        let instances = self.instances.read().unwrap().clone();
+        let running_frpc = service::discover_running_frpc_processes();
+        // 按依赖分层启动（被依赖的实例先启动），层内按名称排序使启动顺序稳定
+        let names: Vec<String> = instances.keys().cloned().collect();
+        let mut started = Vec::new();
+        for id in config::dependency_layers(&names, &self.instance_settings).concat() {
+            let (exe, conf) = instances[&id].clone();
+            if let Some((_, pid)) = running_frpc.iter().find(|(n, _)| n == &id) {
+                if FrpcProcess::is_pid_running(*pid) {
+                    let process = FrpcProcess::from_pid(*pid, id.clone(), exe, conf);
+                    log::info!("[{}] 检测到已运行的进程 (PID: {})", id, pid);
+                    timeline::record(&id, TimelineEvent::Attach { pid: *pid });
+                    self.health
+                        .lock()
+                        .unwrap()
+                        .entry(id.clone())
+                        .or_default()
+                        .started_at = Some(Instant::now());
+                    started.push((id, process));
+                    continue;
+                }
+            }
+            // 启动前确认 frpc.exe 可执行（同一文件只检测一次），不可执行时明确报错并跳过
+            match exe_check::check_frpc_exe(&exe) {
+                Ok(version) => log::debug!("[{}] frpc 可执行，版本 {}", id, version),
+                Err(e) => {
+                    log::error!("[{}] frpc 可执行文件无法运行，跳过启动: {:#}", id, e);
+                    continue;
+                }
+            }
+            match self.start_instance(&id, &exe, &conf) {
+                Ok(p) => {
+                    log::info!("[{}] frpc 进程已启动", id);
+                    timeline::record(&id, TimelineEvent::Start { pid: p.pid() });
+                    started.push((id, p));
+                }
+                Err(e) => log::error!("启动 frpc 实例失败: {:?}", e),
+            }
+        }
+        if started.is_empty() {
+            log::warn!("没有任何 frpc 进程成功启动");
+        } else {
+            log::info!("成功启动 {} 个 frpc 实例", started.len());
+        }
+        self.processes.lock().unwrap().extend(started);
+    }
+
+    /// 启动一个 frpc 实例，配置了启动探测时在后台异步确认隧道建立
+    ///
+    /// 探测超时且配置了 `probe_restart_on_timeout` 时终止进程，由进程守护提前重启
+    pub(crate) fn start_instance(
+        &self,
+        name: &str,
+        exe: &Path,
+        conf: &Path,
+    ) -> Result<FrpcProcess> {
+        let meta = self.settings(name);
+        let probe_enabled = meta.probe_timeout_secs > 0;
+        let (tx, rx) = mpsc::channel();
+        let process = FrpcProcess::start_with_settings(
+            name.to_string(),
+            exe.to_path_buf(),
+            conf.to_path_buf(),
+            probe_enabled.then_some(tx),
+            Some(meta.clone()),
+        )
+        .inspect_err(|e| {
+            etw::write(EtwEvent::StartupFailure {
+                instance: name,
+                error: &format!("{:#}", e),
+            })
+        })?;
+        {
+            let mut h = self.health.lock().unwrap();
+            let entry = h.entry(name.to_string()).or_default();
+            entry.healthy = !probe_enabled;
+            entry.started_at = Some(Instant::now());
+        }
+        if !probe_enabled {
+            return Ok(process);
+        }
+
+        let pid = process.pid();
+        let n = name.to_string();
+        let health = Arc::clone(&self.health);
+        let paused = Arc::clone(&self.monitor_paused);
+        let restart_on_timeout = meta.probe_restart_on_timeout;
+        probe::spawn_startup_probe(
+            name.to_string(),
+            conf.to_path_buf(),
+            rx,
+            meta.ready_pattern.clone(),
+            Duration::from_secs(meta.probe_timeout_secs),
+            move |passed| {
+                if passed {
+                    let mut h = health.lock().unwrap();
+                    let entry = h.entry(n).or_default();
+                    entry.healthy = true;
+                    entry.consecutive_restarts = 0;
+                } else if paused.load(Ordering::SeqCst) {
+                    log::info!("[{}] 启动探测超时，监控已暂停，不做处理", n);
+                } else if restart_on_timeout && FrpcProcess::is_pid_running(pid) {
+                    log::warn!("[{}] 启动探测超时，提前重启 (PID: {})", n, pid);
+                    if let Err(e) = FrpcProcess::kill_pid(pid) {
+                        log::error!("[{}] 终止探测超时的进程失败: {:?}", n, e);
+                    }
+                }
+            },
+        );
+        Ok(process)
+    }
+
+    /// 进程守护的一轮检查：检测已退出的进程并重启；监控暂停时跳过
+    pub fn supervise_once(&mut self) {
+        // 滚动重启与监控暂停无关，照常推进
+        self.advance_restarts();
+        if self.monitor_paused.load(Ordering::SeqCst) {
+            return;
+        }
+        if !self.panic_recovery {
+            self.supervise_round();
+            return;
+        }
+        let result = panic::catch_unwind(AssertUnwindSafe(|| self.supervise_round()));
+        if let Err(payload) = result {
+            log::error!(
+                "进程守护本轮检查发生 panic: {}，已恢复，{} 个待重启实例将在下一轮继续处理",
+                panic_message(payload.as_ref()),
+                self.pending_restarts.len()
+            );
+            // panic 时持有的锁会被标记为中毒，清除后管道线程与下一轮检查才能继续使用
+            self.processes.clear_poison();
+            self.guard_stopped.clear_poison();
+            self.instances.clear_poison();
+            self.health.clear_poison();
+            self.restarts.clear_poison();
+        }
+    }
+
+    fn supervise_round(&mut self) {
+        let window =
+            config::active_maintenance_window(&self.maintenance_windows, Local::now().time())
+                .cloned();
+        if window != self.maintenance {
+            if let Some(entered) = &window {
+                log::info!("进入维护窗口 {}，实例退出后只记录，不重启、不告警", entered);
+            } else if let Some(left) = &self.maintenance {
+                log::info!("维护窗口 {} 结束，恢复进程守护", left);
+                self.queue_missing_instances();
+            }
+            self.maintenance = window;
+        }
+
+        let now = Instant::now();
+        let instances = &self.instances;
+        let configs = || {
+            instances
+                .read()
+                .unwrap()
+                .values()
+                .map(|(_, conf)| conf.clone())
+                .collect()
+        };
+        if self.circuit_breaker.poll(now, configs) {
+            log::info!("frps 已可连接，熔断恢复，统一拉起未运行的实例");
+            self.queue_missing_instances();
+        }
+        self.check_paths(now);
+        let hold = if self.maintenance.is_some() {
+            Some("维护窗口内，窗口结束后拉起")
+        } else if self.circuit_breaker.is_open() {
+            Some("熔断中，恢复后统一拉起")
+        } else {
+            None
+        };
+        self.check_health(hold);
+        self.run_output_actions(hold);
+        let exited = self.detect_exited(hold);
+        if self.circuit_breaker.record_crashes(exited, now) {
+            // 已在待重启列表中的实例也不再重启，恢复后统一拉起
+            self.pending_restarts.clear();
+            let settings = self.circuit_breaker.settings().cloned().unwrap_or_default();
+            alert::dispatch_service_alert(&AlertEvent::CircuitOpen {
+                crashes: self.circuit_breaker.status().map_or(0, |s| s.crashes),
+                window_secs: settings.window_secs,
+                open_secs: settings.open_secs,
+            });
+        }
+        if self.circuit_breaker.is_open() {
+            return;
+        }
+        self.restart_pending();
+    }
+
+    /// 一轮检查的第一步：检测已退出的进程，加入待重启列表，返回意外退出的实例数
+    ///
+    /// 待重启实例记录在 `pending_restarts` 中，由 [`restart_pending`](Self::restart_pending) 逐个处理完成后才移除，
+    /// 即使本轮中途 panic，未处理的实例也会在下一轮继续重启而不会丢失。
+    /// `hold` 为暂停重启的原因（维护窗口、熔断），设置时退出的实例只记录，不加入待重启列表、不告警
+    fn detect_exited(&mut self, hold: Option<&str>) -> usize {
+        let mut exited = 0;
+        // 告警可能发送 HTTP 请求，收集后在释放锁之后再发送
+        let mut alerts = Vec::new();
+        {
+            let gs = self.guard_stopped.lock().unwrap();
+            let mut proc_list = self.processes.lock().unwrap();
+            let (health, pending) = (&self.health, &mut self.pending_restarts);
+            proc_list.retain_mut(|(name, proc)| {
+                if FrpcProcess::is_pid_running(proc.pid()) {
+                    return true;
+                }
+                // 只有子进程句柄能取得真实退出码，从 PID 恢复跟踪的进程记为未知
+                let exit_code = if proc.has_child_handle() {
+                    proc.check_exit_status().and_then(|status| status.code())
+                } else {
+                    None
+                };
+                {
+                    let mut h = health.lock().unwrap();
+                    let entry = h.entry(name.clone()).or_default();
+                    entry.last_exit_code = exit_code;
+                    entry.started_at = None;
+                    entry.last_health_check = None;
+                    entry.unhealthy_checks = 0;
+                }
+                if gs.contains(name) {
+                    log::info!("[{}] 进程已退出（UI 手动停止，不重启）", name);
+                } else if let Some(hold) = hold {
+                    log::info!(
+                        "[{}] 进程已退出（{}，不重启、不告警），退出码: {:?}",
+                        name,
+                        hold,
+                        exit_code
+                    );
+                } else {
+                    exited += 1;
+                    // 暂不重启，等 grace period 后再确认
+                    let (healthy, restarts) = health
+                        .lock()
+                        .unwrap()
+                        .get(name)
+                        .map_or((true, 0), |h| (h.healthy, h.consecutive_restarts));
+                    if healthy {
+                        log::info!("[{}] 进程已退出，等待确认后重启", name);
+                    } else {
+                        log::warn!("[{}] 进程在启动探测通过前退出，等待确认后重启", name);
+                    }
+                    etw::write(EtwEvent::InstanceCrash { instance: name });
+                    timeline::record(
+                        name,
+                        TimelineEvent::Crash {
+                            pid: proc.pid(),
+                            exit_code,
+                        },
+                    );
+                    alerts.push((
+                        name.clone(),
+                        AlertEvent::Crash {
+                            instance: name.clone(),
+                            consecutive_restarts: restarts,
+                        },
+                    ));
+                    pending.push(name.clone());
+                }
+                false
+            });
+        }
+
+        self.dispatch_alerts(alerts);
+        exited
+    }
+
+    /// 发送收集到的告警（实例名, 事件），须在释放进程列表等锁之后调用
+    fn dispatch_alerts(&self, alerts: Vec<(String, AlertEvent)>) {
+        for (name, event) in alerts {
+            alert::dispatch_alert(&event, &self.settings(&name).alert);
+        }
+    }
+
+    /// 一轮检查前的健康检查：按各实例的 `health_check` 设置评估到期的运行中进程
+    ///
+    /// 只检查启动探测已通过的进程（从 PID 恢复跟踪的进程没有启动探测，直接检查）；连续 `unhealthy_threshold` 次不健康时终止进程，
+    /// 由下一轮检查按崩溃处理并重启。`hold` 不为 None（维护窗口、熔断中）时只评估与记录，不终止
+    fn check_health(&self, hold: Option<&str>) {
+        let checks: HashMap<&str, &HealthCheckConfig> = self
+            .instance_settings
+            .iter()
+            .filter_map(|(name, meta)| Some((name.as_str(), meta.health_check.as_ref()?)))
+            .collect();
+        if checks.is_empty() {
+            return;
+        }
+        let now = Instant::now();
+        // 在锁内只取到期进程的快照，评估可能访问 admin API，不持有进程列表与健康状态锁
+        let due: Vec<(String, &HealthCheckConfig, HealthTarget)> = {
+            let proc_list = self.processes.lock().unwrap();
+            let h = self.health.lock().unwrap();
+            proc_list
+                .iter()
+                .filter_map(|(name, proc)| {
+                    let check = *checks.get(name.as_str())?;
+                    let due = h.get(name).is_some_and(|h| {
+                        (h.healthy || !proc.has_child_handle())
+                            && h.last_health_check.as_ref().is_none_or(|(at, _)| {
+                                now.duration_since(*at)
+                                    >= Duration::from_secs(check.interval_secs.max(1))
+                            })
+                    });
+                    due.then(|| (name.clone(), check, HealthTarget::of(proc)))
+                })
+                .collect()
+        };
+        let mut to_kill = Vec::new();
+        for (name, check, target) in due {
+            let status = check.is_healthy(&target);
+            let mut h = self.health.lock().unwrap();
+            let entry = h.entry(name.clone()).or_default();
+            let previous = entry.last_health_check.take().map(|(_, status)| status);
+            match &status {
+                HealthStatus::Healthy => {
+                    if previous.is_some_and(|p| p != HealthStatus::Healthy) {
+                        log::info!("[{}] 健康检查恢复正常", name);
+                    }
+                    entry.unhealthy_checks = 0;
+                }
+                HealthStatus::Degraded(_) => {
+                    if previous.as_ref() != Some(&status) {
+                        log::warn!("[{}] 健康检查: {}", name, status);
+                    }
+                    entry.unhealthy_checks = 0;
+                }
+                HealthStatus::Unhealthy(_) => {
+                    entry.unhealthy_checks += 1;
+                    log::warn!(
+                        "[{}] 健康检查: {}（连续第 {} 次，共 {} 次后重启）",
+                        name,
+                        status,
+                        entry.unhealthy_checks,
+                        check.unhealthy_threshold.max(1)
+                    );
+                    if entry.unhealthy_checks >= check.unhealthy_threshold.max(1) {
+                        entry.unhealthy_checks = 0;
+                        to_kill.push((name.clone(), target.pid()));
+                    }
+                }
+            }
+            entry.last_health_check = Some((now, status));
+        }
+        for (name, pid) in to_kill {
+            if let Some(hold) = hold {
+                log::info!("[{}] 健康检查不通过，{}，暂不重启", name, hold);
+            } else if self.monitor_paused.load(Ordering::SeqCst) {
+                log::info!("[{}] 健康检查不通过，监控已暂停，不做处理", name);
+            } else {
+                log::warn!(
+                    "[{}] 健康检查不通过，终止进程由进程守护重启 (PID: {})",
+                    name,
+                    pid
+                );
+                if let Err(e) = FrpcProcess::kill_pid(pid) {
+                    log::error!("[{}] 终止不健康的进程失败: {:?}", name, e);
+                }
+            }
+        }
+    }
+
+    /// 一轮检查的第二步：重启待重启列表中的实例，处理完一个移除一个
+    fn restart_pending(&mut self) {
+        // 等待 500ms 给 STOP 命令到达的时间，然后重新检查 guard_stopped
+        if self.pending_restarts.is_empty() {
+            return;
+        }
+        thread::sleep(Duration::from_millis(500));
+        let instances = self.instances.read().unwrap().clone();
+        // 启动记录中的哈希与 frpc --version 较慢，先在锁外算好（进入缓存），持锁期间只启动进程
+        for name in &self.pending_restarts {
+            if let Some((exe, conf)) = instances.get(name) {
+                frpc_mg::prefetch_launch_info(exe, conf);
+            }
+        }
+        let mut alerts = Vec::new();
+        let gs = self.guard_stopped.lock().unwrap();
+        let mut proc_list = self.processes.lock().unwrap();
+        while let Some(name) = self.pending_restarts.first().cloned() {
+            if gs.contains(&name) {
+                log::info!("[{}] 等待期间收到停止命令，取消重启", name);
+            } else if let Some((exe, conf)) = instances.get(&name) {
+                let missing = path_check::missing_paths(exe, conf);
+                let started = if missing.is_empty() {
+                    self.start_instance(&name, exe, conf)
+                } else {
+                    Err(anyhow::anyhow!(
+                        "{}（运行期间被删除或移动），请恢复文件或在界面中修改实例路径",
+                        missing.join("；")
+                    ))
+                };
+                match started {
+                    Ok(p) => {
+                        let restarts = {
+                            let mut h = self.health.lock().unwrap();
+                            let entry = h.entry(name.clone()).or_default();
+                            entry.consecutive_restarts += 1;
+                            entry.restarts_total += 1;
+                            entry.consecutive_restarts
+                        };
+                        self.total_restarts.fetch_add(1, Ordering::SeqCst);
+                        log::info!(
+                            "[{}] 进程守护重启成功（连续第 {} 次，启动探测通过后清零）",
+                            name,
+                            restarts
+                        );
+                        etw::write(EtwEvent::InstanceRestart {
+                            instance: &name,
+                            pid: p.pid(),
+                        });
+                        timeline::record(
+                            &name,
+                            TimelineEvent::Restart {
+                                pid: p.pid(),
+                                consecutive: restarts,
+                            },
+                        );
+                        proc_list.push((name.clone(), p));
+                    }
+                    Err(e) => {
+                        log::error!("[{}] 进程守护重启失败: {:?}", name, e);
+                        alerts.push((
+                            name.clone(),
+                            AlertEvent::GiveUp {
+                                instance: name.clone(),
+                                error: format!("{:#}", e),
+                            },
+                        ));
+                    }
+                }
+            }
+            self.pending_restarts.remove(0);
+        }
+        drop(proc_list);
+        drop(gs);
+        self.dispatch_alerts(alerts);
+        // 通知 UI 更新界面显示
+        service::signal_process_changed();
+    }
+
+    /// 执行输出规则（`on_output`）命中的动作；只作用于产生该输出的进程，该进程已退出或已被替换时忽略
+    ///
+    /// `hold` 不为 None（维护窗口、熔断中）时不执行 restart，其余动作照常执行
+    fn run_output_actions(&self, hold: Option<&str>) {
+        for matched in output_pump::take_output_matches() {
+            let name = matched.instance.as_str();
+            let running = self
+                .processes
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|(n, p)| n == name && p.pid() == matched.pid);
+            if !running {
+                log::debug!(
+                    "[{}] 输出规则命中的进程 (PID: {}) 已不在运行，忽略动作 {:?}",
+                    name,
+                    matched.pid,
+                    matched.action
+                );
+                continue;
+            }
+            let policy = self.settings(name).alert;
+            let event = |stopped| AlertEvent::OutputMatched {
+                instance: matched.instance.clone(),
+                pattern: matched.pattern.clone(),
+                line: matched.line.clone(),
+                stopped,
+            };
+            match matched.action {
+                OutputAction::Alert => alert::dispatch_alert(&event(false), &policy),
+                OutputAction::Restart => {
+                    let entry = self.instances.read().unwrap().get(name).cloned();
+                    if let Some(hold) = hold {
+                        log::info!("[{}] 输出匹配 restart 规则，{}，不重启", name, hold);
+                    } else if let Some(entry) = entry {
+                        log::info!("[{}] 输出匹配 restart 规则，重启实例", name);
+                        self.rolling_restart(name, &entry);
+                        service::signal_process_changed();
+                    } else {
+                        log::warn!("[{}] 输出匹配 restart 规则，但不是自启动实例，不重启", name);
+                    }
+                }
+                OutputAction::Stop => {
+                    // 先加入手动停止列表，进程守护不会把这次退出当作崩溃重启
+                    self.guard_stopped.lock().unwrap().insert(name.to_string());
+                    let removed = {
+                        let mut proc_list = self.processes.lock().unwrap();
+                        let pos = proc_list.iter().position(|(n, _)| n == name);
+                        pos.map(|i| proc_list.remove(i))
+                    };
+                    if let Some((_, mut proc)) = removed {
+                        if let Err(e) = proc.stop(self.graceful_stop_timeout(name)) {
+                            log::error!("[{}] 输出规则停止实例失败: {:?}", name, e);
+                        }
+                    }
+                    log::error!(
+                        "[{}] 输出匹配 stop 规则，已停止实例，不再重启（需手动启动）",
+                        name
+                    );
+                    alert::dispatch_alert(&event(true), &policy);
+                    service::signal_process_changed();
+                }
+                OutputAction::Ignore => {}
+            }
+        }
+    }
+
+    /// 排队滚动重启单个实例（先停后启或蓝绿，见 [`RestartQueue`]），空闲时立即开始
+    ///
+    /// 等待新进程就绪由每轮进程守护推进，不阻塞调用方；已收到停止请求时不再重启
+    pub(crate) fn rolling_restart(&self, name: &str, entry: &(PathBuf, PathBuf)) {
+        if self.stop_requested() {
+            log::info!("[{}] 服务正在停止，跳过滚动重启", name);
+            return;
+        }
+        let mut restarts = self.restarts.lock().unwrap();
+        restarts.push(name, entry.clone());
+        restarts.advance(self, Instant::now());
+    }
+
+    /// 推进排队的滚动重启；已收到停止请求时丢弃排队的重启并回滚进行中的蓝绿重启
+    pub(crate) fn advance_restarts(&self) {
+        let mut restarts = self.restarts.lock().unwrap();
+        if self.stop_requested() {
+            restarts.abort(self);
+        } else {
+            restarts.advance(self, Instant::now());
+        }
+    }
+
+    /// 服务退出前调用：丢弃排队的重启，进行中的蓝绿重启停止新进程，旧进程继续运行
+    pub(crate) fn abort_restarts(&self) {
+        self.restarts.lock().unwrap().abort(self);
+    }
+
+    /// 重新加载熔断设置；设置中关闭熔断时立即拉起熔断期间未重启的实例
+    pub(crate) fn set_circuit_breaker(&mut self, settings: Option<CircuitBreakerSettings>) {
+        if self.circuit_breaker.set_settings(settings) {
+            log::info!("熔断已在设置中关闭，统一拉起未运行的实例");
+            self.queue_missing_instances();
+        }
+    }
+
+    /// 按间隔检查运行中实例的程序与配置文件，新发现消失时按实例告警策略告警
+    fn check_paths(&mut self, now: Instant) {
+        let (processes, instances) = (&self.processes, &self.instances);
+        let running = || {
+            let instances = instances.read().unwrap();
+            processes
+                .lock()
+                .unwrap()
+                .iter()
+                .filter_map(|(name, _)| {
+                    let (exe, conf) = instances.get(name)?;
+                    Some((name.clone(), exe.clone(), conf.clone()))
+                })
+                .collect()
+        };
+        for (instance, missing) in self.path_check.poll(now, running) {
+            let policy = self.settings(&instance).alert;
+            alert::dispatch_alert(&AlertEvent::PathMissing { instance, missing }, &policy);
+        }
+    }
+
+    /// 把未在运行的实例（手动停止的除外）加入待重启列表，由本轮检查拉起
+    fn queue_missing_instances(&mut self) {
+        let running: HashSet<String> = self
+            .processes
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, _)| name.clone())
+            .collect();
+        let guard_stopped = self.guard_stopped.lock().unwrap();
+        let mut missing: Vec<String> = self
+            .instances
+            .read()
+            .unwrap()
+            .keys()
+            .filter(|name| !running.contains(*name) && !guard_stopped.contains(*name))
+            .filter(|name| !self.pending_restarts.contains(name))
+            .cloned()
+            .collect();
+        drop(guard_stopped);
+        missing.sort();
+        if !missing.is_empty() {
+            log::info!("拉起维护窗口内退出的实例: {}", missing.join(", "));
+        }
+        self.pending_restarts.extend(missing);
+    }
+
+    /// 按依赖的逆序停止进程：依赖其他实例的先停止，同一层的实例并行停止；
+    /// 每个实例先按其 `graceful_stop_timeout_secs` 正常停止，超时后强制终止，
+    /// 停止后从 `pending` 中移除（停止诊断据此判断哪些实例尚未退出）
+    pub(crate) fn stop_in_dependency_order(
+        &self,
+        mut processes: Vec<(String, FrpcProcess)>,
+        pending: Option<&Arc<PendingStops>>,
+    ) {
+        let names: Vec<String> = processes.iter().map(|(name, _)| name.clone()).collect();
+        for layer in config::dependency_layers(&names, &self.instance_settings)
+            .into_iter()
+            .rev()
+        {
+            let mut batch = Vec::new();
+            while let Some(i) = processes.iter().position(|(n, _)| layer.contains(n)) {
+                batch.push(processes.swap_remove(i));
+            }
+            let handles: Vec<_> = batch
+                .into_iter()
+                .map(|(name, mut proc)| {
+                    let pending = pending.cloned();
+                    let timeout = self.graceful_stop_timeout(&name);
+                    thread::spawn(move || {
+                        if let Err(e) = proc.stop(timeout) {
+                            log::error!("[{}] 停止 frpc 进程失败: {:?}", name, e);
+                        }
+                        if let Some(pending) = pending {
+                            pending.done(&name);
+                        }
+                    })
+                })
+                .collect();
+            for handle in handles {
+                let _ = handle.join();
+            }
+        }
+    }
+
+    /// 停止所有跟踪的 frpc 进程（按依赖的逆序，各实例按其宽限时间）
+    pub fn stop_all(&mut self) {
+        let stopped = std::mem::take(&mut *self.processes.lock().unwrap());
+        self.stop_in_dependency_order(stopped, None);
+    }
+
+    /// 当前跟踪的实例名与 PID
+    pub fn running(&self) -> Vec<(String, u32)> {
+        self.processes
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, proc)| (name.clone(), proc.pid()))
+            .collect()
+    }
+
+    /// 启动所有实例并持续守护，直到 `shutdown` 收到消息或发送端全部被丢弃
+    pub fn run(mut self, shutdown: Receiver<()>) -> Result<()> {
+        self.start();
+        while let Err(RecvTimeoutError::Timeout) = shutdown.recv_timeout(self.poll_interval) {
+            self.supervise_once();
+        }
+        self.stop_requested.store(true, Ordering::SeqCst);
+        self.abort_restarts();
+        if self.stop_processes_on_shutdown {
+            self.stop_all();
+        }
+        Ok(())
+    }
+}
+
+/// 蓝绿重启中已启动、尚未加入进程列表的新进程，以及其启动探测的结果
+pub(crate) struct Standby {
+    process: FrpcProcess,
+    ready: Receiver<bool>,
+}
+
+impl RestartSteps for FrpcSupervisor {
+    type Standby = Standby;
+
+    /// 旧进程已退出时没有需要保持的连接，蓝绿重启按常规方式重启
+    fn plan(&self, name: &str) -> (RestartStrategy, Duration) {
+        let meta = self.settings(name);
+        let old_running = self
+            .processes
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|(n, p)| n == name && FrpcProcess::is_pid_running(p.pid()));
+        match meta.restart_strategy {
+            RestartStrategy::BlueGreen if old_running => {
+                (RestartStrategy::BlueGreen, blue_green_ready_timeout(&meta))
+            }
+            _ => (
+                RestartStrategy::StopFirst,
+                Duration::from_secs(meta.probe_timeout_secs),
+            ),
+        }
+    }
+
+    fn stop_old(&self, name: &str) {
+        let removed = {
+            let mut proc_list = self.processes.lock().unwrap();
+            let pos = proc_list.iter().position(|(n, _)| n == name);
+            pos.map(|i| proc_list.remove(i))
+        };
+        if let Some((_, mut proc)) = removed {
+            if let Err(e) = proc.stop(self.graceful_stop_timeout(name)) {
+                log::error!("[{}] 滚动重启：停止失败: {:?}", name, e);
+            }
+        }
+    }
+
+    fn start_new(&self, name: &str, (exe, conf): &(PathBuf, PathBuf)) -> bool {
+        match self.start_instance(name, exe, conf) {
+            Ok(p) => {
+                self.processes.lock().unwrap().push((name.to_string(), p));
+                true
+            }
+            Err(e) => {
+                log::error!("[{}] 滚动重启：启动失败: {:?}", name, e);
+                false
+            }
+        }
+    }
+
+    fn is_ready(&self, name: &str) -> bool {
+        self.health
+            .lock()
+            .unwrap()
+            .get(name)
+            .is_some_and(|h| h.healthy)
+    }
+
+    /// 等待期间新进程不在进程列表中，进程守护只看到旧进程
+    fn start_standby(&self, name: &str, (exe, conf): &(PathBuf, PathBuf)) -> Option<Standby> {
+        let meta = self.settings(name);
+        let (tx, rx) = mpsc::channel();
+        let process = match FrpcProcess::start_with_settings(
+            name.to_string(),
+            exe.clone(),
+            conf.clone(),
+            Some(tx),
+            Some(meta.clone()),
+        ) {
+            Ok(p) => p,
+            Err(e) => {
+                etw::write(EtwEvent::StartupFailure {
+                    instance: name,
+                    error: &format!("{:#}", e),
+                });
+                log::error!(
+                    "[{}] 蓝绿重启：新进程启动失败，旧进程继续运行: {:?}",
+                    name,
+                    e
+                );
+                return None;
+            }
+        };
+        let (done_tx, done_rx) = mpsc::channel();
+        probe::spawn_startup_probe(
+            name.to_string(),
+            conf.clone(),
+            rx,
+            meta.ready_pattern.clone(),
+            blue_green_ready_timeout(&meta),
+            move |passed| {
+                let _ = done_tx.send(passed);
+            },
+        );
+        Some(Standby {
+            process,
+            ready: done_rx,
+        })
+    }
+
+    /// 新进程启动后立即退出时不必等到探测超时
+    fn standby_ready(&self, standby: &mut Standby) -> Option<bool> {
+        match standby.ready.try_recv() {
+            Ok(passed) => Some(passed && standby.process.is_running()),
+            Err(TryRecvError::Disconnected) => Some(false),
+            Err(TryRecvError::Empty) => (!standby.process.is_running()).then_some(false),
+        }
+    }
+
+    fn promote(&self, name: &str, standby: Standby) {
+        let new_pid = standby.process.pid();
+        let old = {
+            let mut proc_list = self.processes.lock().unwrap();
+            let old = proc_list
+                .iter()
+                .position(|(n, _)| n == name)
+                .map(|i| proc_list.remove(i));
+            proc_list.push((name.to_string(), standby.process));
+            let mut h = self.health.lock().unwrap();
+            let entry = h.entry(name.to_string()).or_default();
+            entry.healthy = true;
+            entry.consecutive_restarts = 0;
+            entry.started_at = Some(Instant::now());
+            old
+        };
+        if let Some((_, mut old_proc)) = old {
+            if let Err(e) = old_proc.stop(self.graceful_stop_timeout(name)) {
+                log::error!("[{}] 蓝绿重启：停止旧进程失败: {:?}", name, e);
+            }
+        }
+        log::info!("[{}] 蓝绿重启完成，新进程 PID: {}", name, new_pid);
+        service::signal_process_changed();
+    }
+
+    fn discard(&self, name: &str, mut standby: Standby) {
+        log::error!(
+            "[{}] 蓝绿重启：新进程 (PID: {}) 未就绪，已回滚，旧进程继续运行",
+            name,
+            standby.process.pid()
+        );
+        if let Err(e) = standby.process.stop(Duration::ZERO) {
+            log::error!("[{}] 蓝绿重启：停止未就绪的新进程失败: {:?}", name, e);
+        }
+    }
+}
+
+/// 从 panic 负载中提取可读的消息
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "未知 panic".to_string()
+    }
+}
@@ -0,0 +1,124 @@
+//! 汇总近期错误（`tail-errors`）：扫描 logs/ 下所有日志文件中的 ERROR 与 WARN 行，按时间排序输出
+//!
+//! 主日志、错误汇总日志与实例日志会记录同一条日志，完全相同的行只输出一次。
+//! 文件名中的日期早于起始时间的文件直接跳过；文本与 JSON 两种行格式都能识别
+
+use anyhow::{Context, Result};
+use chrono::{Local, NaiveDate, NaiveDateTime};
+use log::Level;
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::config;
+use crate::watch_logs;
+
+/// 日志行时间戳格式
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// 解析 `--since` 参数：数字加单位 `s`/`m`/`h`/`d`，如 `30m`、`1h`、`2d`
+pub fn parse_since(value: &str) -> Result<Duration> {
+    let usage = || format!("--since 无效: {}（如 30m、1h、2d）", value);
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .with_context(usage)?;
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number.parse().ok().with_context(usage)?;
+    let unit_secs: u64 = match unit.to_ascii_lowercase().as_str() {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => anyhow::bail!(usage()),
+    };
+    let secs = number.checked_mul(unit_secs).with_context(usage)?;
+    anyhow::ensure!(secs > 0, usage());
+    Ok(Duration::from_secs(secs))
+}
+
+/// 输出 `since` 时间内的 ERROR 与 WARN 行，返回输出的行数
+pub fn run(since: Duration) -> Result<usize> {
+    let logs_dir = config::logs_dir()?;
+    let cutoff =
+        Local::now().naive_local() - chrono::Duration::from_std(since).context("--since 过大")?;
+    let mut files = Vec::new();
+    collect_log_files(&logs_dir, &mut files);
+
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut entries: Vec<(NaiveDateTime, String)> = Vec::new();
+    for path in files {
+        if file_date(&path).is_some_and(|date| date < cutoff.date()) {
+            continue;
+        }
+        let Ok(file) = std::fs::File::open(&path) else {
+            continue;
+        };
+        for line in BufReader::new(file).lines().map_while(|l| l.ok()) {
+            let Some(time) = warning_time(&line) else {
+                continue;
+            };
+            if time >= cutoff && seen.insert(line.clone()) {
+                entries.push((time, line));
+            }
+        }
+    }
+    // 按时间稳定排序，同一秒内保持文件中的先后顺序
+    entries.sort_by_key(|(time, _)| *time);
+
+    let mut out = std::io::stdout().lock();
+    for (_, line) in &entries {
+        let _ = writeln!(out, "{}", line);
+    }
+    let _ = out.flush();
+    Ok(entries.len())
+}
+
+/// logs/ 及其下一级目录（实例日志）中的 .log 文件
+fn collect_log_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            if let Ok(sub) = std::fs::read_dir(&path) {
+                files.extend(
+                    sub.flatten()
+                        .map(|e| e.path())
+                        .filter(|p| p.is_file() && is_log_file(p)),
+                );
+            }
+        } else if is_log_file(&path) {
+            files.push(path);
+        }
+    }
+}
+
+fn is_log_file(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("log"))
+}
+
+/// 文件名中的日期（`YYYY-MM-DD.log`、`errors-YYYY-MM-DD.log`、`YYYY-MM-DD-manual-*.log`）
+fn file_date(path: &Path) -> Option<NaiveDate> {
+    let name = path.file_name()?.to_str()?;
+    let name = name.strip_prefix("errors-").unwrap_or(name);
+    NaiveDate::parse_from_str(name.get(..10)?, "%Y-%m-%d").ok()
+}
+
+/// ERROR 或 WARN 行的时间，其他行返回 None
+fn warning_time(line: &str) -> Option<NaiveDateTime> {
+    let (level, timestamp) = if line.starts_with('{') {
+        let value: serde_json::Value = serde_json::from_str(line).ok()?;
+        let level: Level = value.get("level")?.as_str()?.parse().ok()?;
+        (level, value.get("time")?.as_str()?.to_string())
+    } else {
+        (watch_logs::line_level(line)?, line.get(..19)?.to_string())
+    };
+    if level > Level::Warn {
+        return None;
+    }
+    NaiveDateTime::parse_from_str(&timestamp, TIMESTAMP_FORMAT).ok()
+}
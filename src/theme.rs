@@ -4,6 +4,8 @@ use gpui::App;
 use gpui_component::theme::{Theme, ThemeMode, ThemeRegistry};
 use std::sync::RwLock;
 
+use crate::config;
+
 /// 当前主题名称（全局状态）
 static CURRENT_THEME: RwLock<String> = RwLock::new(String::new());
 
@@ -117,9 +119,7 @@ pub fn apply_theme(theme_name: &str, cx: &mut gpui::App) {
 
 /// 获取主题偏好文件路径
 fn theme_pref_path() -> Option<std::path::PathBuf> {
-    std::env::current_exe()
-        .ok()
-        .and_then(|p| p.parent().map(|p| p.join("conf").join("theme.json")))
+    config::conf_dir().ok().map(|dir| dir.join("theme.json"))
 }
 
 /// 保存主题偏好
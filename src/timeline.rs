@@ -0,0 +1,189 @@
+//! 实例事件时间线：把实例的启动、崩溃、重启成功追加到 logs/<实例名>/timeline.jsonl，便于事后复盘时直接导入分析
+//!
+//! 每行一个 JSON 对象，字段固定（见 [`TimelineRecord`]），新增字段只追加、不修改已有字段的含义；
+//! `v` 为 schema 版本，字段含义变化时递增。文件超过 [`MAX_FILE_BYTES`] 时轮转为 timeline.1.jsonl
+//! （已有的依次后移），最多保留 [`MAX_ROTATED_FILES`] 个旧文件。写入失败只记录日志，不影响进程守护
+
+use anyhow::{Context, Result};
+use chrono::Local;
+use serde::Serialize;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::config;
+
+/// 事件 schema 版本
+const SCHEMA_VERSION: u32 = 1;
+
+/// 时间线文件名（位于 logs/<实例名>/）
+const TIMELINE_FILE: &str = "timeline.jsonl";
+
+/// 单个文件的大小上限，超过后轮转
+const MAX_FILE_BYTES: u64 = 1024 * 1024;
+
+/// 轮转后保留的旧文件数
+const MAX_ROTATED_FILES: usize = 3;
+
+/// 多个线程（进程守护、启动探测）可能同时追加，轮转与写入需互斥
+static WRITE_LOCK: Mutex<()> = Mutex::new(());
+
+/// 时间线事件
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub(crate) enum TimelineEvent {
+    /// 服务启动或应用期望状态时启动了实例
+    Start { pid: u32 },
+    /// 服务启动时接管了已在运行的进程（按 PID 跟踪）
+    Attach { pid: u32 },
+    /// 进程意外退出（手动停止、维护窗口与熔断期间的退出不记录）；退出码无法获取时为 null
+    Crash { pid: u32, exit_code: Option<i32> },
+    /// 进程守护重启成功；`consecutive` 为连续重启次数，启动探测通过后清零
+    Restart { pid: u32, consecutive: u32 },
+}
+
+/// timeline.jsonl 中的一行
+#[derive(Debug, Serialize)]
+struct TimelineRecord<'a> {
+    v: u32,
+    /// 本地时间，RFC 3339（毫秒）
+    ts: String,
+    instance: &'a str,
+    #[serde(flatten)]
+    event: TimelineEvent,
+}
+
+/// 追加一条事件，失败时记录警告
+pub(crate) fn record(instance: &str, event: TimelineEvent) {
+    if let Err(e) = append(instance, event) {
+        log::warn!("[{}] 写入事件时间线失败: {:#}", instance, e);
+    }
+}
+
+fn append(instance: &str, event: TimelineEvent) -> Result<()> {
+    let record = TimelineRecord {
+        v: SCHEMA_VERSION,
+        ts: Local::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, false),
+        instance,
+        event,
+    };
+    let mut line = serde_json::to_string(&record)?;
+    line.push('\n');
+    write_line(&config::logs_dir()?.join(instance), &line, MAX_FILE_BYTES)
+}
+
+/// 把一行追加到 `dir` 下的时间线文件，追加后超过 `max_bytes` 时先轮转
+fn write_line(dir: &Path, line: &str, max_bytes: u64) -> Result<()> {
+    fs::create_dir_all(dir).context(format!("无法创建目录 {:?}", dir))?;
+    let path = dir.join(TIMELINE_FILE);
+    let _guard = WRITE_LOCK.lock().unwrap();
+    let size = fs::metadata(&path).map_or(0, |m| m.len());
+    if size > 0 && size + line.len() as u64 > max_bytes {
+        rotate(dir)?;
+    }
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(line.as_bytes()))
+        .context(format!("无法写入 {:?}", path))
+}
+
+/// timeline.jsonl → timeline.1.jsonl，已有的旧文件依次后移，超出保留数的删除
+fn rotate(dir: &Path) -> Result<()> {
+    let oldest = rotated_path(dir, MAX_ROTATED_FILES);
+    if oldest.exists() {
+        fs::remove_file(&oldest).context(format!("无法删除 {:?}", oldest))?;
+    }
+    for index in (1..MAX_ROTATED_FILES).rev() {
+        let from = rotated_path(dir, index);
+        if from.exists() {
+            fs::rename(&from, rotated_path(dir, index + 1))
+                .context(format!("无法轮转 {:?}", from))?;
+        }
+    }
+    let current = dir.join(TIMELINE_FILE);
+    fs::rename(&current, rotated_path(dir, 1)).context(format!("无法轮转 {:?}", current))
+}
+
+fn rotated_path(dir: &Path, index: usize) -> PathBuf {
+    dir.join(format!("timeline.{}.jsonl", index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("frpdesk-timeline-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn line(event: TimelineEvent) -> String {
+        let record = TimelineRecord {
+            v: SCHEMA_VERSION,
+            ts: "2026-01-01T00:00:00.000+08:00".to_string(),
+            instance: "web",
+            event,
+        };
+        serde_json::to_string(&record).unwrap() + "\n"
+    }
+
+    fn events(path: &Path) -> Vec<serde_json::Value> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn events_are_appended_in_order() {
+        let dir = temp_dir("order");
+        for event in [
+            TimelineEvent::Start { pid: 10 },
+            TimelineEvent::Crash {
+                pid: 10,
+                exit_code: None,
+            },
+            TimelineEvent::Restart {
+                pid: 11,
+                consecutive: 1,
+            },
+        ] {
+            write_line(&dir, &line(event), MAX_FILE_BYTES).unwrap();
+        }
+        let events = events(&dir.join(TIMELINE_FILE));
+        let kinds: Vec<_> = events
+            .iter()
+            .map(|e| e["event"].as_str().unwrap())
+            .collect();
+        assert_eq!(kinds, ["start", "crash", "restart"]);
+        assert_eq!(events[0]["v"], SCHEMA_VERSION);
+        assert_eq!(events[0]["instance"], "web");
+        assert_eq!(events[1]["exit_code"], serde_json::Value::Null);
+        assert_eq!(events[2]["pid"], 11);
+        assert_eq!(events[2]["consecutive"], 1);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn full_file_is_rotated() {
+        let dir = temp_dir("rotate");
+        let line = line(TimelineEvent::Attach { pid: 1 });
+        // 每个文件只容得下一行
+        let max = line.len() as u64;
+        for _ in 0..MAX_ROTATED_FILES + 2 {
+            write_line(&dir, &line, max).unwrap();
+        }
+        assert_eq!(events(&dir.join(TIMELINE_FILE)).len(), 1);
+        for index in 1..=MAX_ROTATED_FILES {
+            assert_eq!(events(&rotated_path(&dir, index)).len(), 1);
+        }
+        assert!(!rotated_path(&dir, MAX_ROTATED_FILES + 1).exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,125 @@
+//! Windows 通知中心（Toast）：交互模式下实例掉线/恢复时弹出系统通知
+//!
+//! - 使用 WinRT `ToastNotificationManager`，未打包的桌面程序需先在
+//!   `HKCU\Software\Classes\AppUserModelId\<AUMID>` 下注册显示名称
+//! - 服务运行在会话 0，没有桌面，不推送，只记录日志
+//! - 推送在后台线程中进行，失败只记录日志，不影响界面
+
+use anyhow::{Context, Result};
+use windows::core::HSTRING;
+use windows::Data::Xml::Dom::XmlDocument;
+use windows::UI::Notifications::{ToastNotification, ToastNotificationManager};
+use windows_sys::Win32::Foundation::ERROR_SUCCESS;
+use windows_sys::Win32::System::Registry::{
+    RegCloseKey, RegCreateKeyExW, RegSetValueExW, HKEY, HKEY_CURRENT_USER, KEY_SET_VALUE,
+    REG_OPTION_NON_VOLATILE, REG_SZ,
+};
+use windows_sys::Win32::System::RemoteDesktop::ProcessIdToSessionId;
+
+use crate::config;
+
+/// 通知使用的 AppUserModelID
+const APP_USER_MODEL_ID: &str = "Colzry.FrpDesk";
+
+/// 通知中心中显示的应用名称
+const DISPLAY_NAME: &str = "FrpDesk";
+
+fn to_wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+/// 当前进程是否运行在会话 0（服务会话，没有交互桌面）
+fn in_session_zero() -> bool {
+    let mut session_id = 0u32;
+    let ok = unsafe { ProcessIdToSessionId(std::process::id(), &mut session_id) };
+    ok == 0 || session_id == 0
+}
+
+/// 推送一条系统通知（设置中关闭或没有交互会话时只记录日志）
+pub fn notify(title: &str, body: &str) {
+    if !config::load_settings().toast_notifications {
+        return;
+    }
+    if in_session_zero() {
+        log::debug!("当前没有交互会话，跳过系统通知: {} - {}", title, body);
+        return;
+    }
+    let title = title.to_string();
+    let body = body.to_string();
+    let result = std::thread::Builder::new()
+        .name("toast".to_string())
+        .spawn(move || {
+            if let Err(e) = show(&title, &body) {
+                log::warn!("推送系统通知失败: {:#}", e);
+            }
+        });
+    if let Err(e) = result {
+        log::warn!("无法创建系统通知线程: {}", e);
+    }
+}
+
+fn show(title: &str, body: &str) -> Result<()> {
+    register_app_id()?;
+    let xml = format!(
+        "<toast><visual><binding template=\"ToastGeneric\"><text>{}</text><text>{}</text></binding></visual></toast>",
+        escape_xml(title),
+        escape_xml(body)
+    );
+    let doc = XmlDocument::new().context("无法创建通知内容")?;
+    doc.LoadXml(&HSTRING::from(xml))
+        .context("无法解析通知内容")?;
+    let toast = ToastNotification::CreateToastNotification(&doc).context("无法创建通知")?;
+    let notifier =
+        ToastNotificationManager::CreateToastNotifierWithId(&HSTRING::from(APP_USER_MODEL_ID))
+            .context("无法创建通知发送器")?;
+    notifier.Show(&toast).context("无法显示通知")?;
+    Ok(())
+}
+
+/// 在当前用户下注册 AppUserModelID 的显示名称，否则未打包程序的通知不会显示
+fn register_app_id() -> Result<()> {
+    let subkey = to_wide(&format!(
+        "Software\\Classes\\AppUserModelId\\{}",
+        APP_USER_MODEL_ID
+    ));
+    let value_name = to_wide("DisplayName");
+    let value = to_wide(DISPLAY_NAME);
+    unsafe {
+        let mut key: HKEY = 0;
+        let status = RegCreateKeyExW(
+            HKEY_CURRENT_USER,
+            subkey.as_ptr(),
+            0,
+            std::ptr::null(),
+            REG_OPTION_NON_VOLATILE,
+            KEY_SET_VALUE,
+            std::ptr::null(),
+            &mut key,
+            std::ptr::null_mut(),
+        );
+        if status != ERROR_SUCCESS {
+            anyhow::bail!("无法注册通知应用标识，错误码: {}", status);
+        }
+        let status = RegSetValueExW(
+            key,
+            value_name.as_ptr(),
+            0,
+            REG_SZ,
+            value.as_ptr() as *const u8,
+            (value.len() * 2) as u32,
+        );
+        RegCloseKey(key);
+        if status != ERROR_SUCCESS {
+            anyhow::bail!("无法写入通知应用名称，错误码: {}", status);
+        }
+    }
+    Ok(())
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
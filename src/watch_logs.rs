@@ -0,0 +1,216 @@
+//! 跟踪日志（`watch-logs`）：持续输出当天日志文件的新内容，按日志级别着色
+//!
+//! ERROR 行显示为红色，WARN 为黄色，frpc 的 STDOUT 输出为绿色；标准输出不是终端（重定向到文件或管道）时不着色。
+//! 日志由服务写入，这里只按固定间隔读取文件末尾，跨过零点后自动切换到新一天的文件，
+//! 文件被轮转（`rotate-logs`）或截断时从新文件开头继续。
+
+use anyhow::{Context, Result};
+use chrono::Local;
+use log::{Level, LevelFilter};
+use std::fs::File;
+use std::io::{IsTerminal, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use windows_sys::Win32::System::Console::{
+    GetConsoleMode, GetStdHandle, SetConsoleMode, ENABLE_VIRTUAL_TERMINAL_PROCESSING,
+    STD_OUTPUT_HANDLE,
+};
+
+use crate::config;
+
+/// 读取新内容的间隔
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// 开始跟踪时先输出文件末尾这么多字节（从其中第一个完整行开始）
+const TAIL_BYTES: u64 = 8 * 1024;
+
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+const GREEN: &str = "\x1b[32m";
+const RESET: &str = "\x1b[0m";
+
+/// 解析 `--level` 参数：只显示该级别及更严重的行
+pub fn parse_level(value: &str) -> Result<LevelFilter> {
+    match value.to_ascii_lowercase().as_str() {
+        "error" => Ok(LevelFilter::Error),
+        "warn" | "warning" => Ok(LevelFilter::Warn),
+        "info" => Ok(LevelFilter::Info),
+        "debug" => Ok(LevelFilter::Debug),
+        "trace" => Ok(LevelFilter::Trace),
+        other => anyhow::bail!(
+            "不支持的日志级别: {}（可选 error/warn/info/debug/trace）",
+            other
+        ),
+    }
+}
+
+/// 跟踪主日志（instance 为 None）或指定实例的日志，直到按 Ctrl+C 结束
+pub fn run(instance: Option<&str>, min_level: LevelFilter) -> Result<i32> {
+    let dir = match instance {
+        Some(name) => {
+            if config::find_config(name).is_none() {
+                anyhow::bail!("实例 '{}' 不存在", name);
+            }
+            config::logs_dir()?.join(name)
+        }
+        None => config::logs_dir()?,
+    };
+    let color = std::io::stdout().is_terminal() && enable_ansi();
+    let mut follower = Follower {
+        min_level,
+        color,
+        current_level: None,
+    };
+
+    let mut path = today_log(&dir);
+    let mut tail = Some(TAIL_BYTES);
+    let mut waiting = false;
+    loop {
+        let (mut file, mut offset) = match File::open(&path) {
+            Ok(f) => {
+                eprintln!("==> {} <==", path.display());
+                waiting = false;
+                open_at(f, tail.take())?
+            }
+            Err(_) => {
+                if !waiting {
+                    eprintln!("等待日志文件 {} 创建...", path.display());
+                    waiting = true;
+                }
+                tail = None;
+                thread::sleep(POLL_INTERVAL);
+                path = today_log(&dir);
+                continue;
+            }
+        };
+        let mut pending: Vec<u8> = Vec::new();
+        loop {
+            let len = std::fs::metadata(&path).map(|m| m.len()).ok();
+            match len {
+                // 文件被轮转（重命名后重新创建）或截断，重新打开
+                Some(len) if len < offset => break,
+                None => break,
+                _ => {}
+            }
+            let mut chunk = Vec::new();
+            let read = file
+                .read_to_end(&mut chunk)
+                .context(format!("无法读取日志文件 {}", path.display()))?;
+            offset += read as u64;
+            if read > 0 {
+                pending.extend_from_slice(&chunk);
+                follower.print_complete_lines(&mut pending);
+            }
+
+            let today = today_log(&dir);
+            if today != path {
+                // 跨过零点：输出旧文件剩余内容后切换
+                follower.flush_partial(&mut pending);
+                path = today;
+                break;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+fn today_log(dir: &Path) -> PathBuf {
+    dir.join(format!("{}.log", Local::now().format("%Y-%m-%d")))
+}
+
+/// 定位到开始读取的位置：首次打开时为文件末尾附近第一个完整行，之后从文件开头
+fn open_at(mut file: File, tail: Option<u64>) -> Result<(File, u64)> {
+    let len = file.metadata()?.len();
+    let Some(tail) = tail.filter(|t| len > *t) else {
+        return Ok((file, 0));
+    };
+    let start = len - tail;
+    file.seek(SeekFrom::Start(start))?;
+    let mut skipped = 0u64;
+    let mut byte = [0u8; 1];
+    while file.read(&mut byte)? == 1 {
+        skipped += 1;
+        if byte[0] == b'\n' {
+            break;
+        }
+    }
+    Ok((file, start + skipped))
+}
+
+/// 在 Windows 控制台上启用 ANSI 转义序列（Windows 10 1511 起支持），失败时不着色
+fn enable_ansi() -> bool {
+    unsafe {
+        let handle = GetStdHandle(STD_OUTPUT_HANDLE);
+        let mut mode = 0;
+        if GetConsoleMode(handle, &mut mode) == 0 {
+            return false;
+        }
+        SetConsoleMode(handle, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING) != 0
+    }
+}
+
+struct Follower {
+    min_level: LevelFilter,
+    color: bool,
+    /// 上一条带级别标记的日志的级别；多行消息的后续行沿用它决定是否显示与颜色
+    current_level: Option<Level>,
+}
+
+impl Follower {
+    /// 输出缓冲区中所有完整的行，不完整的行留待下次读取
+    fn print_complete_lines(&mut self, pending: &mut Vec<u8>) {
+        let Some(last_newline) = pending.iter().rposition(|b| *b == b'\n') else {
+            return;
+        };
+        let rest = pending.split_off(last_newline + 1);
+        let text = String::from_utf8_lossy(pending).into_owned();
+        *pending = rest;
+        self.print_lines(&text);
+    }
+
+    /// 切换文件前输出没有换行结尾的最后一行
+    fn flush_partial(&mut self, pending: &mut Vec<u8>) {
+        if !pending.is_empty() {
+            let text = String::from_utf8_lossy(pending).into_owned();
+            pending.clear();
+            self.print_lines(&text);
+        }
+    }
+
+    fn print_lines(&mut self, text: &str) {
+        let mut out = std::io::stdout().lock();
+        for line in text.lines() {
+            if let Some(level) = line_level(line) {
+                self.current_level = Some(level);
+            }
+            // 文件开头的续行不知道所属日志的级别，按 Info 处理
+            let level = self.current_level.unwrap_or(Level::Info);
+            if level > self.min_level {
+                continue;
+            }
+            let color = match level {
+                _ if !self.color => None,
+                Level::Error => Some(RED),
+                Level::Warn => Some(YELLOW),
+                _ if line.contains("FRPC STDOUT: ") => Some(GREEN),
+                _ => None,
+            };
+            let _ = match color {
+                Some(color) => writeln!(out, "{}{}{}", color, line, RESET),
+                None => writeln!(out, "{}", line),
+            };
+        }
+        let _ = out.flush();
+    }
+}
+
+/// 解析日志行 `YYYY-MM-DD HH:MM:SS [LEVEL] ...` 中的级别，不是日志行开头（多行消息的续行）时返回 None
+pub(crate) fn line_level(line: &str) -> Option<Level> {
+    let rest = line.get(20..)?.strip_prefix('[')?;
+    if line.as_bytes().get(4) != Some(&b'-') || line.as_bytes().get(13) != Some(&b':') {
+        return None;
+    }
+    let (level, _) = rest.split_once(']')?;
+    level.parse().ok()
+}